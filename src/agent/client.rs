@@ -0,0 +1,89 @@
+use base64;
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Result as IoResult};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use agent;
+use op_vault::key::MainKey;
+use op_vault::vault::{LockedVault, Vault};
+
+/// How long to wait for the agent to answer.
+const TIMEOUT_SECS: u64 = 10;
+
+/// Sends `request` to the agent and returns its response, failing if no agent of the current
+/// user is listening or it reports an error.
+pub fn send(request: Value) -> IoResult<Value> {
+    let stream = try!(UnixStream::connect(try!(agent::socket_path())));
+    // Make sure the socket was not planted by another user to collect our requests.
+    try!(agent::check_peer(&stream));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS))));
+    try!(agent::write_message(&stream, &request));
+    let response = try!(agent::read_message(&stream));
+    if response.find("ok").and_then(|v| v.as_boolean()) != Some(true) {
+        let error = response.find("error").and_then(|v| v.as_string()).unwrap_or("unknown error");
+        return Err(io::Error::new(io::ErrorKind::Other, error.to_string()));
+    }
+    Ok(response)
+}
+
+/// Unlocks `locked_vault` with keys from the agent, or returns `None` if there is no agent or
+/// it holds another vault.
+pub fn unlock(locked_vault: &LockedVault) -> Option<Vault> {
+    let response = match request_keys(locked_vault, false) {
+        Some(response) => response,
+        None => return None,
+    };
+    match (key(&response, "master_key"),
+           key(&response, "overview_key"),
+           key_fingerprint(&response)) {
+        (Some(master_key), Some(overview_key), Some(key_fingerprint)) => {
+            locked_vault.unlock_with_keys(master_key, overview_key, &key_fingerprint)
+        }
+        _ => None,
+    }
+}
+
+/// Like `unlock`, but asks the agent for the overview key alone, so that the vault can list and
+/// search items but not decrypt their details.
+pub fn unlock_overview(locked_vault: &LockedVault) -> Option<Vault> {
+    let response = match request_keys(locked_vault, true) {
+        Some(response) => response,
+        None => return None,
+    };
+    match (key(&response, "overview_key"), key_fingerprint(&response)) {
+        (Some(overview_key), Some(key_fingerprint)) => {
+            locked_vault.unlock_overview_with_key(overview_key, &key_fingerprint)
+        }
+        _ => None,
+    }
+}
+
+fn request_keys(locked_vault: &LockedVault, overview_only: bool) -> Option<Value> {
+    let path = match fs::canonicalize(locked_vault.path()) {
+        Ok(path) => path,
+        Err(_) => return None,
+    };
+    let mut request = agent::request("keys");
+    request.insert("path".to_string(),
+                   Value::String(path.to_string_lossy().into_owned()));
+    if overview_only {
+        request.insert("overview_only".to_string(), Value::Bool(true));
+    }
+    send(Value::Object(request)).ok()
+}
+
+fn key(response: &Value, name: &str) -> Option<MainKey> {
+    decode(response, name).and_then(|bytes| MainKey::from_bytes(&bytes))
+}
+
+fn key_fingerprint(response: &Value) -> Option<Vec<u8>> {
+    decode(response, "key_fingerprint")
+}
+
+fn decode(response: &Value, name: &str) -> Option<Vec<u8>> {
+    response.find(name)
+        .and_then(|v| v.as_string())
+        .and_then(|s| base64::u8de(s.as_bytes()).ok())
+}
@@ -0,0 +1,134 @@
+//! A daemon that keeps a vault unlocked so that commands need not derive its keys again.
+//!
+//! The agent listens on a Unix domain socket only its user can reach, and answers one request per
+//! connection. Requests and responses are JSON objects on a line of their own. A request names
+//! its `command`; a response has `ok` and either the results or an `error`.
+
+pub mod client;
+pub mod server;
+
+use libc;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Result as IoResult, Write};
+use std::mem;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+/// Overrides the socket path.
+pub const SOCKET_ENV: &'static str = "DECONE_AGENT_SOCK";
+
+/// Returns where the agent of the current user listens: `$DECONE_AGENT_SOCK`, or else
+/// `runtime_path("agent.sock")`.
+pub fn socket_path() -> IoResult<PathBuf> {
+    if let Some(path) = env::var_os(SOCKET_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    runtime_path("agent.sock")
+}
+
+/// Returns a path for `name` in `$XDG_RUNTIME_DIR`, prefixed with `decone-`, or else in a private
+/// directory under `/tmp`, which is created if missing.
+pub fn runtime_path(name: &str) -> IoResult<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(PathBuf::from(dir).join(format!("decone-{}", name)));
+    }
+    let uid = unsafe { libc::geteuid() };
+    let dir = PathBuf::from(format!("/tmp/decone-{}", uid));
+    if let Err(e) = DirBuilder::new().mode(0o700).create(&dir) {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+    }
+    // Someone else could have made the directory first to listen in our place.
+    let metadata = try!(fs::symlink_metadata(&dir));
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                  format!("{:?} is not a private directory", dir)));
+    }
+    Ok(dir.join(name))
+}
+
+/// Listens at `path` with permissions for the owner only, replacing a socket left behind by a
+/// process that is gone, and fails if another process is listening there.
+pub fn bind_socket(path: &Path) -> IoResult<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                      format!("an agent is already listening at {:?}", path)));
+        }
+        try!(fs::remove_file(path));
+    }
+    let listener = try!(UnixListener::bind(path));
+    try!(fs::set_permissions(path, fs::Permissions::from_mode(0o600)));
+    Ok(listener)
+}
+
+/// Returns the user ID of the process at the other end of `stream`.
+#[cfg(target_os = "linux")]
+pub fn peer_uid(stream: &UnixStream) -> IoResult<u32> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void,
+                         &mut len)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// Returns the user ID of the process at the other end of `stream`.
+#[cfg(not(target_os = "linux"))]
+pub fn peer_uid(stream: &UnixStream) -> IoResult<u32> {
+    let mut uid: libc::uid_t = unsafe { mem::zeroed() };
+    let mut gid: libc::gid_t = unsafe { mem::zeroed() };
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Fails unless the process at the other end of `stream` runs as the current user.
+pub fn check_peer(stream: &UnixStream) -> IoResult<()> {
+    let uid = try!(peer_uid(stream));
+    if uid != unsafe { libc::geteuid() } {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                  format!("peer runs as user {}", uid)));
+    }
+    Ok(())
+}
+
+/// Reads a JSON object from a line of `stream`.
+pub fn read_message(stream: &UnixStream) -> IoResult<Value> {
+    let mut line = String::new();
+    try!(BufReader::new(stream).read_line(&mut line));
+    let value: Value = try!(serde_json::from_str(&line)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    if value.as_object().is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message is not an object"));
+    }
+    Ok(value)
+}
+
+/// Writes `message` on a line of `stream`.
+pub fn write_message(mut stream: &UnixStream, message: &Value) -> IoResult<()> {
+    let s = try!(serde_json::to_string(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    writeln!(stream, "{}", s)
+}
+
+/// Builds a request for `command`.
+pub fn request(command: &str) -> BTreeMap<String, Value> {
+    let mut request = BTreeMap::new();
+    request.insert("command".to_string(), Value::String(command.to_string()));
+    request
+}
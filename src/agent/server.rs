@@ -0,0 +1,271 @@
+use libc;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Result as IoResult, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use agent;
+use op_vault::vault::Vault;
+use util::encode_base64;
+
+/// How long a client may take to send its request.
+const CLIENT_TIMEOUT_SECS: u64 = 5;
+
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    vault: Vault,
+    idle_timeout: Duration,
+}
+
+impl Server {
+    /// Listens at `path`, replacing a socket left behind by an agent that is gone, and fails if
+    /// another agent is listening there.
+    pub fn bind<P: AsRef<Path>>(path: P, vault: Vault, idle_timeout: Duration) -> IoResult<Server> {
+        let path = path.as_ref();
+        let listener = try!(agent::bind_socket(path));
+        Ok(Server {
+            listener: listener,
+            path: path.to_path_buf(),
+            vault: vault,
+            idle_timeout: idle_timeout,
+        })
+    }
+
+    /// Answers requests until the agent is told to lock or has been idle for the timeout, then
+    /// removes the socket. The vault is locked when the server is dropped.
+    pub fn run(&self) -> IoResult<()> {
+        let mut last_request = Instant::now();
+        loop {
+            let elapsed = last_request.elapsed();
+            if elapsed >= self.idle_timeout {
+                break;
+            }
+            if !try!(self.wait_for_client(self.idle_timeout - elapsed)) {
+                continue;
+            }
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => continue,
+            };
+            // Clients of other users are turned away without an answer and do not keep the
+            // agent awake.
+            if agent::check_peer(&stream).is_err() {
+                continue;
+            }
+            last_request = Instant::now();
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SECS)));
+            let request = match agent::read_message(&stream) {
+                Ok(request) => request,
+                Err(e) => {
+                    let _ = agent::write_message(&stream, &error_response(&format!("{}", e)));
+                    continue;
+                }
+            };
+            let command = request.find("command").and_then(|v| v.as_string()).unwrap_or("");
+            let response = self.handle(command, &request);
+            let _ = agent::write_message(&stream, &response);
+            if command == "lock" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a connection, returning whether one is pending.
+    fn wait_for_client(&self, timeout: Duration) -> IoResult<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1000000) as u64;
+        let millis = if millis > i32::max_value() as u64 {
+            i32::max_value()
+        } else {
+            millis as i32
+        };
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(e) }
+            }
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    fn handle(&self, command: &str, request: &Value) -> Value {
+        match command {
+            "keys" => {
+                // Only hand out the keys for the vault they belong to.
+                let path = request.find("path").and_then(|v| v.as_string()).unwrap_or("");
+                if !same_path(Path::new(path), self.vault.path()) {
+                    return error_response("the agent holds another vault");
+                }
+                let profile = self.vault.profile();
+                let mut response = ok_response();
+                // Commands that only read overviews get no master key.
+                if request.find("overview_only").and_then(|v| v.as_boolean()) != Some(true) {
+                    let master_key = match profile.master_key() {
+                        Some(master_key) => master_key,
+                        None => return error_response("the agent holds no master key"),
+                    };
+                    response.insert("master_key".to_string(),
+                                    Value::String(encode_base64(&master_key.to_bytes())));
+                }
+                response.insert("overview_key".to_string(),
+                                Value::String(encode_base64(&profile.overview_key().to_bytes())));
+                // Lets the client make sure the keys belong to its copy of profile.js.
+                response.insert("key_fingerprint".to_string(),
+                                Value::String(encode_base64(profile.key_fingerprint())));
+                Value::Object(response)
+            }
+            "status" => {
+                let mut response = ok_response();
+                let path = fs::canonicalize(self.vault.path())
+                    .unwrap_or_else(|_| self.vault.path().to_path_buf());
+                response.insert("path".to_string(),
+                                Value::String(path.to_string_lossy().into_owned()));
+                response.insert("pid".to_string(),
+                                Value::U64(unsafe { libc::getpid() } as u64));
+                Value::Object(response)
+            }
+            "lock" => Value::Object(ok_response()),
+            _ => error_response(&format!("unknown command {}", command)),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Keeps the memory of the process out of core dumps and out of reach of debuggers running as
+/// the same user where the system allows, and the pages mapped so far, which hold the unlocked
+/// keys, out of swap.
+///
+/// Later pages are not locked: long-running servers would otherwise fail to allocate once they
+/// outgrow `RLIMIT_MEMLOCK`.
+pub fn protect_memory() -> IoResult<()> {
+    disable_dumping();
+    if unsafe { libc::mlockall(libc::MCL_CURRENT) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn disable_dumping() {
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_dumping() {}
+
+/// Detaches from the terminal, returning the process ID of the agent in the parent, which should
+/// exit, and `None` in the agent.
+pub fn daemonize() -> IoResult<Option<u32>> {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            unsafe {
+                libc::setsid();
+                let null = libc::open(b"/dev/null\0".as_ptr() as *const libc::c_char, libc::O_RDWR);
+                if null >= 0 {
+                    libc::dup2(null, 0);
+                    libc::dup2(null, 1);
+                    libc::dup2(null, 2);
+                    if null > 2 {
+                        libc::close(null);
+                    }
+                }
+            }
+            Ok(None)
+        }
+        pid => Ok(Some(pid as u32)),
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn ok_response() -> BTreeMap<String, Value> {
+    let mut response = BTreeMap::new();
+    response.insert("ok".to_string(), Value::Bool(true));
+    response
+}
+
+fn error_response(message: &str) -> Value {
+    let mut response = BTreeMap::new();
+    response.insert("ok".to_string(), Value::Bool(false));
+    response.insert("error".to_string(), Value::String(message.to_string()));
+    Value::Object(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use agent;
+    use testing::{sample_vault, SAMPLE_VAULT_PATH};
+    use super::Server;
+
+    fn send(path: &::std::path::Path, request: Value) -> Value {
+        let stream = UnixStream::connect(path).unwrap();
+        agent::write_message(&stream, &request).unwrap();
+        agent::read_message(&stream).unwrap()
+    }
+
+    #[test]
+    fn test_server() {
+        let vault = sample_vault();
+        let path = env::temp_dir().join("decone-test-agent.sock");
+        let server = Server::bind(&path, vault, Duration::from_secs(60)).unwrap();
+        let handle = thread::spawn(move || server.run().unwrap());
+
+        let mut request = agent::request("keys");
+        let vault_path = fs::canonicalize(SAMPLE_VAULT_PATH).unwrap();
+        request.insert("path".to_string(),
+                       Value::String(vault_path.to_string_lossy().into_owned()));
+        let response = send(&path, Value::Object(request));
+        assert_eq!(response.find("ok").and_then(|v| v.as_boolean()), Some(true));
+        assert!(response.find("master_key").is_some());
+
+        let mut request = agent::request("keys");
+        request.insert("path".to_string(),
+                       Value::String(vault_path.to_string_lossy().into_owned()));
+        request.insert("overview_only".to_string(), Value::Bool(true));
+        let response = send(&path, Value::Object(request));
+        assert!(response.find("master_key").is_none());
+        assert!(response.find("overview_key").is_some());
+
+        let mut request = agent::request("keys");
+        request.insert("path".to_string(), Value::String("/elsewhere".to_string()));
+        let response = send(&path, Value::Object(request));
+        assert_eq!(response.find("ok").and_then(|v| v.as_boolean()), Some(false));
+
+        send(&path, Value::Object(agent::request("lock")));
+        handle.join().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,157 @@
+//! Just enough HTTP/1.1 to answer one bodiless request per connection.
+
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Result as IoResult, Write};
+
+use totp;
+
+/// Requests with longer heads are refused rather than read.
+const MAX_HEAD_LEN: u64 = 8 * 1024;
+
+pub struct Request {
+    pub method: String,
+    /// The path without the query.
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Reads the request line and headers from `reader`.
+    pub fn read<R: Read>(reader: R) -> IoResult<Request> {
+        let mut reader = BufReader::new(reader.take(MAX_HEAD_LEN));
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if try!(reader.read_line(&mut line)) == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "request is incomplete"));
+            }
+            if line == "\r\n" || line == "\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        Request::parse(&head)
+    }
+
+    pub fn parse(head: &str) -> IoResult<Request> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed request");
+        let mut lines = head.lines();
+        let request_line = try!(lines.next().ok_or_else(&malformed));
+        let parts: Vec<&str> = request_line.split(' ').collect();
+        if parts.len() != 3 || !parts[2].starts_with("HTTP/1.") || !parts[1].starts_with('/') {
+            return Err(malformed());
+        }
+        let mut headers = Vec::new();
+        for line in lines {
+            let colon = try!(line.find(':').ok_or_else(&malformed));
+            let name = line[..colon].trim().to_lowercase();
+            headers.push((name, line[colon + 1..].trim().to_string()));
+        }
+        Ok(Request {
+            method: parts[0].to_string(),
+            path: parts[1].split('?').next().unwrap_or("").to_string(),
+            headers: headers,
+        })
+    }
+
+    /// Returns the value of the header `name`, which must be lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| &value[..])
+    }
+
+    /// Returns the percent-decoded segments of the path.
+    pub fn segments(&self) -> Vec<String> {
+        self.path.split('/').filter(|s| !s.is_empty()).map(totp::percent_decode).collect()
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status: status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body,
+        }
+    }
+
+    pub fn json(status: u16, json: &Value) -> Response {
+        let mut body = serde_json::to_string(json).unwrap_or_else(|_| "{}".to_string());
+        body.push('\n');
+        Response::new(status, "application/json", body.into_bytes())
+    }
+
+    /// Returns a JSON object with an `error` message.
+    pub fn error(status: u16, message: &str) -> Response {
+        let mut json = BTreeMap::new();
+        json.insert("error".to_string(), Value::String(message.to_string()));
+        Response::json(status, &Value::Object(json))
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> IoResult<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for &(ref name, ref value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        // Secrets must not linger in caches along the way.
+        head.push_str("Cache-Control: no-store\r\nConnection: close\r\n\r\n");
+        try!(writer.write_all(head.as_bytes()));
+        writer.write_all(&self.body)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Request, Response};
+
+    #[test]
+    fn test_request() {
+        let head = b"GET /v1/items/Secure%20Note/fields/pass%2Fword?x=1 HTTP/1.1\r\n\
+                     Host: 127.0.0.1\r\nAuthorization: Bearer abc\r\n\r\nignored";
+        let request = Request::read(&head[..]).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/v1/items/Secure%20Note/fields/pass%2Fword");
+        assert_eq!(request.segments(), vec!["v1", "items", "Secure Note", "fields", "pass/word"]);
+        assert_eq!(request.header("authorization"), Some("Bearer abc"));
+        assert_eq!(request.header("cookie"), None);
+
+        assert!(Request::read(&b"GET /v1/items HTTP/1.1\r\nHost: x\r\n"[..]).is_err());
+        assert!(Request::read(&b"GET v1 HTTP/1.1\r\n\r\n"[..]).is_err());
+        assert!(Request::read(&[b'a'; 9000][..]).is_err());
+    }
+
+    #[test]
+    fn test_response() {
+        let mut buf = Vec::new();
+        Response::error(404, "no such item").with_header("X-A", "b").write_to(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(),
+                   "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nX-A: b\r\n\
+                    Content-Length: 25\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n\
+                    {\"error\":\"no such item\"}\n");
+    }
+}
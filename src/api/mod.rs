@@ -0,0 +1,470 @@
+//! A read-only JSON API over HTTP, for tools that cannot link decone.
+//!
+//! Every request must carry `Authorization: Bearer <token>` with the token made when the server
+//! starts, and requests beyond the rate limit are turned away with 429. Requests with a wrong
+//! token are limited on their own so that they cannot use up the limit of real clients. Only
+//! loopback addresses are served, since requests and secrets travel unencrypted.
+//!
+//! ```text
+//! GET /v1/items                                   overviews of the items outside the trash
+//! GET /v1/items/<item>                            the overview of an item and its attachments
+//! GET /v1/items/<item>/fields/[<section>/]<field> the value of a field, as {"value": ...}
+//! GET /v1/items/<item>/totp                       the current one-time password
+//! GET /v1/items/<item>/attachments/<uuid>         the contents of an attachment
+//! GET /v1/folders                                 the folders
+//! ```
+//!
+//! `<item>` is a UUID or title, and each segment of the path is percent-decoded. Errors are
+//! JSON objects with an `error` message.
+
+pub mod http;
+
+use openssl::crypto::rand;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Result as IoResult};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use api::http::{Request, Response};
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use reference;
+use totp::Otp;
+use util::now;
+
+/// How long a client may take to send its whole request, and to read the response.
+const CLIENT_TIMEOUT_SECS: u64 = 5;
+
+/// Requests per second allowed with a wrong token, which slows down guessing it.
+const FAILED_AUTH_RATE: u32 = 5;
+
+pub struct Server {
+    listener: TcpListener,
+    responder: Arc<Responder>,
+}
+
+/// What the connections share.
+struct Responder {
+    vault: Vault,
+    token: String,
+    limiter: Mutex<RateLimiter>,
+    failed_auth_limiter: Mutex<RateLimiter>,
+}
+
+impl Server {
+    /// Listens at `address`, which must be a loopback address, allowing `rate` requests per
+    /// second with bursts of twice as many.
+    pub fn bind(address: &str, vault: Vault, rate: u32) -> IoResult<Server> {
+        let addresses: Vec<SocketAddr> = try!(address.to_socket_addrs()).collect();
+        if addresses.is_empty() || addresses.iter().any(|a| !a.ip().is_loopback()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} is not a loopback address", address)));
+        }
+        let listener = try!(TcpListener::bind(&addresses[..]));
+        let token = rand::rand_bytes(32).iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(Server {
+            listener: listener,
+            responder: Arc::new(Responder {
+                vault: vault,
+                token: token,
+                limiter: Mutex::new(RateLimiter::new(rate)),
+                failed_auth_limiter: Mutex::new(RateLimiter::new(FAILED_AUTH_RATE)),
+            }),
+        })
+    }
+
+    /// Returns the bearer token clients must send.
+    pub fn token(&self) -> &str {
+        &self.responder.token
+    }
+
+    pub fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers requests until the process is killed, each connection on a thread of its own so
+    /// that a slow client cannot hold up the others.
+    pub fn run(&self) -> IoResult<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let responder = self.responder.clone();
+            thread::spawn(move || serve(stream, &responder));
+        }
+        Ok(())
+    }
+}
+
+fn serve(mut stream: TcpStream, responder: &Responder) {
+    let timeout = Duration::from_secs(CLIENT_TIMEOUT_SECS);
+    let _ = stream.set_write_timeout(Some(timeout));
+    let reader = DeadlineReader {
+        stream: &stream,
+        deadline: Instant::now() + timeout,
+    };
+    let response = match Request::read(reader) {
+        Ok(request) => responder.respond(&request, Instant::now()),
+        Err(e) => Response::error(400, &format!("{}", e)),
+    };
+    let _ = response.write_to(&mut stream);
+}
+
+impl Responder {
+    fn respond(&self, request: &Request, time: Instant) -> Response {
+        if !authorized(request, &self.token) {
+            if !self.failed_auth_limiter.lock().unwrap().allow(time) {
+                return too_many_requests();
+            }
+            return Response::error(401, "missing or invalid token")
+                .with_header("WWW-Authenticate", "Bearer");
+        }
+        if !self.limiter.lock().unwrap().allow(time) {
+            return too_many_requests();
+        }
+        if request.method != "GET" {
+            return Response::error(405, "only GET is allowed").with_header("Allow", "GET");
+        }
+        handle(&self.vault, request, now())
+    }
+}
+
+fn too_many_requests() -> Response {
+    Response::error(429, "too many requests").with_header("Retry-After", "1")
+}
+
+/// Reads from a stream until `deadline`, however slowly the bytes trickle in.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> Read for DeadlineReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request took too long"));
+        }
+        try!(self.stream.set_read_timeout(Some(self.deadline - now)));
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Routes an authorized GET request at `time`.
+pub fn handle(vault: &Vault, request: &Request, time: u64) -> Response {
+    let segments = request.segments();
+    let s: Vec<&str> = segments.iter().map(|s| &s[..]).collect();
+    if s.len() < 2 || s[0] != "v1" || (s[1] != "items" && s.len() > 2) {
+        return Response::error(404, "no such endpoint");
+    }
+    let result = match (s.len(), s[1]) {
+        (2, "items") => Ok(Response::json(200, &list_items(vault))),
+        (2, "folders") => Ok(Response::json(200, &list_folders(vault))),
+        (3, _) => item_response(vault, s[2]),
+        (4, _) if s[3] == "totp" => totp_response(vault, s[2], time),
+        (5, _) if s[3] == "fields" => field_response(vault, s[2], None, s[4]),
+        (6, _) if s[3] == "fields" => field_response(vault, s[2], Some(s[4]), s[5]),
+        (5, _) if s[3] == "attachments" => attachment_response(vault, s[2], s[4]),
+        _ => return Response::error(404, "no such endpoint"),
+    };
+    result.unwrap_or_else(|e| error_response(&e))
+}
+
+fn item_response(vault: &Vault, item: &str) -> IoResult<Response> {
+    let item = try!(vault.find_item(item));
+    let mut json = overview(&item);
+    let attachments = try!(vault.attachments(&item))
+        .iter()
+        .map(|attachment| {
+            let mut json = BTreeMap::new();
+            json.insert("uuid".to_string(), Value::String(attachment.uuid().to_string()));
+            json.insert("file_name".to_string(),
+                        Value::String(attachment.file_name().to_string()));
+            json.insert("size".to_string(), Value::U64(attachment.size()));
+            Value::Object(json)
+        })
+        .collect();
+    json.insert("attachments".to_string(), Value::Array(attachments));
+    Ok(Response::json(200, &Value::Object(json)))
+}
+
+fn list_items(vault: &Vault) -> Value {
+    let items = vault.items()
+        .iter()
+        .filter(|item| !item.is_trashed())
+        .map(|item| Value::Object(overview(item)))
+        .collect();
+    Value::Array(items)
+}
+
+fn list_folders(vault: &Vault) -> Value {
+    let folders = vault.folders();
+    let json = folders.iter()
+        .map(|folder| {
+            let mut json = BTreeMap::new();
+            json.insert("uuid".to_string(), Value::String(folder.uuid().to_string()));
+            json.insert("title".to_string(), Value::String(folder.title().to_string()));
+            json.insert("path".to_string(), Value::String(folder.path(&folders)));
+            json.insert("parent".to_string(),
+                        folder.parent()
+                            .map(|uuid| Value::String(uuid.to_string()))
+                            .unwrap_or(Value::Null));
+            json.insert("smart".to_string(), Value::Bool(folder.is_smart()));
+            Value::Object(json)
+        })
+        .collect();
+    Value::Array(json)
+}
+
+/// Returns what the overview of `item` tells, which needs no decryption of its details.
+fn overview(item: &Item) -> BTreeMap<String, Value> {
+    let string_or_null = |s: Option<&str>| {
+        s.map(|s| Value::String(s.to_string())).unwrap_or(Value::Null)
+    };
+    let mut json = BTreeMap::new();
+    json.insert("uuid".to_string(), Value::String(item.uuid().to_string()));
+    json.insert("title".to_string(), string_or_null(item.title()));
+    json.insert("category".to_string(), Value::String(item.category().name().to_string()));
+    json.insert("folder".to_string(), string_or_null(item.folder()));
+    json.insert("url".to_string(), string_or_null(item.url()));
+    json.insert("urls".to_string(),
+                Value::Array(item.urls().into_iter().map(Value::String).collect()));
+    // The additional info of other categories is a description rather than a username.
+    let username = if item.category() == Category::Login { item.ainfo() } else { None };
+    json.insert("username".to_string(), string_or_null(username));
+    json.insert("tags".to_string(),
+                Value::Array(item.tags().into_iter().map(Value::String).collect()));
+    json.insert("created".to_string(), Value::U64(item.created()));
+    json.insert("updated".to_string(), Value::U64(item.updated()));
+    json.insert("trashed".to_string(), Value::Bool(item.is_trashed()));
+    json
+}
+
+fn field_response(vault: &Vault,
+                  item: &str,
+                  section: Option<&str>,
+                  field: &str)
+                  -> IoResult<Response> {
+    let item = try!(vault.find_item(item));
+    let details = try!(decrypt_details(vault, &item));
+    let value = try!(reference::section_field_value(&item, &details, section, field));
+    let mut json = BTreeMap::new();
+    json.insert("value".to_string(), Value::String(value));
+    Ok(Response::json(200, &Value::Object(json)))
+}
+
+fn totp_response(vault: &Vault, item: &str, time: u64) -> IoResult<Response> {
+    let item = try!(vault.find_item(item));
+    let details = try!(decrypt_details(vault, &item));
+    let secret = try!(details.totp().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound,
+                       format!("item {} has no one-time password", item.uuid()))
+    }));
+    let otp = try!(Otp::parse(&secret));
+    let mut json = BTreeMap::new();
+    json.insert("code".to_string(), Value::String(otp.code(time)));
+    json.insert("remaining".to_string(),
+                otp.remaining(time).map(Value::U64).unwrap_or(Value::Null));
+    Ok(Response::json(200, &Value::Object(json)))
+}
+
+fn attachment_response(vault: &Vault, item: &str, uuid: &str) -> IoResult<Response> {
+    let item = try!(vault.find_item(item));
+    let attachment = try!(try!(vault.attachments(&item))
+        .into_iter()
+        .find(|attachment| attachment.uuid() == uuid)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no attachment {}", uuid))
+        }));
+    let contents = try!(vault.attachment_contents(&item, &attachment).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("could not decrypt attachment {}", uuid))
+    }));
+    let file_name: String = attachment.file_name()
+        .chars()
+        .map(|c| if c == '"' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    Ok(Response::new(200, "application/octet-stream", contents)
+        .with_header("Content-Disposition", &format!("attachment; filename=\"{}\"", file_name)))
+}
+
+fn decrypt_details(vault: &Vault, item: &Item) -> IoResult<ItemDetails> {
+    vault.item_details(item).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("could not decrypt item {}", item.uuid()))
+    })
+}
+
+fn error_response(e: &io::Error) -> Response {
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => 404,
+        // Such as titles or field labels that match more than one thing.
+        io::ErrorKind::InvalidInput => 409,
+        _ => 500,
+    };
+    Response::error(status, &format!("{}", e))
+}
+
+/// Compares the bearer token of `request` with `token` in time independent of where they differ.
+fn authorized(request: &Request, token: &str) -> bool {
+    let given = match request.header("authorization") {
+        Some(value) if value.starts_with("Bearer ") => value["Bearer ".len()..].trim().as_bytes(),
+        _ => return false,
+    };
+    let token = token.as_bytes();
+    given.len() == token.len() &&
+    given.iter().zip(token.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// A token bucket that refills at a steady rate up to a burst.
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> RateLimiter {
+        RateLimiter {
+            rate: rate as f64,
+            burst: 2.0 * rate as f64,
+            tokens: 2.0 * rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes a token at `now` if one is left.
+    fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last);
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        self.tokens = (self.tokens + seconds * self.rate).min(self.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use api::http::Request;
+    use testing::sample_vault;
+    use super::{RateLimiter, Server, authorized, handle};
+
+    fn get(path: &str) -> Request {
+        Request::parse(&format!("GET {} HTTP/1.1\r\nAuthorization: Bearer 0123\r\n", path))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_handle() {
+        let vault = sample_vault();
+        let json = |path: &str| {
+            let response = handle(&vault, &get(path), 0);
+            let body = String::from_utf8(response.body).unwrap();
+            let json: Value = serde_json::from_str(&body).unwrap();
+            (response.status, json)
+        };
+
+        let (status, items) = json("/v1/items");
+        assert_eq!(status, 200);
+        assert!(items.as_array().unwrap().len() > 1);
+
+        let (status, hulu) = json("/v1/items/hulu");
+        assert_eq!(status, 200);
+        assert_eq!(hulu.find("username").and_then(|v| v.as_string()),
+                   Some("wendy@appleseed.com"));
+        assert!(hulu.find("attachments").and_then(|v| v.as_array()).is_some());
+        let uuid = hulu.find("uuid").and_then(|v| v.as_string()).unwrap();
+
+        let (status, field) = json(&format!("/v1/items/{}/fields/username", uuid));
+        assert_eq!(status, 200);
+        assert_eq!(field.find("value").and_then(|v| v.as_string()),
+                   Some("wendy@appleseed.com"));
+
+        assert_eq!(json("/v1/items/hulu/fields/no%20such%20field").0, 404);
+        assert_eq!(json("/v1/items/hulu/totp").0, 404);
+        assert_eq!(json("/v1/items/hulu/attachments/0000").0, 404);
+        assert_eq!(json("/v1/items/no%20such%20item").0, 404);
+        assert_eq!(json("/v1/folders").0, 200);
+        assert_eq!(json("/v2/items").0, 404);
+    }
+
+    #[test]
+    fn test_authorized() {
+        assert!(authorized(&get("/"), "0123"));
+        assert!(!authorized(&get("/"), "0124"));
+        assert!(!authorized(&get("/"), "01234"));
+        assert!(!authorized(&Request::parse("GET / HTTP/1.1\r\n").unwrap(), "0123"));
+    }
+
+    #[test]
+    fn test_respond() {
+        let server = Server::bind("127.0.0.1:0", sample_vault(), 1).unwrap();
+        let with_token = |token: &str| {
+            let head = format!("GET /v1/folders HTTP/1.1\r\nAuthorization: Bearer {}\r\n", token);
+            Request::parse(&head).unwrap()
+        };
+        let time = Instant::now();
+        let status = |request: &Request| server.responder.respond(request, time).status;
+        let wrong = with_token("0123");
+        while status(&wrong) == 401 {}
+        assert_eq!(status(&wrong), 429);
+
+        let right = with_token(server.token());
+        assert_eq!(status(&right), 200);
+        assert_eq!(status(&right), 200);
+        assert_eq!(status(&right), 429);
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let server = Server::bind("127.0.0.1:0", sample_vault(), 10).unwrap();
+        let address = server.local_addr().unwrap();
+        let token = server.token().to_string();
+        thread::spawn(move || server.run().unwrap());
+
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"GET /v1/folders HT").unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream,
+               "GET /v1/folders HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+               token)
+            .unwrap();
+        let start = Instant::now();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(start.elapsed() < Duration::from_secs(super::CLIENT_TIMEOUT_SECS));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(1);
+        let start = Instant::now();
+        limiter.last = start;
+        assert!(limiter.allow(start));
+        assert!(limiter.allow(start));
+        assert!(!limiter.allow(start));
+        assert!(!limiter.allow(start + Duration::from_millis(500)));
+        assert!(limiter.allow(start + Duration::from_millis(1000)));
+        assert!(!limiter.allow(start + Duration::from_millis(1000)));
+    }
+}
@@ -0,0 +1,151 @@
+//! Lookups in a downloaded copy of the Have I Been Pwned Pwned Passwords list.
+//!
+//! The list ordered by hash has one `SHA1HASH:COUNT` line per password, with the hash in
+//! uppercase hex. It is tens of gigabytes, so it is memory-mapped and binary-searched in place.
+
+use libc;
+use openssl::crypto::hash;
+use std::fs::File;
+use std::io::{self, Result as IoResult};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+const HASH_LENGTH: usize = 40;
+
+pub struct PwnedPasswords {
+    data: *const u8,
+    len: usize,
+}
+
+impl PwnedPasswords {
+    pub fn open<P: AsRef<Path>>(path: P) -> IoResult<PwnedPasswords> {
+        let file = try!(File::open(path));
+        let len = try!(file.metadata()).len() as usize;
+        if len == 0 {
+            return Ok(PwnedPasswords {
+                data: ptr::null(),
+                len: 0,
+            });
+        }
+        // The mapping stays valid after the file is closed.
+        let data = unsafe {
+            libc::mmap(ptr::null_mut(),
+                       len,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE,
+                       file.as_raw_fd(),
+                       0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PwnedPasswords {
+            data: data as *const u8,
+            len: len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.data.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.data, self.len) }
+        }
+    }
+
+    /// Returns how many times `password` appears in breaches, or `None` if it is not listed.
+    pub fn count(&self, password: &str) -> Option<u64> {
+        let digest = hash::hash(hash::Type::SHA1, password.as_bytes());
+        let hex: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+        self.count_hash(hex.as_bytes())
+    }
+
+    fn count_hash(&self, hash: &[u8]) -> Option<u64> {
+        let bytes = self.bytes();
+        // Searches for the first line whose hash is not less than `hash`. Both bounds stay at
+        // line starts, so the line around the middle is never before `low`.
+        let mut low = 0;
+        let mut high = bytes.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let start = line_start(bytes, mid);
+            let end = line_end(bytes, start);
+            if &line_hash(&bytes[start..end])[..] < hash {
+                low = next_line(bytes, end);
+            } else {
+                high = start;
+            }
+        }
+        let end = line_end(bytes, low);
+        let line = &bytes[low..end];
+        if &line_hash(line)[..] != hash {
+            return None;
+        }
+        let count = String::from_utf8_lossy(&line[HASH_LENGTH..]);
+        Some(count.trim_left_matches(':').trim().parse().unwrap_or(1))
+    }
+}
+
+impl Drop for PwnedPasswords {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe {
+                libc::munmap(self.data as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+fn line_start(bytes: &[u8], pos: usize) -> usize {
+    bytes[..pos].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(bytes: &[u8], pos: usize) -> usize {
+    bytes[pos..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| pos + i)
+}
+
+fn next_line(bytes: &[u8], end: usize) -> usize {
+    if end < bytes.len() { end + 1 } else { end }
+}
+
+/// Returns the hash of a line, uppercased in case the list was written in lowercase.
+fn line_hash(line: &[u8]) -> Vec<u8> {
+    let len = if line.len() < HASH_LENGTH { line.len() } else { HASH_LENGTH };
+    line[..len].iter().map(|b| if *b >= b'a' && *b <= b'f' { b - 32 } else { *b }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use openssl::crypto::hash;
+    use super::PwnedPasswords;
+
+    #[test]
+    fn test_count() {
+        let mut lines: Vec<String> = (0..1000)
+            .map(|i| {
+                let digest = hash::hash(hash::Type::SHA1, format!("password{}", i).as_bytes());
+                let hex: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("{}:{}", hex, i + 1)
+            })
+            .collect();
+        lines.sort();
+        let path = env::temp_dir().join("decone-test-pwned.txt");
+        {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(lines.join("\r\n").as_bytes()).unwrap();
+        }
+        let pwned = PwnedPasswords::open(&path).unwrap();
+        for i in 0..1000 {
+            assert_eq!(pwned.count(&format!("password{}", i)), Some(i + 1));
+        }
+        assert_eq!(pwned.count("password1000"), None);
+        assert_eq!(pwned.count(""), None);
+        drop(pwned);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Reports on passwords that are reused, weak or old, and on URLs without TLS.
+
+pub mod breach;
+pub mod strength;
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult, Write};
+
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use self::breach::PwnedPasswords;
+use self::strength::{Estimator, Strength};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub struct ItemRef {
+    pub uuid: String,
+    pub title: String,
+}
+
+impl ItemRef {
+    fn new(item: &Item) -> Self {
+        ItemRef {
+            uuid: item.uuid().to_string(),
+            title: item.title().unwrap_or("").to_string(),
+        }
+    }
+
+    fn to_json(&self) -> BTreeMap<String, Value> {
+        let mut json = BTreeMap::new();
+        json.insert("title".to_string(), Value::String(self.title.clone()));
+        json.insert("uuid".to_string(), Value::String(self.uuid.clone()));
+        json
+    }
+}
+
+pub struct Report {
+    /// Groups of items sharing a password.
+    pub reused: Vec<Vec<ItemRef>>,
+    pub weak: Vec<(ItemRef, Strength)>,
+    /// Items whose password appears in breaches, with the number of times it appears.
+    pub breached: Vec<(ItemRef, u64)>,
+    /// Items whose password has not changed for longer than allowed, with its age in days.
+    pub old: Vec<(ItemRef, u64)>,
+    /// Items with `http://` URLs.
+    pub insecure_urls: Vec<(ItemRef, String)>,
+}
+
+/// Checks the passwords and URLs of the items of `vault` outside the trash. Passwords older than
+/// `max_age_days` at Unix time `now` are reported as old, and those in `pwned`, if given, as
+/// breached.
+///
+/// A password was last changed when the newest entry of its history was replaced or, if it has
+/// no history, no later than when the item was last updated.
+pub fn audit(vault: &Vault,
+             now: u64,
+             max_age_days: u64,
+             pwned: Option<&PwnedPasswords>)
+             -> IoResult<Report> {
+    let estimator = Estimator::new();
+    let mut report = Report {
+        reused: Vec::new(),
+        weak: Vec::new(),
+        breached: Vec::new(),
+        old: Vec::new(),
+        insecure_urls: Vec::new(),
+    };
+    let mut by_password: BTreeMap<String, Vec<ItemRef>> = BTreeMap::new();
+    let mut items = vault.items();
+    items.sort_by(|a, b| a.title().cmp(&b.title()));
+    for item in items.iter().filter(|item| !item.is_trashed()) {
+        for url in item.urls() {
+            if url.to_lowercase().starts_with("http://") {
+                report.insecure_urls.push((ItemRef::new(item), url));
+            }
+        }
+
+        let details = try!(vault.item_details(item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let password = match details.password() {
+            Some(ref password) if !password.is_empty() => password.clone(),
+            _ => continue,
+        };
+
+        let username = details.username().unwrap_or_else(String::new);
+        let user_inputs = [item.title().unwrap_or(""), &username[..], item.url().unwrap_or("")];
+        let strength = estimator.estimate(&password, &user_inputs);
+        if strength.is_weak() {
+            report.weak.push((ItemRef::new(item), strength));
+        }
+        if let Some(count) = pwned.and_then(|pwned| pwned.count(&password)) {
+            report.breached.push((ItemRef::new(item), count));
+        }
+
+        let changed = details.password_history()
+            .iter()
+            .map(|entry| entry.time())
+            .max()
+            .unwrap_or_else(|| item.updated());
+        let age = now.saturating_sub(changed) / SECONDS_PER_DAY;
+        if age > max_age_days {
+            report.old.push((ItemRef::new(item), age));
+        }
+
+        by_password.entry(password).or_insert_with(Vec::new).push(ItemRef::new(item));
+    }
+    report.reused = by_password.into_iter()
+        .map(|(_, items)| items)
+        .filter(|items| items.len() > 1)
+        .collect();
+    Ok(report)
+}
+
+impl Report {
+    pub fn to_json(&self) -> Value {
+        let mut json = BTreeMap::new();
+        json.insert("reused".to_string(),
+                    Value::Array(self.reused
+                        .iter()
+                        .map(|items| {
+                            Value::Array(items.iter()
+                                .map(|item| Value::Object(item.to_json()))
+                                .collect())
+                        })
+                        .collect()));
+        json.insert("weak".to_string(),
+                    Value::Array(self.weak
+                        .iter()
+                        .map(|&(ref item, strength)| {
+                            let mut json = item.to_json();
+                            json.insert("score".to_string(), Value::U64(strength.score as u64));
+                            json.insert("bits".to_string(), Value::U64(strength.bits as u64));
+                            Value::Object(json)
+                        })
+                        .collect()));
+        json.insert("breached".to_string(),
+                    Value::Array(self.breached
+                        .iter()
+                        .map(|&(ref item, count)| {
+                            let mut json = item.to_json();
+                            json.insert("count".to_string(), Value::U64(count));
+                            Value::Object(json)
+                        })
+                        .collect()));
+        json.insert("old".to_string(),
+                    Value::Array(self.old
+                        .iter()
+                        .map(|&(ref item, days)| {
+                            let mut json = item.to_json();
+                            json.insert("days".to_string(), Value::U64(days));
+                            Value::Object(json)
+                        })
+                        .collect()));
+        json.insert("insecure_urls".to_string(),
+                    Value::Array(self.insecure_urls
+                        .iter()
+                        .map(|&(ref item, ref url)| {
+                            let mut json = item.to_json();
+                            json.insert("url".to_string(), Value::String(url.clone()));
+                            Value::Object(json)
+                        })
+                        .collect()));
+        Value::Object(json)
+    }
+
+    /// Writes one line per finding, in columns of the issue, the item title, its UUID and
+    /// details.
+    pub fn write_table<W: Write + ?Sized>(&self, writer: &mut W) -> IoResult<()> {
+        let mut rows = Vec::new();
+        for items in &self.reused {
+            for item in items {
+                let others = items.len() - 1;
+                rows.push(("reused",
+                           item,
+                           format!("same password as {} other item{}",
+                                   others,
+                                   if others == 1 { "" } else { "s" })));
+            }
+        }
+        for &(ref item, strength) in &self.weak {
+            rows.push(("weak",
+                       item,
+                       format!("score {} of 4, about {:.0} bits", strength.score, strength.bits)));
+        }
+        for &(ref item, count) in &self.breached {
+            rows.push(("breached", item, format!("seen {} times in breaches", count)));
+        }
+        for &(ref item, days) in &self.old {
+            rows.push(("old", item, format!("unchanged for {} days", days)));
+        }
+        for &(ref item, ref url) in &self.insecure_urls {
+            rows.push(("http", item, url.clone()));
+        }
+        let width = rows.iter()
+            .map(|&(_, item, _)| item.title.chars().count())
+            .chain(Some("TITLE".len()))
+            .max()
+            .unwrap_or(0);
+        try!(writeln!(writer,
+                      "{:<8} {:<width$} {:<32} {}",
+                      "ISSUE",
+                      "TITLE",
+                      "UUID",
+                      "DETAIL",
+                      width = width));
+        for (issue, item, detail) in rows {
+            try!(writeln!(writer,
+                          "{:<8} {:<width$} {:<32} {}",
+                          issue,
+                          item.title,
+                          item.uuid,
+                          detail,
+                          width = width));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::sample_vault;
+    use super::audit;
+
+    #[test]
+    fn test_audit() {
+        let vault = sample_vault();
+        let report = audit(&vault, 1500000000, 365, None).unwrap();
+        // The sample items were last touched in 2012-2013.
+        assert!(!report.old.is_empty());
+        assert!(report.old.iter().all(|&(_, days)| days > 365));
+        assert!(audit(&vault, 0, 365, None).unwrap().old.is_empty());
+        for items in &report.reused {
+            let passwords: Vec<String> = items.iter()
+                .map(|item| {
+                    let item = vault.item(&item.uuid).unwrap();
+                    vault.item_details(&item).unwrap().password().unwrap()
+                })
+                .collect();
+            assert!(passwords.iter().all(|password| *password == passwords[0]));
+        }
+        assert!(report.insecure_urls.iter().all(|&(_, ref url)| url.starts_with("http://")));
+        let mut table = Vec::new();
+        report.write_table(&mut table).unwrap();
+        assert!(String::from_utf8(table).unwrap().starts_with("ISSUE"));
+        assert!(report.to_json().find("weak").is_some());
+    }
+}
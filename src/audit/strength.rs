@@ -0,0 +1,528 @@
+//! Password strength estimation after zxcvbn.
+//!
+//! A password is split into the sequence of patterns (dictionary words, keyboard walks, repeats,
+//! sequences, dates and leftover characters) that an attacker trying likely guesses first would
+//! need the fewest guesses to reach, and its strength is the number of those guesses.
+
+use std::collections::HashMap;
+
+use generator;
+
+/// Common passwords, most common first.
+const COMMON_PASSWORDS: &'static str = include_str!("common_passwords.txt");
+
+/// Characters beyond this are only counted as random characters.
+const MAX_MATCHED_LENGTH: usize = 100;
+
+const KEYBOARD_ROWS: [&'static str; 4] =
+    ["`1234567890-=", "qwertyuiop[]\\", "asdfghjkl;'", "zxcvbnm,./"];
+const KEYBOARD_SHIFTED_ROWS: [&'static str; 4] =
+    ["~!@#$%^&*()_+", "QWERTYUIOP{}|", "ASDFGHJKL:\"", "ZXCVBNM<>?"];
+
+/// Substitutions of look-alike characters for letters.
+const L33T: [(char, char); 13] = [('4', 'a'), ('@', 'a'), ('8', 'b'), ('3', 'e'), ('6', 'g'),
+                                  ('1', 'i'), ('!', 'i'), ('0', 'o'), ('5', 's'), ('$', 's'),
+                                  ('7', 't'), ('+', 't'), ('2', 'z')];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Strength {
+    /// The base-2 logarithm of the estimated number of guesses.
+    pub bits: f64,
+    /// 0 (too guessable) to 4 (very unguessable), as zxcvbn scores.
+    pub score: u8,
+}
+
+impl Strength {
+    pub fn is_weak(&self) -> bool {
+        self.score < 3
+    }
+}
+
+/// Holds the dictionaries, which are worth building once for many passwords.
+pub struct Estimator {
+    ranks: HashMap<String, usize>,
+}
+
+impl Estimator {
+    pub fn new() -> Self {
+        let mut ranks = HashMap::new();
+        for (i, password) in COMMON_PASSWORDS.lines().enumerate() {
+            ranks.insert(password.trim().to_lowercase(), i + 1);
+        }
+        // The wordlist is in alphabetical order, so every word gets the rank of its size.
+        let words = generator::wordlist();
+        let word_rank = COMMON_PASSWORDS.lines().count() + words.len();
+        for word in words {
+            ranks.entry(word.to_string()).or_insert(word_rank);
+        }
+        Estimator { ranks: ranks }
+    }
+
+    /// Estimates the strength of `password`. Words in `user_inputs`, such as the title and
+    /// username of the item, are among the first an attacker would try.
+    pub fn estimate(&self, password: &str, user_inputs: &[&str]) -> Strength {
+        let user_ranks: HashMap<String, usize> = user_inputs.iter()
+            .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+            .filter(|word| word.chars().count() >= 3)
+            .enumerate()
+            .map(|(i, word)| (word.to_lowercase(), i + 1))
+            .collect();
+        let chars: Vec<char> = password.chars().collect();
+        let (matched, rest) = if chars.len() > MAX_MATCHED_LENGTH {
+            chars.split_at(MAX_MATCHED_LENGTH)
+        } else {
+            (&chars[..], &[][..])
+        };
+        let cardinality = cardinality(&chars);
+        let bits = self.minimum_bits(matched, &user_ranks, cardinality) +
+                   rest.len() as f64 * cardinality.log2();
+        let log10 = bits * 2f64.log10();
+        let score = if log10 < 3.0 {
+            0
+        } else if log10 < 6.0 {
+            1
+        } else if log10 < 8.0 {
+            2
+        } else if log10 < 10.0 {
+            3
+        } else {
+            4
+        };
+        Strength {
+            bits: bits,
+            score: score,
+        }
+    }
+
+    /// Finds the sequence of matches covering `chars` that needs the fewest guesses, returning
+    /// the base-2 logarithm of the number of guesses.
+    fn minimum_bits(&self,
+                    chars: &[char],
+                    user_ranks: &HashMap<String, usize>,
+                    cardinality: f64)
+                    -> f64 {
+        let n = chars.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let mut matches = Vec::new();
+        self.dictionary_matches(chars, user_ranks, &mut matches);
+        sequence_matches(chars, &mut matches);
+        repeat_matches(self, chars, user_ranks, &mut matches);
+        keyboard_matches(chars, &mut matches);
+        date_matches(chars, &mut matches);
+
+        // match_bits[start][end] is the fewest bits to guess chars[start..end] as one match.
+        // Any run of characters can be guessed by brute force.
+        let mut match_bits: Vec<Vec<f64>> = (0..n + 1)
+            .map(|start| {
+                (0..n + 1)
+                    .map(|end| end.saturating_sub(start) as f64 * cardinality.log2())
+                    .collect()
+            })
+            .collect();
+        for m in &matches {
+            if m.bits < match_bits[m.start][m.end] {
+                match_bits[m.start][m.end] = m.bits;
+            }
+        }
+
+        // best[j][k] is the fewest bits to guess the first j characters as k matches.
+        let infinity = ::std::f64::INFINITY;
+        let mut best = vec![vec![infinity; n + 1]; n + 1];
+        best[0][0] = 0.0;
+        for end in 1..n + 1 {
+            for start in 0..end {
+                let bits = match_bits[start][end];
+                for k in 0..end {
+                    if best[start][k] + bits < best[end][k + 1] {
+                        best[end][k + 1] = best[start][k] + bits;
+                    }
+                }
+            }
+        }
+        // The attacker also has to try the matches in every order.
+        (1..n + 1)
+            .map(|k| best[n][k] + (1..k + 1).map(|i| (i as f64).log2()).sum::<f64>())
+            .fold(infinity, f64::min)
+    }
+
+    fn dictionary_matches(&self,
+                          chars: &[char],
+                          user_ranks: &HashMap<String, usize>,
+                          matches: &mut Vec<Match>) {
+        for start in 0..chars.len() {
+            for end in start + 3..chars.len() + 1 {
+                let token = &chars[start..end];
+                let mut best: Option<f64> = None;
+                for (word, substitutions) in unleet(token) {
+                    let reversed: String = word.chars().rev().collect();
+                    let candidates = [(self.rank(&word, user_ranks), 0.0),
+                                      (self.rank(&reversed, user_ranks), 1.0)];
+                    for &(rank, reversal) in &candidates {
+                        if let Some(rank) = rank {
+                            let bits = (rank as f64).log2() + uppercase_bits(token) +
+                                       substitutions as f64 + reversal;
+                            if best.map_or(true, |best| bits < best) {
+                                best = Some(bits);
+                            }
+                        }
+                    }
+                }
+                if let Some(bits) = best {
+                    matches.push(Match::new(start, end, bits));
+                }
+            }
+        }
+    }
+
+    fn rank(&self, word: &str, user_ranks: &HashMap<String, usize>) -> Option<usize> {
+        match (user_ranks.get(word), self.ranks.get(word)) {
+            (Some(&a), Some(&b)) => Some(if a < b { a } else { b }),
+            (Some(&rank), None) | (None, Some(&rank)) => Some(rank),
+            (None, None) => None,
+        }
+    }
+}
+
+struct Match {
+    start: usize,
+    end: usize,
+    bits: f64,
+}
+
+impl Match {
+    fn new(start: usize, end: usize, bits: f64) -> Self {
+        Match {
+            start: start,
+            end: end,
+            bits: bits,
+        }
+    }
+}
+
+/// Returns the size of the smallest character set containing `chars`.
+fn cardinality(chars: &[char]) -> f64 {
+    let mut cardinality = 0;
+    if chars.iter().any(|c| c.is_lowercase()) {
+        cardinality += 26;
+    }
+    if chars.iter().any(|c| c.is_uppercase()) {
+        cardinality += 26;
+    }
+    if chars.iter().any(|c| c.is_digit(10)) {
+        cardinality += 10;
+    }
+    if chars.iter().any(|c| !c.is_alphanumeric()) {
+        cardinality += 33;
+    }
+    if chars.iter().any(|&c| c as u32 > 0x7f) {
+        cardinality += 100;
+    }
+    if cardinality == 0 { 10.0 } else { cardinality as f64 }
+}
+
+/// Returns the lowercased token with each way of undoing look-alike substitutions, along with
+/// the number of substituted characters.
+fn unleet(token: &[char]) -> Vec<(String, usize)> {
+    let lower: String = token.iter().flat_map(|c| c.to_lowercase()).collect();
+    let mut variants = vec![(lower.clone(), 0)];
+    // '1' stands for either 'i' or 'l', so try both.
+    for &one in &['i', 'l'] {
+        let mut substitutions = 0;
+        let word: String = lower.chars()
+            .map(|c| {
+                let letter = if c == '1' {
+                    Some(one)
+                } else {
+                    L33T.iter().find(|&&(from, _)| from == c).map(|&(_, to)| to)
+                };
+                match letter {
+                    Some(letter) => {
+                        substitutions += 1;
+                        letter
+                    }
+                    None => c,
+                }
+            })
+            .collect();
+        if substitutions > 0 && substitutions < token.len() {
+            variants.push((word, substitutions));
+        }
+    }
+    variants
+}
+
+/// Returns the bits for guessing where the uppercase letters of `token` are.
+fn uppercase_bits(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        0.0
+    } else if lower == 0 || (upper == 1 && token[0].is_uppercase()) {
+        // All caps and a capitalized first letter are the usual variations.
+        1.0
+    } else {
+        upper as f64
+    }
+}
+
+/// Finds runs like "abcd" or "9876" whose characters step by one.
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 1;
+        if delta == 1 || delta == -1 {
+            while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == delta {
+                end += 1;
+            }
+        }
+        if end - start >= 3 {
+            let first = chars[start];
+            let base: f64 = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_digit(10) {
+                10.0
+            } else {
+                26.0
+            };
+            let descending = if delta < 0 { 1.0 } else { 0.0 };
+            let bits = base.log2() + ((end - start) as f64).log2() + descending;
+            for i in start..end - 2 {
+                matches.push(Match::new(i, end, bits));
+            }
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+/// Finds blocks repeated back to back, like "aaaa" or "abcabc".
+///
+/// As in zxcvbn, only the longest repeat from each start is scored, by its shortest block, and
+/// the search goes on after it. Scoring every block would estimate each of them recursively,
+/// which takes exponential time on long repeats.
+fn repeat_matches(estimator: &Estimator,
+                  chars: &[char],
+                  user_ranks: &HashMap<String, usize>,
+                  matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let mut longest: Option<(usize, usize)> = None;
+        for period in 1..(chars.len() - start) / 2 + 1 {
+            let block = &chars[start..start + period];
+            let mut count = 1;
+            while start + (count + 1) * period <= chars.len() &&
+                  &chars[start + count * period..start + (count + 1) * period] == block {
+                count += 1;
+            }
+            if count < 2 || count * period < 3 {
+                continue;
+            }
+            // Shorter periods come first, so "abab" repeated is scored as "ab".
+            if longest.map_or(true, |(p, c)| count * period > p * c) {
+                longest = Some((period, count));
+            }
+        }
+        match longest {
+            Some((period, count)) => {
+                let block = &chars[start..start + period];
+                let block_bits = estimator.minimum_bits(block, user_ranks, cardinality(block));
+                let bits = block_bits + (count as f64).log2();
+                matches.push(Match::new(start, start + count * period, bits));
+                start += count * period;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+/// Finds walks along the keyboard like "qwerty" or "zaq1", allowing shifted keys.
+fn keyboard_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let positions: Vec<Option<(f64, f64, bool)>> =
+        chars.iter().map(|&c| key_position(c)).collect();
+    let adjacent = |a: (f64, f64, bool), b: (f64, f64, bool)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        (dy == 0.0 && dx.abs() == 1.0) || (dy.abs() == 1.0 && dx.abs() <= 0.5)
+    };
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 1;
+        let mut direction = None;
+        while end < chars.len() {
+            match (positions[end - 1], positions[end]) {
+                (Some(a), Some(b)) if adjacent(a, b) => {
+                    let step = (b.0 - a.0, b.1 - a.1);
+                    if direction.is_some() && direction != Some(step) {
+                        turns += 1;
+                    }
+                    direction = Some(step);
+                    end += 1;
+                }
+                _ => break,
+            }
+        }
+        if end - start >= 4 {
+            let shifted = positions[start..end].iter().any(|p| p.map_or(false, |p| p.2));
+            let keys = KEYBOARD_ROWS.iter().map(|row| row.len()).sum::<usize>() as f64;
+            // Choose a starting key, the length and, at each turn, one of about 4 directions.
+            let bits = keys.log2() + ((end - start) as f64).log2() + 2.0 * turns as f64 +
+                       if shifted { 1.0 } else { 0.0 };
+            matches.push(Match::new(start, end, bits));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+/// Returns the column and row of the key for `c` on a US keyboard, where each row is offset half
+/// a key from the one above, and whether Shift is needed.
+fn key_position(c: char) -> Option<(f64, f64, bool)> {
+    let rows = KEYBOARD_ROWS.iter().zip(KEYBOARD_SHIFTED_ROWS.iter());
+    for (row, (plain, shifted)) in rows.enumerate() {
+        let offset = row as f64 * 0.5;
+        if let Some(column) = plain.chars().position(|k| k == c) {
+            return Some((column as f64 + offset, row as f64, false));
+        }
+        if let Some(column) = shifted.chars().position(|k| k == c) {
+            return Some((column as f64 + offset, row as f64, true));
+        }
+    }
+    None
+}
+
+/// Finds years like "1984" and dates like "19840519" or "05/19/84".
+fn date_matches(chars: &[char], matches: &mut Vec<Match>) {
+    // Years are guessed outward from the present, but at least 20 of them are tried.
+    let year_bits = |year: u32| {
+        let distance = (year as f64 - 2017.0).abs();
+        (if distance < 20.0 { 20.0 } else { distance }).log2()
+    };
+    for start in 0..chars.len() {
+        for end in start + 4..chars.len() + 1 {
+            if end - start > 10 {
+                break;
+            }
+            let token: String = chars[start..end].iter().cloned().collect();
+            if end - start == 4 && token.chars().all(|c| c.is_digit(10)) {
+                let year: u32 = token.parse().unwrap_or(0);
+                if year >= 1900 && year <= 2039 {
+                    matches.push(Match::new(start, end, year_bits(year)));
+                }
+                continue;
+            }
+            if let Some(year) = parse_date(&token) {
+                // A day of the year and the year.
+                matches.push(Match::new(start, end, 365f64.log2() + year_bits(year)));
+            }
+        }
+    }
+}
+
+/// Parses a date of six or eight digits, optionally with one kind of separator, in year-first,
+/// day-first or month-first order, and returns its year.
+fn parse_date(token: &str) -> Option<u32> {
+    let separators: Vec<char> = token.chars().filter(|c| !c.is_digit(10)).collect();
+    let parts: Vec<String> = if separators.is_empty() {
+        let digits = token;
+        match digits.len() {
+            6 => vec![&digits[..2], &digits[2..4], &digits[4..]],
+            8 => {
+                if digits.starts_with("19") || digits.starts_with("20") {
+                    vec![&digits[..4], &digits[4..6], &digits[6..]]
+                } else {
+                    vec![&digits[..2], &digits[2..4], &digits[4..]]
+                }
+            }
+            _ => return None,
+        }
+        .into_iter()
+        .map(|part| part.to_string())
+        .collect()
+    } else {
+        let separator = separators[0];
+        if separators.len() != 2 || separators[1] != separator || !"/-._ ".contains(separator) {
+            return None;
+        }
+        token.split(separator).map(|part| part.to_string()).collect()
+    };
+    if parts.len() != 3 || parts.iter().any(|part| part.is_empty() || part.len() > 4) {
+        return None;
+    }
+    let numbers: Vec<u32> = parts.iter().filter_map(|part| part.parse().ok()).collect();
+    if numbers.len() != 3 {
+        return None;
+    }
+    let full_year = |year: u32, digits: usize| {
+        if digits == 4 {
+            year
+        } else if year > 50 {
+            1900 + year
+        } else {
+            2000 + year
+        }
+    };
+    let valid = |day: u32, month: u32| day >= 1 && day <= 31 && month >= 1 && month <= 12;
+    let (first, middle, last) = (numbers[0], numbers[1], numbers[2]);
+    let year = if parts[0].len() == 4 && valid(last, middle) {
+        full_year(first, 4)
+    } else if parts[0].len() <= 2 && (valid(first, middle) || valid(middle, first)) &&
+              parts[2].len() != 3 {
+        full_year(last, parts[2].len())
+    } else {
+        return None;
+    };
+    if year >= 1900 && year <= 2039 { Some(year) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Estimator, parse_date};
+
+    #[test]
+    fn test_estimate() {
+        let estimator = Estimator::new();
+        let weak = ["password", "P@ssw0rd", "qwerty123", "abcdefgh", "aaaaaaaaaaaa",
+                    "19810519", "zaq1xsw2", "drowssap", "Hulu2017"];
+        for password in &weak {
+            let strength = estimator.estimate(password, &["Hulu"]);
+            assert!(strength.is_weak(), "{} scored {:?}", password, strength);
+        }
+        let strong = ["correct horse battery staple", "Tr0ub4dor&3-x9!kQ", "k8#Vq2!zLm9@Wp"];
+        for password in &strong {
+            let strength = estimator.estimate(password, &[]);
+            assert!(!strength.is_weak(), "{} scored {:?}", password, strength);
+        }
+        assert!(estimator.estimate("password", &[]).bits <
+                estimator.estimate("pxqsmord", &[]).bits);
+    }
+
+    #[test]
+    fn test_estimate_long_repeats() {
+        let estimator = Estimator::new();
+        let start = Instant::now();
+        for block in &["a", "ab", "abcab", "Tr0ub4dor&3"] {
+            let password: String = block.chars().cycle().take(100).collect();
+            let block_bits = estimator.estimate(block, &[]).bits;
+            assert!(estimator.estimate(&password, &[]).bits < block_bits + 20.0,
+                    "{}",
+                    password);
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("19810519"), Some(1981));
+        assert_eq!(parse_date("05/19/81"), Some(1981));
+        assert_eq!(parse_date("19-05-2001"), Some(2001));
+        assert_eq!(parse_date("123456"), None);
+        assert_eq!(parse_date("05/19-81"), None);
+    }
+}
@@ -0,0 +1,428 @@
+use openssl::crypto::{hash, hmac, pkcs5, rand, symm};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult, Write};
+
+use iso8601;
+use op_vault::category::Category;
+use op_vault::details::{ItemDetails, SectionField};
+use op_vault::folder::Folder;
+use op_vault::item::Item;
+use op_vault::uuid;
+use op_vault::vault::Vault;
+use util::encode_base64;
+
+/// The PBKDF2 iterations of password-protected exports, matching what Bitwarden uses.
+const KDF_ITERATIONS: usize = 600000;
+const KDF_TYPE_PBKDF2_SHA256: u64 = 0;
+
+const ITEM_TYPE_LOGIN: u64 = 1;
+const ITEM_TYPE_SECURE_NOTE: u64 = 2;
+const ITEM_TYPE_CARD: u64 = 3;
+const ITEM_TYPE_IDENTITY: u64 = 4;
+
+const FIELD_TYPE_TEXT: u64 = 0;
+const FIELD_TYPE_HIDDEN: u64 = 1;
+
+/// Writes the folders and items of `vault` in the JSON format that Bitwarden imports. If
+/// `password` is given, the export is encrypted as Bitwarden's password-protected exports are.
+///
+/// Logins, credit cards, identities and secure notes map to the Bitwarden types of the same
+/// name, and the other categories to secure notes. Fields that have no place in the Bitwarden
+/// type become custom fields. Attachments are not part of the format and are left out.
+pub fn export<W: Write + ?Sized>(vault: &Vault,
+                                 writer: &mut W,
+                                 password: Option<&str>)
+                                 -> IoResult<()> {
+    let mut json = try!(to_json(vault));
+    if let Some(password) = password {
+        json = try!(encrypt(&json, password, KDF_ITERATIONS));
+    }
+    let s = try!(serde_json::to_string_pretty(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    writeln!(writer, "{}", s)
+}
+
+pub fn to_json(vault: &Vault) -> IoResult<Value> {
+    let folders: Vec<Folder> = vault.folders().into_iter().filter(|f| !f.is_smart()).collect();
+    let folder_records = folders.iter()
+        .map(|folder| {
+            let mut record = BTreeMap::new();
+            record.insert("id".to_string(), Value::String(guid(folder.uuid())));
+            // Bitwarden expresses nesting with slashes in folder names.
+            record.insert("name".to_string(), Value::String(folder.path(&folders)));
+            Value::Object(record)
+        })
+        .collect();
+
+    let mut item_records = Vec::new();
+    for item in vault.items() {
+        if item.category() == Category::Tombstone {
+            continue;
+        }
+        let details = try!(vault.item_details(&item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let folder = item.folder().and_then(|uuid| folders.iter().find(|f| f.uuid() == uuid));
+        item_records.push(item_record(&item, &details, folder));
+    }
+
+    let mut json = BTreeMap::new();
+    json.insert("encrypted".to_string(), Value::Bool(false));
+    json.insert("folders".to_string(), Value::Array(folder_records));
+    json.insert("items".to_string(), Value::Array(item_records));
+    Ok(Value::Object(json))
+}
+
+fn item_record(item: &Item, details: &ItemDetails, folder: Option<&Folder>) -> Value {
+    let mut record = BTreeMap::new();
+    let mut fields: Vec<SectionField> = details.sections()
+        .iter()
+        .flat_map(|section| section.fields().iter().cloned())
+        .collect();
+    let mut custom_fields = Vec::new();
+
+    let item_type = match item.category() {
+        Category::Login | Category::Password => {
+            let username = details.username().or_else(|| take_text(&mut fields, "username"));
+            let password = details.password().or_else(|| take_text(&mut fields, "password"));
+            // Sections may repeat the login fields.
+            fields.retain(|field| {
+                !(field.name() == "username" && field.value_string() == username) &&
+                !(field.name() == "password" && field.value_string() == password)
+            });
+            let mut totp = None;
+            for field in take_totp_fields(&mut fields) {
+                match totp {
+                    None => totp = field.value_string(),
+                    Some(_) => custom_fields.push(custom_field(&field)),
+                }
+            }
+            for field in details.fields() {
+                if field.designation().is_some() || field.value().is_empty() {
+                    continue;
+                }
+                let kind = if field.kind() == "P" { FIELD_TYPE_HIDDEN } else { FIELD_TYPE_TEXT };
+                custom_fields.push(field_record(field.name(), field.value(), kind));
+            }
+            let uris = item.urls()
+                .into_iter()
+                .map(|url| {
+                    let mut uri = BTreeMap::new();
+                    uri.insert("match".to_string(), Value::Null);
+                    uri.insert("uri".to_string(), Value::String(url));
+                    Value::Object(uri)
+                })
+                .collect();
+            let mut login = BTreeMap::new();
+            login.insert("username".to_string(), string_or_null(username));
+            login.insert("password".to_string(), string_or_null(password));
+            login.insert("totp".to_string(), string_or_null(totp));
+            login.insert("uris".to_string(), Value::Array(uris));
+            record.insert("login".to_string(), Value::Object(login));
+            ITEM_TYPE_LOGIN
+        }
+        Category::CreditCard => {
+            let mut card = BTreeMap::new();
+            card.insert("cardholderName".to_string(),
+                        string_or_null(take_text(&mut fields, "cardholder")));
+            card.insert("brand".to_string(),
+                        string_or_null(take_text(&mut fields, "type").map(|t| card_brand(&t))));
+            card.insert("number".to_string(),
+                        string_or_null(take_text(&mut fields, "ccnum")));
+            card.insert("code".to_string(), string_or_null(take_text(&mut fields, "cvv")));
+            let expiry = take_field(&mut fields, "expiry").and_then(|f| f.value().as_u64());
+            card.insert("expMonth".to_string(),
+                        string_or_null(expiry.map(|e| (e % 100).to_string())));
+            card.insert("expYear".to_string(),
+                        string_or_null(expiry.map(|e| (e / 100).to_string())));
+            record.insert("card".to_string(), Value::Object(card));
+            ITEM_TYPE_CARD
+        }
+        Category::Identity => {
+            let mut identity = BTreeMap::new();
+            for &(key, name) in &[("firstName", "firstname"),
+                                  ("middleName", "initial"),
+                                  ("lastName", "lastname"),
+                                  ("company", "company"),
+                                  ("email", "email"),
+                                  ("phone", "defphone"),
+                                  ("username", "username")] {
+                identity.insert(key.to_string(), string_or_null(take_text(&mut fields, name)));
+            }
+            let address = take_field(&mut fields, "address");
+            let address_part = |key| {
+                address.as_ref()
+                    .and_then(|f| f.value().find(key))
+                    .and_then(|v| v.as_string())
+                    .and_then(|s| if s.is_empty() { None } else { Some(s.to_string()) })
+            };
+            let street = address_part("street").unwrap_or_else(String::new);
+            let mut lines = street.lines();
+            identity.insert("address1".to_string(),
+                            string_or_null(lines.next().map(|s| s.to_string())));
+            let rest: Vec<&str> = lines.collect();
+            identity.insert("address2".to_string(),
+                            string_or_null(if rest.is_empty() {
+                                None
+                            } else {
+                                Some(rest.join(", "))
+                            }));
+            identity.insert("address3".to_string(), Value::Null);
+            identity.insert("city".to_string(), string_or_null(address_part("city")));
+            identity.insert("state".to_string(), string_or_null(address_part("state")));
+            identity.insert("postalCode".to_string(), string_or_null(address_part("zip")));
+            identity.insert("country".to_string(),
+                            string_or_null(address_part("country").map(|c| c.to_uppercase())));
+            for key in &["title", "ssn", "passportNumber", "licenseNumber"] {
+                identity.insert(key.to_string(), Value::Null);
+            }
+            record.insert("identity".to_string(), Value::Object(identity));
+            ITEM_TYPE_IDENTITY
+        }
+        _ => {
+            let mut secure_note = BTreeMap::new();
+            secure_note.insert("type".to_string(), Value::U64(0));
+            record.insert("secureNote".to_string(), Value::Object(secure_note));
+            ITEM_TYPE_SECURE_NOTE
+        }
+    };
+
+    for field in &fields {
+        if field.value_string().is_some() {
+            custom_fields.push(custom_field(field));
+        }
+    }
+    let tags = item.tags();
+    if !tags.is_empty() {
+        custom_fields.push(field_record("tags", &tags.join(", "), FIELD_TYPE_TEXT));
+    }
+    if !custom_fields.is_empty() {
+        record.insert("fields".to_string(), Value::Array(custom_fields));
+    }
+    let history: Vec<Value> = details.password_history()
+        .iter()
+        .map(|entry| {
+            let mut history_entry = BTreeMap::new();
+            history_entry.insert("lastUsedDate".to_string(),
+                                 Value::String(iso8601::format(entry.time())));
+            history_entry.insert("password".to_string(), Value::String(entry.value().to_string()));
+            Value::Object(history_entry)
+        })
+        .collect();
+    if !history.is_empty() {
+        record.insert("passwordHistory".to_string(), Value::Array(history));
+    }
+
+    record.insert("id".to_string(), Value::String(guid(item.uuid())));
+    record.insert("organizationId".to_string(), Value::Null);
+    record.insert("folderId".to_string(),
+                  string_or_null(folder.map(|folder| guid(folder.uuid()))));
+    record.insert("collectionIds".to_string(), Value::Null);
+    record.insert("type".to_string(), Value::U64(item_type));
+    record.insert("reprompt".to_string(), Value::U64(0));
+    record.insert("name".to_string(),
+                  Value::String(item.title().unwrap_or("").to_string()));
+    record.insert("notes".to_string(),
+                  string_or_null(details.notes().map(|notes| notes.to_string())));
+    record.insert("favorite".to_string(), Value::Bool(item.fave().is_some()));
+    record.insert("creationDate".to_string(),
+                  Value::String(iso8601::format(item.created())));
+    record.insert("revisionDate".to_string(),
+                  Value::String(iso8601::format(item.updated())));
+    record.insert("deletedDate".to_string(),
+                  if item.is_trashed() {
+                      Value::String(iso8601::format(item.updated()))
+                  } else {
+                      Value::Null
+                  });
+    Value::Object(record)
+}
+
+fn take_field(fields: &mut Vec<SectionField>, name: &str) -> Option<SectionField> {
+    fields.iter().position(|field| field.name() == name).map(|i| fields.remove(i))
+}
+
+fn take_text(fields: &mut Vec<SectionField>, name: &str) -> Option<String> {
+    take_field(fields, name).and_then(|field| field.value_string())
+}
+
+fn take_totp_fields(fields: &mut Vec<SectionField>) -> Vec<SectionField> {
+    let is_totp = |field: &SectionField| {
+        field.name().starts_with("TOTP_") ||
+        field.value_string().map_or(false, |v| v.starts_with("otpauth://"))
+    };
+    let totp_fields = fields.iter().filter(|field| is_totp(field)).cloned().collect();
+    fields.retain(|field| !is_totp(field));
+    totp_fields
+}
+
+fn custom_field(field: &SectionField) -> Value {
+    let name = if field.title().is_empty() { field.name() } else { field.title() };
+    let kind = if field.is_concealed() { FIELD_TYPE_HIDDEN } else { FIELD_TYPE_TEXT };
+    field_record(name, &field_text(field).unwrap_or_else(String::new), kind)
+}
+
+fn field_record(name: &str, value: &str, kind: u64) -> Value {
+    let mut record = BTreeMap::new();
+    record.insert("name".to_string(), Value::String(name.to_string()));
+    record.insert("value".to_string(), Value::String(value.to_string()));
+    record.insert("type".to_string(), Value::U64(kind));
+    record.insert("linkedId".to_string(), Value::Null);
+    Value::Object(record)
+}
+
+/// Returns the value of a section field as text, formatting dates, months and addresses
+/// rather than showing their raw JSON.
+fn field_text(field: &SectionField) -> Option<String> {
+    match (field.kind(), field.value()) {
+        ("date", &Value::U64(time)) => Some(iso8601::format(time)[..10].to_string()),
+        ("monthYear", &Value::U64(month)) => Some(format!("{:04}-{:02}", month / 100, month % 100)),
+        ("address", &Value::Object(ref address)) => {
+            let parts: Vec<&str> = ["street", "city", "state", "zip", "country"]
+                .iter()
+                .filter_map(|key| address.get(*key).and_then(|v| v.as_string()))
+                .filter(|s| !s.is_empty())
+                .collect();
+            Some(parts.join(", "))
+        }
+        _ => field.value_string(),
+    }
+}
+
+/// Maps a 1Password card type to the brand names Bitwarden uses.
+fn card_brand(card_type: &str) -> String {
+    let brand = match card_type {
+        "visa" => "Visa",
+        "mc" => "Mastercard",
+        "amex" => "Amex",
+        "discover" => "Discover",
+        "diners" => "Diners Club",
+        "jcb" => "JCB",
+        "maestro" => "Maestro",
+        "unionpay" => "UnionPay",
+        _ => "Other",
+    };
+    brand.to_string()
+}
+
+fn string_or_null(s: Option<String>) -> Value {
+    s.map(Value::String).unwrap_or(Value::Null)
+}
+
+/// Formats an OPVault UUID in the hyphenated lowercase form of Bitwarden IDs.
+fn guid(uuid: &str) -> String {
+    let uuid = uuid.to_lowercase();
+    if uuid.len() != 32 || !uuid.chars().all(|c| c.is_digit(16)) {
+        return uuid;
+    }
+    format!("{}-{}-{}-{}-{}",
+            &uuid[0..8],
+            &uuid[8..12],
+            &uuid[12..16],
+            &uuid[16..20],
+            &uuid[20..32])
+}
+
+/// Wraps `json` in a password-protected export. The key is derived from the password with
+/// PBKDF2-SHA256 over the base64 salt, then stretched with HKDF into encryption and MAC keys.
+fn encrypt(json: &Value, password: &str, iterations: usize) -> IoResult<Value> {
+    let plaintext = try!(serde_json::to_string(json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    let salt = encode_base64(&rand::rand_bytes(16));
+    let key = pkcs5::pbkdf2_hmac_sha256(password, salt.as_bytes(), iterations, 32);
+    let enc_key = hkdf_expand(&key, b"enc");
+    let mac_key = hkdf_expand(&key, b"mac");
+    let validation = guid(&uuid::generate());
+
+    let mut export = BTreeMap::new();
+    export.insert("encrypted".to_string(), Value::Bool(true));
+    export.insert("passwordProtected".to_string(), Value::Bool(true));
+    export.insert("salt".to_string(), Value::String(salt));
+    export.insert("kdfType".to_string(), Value::U64(KDF_TYPE_PBKDF2_SHA256));
+    export.insert("kdfIterations".to_string(), Value::U64(iterations as u64));
+    export.insert("encKeyValidation_DO_NOT_EDIT".to_string(),
+                  Value::String(enc_string(&enc_key, &mac_key, validation.as_bytes())));
+    export.insert("data".to_string(),
+                  Value::String(enc_string(&enc_key, &mac_key, plaintext.as_bytes())));
+    Ok(Value::Object(export))
+}
+
+/// Returns the first 32 bytes of HKDF-Expand with SHA-256, which is a single HMAC.
+fn hkdf_expand(key: &[u8], info: &[u8]) -> Vec<u8> {
+    let mut message = info.to_vec();
+    message.push(1);
+    hmac::hmac(hash::Type::SHA256, key, &message)
+}
+
+/// Encrypts `plaintext` into a Bitwarden "EncString" of type 2, AES-256-CBC with an
+/// HMAC-SHA256 over the IV and ciphertext.
+fn enc_string(enc_key: &[u8], mac_key: &[u8], plaintext: &[u8]) -> String {
+    let iv = rand::rand_bytes(16);
+    let ciphertext = symm::encrypt(symm::Type::AES_256_CBC, enc_key, &iv, plaintext);
+    let mut message = iv.clone();
+    message.extend_from_slice(&ciphertext);
+    let mac = hmac::hmac(hash::Type::SHA256, mac_key, &message);
+    format!("2.{}|{}|{}",
+            encode_base64(&iv),
+            encode_base64(&ciphertext),
+            encode_base64(&mac))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64;
+    use openssl::crypto::{hash, hmac, pkcs5, symm};
+    use serde_json::{self, Value};
+
+    use testing::sample_vault;
+    use super::{encrypt, hkdf_expand, to_json};
+
+    #[test]
+    fn test_to_json() {
+        let vault = sample_vault();
+        let json = to_json(&vault).unwrap();
+        let items = json.find("items").unwrap().as_array().unwrap();
+        let find = |id: &str| items.iter().find(|i| i.find("id").unwrap().as_string() == Some(id));
+
+        let card = find("d06307ad-a44c-4031-ba2f-f4b174de79cb").unwrap();
+        assert_eq!(card.find("type").unwrap().as_u64(), Some(3));
+        assert_eq!(card.lookup("card.brand").unwrap().as_string(), Some("Mastercard"));
+        assert_eq!(card.lookup("card.expMonth").unwrap().as_string(), Some("11"));
+        assert_eq!(card.lookup("card.expYear").unwrap().as_string(), Some("2014"));
+        assert!(card.find("folderId").unwrap().as_string().is_some());
+
+        let identity = find("0ede2b13-d7ac-4e2c-9105-842682acb187").unwrap();
+        assert_eq!(identity.lookup("identity.firstName").unwrap().as_string(), Some("Wendy"));
+        assert_eq!(identity.lookup("identity.city").unwrap().as_string(), Some("Aurora"));
+        let fields = identity.find("fields").unwrap().as_array().unwrap();
+        assert!(fields.iter().any(|f| f.find("value").unwrap().as_string() == Some("1981-05-19")));
+
+        let hulu = find("13c8e12a-c8e5-4b1f-873b-ab0824e521bc").unwrap();
+        assert_eq!(hulu.find("type").unwrap().as_u64(), Some(1));
+        assert!(hulu.lookup("login.password").unwrap().as_string().is_some());
+        assert!(!hulu.lookup("login.uris").unwrap().as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let json = Value::String("secret".to_string());
+        let export = encrypt(&json, "hunter2", 1000).unwrap();
+        let salt = export.find("salt").unwrap().as_string().unwrap();
+        let key = pkcs5::pbkdf2_hmac_sha256("hunter2", salt.as_bytes(), 1000, 32);
+        let data = export.find("data").unwrap().as_string().unwrap();
+        assert!(data.starts_with("2."));
+        let parts: Vec<Vec<u8>> =
+            data[2..].split('|').map(|p| base64::u8de(p.as_bytes()).unwrap()).collect();
+        let mut message = parts[0].clone();
+        message.extend_from_slice(&parts[1]);
+        assert_eq!(hmac::hmac(hash::Type::SHA256, &hkdf_expand(&key, b"mac"), &message),
+                   parts[2]);
+        let plaintext = symm::decrypt(symm::Type::AES_256_CBC,
+                                      &hkdf_expand(&key, b"enc"),
+                                      &parts[0],
+                                      &parts[1]);
+        assert_eq!(serde_json::to_string(&json).unwrap().into_bytes(), plaintext);
+    }
+}
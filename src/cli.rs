@@ -1,10 +1,50 @@
 use clap::{App, ArgMatches, SubCommand};
+use libc;
 use rpassword;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
 use std::env;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{self, Read, Result as IoResult, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
 
+use agent;
+use api;
 use agile_keychain::attachment;
+use audit;
+use audit::breach::PwnedPasswords;
+use bitwarden;
+use clipboard::{self, Clearing, Clipboard};
+use csv;
+use fs_ext::create_private_file;
+use generator::{self, CharClass};
+use git_credential;
+use import;
+use inject;
+use kdbx;
+use kdbx::cipher::Cipher;
+use kdbx::database::Database;
+use kdbx::key::CompositeKey;
+use native_host;
+use one_pif;
 use op_vault;
+use op_vault::category::Category;
+use op_vault::item::NewItem;
+use op_vault::vault::{LockedVault, Vault};
+use reference::{self, Reference};
+use run;
+use search::{self, Query};
+use secret_service;
+use secret_service::dbus::Connection;
+use ssh_agent;
+use totp;
+use tui;
+use url::matcher::{MatchMode, Matcher};
+use util::now;
 
 pub struct Cli;
 
@@ -17,28 +57,216 @@ impl Cli {
         let crate_version = crate_version!();
         let app = App::new("decone")
             .version(&crate_version)
+            .subcommand(SubCommand::with_name("agent")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--timeout=[seconds] \
+                                         'Lock after this long without requests (default 900)'")
+                        .arg_from_usage("--foreground 'Stay attached to the terminal'")
+                        .arg_from_usage("--status 'Show the vault the running agent holds'")
+                        .arg_from_usage("--stop 'Lock the vault and stop the running agent'"))
+            .subcommand(SubCommand::with_name("audit")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--days=[days] \
+                                         'Report passwords unchanged for longer (default 365)'")
+                        .arg_from_usage("--breached=[file] \
+                                         'Check passwords against a Pwned Passwords SHA-1 list \
+                                          ordered by hash'")
+                        .arg_from_usage("--json 'Write the report as JSON'"))
+            .subcommand(SubCommand::with_name("copy")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--timeout=[seconds] \
+                                         'Clear the clipboard after this long if it still holds \
+                                          the secret, or never if 0 (default 45); most terminals \
+                                          need $DECONE_CLIPBOARD_PASTE for this'")
+                        .arg_from_usage("<item> 'Item UUID or title, or a vault:// reference'")
+                        .arg_from_usage("[field] \
+                                         'Field to copy (default password), unless item is a \
+                                          reference'"))
             .subcommand(SubCommand::with_name("dump-profile")
                         .arg_from_usage("<profile.js>"))
+            .subcommand(SubCommand::with_name("export")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-f --format=<format> \
+                                         'Export format (1pif, bitwarden, csv, kdbx)'")
+                        .arg_from_usage("-o --output=[file] 'Write to file instead of stdout'")
+                        .arg_from_usage("--attachments-dir=[dir] 'Decrypt attachments into dir'")
+                        .arg_from_usage("--cipher=[cipher] 'KDBX cipher (aes, chacha20)'")
+                        .arg_from_usage("--encrypt 'Protect the Bitwarden export with a password'")
+                        .arg_from_usage("--columns=[columns] \
+                                         'CSV columns, e.g. title,url,username,password'")
+                        .arg_from_usage("--category=[categories] \
+                                         'Export only these categories, e.g. Login,Password'")
+                        .arg_from_usage("--folder=[folders] 'Export only these folders'")
+                        .arg_from_usage("--no-formula-escaping \
+                                         'Do not prefix CSV fields starting with =, +, -, @ \
+                                         with a quote'")
+                        .arg_from_usage("--i-understand-this-is-plaintext \
+                                         'Allow writing passwords unencrypted to CSV'"))
             .subcommand(SubCommand::with_name("export-attachments")
                         .arg_from_usage("-u --uuid=<uuid>"))
+            .subcommand(SubCommand::with_name("generate")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-m --mode=[mode] 'chars, syllables or words'")
+                        .arg_from_usage("-l --length=[n] \
+                                         'Number of characters, syllables or words'")
+                        .arg_from_usage("--no-lowercase")
+                        .arg_from_usage("--no-uppercase")
+                        .arg_from_usage("--no-digits")
+                        .arg_from_usage("--no-symbols")
+                        .arg_from_usage("--min-lowercase=[n]")
+                        .arg_from_usage("--min-uppercase=[n]")
+                        .arg_from_usage("--min-digits=[n]")
+                        .arg_from_usage("--min-symbols=[n]")
+                        .arg_from_usage("--no-ambiguous 'Avoid characters such as 0, O, 1 and l'")
+                        .arg_from_usage("--exclude=[chars] 'Characters never to use'")
+                        .arg_from_usage("--separator=[separator] \
+                                         'Goes between syllables or words (default -)'")
+                        .arg_from_usage("--capitalize 'Capitalize syllables or words'")
+                        .arg_from_usage("--save=[title] 'Save as a new login in the vault'")
+                        .arg_from_usage("--username=[username] 'Username of the saved login'")
+                        .arg_from_usage("--url=[url] 'URL of the saved login'"))
+            .subcommand(SubCommand::with_name("git-credential")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("<operation> 'get, store or erase, as called by git'"))
+            .subcommand(SubCommand::with_name("import")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-f --format=<format> 'Import format (1pif, csv, kdbx)'")
+                        .arg_from_usage("-n --dry-run 'Show what would be imported'")
+                        .arg_from_usage("--attachments-dir=[dir] \
+                                         'Read 1PIF attachments from dir, laid out as export \
+                                          writes them'")
+                        .arg_from_usage("--key-file=[file] 'KeePass key file'")
+                        .arg_from_usage("--preset=[preset] \
+                                         'CSV layout (chrome, edge, firefox, safari)'")
+                        .arg_from_usage("--columns=[mapping] \
+                                         'CSV columns as field=column, e.g. title=Name,url=Site'")
+                        .arg_from_usage("<file> 'File to import'"))
+            .subcommand(SubCommand::with_name("inject")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-i --input=<file> \
+                                         'Template with references such as \
+                                          {{ vault://<vault>/<item>/<section>/<field> }}'")
+                        .arg_from_usage("-o --output=[file] 'Write to file instead of stdout'"))
             .subcommand(SubCommand::with_name("list-attachments"))
             .subcommand(SubCommand::with_name("list-folders")
                         .arg_from_usage("<folders.js>"))
+            .subcommand(SubCommand::with_name("match-url")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--mode=[mode] \
+                                         'Match the registrable domain, the host, or the host \
+                                          and leading path: domain, host or prefix \
+                                          (default domain)'")
+                        .arg_from_usage("<url> 'Address of the page'"))
+            .subcommand(SubCommand::with_name("native-host")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--match=[mode] \
+                                         'How logins match pages: domain, host or prefix \
+                                          (default domain)'")
+                        .arg_from_usage("[browser-args]... 'Passed by the browser and ignored'"))
+            .subcommand(SubCommand::with_name("run")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-e --env=[assignment]... \
+                                         'Set a variable to an <item>/<field> or vault:// \
+                                          reference, e.g. DB_PASS=Server/password'")
+                        .arg_from_usage("--env-file=[file] \
+                                         'Read NAME=<reference> lines from file'")
+                        .arg_from_usage("--mask 'Conceal the secrets in the output of the command'")
+                        .arg_from_usage("<command>... 'Command to run, after --'"))
+            .subcommand(SubCommand::with_name("search")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("-n --limit=[n] 'Show at most n items'")
+                        .arg_from_usage("<query>... \
+                                         'Words to match and filters such as category:login \
+                                          tag:prod folder:Social trashed:true'"))
+            .subcommand(SubCommand::with_name("secret-service")
+                        .arg_from_usage("-p --path=[path]"))
+            .subcommand(SubCommand::with_name("serve")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--listen=[address] \
+                                         'Loopback address to listen on (default 127.0.0.1:8734)'")
+                        .arg_from_usage("--rate=[n] 'Requests allowed per second (default 10)'")
+                        .arg_from_usage("--token-file=[file] \
+                                         'Write the bearer token to file instead of stdout'"))
+            .subcommand(SubCommand::with_name("ssh-agent")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--socket=[path] 'Listen at path instead of the default'")
+                        .arg_from_usage("--confirm 'Ask through $SSH_ASKPASS before each use'")
+                        .arg_from_usage("--foreground 'Stay attached to the terminal'")
+                        .arg_from_usage("-l --list 'Print the public keys found and exit'"))
+            .subcommand(SubCommand::with_name("totp")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("<item> 'Item UUID or title'"))
+            .subcommand(SubCommand::with_name("tui")
+                        .arg_from_usage("-p --path=[path]")
+                        .arg_from_usage("--timeout=[seconds] \
+                                         'Lock after this long without a key press \
+                                          (default 300)'"))
             .subcommand(SubCommand::with_name("unlock-vault")
                         .arg_from_usage("-p --path=[path]"));
         match app.get_matches_lossy().subcommand() {
+            ("agent", Some(matches)) => {
+                run_agent(matches);
+            }
+            ("audit", Some(matches)) => {
+                audit(matches);
+            }
+            ("copy", Some(matches)) => {
+                copy(matches);
+            }
             ("dump-profile", Some(matches)) => {
                 dump_profile(matches);
             }
+            ("export", Some(matches)) => {
+                export(matches);
+            }
             ("export-attachments", Some(matches)) => {
                 export_attachments(matches);
             }
+            ("generate", Some(matches)) => {
+                generate(matches);
+            }
+            ("git-credential", Some(matches)) => {
+                git_credential(matches);
+            }
+            ("import", Some(matches)) => {
+                import(matches);
+            }
+            ("inject", Some(matches)) => {
+                inject(matches);
+            }
             ("list-attachments", Some(matches)) => {
                 list_attachments(matches);
             }
             ("list-folders", Some(matches)) => {
                 list_folders(matches);
             }
+            ("match-url", Some(matches)) => {
+                match_url(matches);
+            }
+            ("native-host", Some(matches)) => {
+                native_host(matches);
+            }
+            ("run", Some(matches)) => {
+                run_command(matches);
+            }
+            ("search", Some(matches)) => {
+                search(matches);
+            }
+            ("secret-service", Some(matches)) => {
+                run_secret_service(matches);
+            }
+            ("serve", Some(matches)) => {
+                serve(matches);
+            }
+            ("ssh-agent", Some(matches)) => {
+                run_ssh_agent(matches);
+            }
+            ("totp", Some(matches)) => {
+                totp(matches);
+            }
+            ("tui", Some(matches)) => {
+                tui(matches);
+            }
             ("unlock-vault", Some(matches)) => {
                 unlock_vault(matches);
             }
@@ -47,6 +275,196 @@ impl Cli {
     }
 }
 
+const DEFAULT_AGENT_TIMEOUT_SECS: u64 = 15 * 60;
+
+fn run_agent<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    if matches.is_present("status") || matches.is_present("stop") {
+        let command = if matches.is_present("stop") { "lock" } else { "status" };
+        match agent::client::send(Value::Object(agent::request(command))) {
+            Ok(ref response) if command == "status" => {
+                println!("Agent {} holds {}",
+                         response.find("pid").and_then(|v| v.as_u64()).unwrap_or(0),
+                         response.find("path").and_then(|v| v.as_string()).unwrap_or(""));
+            }
+            Ok(_) => println!("Locked"),
+            Err(e) => println!("No agent: {}", e),
+        }
+        return;
+    }
+    let timeout = match matches.value_of("timeout").map(|secs| secs.parse()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => {
+            println!("Invalid timeout: {}", matches.value_of("timeout").unwrap_or(""));
+            return;
+        }
+        None => DEFAULT_AGENT_TIMEOUT_SECS,
+    };
+    let socket_path = match agent::socket_path() {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    if agent::client::send(Value::Object(agent::request("status"))).is_ok() {
+        println!("An agent is already running at {:?}", socket_path);
+        return;
+    }
+    // The agent hands out the master key, so it must hold it.
+    let locked_vault = open_locked_vault(matches);
+    let vault = match locked_vault.and_then(|v| unlock_with_password(&v, Access::Details)) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let server = match agent::server::Server::bind(&socket_path,
+                                                   vault,
+                                                   Duration::from_secs(timeout)) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    if !matches.is_present("foreground") {
+        match agent::server::daemonize() {
+            Ok(Some(pid)) => {
+                println!("{}={}; export {};", agent::SOCKET_ENV, socket_path.display(),
+                         agent::SOCKET_ENV);
+                let _ = writeln!(io::stderr(), "Agent {} started", pid);
+                // The agent owns the socket now, so leave it in place.
+                mem::forget(server);
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                println!("{:?}", e);
+                return;
+            }
+        }
+    }
+    protect_memory_or_warn();
+    if let Err(e) = server.run() {
+        let _ = writeln!(io::stderr(), "{:?}", e);
+    }
+}
+
+fn audit<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let days = match matches.value_of("days").map(|days| days.parse()) {
+        Some(Ok(days)) => days,
+        Some(Err(_)) => {
+            println!("Invalid number of days: {}", matches.value_of("days").unwrap_or(""));
+            return;
+        }
+        None => 365,
+    };
+    let pwned = match matches.value_of("breached").map(PwnedPasswords::open) {
+        Some(Ok(pwned)) => Some(pwned),
+        Some(Err(e)) => {
+            println!("{:?}", e);
+            return;
+        }
+        None => None,
+    };
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let result = audit::audit(&vault, now(), days, pwned.as_ref()).and_then(|report| {
+        if matches.is_present("json") {
+            let s = try!(serde_json::to_string_pretty(&report.to_json())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+            println!("{}", s);
+            Ok(())
+        } else {
+            report.write_table(&mut io::stdout())
+        }
+    });
+    if let Err(e) = result {
+        println!("{:?}", e);
+    }
+}
+
+const DEFAULT_COPY_TIMEOUT_SECS: u64 = 45;
+
+fn copy<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let timeout = match matches.value_of("timeout").map(|secs| secs.parse()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => {
+            println!("Invalid timeout: {}", matches.value_of("timeout").unwrap_or(""));
+            return;
+        }
+        None => DEFAULT_COPY_TIMEOUT_SECS,
+    };
+    let item = matches.value_of("item").unwrap_or("");
+    let field = matches.value_of("field");
+    // A reference names its field already; a second one would be ignored.
+    if item.starts_with("vault://") && field.is_some() {
+        println!("A vault:// reference takes no field: {}", field.unwrap_or(""));
+        return;
+    }
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    protect_memory_or_warn();
+    let result = if item.starts_with("vault://") {
+        Reference::parse(item).and_then(|r| r.resolve(&vault))
+    } else {
+        reference::resolve(&vault, item, field.unwrap_or("password"))
+    };
+    let secret = match result {
+        Ok(secret) => secret,
+        Err(e) => return println!("{:?}", e),
+    };
+    drop(vault);
+
+    let clipboard = Clipboard::from_env();
+    // Say so before the secret is on the clipboard, while there is still a choice.
+    if timeout != 0 && !clipboard.has_paste_command() {
+        let _ = writeln!(io::stderr(),
+                         "Warning: {} is not set, so the clipboard may not be cleared; set it to \
+                          a command that prints the clipboard",
+                         clipboard::PASTE_ENV);
+    }
+    if let Err(e) = clipboard.copy(&secret) {
+        return println!("{:?}", e);
+    }
+    if timeout == 0 {
+        let _ = writeln!(io::stderr(), "Copied to the clipboard");
+        return;
+    }
+    let _ = writeln!(io::stderr(),
+                     "Copied to the clipboard; clearing in {} seconds, or press Enter to clear now",
+                     timeout);
+    wait_for_enter(Duration::from_secs(timeout));
+    match clipboard.clear_if(&secret) {
+        Ok(Clearing::Cleared) => {
+            let _ = writeln!(io::stderr(), "Cleared the clipboard");
+        }
+        Ok(Clearing::Replaced) => {
+            let _ = writeln!(io::stderr(), "The clipboard holds something else now; left it");
+        }
+        Ok(Clearing::Unreadable) => {
+            let _ = writeln!(io::stderr(),
+                             "Could not read the clipboard back, so it was not cleared; set {} \
+                              to a command that prints it",
+                             clipboard::PASTE_ENV);
+        }
+        Err(e) => println!("{:?}", e),
+    }
+}
+
+/// Waits for `timeout`, or until Enter is pressed if stdin is a terminal.
+fn wait_for_enter(timeout: Duration) {
+    if unsafe { libc::isatty(0) } != 1 {
+        return thread::sleep(timeout);
+    }
+    if let Ok(true) = tui::terminal::wait_for_input(0, timeout) {
+        let mut line = String::new();
+        let _ = io::stdin().read_line(&mut line);
+    }
+}
+
 fn dump_profile<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
     if let Some(path) = matches.value_of("profile.js") {
         let profile = op_vault::profile::LockedProfile::from_file(path);
@@ -54,10 +472,379 @@ fn dump_profile<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
     }
 }
 
+const EXPORT_FORMATS: &'static [&'static str] = &["1pif", "bitwarden", "csv", "kdbx"];
+
+fn export<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let format = matches.value_of("format").unwrap_or("");
+    if !EXPORT_FORMATS.contains(&format) {
+        println!("Unknown format: {} (expected one of {})", format, EXPORT_FORMATS.join(", "));
+        return;
+    }
+    if format == "csv" && !matches.is_present("i-understand-this-is-plaintext") {
+        println!("CSV exports contain every password in plain text. Pass \
+                  --i-understand-this-is-plaintext to write one anyway.");
+        return;
+    }
+    let mut kdbx_settings = kdbx::Settings::new();
+    if let Some(name) = matches.value_of("cipher") {
+        match Cipher::from_name(name) {
+            Some(cipher) => kdbx_settings.cipher = cipher,
+            None => {
+                println!("Unknown cipher: {} (expected aes or chacha20)", name);
+                return;
+            }
+        }
+    }
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let result = match format {
+        "1pif" => {
+            let attachments_dir = matches.value_of("attachments-dir").map(Path::new);
+            with_output(matches, |w| one_pif::export(&vault, w, attachments_dir))
+        }
+        "bitwarden" => {
+            let password = if matches.is_present("encrypt") {
+                match read_new_password("Bitwarden export") {
+                    Ok(Some(password)) => Some(password),
+                    Ok(None) => return,
+                    Err(e) => {
+                        println!("{:?}", e);
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+            with_output(matches,
+                        |w| bitwarden::export(&vault, w, password.as_ref().map(|s| &s[..])))
+        }
+        "csv" => {
+            let columns = matches.value_of("columns")
+                .map(split_list)
+                .unwrap_or_else(|| {
+                    csv::DEFAULT_EXPORT_COLUMNS.iter().map(|c| c.to_string()).collect()
+                });
+            let mut filter = csv::ExportFilter::new();
+            filter.categories =
+                matches.value_of("category").map(split_list).unwrap_or_else(Vec::new);
+            filter.folders = matches.value_of("folder").map(split_list).unwrap_or_else(Vec::new);
+            let escape_formulas = !matches.is_present("no-formula-escaping");
+            with_output(matches,
+                        |w| csv::export(&vault, w, &columns, &filter, escape_formulas))
+        }
+        "kdbx" => {
+            let password = match read_new_password("KeePass database") {
+                Ok(Some(password)) => password,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("{:?}", e);
+                    return;
+                }
+            };
+            let key = CompositeKey::from_password(&password);
+            with_output(matches,
+                        |w| kdbx::export::export(&vault, w, &key, &kdbx_settings))
+        }
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        println!("{:?}", e);
+    }
+}
+
+/// Splits a comma-separated option value, trimming each element and dropping empty ones.
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect()
+}
+
 fn export_attachments<'n, 'a>(_matches: &ArgMatches<'n, 'a>) {
     println!("Not yet implemented");
 }
 
+fn generate<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let recipe = match recipe(matches) {
+        Ok(recipe) => recipe,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let password = match generator::generate(&recipe) {
+        Ok(password) => password,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    let title = match matches.value_of("save") {
+        Some(title) => title,
+        None => {
+            println!("{}", password);
+            let _ = writeln!(io::stderr(), "About {:.0} bits of entropy", recipe.entropy());
+            return;
+        }
+    };
+    let mut vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let mut fields = Vec::new();
+    if let Some(username) = matches.value_of("username") {
+        fields.push(import::login_field("username", "T", username));
+    }
+    fields.push(import::login_field("password", "P", &password));
+    let mut details = BTreeMap::new();
+    details.insert("fields".to_string(), Value::Array(fields));
+    let mut new_item = NewItem::new(Category::Login, title, Value::Object(details));
+    new_item.urls = matches.value_of("url").into_iter().map(|url| url.to_string()).collect();
+    match vault.create_item(&new_item) {
+        Ok(item) => {
+            println!("{}", password);
+            let _ = writeln!(io::stderr(), "Saved as {}", item.uuid());
+        }
+        Err(e) => println!("{:?}", e),
+    }
+}
+
+/// Builds a password recipe from the options of `generate`.
+fn recipe<'n, 'a>(matches: &ArgMatches<'n, 'a>) -> Result<generator::Recipe, String> {
+    let mut recipe = generator::Recipe::new();
+    if let Some(name) = matches.value_of("mode") {
+        recipe.mode = try!(generator::Mode::from_name(name)
+            .ok_or_else(|| format!("Unknown mode: {} (expected chars, syllables or words)", name)));
+        recipe.length = match recipe.mode {
+            generator::Mode::Characters => 20,
+            generator::Mode::Syllables => 6,
+            generator::Mode::Words => 5,
+        };
+    }
+    if let Some(length) = matches.value_of("length") {
+        recipe.length = try!(length.parse().map_err(|_| format!("Invalid length: {}", length)));
+    }
+    let classes = [(CharClass::Lowercase, "lowercase"),
+                   (CharClass::Uppercase, "uppercase"),
+                   (CharClass::Digits, "digits"),
+                   (CharClass::Symbols, "symbols")];
+    recipe.classes.clear();
+    for &(class, name) in &classes {
+        if matches.is_present(&format!("no-{}", name)) {
+            continue;
+        }
+        let min = match matches.value_of(&format!("min-{}", name)) {
+            Some(min) => try!(min.parse().map_err(|_| format!("Invalid count: {}", min))),
+            None => 0,
+        };
+        recipe.classes.push((class, min));
+    }
+    recipe.exclude_ambiguous = matches.is_present("no-ambiguous");
+    recipe.exclude = matches.value_of("exclude").unwrap_or("").to_string();
+    if let Some(separator) = matches.value_of("separator") {
+        recipe.separator = separator.to_string();
+    }
+    recipe.capitalize = matches.is_present("capitalize");
+    Ok(recipe)
+}
+
+const IMPORT_FORMATS: &'static [&'static str] = &["1pif", "csv", "kdbx"];
+
+enum ImportSource {
+    Csv(Vec<Vec<String>>, csv::Columns),
+    OnePif(Vec<Value>),
+    Kdbx(Database),
+}
+
+fn git_credential<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let stdin = io::stdin();
+    let attributes = match git_credential::read_attributes(stdin.lock()) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "{}", e);
+            process::exit(1);
+        }
+    };
+    let operation = matches.value_of("operation").unwrap_or("");
+    // Erasing is a no-op on purpose: git asks for it whenever a password is rejected, and the
+    // vault is shared with more than git, so logins are never deleted on its behalf.
+    if operation == "erase" {
+        return;
+    }
+    if operation != "get" && operation != "store" {
+        let _ = writeln!(io::stderr(), "Unknown operation {}", operation);
+        process::exit(1);
+    }
+    // Git owns stdin, so the vault can only be unlocked by a running agent.
+    let mut vault = match open_locked_vault(matches).and_then(|v| agent::client::unlock(&v)) {
+        Some(vault) => vault,
+        None => {
+            let _ = writeln!(io::stderr(), "decone: start `decone agent` to unlock the vault");
+            return;
+        }
+    };
+    let item = git_credential::find_login(&vault, &attributes);
+    if operation == "store" {
+        // Items cannot be edited yet, so a changed password is left for the user to update.
+        if let Some(item) = item {
+            let stored = vault.item_details(&item).and_then(|details| details.password());
+            let given = attributes.get("password");
+            if given.is_some() && given != stored.as_ref() {
+                let _ = writeln!(io::stderr(),
+                                 "decone: the password of {} differs from the one git used; \
+                                  update it in the vault",
+                                 item.title().unwrap_or(item.uuid()));
+                process::exit(1);
+            }
+            return;
+        }
+        if let Some(new_item) = git_credential::new_login(&attributes) {
+            if let Err(e) = vault.create_item(&new_item) {
+                let _ = writeln!(io::stderr(), "{}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let details = match item.as_ref().and_then(|item| vault.item_details(item)) {
+        Some(details) => details,
+        None => return,
+    };
+    let username = details.username()
+        .or_else(|| item.as_ref().and_then(|item| item.ainfo()).map(|s| s.to_string()))
+        .unwrap_or_else(String::new);
+    let password = match details.password() {
+        Some(password) => password,
+        None => return,
+    };
+    let stdout = io::stdout();
+    let result = git_credential::write_attributes(stdout.lock(),
+                                                  &[("username", &username),
+                                                    ("password", &password)]);
+    if let Err(e) = result {
+        let _ = writeln!(io::stderr(), "{}", e);
+        process::exit(1);
+    }
+}
+
+fn import<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let format = matches.value_of("format").unwrap_or("");
+    if !IMPORT_FORMATS.contains(&format) {
+        println!("Unknown format: {} (expected one of {})", format, IMPORT_FORMATS.join(", "));
+        return;
+    }
+    let path = matches.value_of("file").unwrap_or("");
+    let source = match format {
+        "1pif" => {
+            File::open(path)
+                .and_then(|mut file| one_pif::read_records(&mut file))
+                .map(ImportSource::OnePif)
+        }
+        "csv" => read_csv(matches, path),
+        "kdbx" => read_kdbx(matches, path).map(ImportSource::Kdbx),
+        _ => unreachable!(),
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    let mut vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let actions = match source {
+        ImportSource::Csv(rows, columns) => csv::plan_import(&vault, &rows, &columns),
+        ImportSource::OnePif(records) => {
+            let attachments_dir = matches.value_of("attachments-dir").map(Path::new);
+            one_pif::plan_import(&vault, &records, attachments_dir)
+        }
+        ImportSource::Kdbx(db) => kdbx::import::plan_import(&vault, &db),
+    };
+    for action in &actions {
+        println!("{}", action);
+    }
+    if matches.is_present("dry-run") {
+        return;
+    }
+    if let Err(e) = import::apply(&mut vault, &actions) {
+        println!("{:?}", e);
+    }
+}
+
+/// Reads the CSV file at `path`, whose first row names the columns. The columns are mapped by
+/// `--columns`, by `--preset`, or by the preset that the header matches.
+fn read_csv<'n, 'a>(matches: &ArgMatches<'n, 'a>, path: &str) -> IoResult<ImportSource> {
+    let mut rows = try!(File::open(path).and_then(|mut file| csv::read_rows(&mut file)));
+    if rows.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "CSV file is empty"));
+    }
+    let header = rows.remove(0);
+    let columns = if let Some(spec) = matches.value_of("columns") {
+        try!(csv::Columns::new(&header, &try!(csv::parse_mapping(spec))))
+    } else {
+        let preset = match matches.value_of("preset") {
+            Some(name) => csv::Preset::from_name(name),
+            None => csv::Preset::detect(&header),
+        };
+        let preset = try!(preset.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "unknown CSV layout; pass --preset or --columns")
+        }));
+        try!(preset.columns(&header))
+    };
+    Ok(ImportSource::Csv(rows, columns))
+}
+
+/// Reads the KeePass database at `path`, unlocking it with a password read from the terminal
+/// and the key file at `--key-file` if given. An empty password means none when there is a
+/// key file.
+fn read_kdbx<'n, 'a>(matches: &ArgMatches<'n, 'a>, path: &str) -> IoResult<Database> {
+    let mut key_file = None;
+    if let Some(key_path) = matches.value_of("key-file") {
+        let mut bytes = Vec::new();
+        try!(File::open(key_path).and_then(|mut file| file.read_to_end(&mut bytes)));
+        key_file = Some(bytes);
+    }
+    let _ = write!(io::stderr(), "Enter password for KeePass database: ");
+    let _ = io::stderr().flush();
+    let password = try!(rpassword::read_password());
+    let password = if password.is_empty() && key_file.is_some() {
+        None
+    } else {
+        Some(&password[..])
+    };
+    let key = try!(CompositeKey::new(password, key_file.as_ref().map(|bytes| &bytes[..])));
+    let mut file = try!(File::open(path));
+    kdbx::reader::read(&mut file, &key)
+}
+
+fn inject<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let mut template = String::new();
+    let path = matches.value_of("input").unwrap_or("");
+    if let Err(e) = File::open(path).and_then(|mut file| file.read_to_string(&mut template)) {
+        return println!("{:?}", e);
+    }
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    // Render everything first so that a failure leaves no partial output behind.
+    let rendered = match inject::render(&template,
+                                        |s| Reference::parse(s).and_then(|r| r.resolve(&vault))) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "{}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = with_output(matches, |w| w.write_all(rendered.as_bytes())) {
+        println!("{:?}", e);
+    }
+}
+
 fn list_attachments<'n, 'a>(_matches: &ArgMatches<'n, 'a>) {
     match attachment::Archive::with_keychain_path(get_default_keychain_path()) {
         Ok(archive) => {
@@ -86,24 +873,456 @@ fn list_folders<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
     }
 }
 
-fn unlock_vault<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
-    use std::io::{self, Write};
-    let path = matches.value_of("path")
-        .and_then(|s| Some(PathBuf::from(s)))
-        .unwrap_or_else(|| get_default_opvault_path());
-    let locked_vault = op_vault::vault::LockedVault::new(&path);
-    if locked_vault.is_err() {
-        println!("Could not open vault at {:?}", path);
+fn match_url<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let mode = match match_mode(matches.value_of("mode")) {
+        Ok(mode) => mode,
+        Err(message) => {
+            println!("{}", message);
+            return;
+        }
+    };
+    let url = matches.value_of("url").unwrap_or("");
+    let matcher = match Matcher::new(url, mode) {
+        Some(matcher) => matcher,
+        None => {
+            println!("Invalid URL: {}", url);
+            return;
+        }
+    };
+    let vault = match open_vault(matches, Access::Overviews) {
+        Some(vault) => vault,
+        None => return,
+    };
+    for m in matcher.rank(vault.items()) {
+        println!("{} {} ({})", m.item.uuid(), m.item.title().unwrap_or(""), m.url);
+    }
+}
+
+/// Parses a match mode, taking `domain` if there is none.
+fn match_mode(name: Option<&str>) -> Result<MatchMode, String> {
+    let name = name.unwrap_or("domain");
+    MatchMode::from_name(name)
+        .ok_or_else(|| format!("Unknown match mode: {} (expected domain, host or prefix)", name))
+}
+
+fn native_host<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let mode = match match_mode(matches.value_of("match")) {
+        Ok(mode) => mode,
+        Err(message) => {
+            // Stdout belongs to the browser.
+            let _ = writeln!(io::stderr(), "{}", message);
+            process::exit(1);
+        }
+    };
+    let locked_vault = match open_locked_vault(matches) {
+        Some(locked_vault) => locked_vault,
+        None => process::exit(1),
+    };
+    protect_memory_or_warn();
+    // The browser owns stdin, so the vault can only be unlocked by a running agent. Memory is
+    // locked again once it is, since only the pages mapped so far are.
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let result = native_host::run(stdin.lock(), stdout.lock(), mode, || {
+        let vault = agent::client::unlock(&locked_vault);
+        if vault.is_some() {
+            protect_memory_or_warn();
+        }
+        vault
+    });
+    if let Err(e) = result {
+        let _ = writeln!(io::stderr(), "{}", e);
+        process::exit(1);
+    }
+}
+
+fn run_command<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let mut assignments = Vec::new();
+    if let Some(path) = matches.value_of("env-file") {
+        match run::read_env_file(path) {
+            Ok(file_assignments) => assignments.extend(file_assignments),
+            Err(e) => run_failed(e),
+        }
+    }
+    for assignment in matches.values_of("env").unwrap_or_else(Vec::new) {
+        match run::parse_assignment(assignment) {
+            Ok(assignment) => assignments.push(assignment),
+            Err(e) => run_failed(e),
+        }
+    }
+    let command: Vec<String> = matches.values_of("command")
+        .unwrap_or_else(Vec::new)
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    if command.is_empty() {
         return;
     }
-    let locked_vault = locked_vault.unwrap();
-    print!("Enter password for vault: ");
-    let _ = io::stdout().flush();
-    let password = rpassword::read_password().unwrap();
-    if locked_vault.unlock(&password).is_some() {
-        println!("Unlocked");
+
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => process::exit(1),
+    };
+    // Resolve everything before starting the command so that it never runs half configured.
+    let mut env = Vec::new();
+    for (name, value) in assignments {
+        let result = if value.starts_with("vault://") {
+            Reference::parse(&value).and_then(|r| r.resolve(&vault))
+        } else {
+            reference::split_item_field(&value)
+                .and_then(|(item, field)| reference::resolve(&vault, item, field))
+        };
+        match result {
+            Ok(value) => env.push((name, value)),
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "{}: {}", name, e);
+                process::exit(1);
+            }
+        }
+    }
+    drop(vault);
+
+    if matches.is_present("mask") {
+        let secrets: Vec<String> = env.iter().map(|&(_, ref value)| value.clone()).collect();
+        match run::run_masked(&command, &env, &secrets) {
+            Ok(code) => process::exit(code),
+            Err(e) => command_failed(&command[0], e),
+        }
     } else {
-        println!("Failed to unlock");
+        command_failed(&command[0], run::exec(&command, &env));
+    }
+}
+
+fn run_failed(e: io::Error) -> ! {
+    let _ = writeln!(io::stderr(), "{}", e);
+    process::exit(1);
+}
+
+/// Reports that `program` could not be run and exits like a shell would, with 127 if it was not
+/// found.
+fn command_failed(program: &str, e: io::Error) -> ! {
+    let _ = writeln!(io::stderr(), "{}: {}", program, e);
+    process::exit(if e.kind() == io::ErrorKind::NotFound { 127 } else { 1 });
+}
+
+fn search<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let limit = match matches.value_of("limit").map(|n| n.parse()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("Invalid limit: {}", matches.value_of("limit").unwrap_or(""));
+            return;
+        }
+        None => usize::max_value(),
+    };
+    let words = matches.values_of("query").unwrap_or_else(Vec::new);
+    let query = match Query::parse(&words.join(" ")) {
+        Ok(query) => query,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    let vault = match open_vault(matches, Access::Overviews) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let folders = vault.folders();
+    for result in search::search(vault.items(), &folders, &query).iter().take(limit) {
+        let item = &result.item;
+        match item.ainfo() {
+            Some(ainfo) if !ainfo.is_empty() => {
+                println!("{} {} ({})", item.uuid(), item.title().unwrap_or(""), ainfo)
+            }
+            _ => println!("{} {}", item.uuid(), item.title().unwrap_or("")),
+        }
+    }
+}
+
+fn run_secret_service<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    // Take the name before asking for the password, so that a running service is found first.
+    let connection = Connection::session().and_then(|mut connection| {
+        connection.request_name(secret_service::BUS_NAME).map(|_| connection)
+    });
+    let mut connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    protect_memory_or_warn();
+    let _ = writeln!(io::stderr(), "Serving {} as {}", vault.name(), secret_service::BUS_NAME);
+    if let Err(e) = secret_service::Service::new(vault).run(&mut connection) {
+        let _ = writeln!(io::stderr(), "{:?}", e);
+    }
+}
+
+const DEFAULT_API_ADDRESS: &'static str = "127.0.0.1:8734";
+const DEFAULT_API_RATE: u32 = 10;
+
+fn serve<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let rate = match matches.value_of("rate").map(|n| n.parse()) {
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            println!("Invalid rate: {}", matches.value_of("rate").unwrap_or(""));
+            return;
+        }
+        None => DEFAULT_API_RATE,
+    };
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let address = matches.value_of("listen").unwrap_or(DEFAULT_API_ADDRESS);
+    let server = match api::Server::bind(address, vault, rate) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    let result = match matches.value_of("token-file") {
+        Some(path) => {
+            create_private_file(path).and_then(|mut file| writeln!(file, "{}", server.token()))
+        }
+        None => {
+            println!("{}", server.token());
+            io::stdout().flush()
+        }
+    };
+    if let Err(e) = result {
+        println!("{:?}", e);
+        return;
+    }
+    if let Ok(address) = server.local_addr() {
+        let _ = writeln!(io::stderr(), "Listening on http://{}/v1/", address);
+    }
+    protect_memory_or_warn();
+    if let Err(e) = server.run() {
+        let _ = writeln!(io::stderr(), "{:?}", e);
+    }
+}
+
+fn run_ssh_agent<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let socket_path = match matches.value_of("socket") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            match ssh_agent::default_socket_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    println!("{:?}", e);
+                    return;
+                }
+            }
+        }
+    };
+    let (identities, errors) = match open_vault(matches, Access::Details) {
+        Some(vault) => ssh_agent::load_identities(&vault),
+        None => return,
+    };
+    for error in errors {
+        let _ = writeln!(io::stderr(), "Skipped {}", error);
+    }
+    if matches.is_present("list") {
+        for identity in &identities {
+            println!("{}", identity.public_key_line());
+        }
+        return;
+    }
+    if identities.is_empty() {
+        println!("No private keys found in the vault");
+        return;
+    }
+    // Only the keys are kept from here on; the vault itself is locked again.
+    let server = match ssh_agent::Server::bind(&socket_path,
+                                               identities,
+                                               matches.is_present("confirm")) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    if !matches.is_present("foreground") {
+        match agent::server::daemonize() {
+            Ok(Some(pid)) => {
+                println!("{}={}; export {};", ssh_agent::SOCKET_ENV, socket_path.display(),
+                         ssh_agent::SOCKET_ENV);
+                println!("SSH_AGENT_PID={}; export SSH_AGENT_PID;", pid);
+                let _ = writeln!(io::stderr(), "Agent {} started", pid);
+                // The agent owns the socket now, so leave it in place.
+                mem::forget(server);
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                println!("{:?}", e);
+                return;
+            }
+        }
+    }
+    protect_memory_or_warn();
+    if let Err(e) = server.run() {
+        let _ = writeln!(io::stderr(), "{:?}", e);
+    }
+}
+
+fn totp<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    let query = matches.value_of("item").unwrap_or("");
+    let result = vault.find_item(query).and_then(|item| {
+        let details = try!(vault.item_details(&item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let secret = try!(details.totp().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound,
+                           format!("item {} has no one-time password", item.uuid()))
+        }));
+        totp::Otp::parse(&secret)
+    });
+    match result {
+        Ok(otp) => {
+            let now = now();
+            // Only the code goes to stdout so that scripts can capture it.
+            println!("{}", otp.code(now));
+            if let Some(remaining) = otp.remaining(now) {
+                let _ = writeln!(io::stderr(), "{}s remaining", remaining);
+            }
+        }
+        Err(e) => println!("{:?}", e),
+    }
+}
+
+const DEFAULT_TUI_TIMEOUT_SECS: u64 = 5 * 60;
+
+fn tui<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    let timeout = match matches.value_of("timeout").map(|secs| secs.parse()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => {
+            println!("Invalid timeout: {}", matches.value_of("timeout").unwrap_or(""));
+            return;
+        }
+        None => DEFAULT_TUI_TIMEOUT_SECS,
+    };
+    let vault = match open_vault(matches, Access::Details) {
+        Some(vault) => vault,
+        None => return,
+    };
+    protect_memory_or_warn();
+    match tui::run(vault, Duration::from_secs(timeout)) {
+        Ok(true) => println!("Locked after {} seconds without input", timeout),
+        Ok(false) => {}
+        Err(e) => println!("{:?}", e),
+    }
+}
+
+fn unlock_vault<'n, 'a>(matches: &ArgMatches<'n, 'a>) {
+    if open_vault(matches, Access::Overviews).is_some() {
+        println!("Unlocked");
+    }
+}
+
+/// How much of a vault a command needs decrypted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    /// Folders and item overviews: titles, usernames, URLs and tags. The master key stays
+    /// encrypted.
+    Overviews,
+    /// Item details and attachments as well.
+    Details,
+}
+
+/// Opens the vault at `--path` (or the default location) and unlocks it for `access` with keys
+/// from a running agent, or else with a password read from the terminal. Prompts and errors go
+/// to stderr so that stdout stays clean for exported data.
+fn open_vault<'n, 'a>(matches: &ArgMatches<'n, 'a>, access: Access) -> Option<Vault> {
+    let locked_vault = match open_locked_vault(matches) {
+        Some(locked_vault) => locked_vault,
+        None => return None,
+    };
+    let vault = match access {
+        Access::Overviews => agent::client::unlock_overview(&locked_vault),
+        Access::Details => agent::client::unlock(&locked_vault),
+    };
+    vault.or_else(|| unlock_with_password(&locked_vault, access))
+}
+
+fn open_locked_vault<'n, 'a>(matches: &ArgMatches<'n, 'a>) -> Option<LockedVault> {
+    let path = get_vault_path(matches);
+    match LockedVault::new(&path) {
+        Ok(locked_vault) => Some(locked_vault),
+        Err(_) => {
+            let _ = writeln!(io::stderr(), "Could not open vault at {:?}", path);
+            None
+        }
+    }
+}
+
+fn unlock_with_password(locked_vault: &LockedVault, access: Access) -> Option<Vault> {
+    let _ = write!(io::stderr(), "Enter password for vault: ");
+    let _ = io::stderr().flush();
+    let password = rpassword::read_password().unwrap();
+    let vault = match access {
+        Access::Overviews => locked_vault.unlock_overview(&password),
+        Access::Details => locked_vault.unlock(&password),
+    };
+    if vault.is_none() {
+        let _ = writeln!(io::stderr(), "Failed to unlock");
+    }
+    vault
+}
+
+/// Reads a password for a file to be created, asking twice to catch typos. Returns `None` if
+/// the two differ, which has been reported.
+fn read_new_password(what: &str) -> IoResult<Option<String>> {
+    let _ = write!(io::stderr(), "Enter new password for {}: ", what);
+    let _ = io::stderr().flush();
+    let password = try!(rpassword::read_password());
+    let _ = write!(io::stderr(), "Confirm password: ");
+    let _ = io::stderr().flush();
+    if try!(rpassword::read_password()) != password {
+        let _ = writeln!(io::stderr(), "Passwords do not match");
+        return Ok(None);
+    }
+    Ok(Some(password))
+}
+
+/// Keeps unlocked secrets out of swap and core dumps, warning if that is not possible.
+fn protect_memory_or_warn() {
+    if let Err(e) = agent::server::protect_memory() {
+        let _ = writeln!(io::stderr(), "Could not lock memory: {}", e);
+    }
+}
+
+fn get_vault_path<'n, 'a>(matches: &ArgMatches<'n, 'a>) -> PathBuf {
+    matches.value_of("path")
+        .and_then(|s| Some(PathBuf::from(s)))
+        .unwrap_or_else(|| get_default_opvault_path())
+}
+
+/// Runs `f` with a writer for `--output`, or stdout if it is not given. Output files are
+/// created readable by the owner only since they usually contain plaintext secrets.
+fn with_output<'n, 'a, F>(matches: &ArgMatches<'n, 'a>, f: F) -> IoResult<()>
+    where F: FnOnce(&mut Write) -> IoResult<()>
+{
+    match matches.value_of("output") {
+        Some(path) => {
+            let mut file = try!(create_private_file(path));
+            f(&mut file)
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            f(&mut lock)
+        }
     }
 }
 
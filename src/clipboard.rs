@@ -0,0 +1,223 @@
+//! Putting secrets on the system clipboard and taking them off again.
+//!
+//! Text is copied with the OSC 52 escape sequence, which the terminal hands on to the clipboard,
+//! or with the command in `$DECONE_CLIPBOARD_COPY`, such as `wl-copy` or
+//! `xclip -selection clipboard`, which reads the text on stdin.
+//!
+//! Clearing must not throw away something copied since, so the clipboard is read back first:
+//! with the command in `$DECONE_CLIPBOARD_PASTE`, such as `wl-paste -n` or
+//! `xclip -selection clipboard -o`, or else by asking the terminal with OSC 52, which most
+//! terminals refuse. A clipboard that cannot be read is left alone, so a paste command is needed
+//! for the clipboard to be cleared reliably.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Result as IoResult, Write};
+use std::os::unix::io::AsRawFd;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use tui::terminal::{self, RawMode};
+
+pub const COPY_ENV: &'static str = "DECONE_CLIPBOARD_COPY";
+pub const PASTE_ENV: &'static str = "DECONE_CLIPBOARD_PASTE";
+
+/// How long to wait for the terminal to answer an OSC 52 query.
+const QUERY_TIMEOUT_MILLIS: u64 = 1000;
+
+/// What `Clipboard::clear_if` found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clearing {
+    /// The clipboard held the text and was cleared.
+    Cleared,
+    /// The clipboard holds something else and was left alone.
+    Replaced,
+    /// The clipboard could not be read and was left alone.
+    Unreadable,
+}
+
+pub struct Clipboard {
+    /// `None` to copy with OSC 52.
+    copy_command: Option<Vec<String>>,
+    paste_command: Option<Vec<String>>,
+    /// Whether OSC 52 sequences must be wrapped for tmux.
+    tmux: bool,
+}
+
+impl Clipboard {
+    /// Returns the clipboard `$DECONE_CLIPBOARD_COPY` and `$DECONE_CLIPBOARD_PASTE` configure.
+    pub fn from_env() -> Clipboard {
+        let command = |name| {
+            env::var(name)
+                .ok()
+                .map(|command| split_command(&command))
+                .and_then(|command| if command.is_empty() { None } else { Some(command) })
+        };
+        Clipboard {
+            copy_command: command(COPY_ENV),
+            paste_command: command(PASTE_ENV),
+            tmux: env::var_os("TMUX").is_some(),
+        }
+    }
+
+    /// Returns whether a paste command reads the clipboard back. Without one, `clear_if` depends
+    /// on the terminal answering an OSC 52 query, or cannot read the clipboard at all.
+    pub fn has_paste_command(&self) -> bool {
+        self.paste_command.is_some()
+    }
+
+    pub fn copy(&self, text: &str) -> IoResult<()> {
+        match self.copy_command {
+            Some(ref command) => run_copy_command(command, text),
+            None => write_terminal(&terminal::osc52(text, self.tmux)),
+        }
+    }
+
+    /// Returns what the clipboard holds, or `None` if it cannot be read.
+    pub fn paste(&self) -> IoResult<Option<String>> {
+        match (&self.paste_command, &self.copy_command) {
+            (&Some(ref command), _) => run_paste_command(command).map(Some),
+            // The terminal only knows what went through it.
+            (&None, &None) => query_terminal(self.tmux),
+            (&None, &Some(_)) => Ok(None),
+        }
+    }
+
+    /// Clears the clipboard if it still holds `text`.
+    pub fn clear_if(&self, text: &str) -> IoResult<Clearing> {
+        let held = match try!(self.paste()) {
+            Some(held) => held,
+            None => return Ok(Clearing::Unreadable),
+        };
+        // Paste commands such as `wl-paste` add a newline unless told not to.
+        if held != text && held.trim_right_matches('\n') != text {
+            return Ok(Clearing::Replaced);
+        }
+        try!(self.copy(""));
+        Ok(Clearing::Cleared)
+    }
+}
+
+/// Splits a configured command into its program and arguments at whitespace.
+fn split_command(command: &str) -> Vec<String> {
+    command.split_whitespace().map(|s| s.to_string()).collect()
+}
+
+fn run_copy_command(command: &[String], text: &str) -> IoResult<()> {
+    let mut child = try!(Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn());
+    {
+        let stdin = try!(child.stdin.as_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "no stdin for the copy command")
+        }));
+        try!(stdin.write_all(text.as_bytes()));
+    }
+    // Close stdin so that the command sees the end of the text.
+    drop(child.stdin.take());
+    let status = try!(child.wait());
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+                                  format!("{} failed with {}", command[0], status)));
+    }
+    Ok(())
+}
+
+fn run_paste_command(command: &[String]) -> IoResult<String> {
+    let output = try!(Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output());
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+                                  format!("{} failed with {}", command[0], output.status)));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `sequence` to the controlling terminal, which is the terminal even when stdout is
+/// redirected.
+fn write_terminal(sequence: &str) -> IoResult<()> {
+    let mut tty = try!(OpenOptions::new().write(true).open("/dev/tty").map_err(|_| {
+        io::Error::new(io::ErrorKind::NotFound,
+                       format!("no terminal to copy to; set {} to a copy command", COPY_ENV))
+    }));
+    try!(tty.write_all(sequence.as_bytes()));
+    tty.flush()
+}
+
+/// Asks the terminal what the clipboard holds, returning `None` if it does not answer.
+fn query_terminal(tmux: bool) -> IoResult<Option<String>> {
+    let mut tty = match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => return Ok(None),
+    };
+    // Keep the answer from being echoed or held back until a newline.
+    let _raw_mode = try!(RawMode::enable(tty.as_raw_fd()));
+    try!(tty.write_all(terminal::osc52_query(tmux).as_bytes()));
+    try!(tty.flush());
+    let deadline = Instant::now() + Duration::from_millis(QUERY_TIMEOUT_MILLIS);
+    let mut reply = Vec::new();
+    loop {
+        match terminal::parse_osc52_reply(&reply) {
+            // Terminals that refuse to tell answer with nothing.
+            Some(ref text) if text.is_empty() => return Ok(None),
+            Some(text) => return Ok(Some(text)),
+            None => {}
+        }
+        let now = Instant::now();
+        if now >= deadline || !try!(terminal::wait_for_input(tty.as_raw_fd(), deadline - now)) {
+            return Ok(None);
+        }
+        let mut buf = [0; 256];
+        let n = try!(tty.read(&mut buf));
+        if n == 0 {
+            return Ok(None);
+        }
+        reply.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use op_vault::uuid;
+    use super::{split_command, Clearing, Clipboard};
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command(" xclip  -selection clipboard "),
+                   vec!["xclip", "-selection", "clipboard"]);
+        assert!(split_command("  ").is_empty());
+    }
+
+    #[test]
+    fn test_clear_if() {
+        let path = env::temp_dir().join(format!("decone-test-clipboard-{}", uuid::generate()));
+        let path = path.to_string_lossy().into_owned();
+        let clipboard = Clipboard {
+            copy_command: Some(vec!["tee".to_string(), path.clone()]),
+            paste_command: Some(vec!["cat".to_string(), path.clone()]),
+            tmux: false,
+        };
+        clipboard.copy("hunter2").unwrap();
+        assert_eq!(clipboard.paste().unwrap(), Some("hunter2".to_string()));
+        assert_eq!(clipboard.clear_if("hunter2").unwrap(), Clearing::Cleared);
+        assert_eq!(clipboard.paste().unwrap(), Some(String::new()));
+
+        clipboard.copy("hunter2").unwrap();
+        File::create(&path).unwrap().write_all(b"copied since\n").unwrap();
+        assert_eq!(clipboard.clear_if("hunter2").unwrap(), Clearing::Replaced);
+        assert_eq!(clipboard.paste().unwrap(), Some("copied since\n".to_string()));
+
+        let clipboard = Clipboard { paste_command: None, ..clipboard };
+        assert_eq!(clipboard.clear_if("copied since").unwrap(), Clearing::Unreadable);
+        fs::remove_file(&path).unwrap();
+    }
+}
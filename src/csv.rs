@@ -0,0 +1,549 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Result as IoResult, Write};
+
+use import::{self, ImportAction};
+use iso8601;
+use one_pif;
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::folder::Folder;
+use op_vault::item::{Item, NewItem};
+use op_vault::uuid;
+use op_vault::vault::Vault;
+
+/// The item attributes that CSV columns can be mapped to.
+pub const FIELDS: &'static [&'static str] = &["title", "url", "username", "password", "notes",
+                                              "otp", "created", "updated"];
+
+/// The columns that `export` can write.
+pub const EXPORT_COLUMNS: &'static [&'static str] = &["title", "url", "username", "password",
+                                                      "otp", "notes", "folder", "tags",
+                                                      "category", "created", "updated"];
+
+/// The columns that `export` writes unless told otherwise.
+pub const DEFAULT_EXPORT_COLUMNS: &'static [&'static str] = &["title", "url", "username",
+                                                              "password", "notes", "folder"];
+
+/// Characters that make spreadsheets treat a field as a formula.
+const FORMULA_PREFIXES: &'static [char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// The column layout of a password export from a browser.
+pub struct Preset {
+    pub name: &'static str,
+    /// Columns that identify the layout, as pairs of a field and a column name.
+    required: &'static [(&'static str, &'static str)],
+    /// Columns that only some versions of the browser write.
+    optional: &'static [(&'static str, &'static str)],
+}
+
+pub const PRESETS: &'static [Preset] = &[
+    Preset {
+        name: "chrome",
+        required: &[("title", "name"),
+                    ("url", "url"),
+                    ("username", "username"),
+                    ("password", "password")],
+        optional: &[("notes", "note")],
+    },
+    Preset {
+        name: "firefox",
+        required: &[("url", "url"),
+                    ("username", "username"),
+                    ("password", "password"),
+                    ("created", "timeCreated")],
+        optional: &[("updated", "timePasswordChanged")],
+    },
+    Preset {
+        name: "safari",
+        required: &[("title", "Title"),
+                    ("url", "URL"),
+                    ("username", "Username"),
+                    ("password", "Password")],
+        optional: &[("notes", "Notes"), ("otp", "OTPAuth")],
+    },
+];
+
+impl Preset {
+    /// Returns the preset called `name`. Edge writes the same layout as Chrome.
+    pub fn from_name(name: &str) -> Option<&'static Preset> {
+        let name = if name == "edge" { "chrome" } else { name };
+        PRESETS.iter().find(|preset| preset.name == name)
+    }
+
+    /// Returns the preset whose columns `header` has.
+    pub fn detect(header: &[String]) -> Option<&'static Preset> {
+        // The presets differ in their title or date columns, and names are compared ignoring
+        // case, so only a header with the columns of several presets matches more than one. The
+        // preset with the most columns wins then.
+        PRESETS.iter()
+            .filter(|preset| {
+                preset.required.iter().all(|&(_, name)| find_column(header, name).is_some())
+            })
+            .max_by_key(|preset| preset.required.len() + preset.optional.len())
+    }
+
+    pub fn columns(&self, header: &[String]) -> IoResult<Columns> {
+        let present_optional = self.optional
+            .iter()
+            .filter(|&&(_, name)| find_column(header, name).is_some());
+        let mapping: Vec<(String, String)> = self.required
+            .iter()
+            .chain(present_optional)
+            .map(|&(field, name)| (field.to_string(), name.to_string()))
+            .collect();
+        Columns::new(header, &mapping)
+    }
+}
+
+/// Where the item attributes are in a row.
+#[derive(Clone, Debug)]
+pub struct Columns {
+    indexes: BTreeMap<String, usize>,
+}
+
+impl Columns {
+    /// Maps fields to the columns of `header` named in `mapping`, as pairs of a field and a
+    /// column name. Column names are matched case-insensitively.
+    pub fn new(header: &[String], mapping: &[(String, String)]) -> IoResult<Self> {
+        let mut indexes = BTreeMap::new();
+        for &(ref field, ref name) in mapping {
+            if !FIELDS.contains(&&field[..]) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("unknown field: {}", field)));
+            }
+            let index = try!(find_column(header, name).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("no such column: {}", name))
+            }));
+            indexes.insert(field.clone(), index);
+        }
+        Ok(Columns { indexes: indexes })
+    }
+
+    /// Returns the non-empty value of `field` in `row`.
+    pub fn get<'a>(&self, row: &'a [String], field: &str) -> Option<&'a str> {
+        self.indexes
+            .get(field)
+            .and_then(|&i| row.get(i))
+            .map(|s| s.trim())
+            .and_then(|s| if s.is_empty() { None } else { Some(s) })
+    }
+}
+
+/// Parses a column mapping such as `title=Name,url=Website` into pairs of a field and a
+/// column name.
+pub fn parse_mapping(spec: &str) -> IoResult<Vec<(String, String)>> {
+    spec.split(',')
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(field), Some(name)) => {
+                    Ok((field.trim().to_string(), name.trim().to_string()))
+                }
+                _ => {
+                    Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       format!("expected field=column: {}", pair)))
+                }
+            }
+        })
+        .collect()
+}
+
+fn find_column(header: &[String], name: &str) -> Option<usize> {
+    header.iter().position(|column| column.trim().to_lowercase() == name.to_lowercase())
+}
+
+/// Reads RFC 4180 CSV into rows of fields. Quoted fields may contain commas, quotes written
+/// twice and line breaks, and a leading byte order mark is ignored.
+pub fn read_rows<R: Read + ?Sized>(reader: &mut R) -> IoResult<Vec<Vec<String>>> {
+    let mut text = String::new();
+    try!(reader.read_to_string(&mut text));
+    let text = text.trim_left_matches('\u{feff}');
+
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ',' => row.push(::std::mem::replace(&mut field, String::new())),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(::std::mem::replace(&mut field, String::new()));
+                rows.push(::std::mem::replace(&mut row, Vec::new()));
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unterminated quoted field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    // Blank lines carry no record.
+    rows.retain(|row| row.len() > 1 || row.get(0).map_or(false, |f| !f.is_empty()));
+    Ok(rows)
+}
+
+/// Works out how to add `rows` to `vault` as logins without changing it.
+///
+/// Rows whose URL and username match an existing login are skipped, comparing against the
+/// overviews, whose `ainfo` is the username of a login, so that no item has to be decrypted.
+/// Rows repeated within the file are skipped too.
+pub fn plan_import(vault: &Vault, rows: &[Vec<String>], columns: &Columns) -> Vec<ImportAction> {
+    let mut existing: BTreeMap<(String, String), String> = BTreeMap::new();
+    for item in vault.items() {
+        if item.category() != Category::Login || item.is_trashed() {
+            continue;
+        }
+        for url in item.urls() {
+            let username = item.ainfo().unwrap_or("").to_string();
+            existing.insert((normalize_url(&url), username), item.uuid().to_string());
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut actions = Vec::new();
+    for row in rows {
+        let url = columns.get(row, "url");
+        let username = columns.get(row, "username");
+        let title = columns.get(row, "title")
+            .map(|title| title.to_string())
+            .or_else(|| url.and_then(one_pif::location_key))
+            .unwrap_or_else(String::new);
+        if url.is_none() && username.is_none() && columns.get(row, "password").is_none() {
+            actions.push(ImportAction::Skip {
+                reason: "no URL, username or password".to_string(),
+                title: title,
+                uuid: "-".to_string(),
+            });
+            continue;
+        }
+        let key = (url.map(normalize_url).unwrap_or_else(String::new),
+                   username.unwrap_or("").to_string());
+        if let Some(uuid) = existing.get(&key) {
+            actions.push(ImportAction::Skip {
+                reason: "already in vault".to_string(),
+                title: title,
+                uuid: uuid.clone(),
+            });
+            continue;
+        }
+        if !seen.insert(key) {
+            actions.push(ImportAction::Skip {
+                reason: "duplicate in file".to_string(),
+                title: title,
+                uuid: "-".to_string(),
+            });
+            continue;
+        }
+        actions.push(ImportAction::CreateItem(new_item(row, columns, &title)));
+    }
+    actions
+}
+
+fn new_item(row: &[String], columns: &Columns, title: &str) -> NewItem {
+    let mut details = BTreeMap::new();
+    let mut fields = Vec::new();
+    if let Some(username) = columns.get(row, "username") {
+        fields.push(import::login_field("username", "T", username));
+    }
+    if let Some(password) = columns.get(row, "password") {
+        fields.push(import::login_field("password", "P", password));
+    }
+    details.insert("fields".to_string(), Value::Array(fields));
+    if let Some(notes) = columns.get(row, "notes") {
+        details.insert("notesPlain".to_string(), Value::String(notes.to_string()));
+    }
+    if let Some(otp) = columns.get(row, "otp") {
+        let field = import::section_field(&format!("TOTP_{}", uuid::generate()),
+                                          "one-time password",
+                                          "concealed",
+                                          otp);
+        details.insert("sections".to_string(),
+                       Value::Array(vec![import::custom_section(vec![field])]));
+    }
+    let mut new_item = NewItem::new(Category::Login, title, Value::Object(details));
+    new_item.created = columns.get(row, "created").and_then(parse_time);
+    new_item.updated = columns.get(row, "updated").and_then(parse_time).or(new_item.created);
+    new_item.urls = columns.get(row, "url").into_iter().map(|url| url.to_string()).collect();
+    new_item
+}
+
+/// Compares URLs loosely, ignoring case and a trailing slash.
+fn normalize_url(url: &str) -> String {
+    url.trim().trim_right_matches('/').to_lowercase()
+}
+
+/// Parses a time as Unix time in seconds or, as Firefox writes, in milliseconds, or as
+/// ISO 8601.
+fn parse_time(s: &str) -> Option<u64> {
+    match s.parse::<u64>() {
+        // Milliseconds pass 10^11 in 1973, while seconds will not for thousands of years.
+        Ok(time) if time >= 100000000000 => Some(time / 1000),
+        Ok(time) => Some(time),
+        Err(_) => iso8601::parse(s),
+    }
+}
+
+/// Selects the items that `export` writes. Empty lists select everything.
+pub struct ExportFilter {
+    /// Category names such as `Login` or `Credit Card`, or type names such as
+    /// `wallet.financial.CreditCard`, compared case-insensitively.
+    pub categories: Vec<String>,
+    /// Folder titles, slash-separated paths or UUIDs. Items in subfolders are selected too.
+    pub folders: Vec<String>,
+}
+
+impl ExportFilter {
+    pub fn new() -> Self {
+        ExportFilter {
+            categories: Vec::new(),
+            folders: Vec::new(),
+        }
+    }
+
+    fn matches(&self, item: &Item, folders: &[Folder]) -> bool {
+        let category = item.category();
+        if !self.categories.is_empty() &&
+           !self.categories.iter().any(|name| {
+            let name = name.to_lowercase();
+            name == category.name().to_lowercase() || name == category.type_name().to_lowercase()
+        }) {
+            return false;
+        }
+        if self.folders.is_empty() {
+            return true;
+        }
+        // Walks up from the folder of the item so that subfolders are selected with their
+        // ancestors.
+        let mut uuid = item.folder();
+        let mut depth = 0;
+        while let Some(folder) = uuid.and_then(|uuid| folders.iter().find(|f| f.uuid() == uuid)) {
+            let path = folder.path(folders);
+            if self.folders
+                .iter()
+                .any(|f| *f == folder.uuid() || *f == folder.title() || *f == path) {
+                return true;
+            }
+            depth += 1;
+            if depth > folders.len() {
+                break;
+            }
+            uuid = folder.parent();
+        }
+        false
+    }
+}
+
+/// Writes the items of `vault` selected by `filter` as CSV with a header row and the given
+/// columns, which must be among `EXPORT_COLUMNS`.
+///
+/// Passwords and other secrets are written in plain text. Trashed items and tombstones are left
+/// out. With `escape_formulas`, fields that a spreadsheet would evaluate are escaped as
+/// `write_row` describes.
+pub fn export<W: Write + ?Sized>(vault: &Vault,
+                                 writer: &mut W,
+                                 columns: &[String],
+                                 filter: &ExportFilter,
+                                 escape_formulas: bool)
+                                 -> IoResult<()> {
+    if let Some(column) = columns.iter().find(|c| !EXPORT_COLUMNS.contains(&&c[..])) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("unknown column {} (expected one of {})",
+                                          column,
+                                          EXPORT_COLUMNS.join(", "))));
+    }
+    let folders: Vec<Folder> = vault.folders().into_iter().filter(|f| !f.is_smart()).collect();
+    try!(write_row(writer, columns, escape_formulas));
+    for item in vault.items() {
+        if item.category() == Category::Tombstone || item.is_trashed() ||
+           !filter.matches(&item, &folders) {
+            continue;
+        }
+        let details = try!(vault.item_details(&item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let row: Vec<String> = columns.iter()
+            .map(|column| export_field(&item, &details, &folders, column))
+            .collect();
+        try!(write_row(writer, &row, escape_formulas));
+    }
+    Ok(())
+}
+
+fn export_field(item: &Item, details: &ItemDetails, folders: &[Folder], column: &str) -> String {
+    match column {
+        "title" => item.title().unwrap_or("").to_string(),
+        "url" => item.url().unwrap_or("").to_string(),
+        "username" => details.username().unwrap_or_else(String::new),
+        "password" => details.password().unwrap_or_else(String::new),
+        "otp" => details.totp().unwrap_or_else(String::new),
+        "notes" => details.notes().unwrap_or("").to_string(),
+        "folder" => {
+            item.folder()
+                .and_then(|uuid| folders.iter().find(|f| f.uuid() == uuid))
+                .map(|folder| folder.path(folders))
+                .unwrap_or_else(String::new)
+        }
+        "tags" => item.tags().join(", "),
+        "category" => item.category().name().to_string(),
+        "created" => iso8601::format(item.created()),
+        "updated" => iso8601::format(item.updated()),
+        _ => String::new(),
+    }
+}
+
+/// Writes a CSV record as RFC 4180 describes, quoting fields that contain commas, quotes or line
+/// breaks, or that start or end with spaces, which some spreadsheets would trim.
+///
+/// With `escape_formulas`, fields starting with `=`, `+`, `-`, `@`, a tab or a carriage return
+/// are prefixed with `'` so that spreadsheets show them as text instead of running them as
+/// formulas.
+pub fn write_row<W: Write + ?Sized, S: AsRef<str>>(writer: &mut W,
+                                                  row: &[S],
+                                                  escape_formulas: bool)
+                                                  -> IoResult<()> {
+    let fields: Vec<String> = row.iter()
+        .map(|field| {
+            let field = field.as_ref();
+            let escaped;
+            let field = if escape_formulas &&
+                           field.starts_with(|c| FORMULA_PREFIXES.contains(&c)) {
+                escaped = format!("'{}", field);
+                &escaped[..]
+            } else {
+                field
+            };
+            if field.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') ||
+               field.starts_with(' ') || field.ends_with(' ') {
+                format!("\"{}\"", field.replace("\"", "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    write!(writer, "{}\r\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use import::ImportAction;
+    use testing::sample_vault;
+    use super::{DEFAULT_EXPORT_COLUMNS, ExportFilter, Preset, export, plan_import, read_rows,
+                write_row};
+
+    #[test]
+    fn test_read_rows() {
+        let csv = "\u{feff}a,\"b,\"\"c\"\"\",d\r\n\r\n\"multi\nline\",,\n";
+        let rows = read_rows(&mut csv.as_bytes()).unwrap();
+        assert_eq!(rows,
+                   vec![vec!["a", "b,\"c\"", "d"], vec!["multi\nline", "", ""]]
+                       .into_iter()
+                       .map(|row| row.into_iter().map(|f| f.to_string()).collect())
+                       .collect::<Vec<Vec<String>>>());
+        assert!(read_rows(&mut "\"open".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_plan_import() {
+        let vault = sample_vault();
+        let hulu = vault.item("13C8E12AC8E54B1F873BAB0824E521BC").unwrap();
+        let csv = format!("\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\
+                           \"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+                           {url},{user},x,,,{{a}},1488603967000,,1488603968000\n\
+                           https://new.example.com/,me,pw,,,{{b}},1488603967000,,\n\
+                           https://new.example.com,me,pw,,,{{c}},1488603967000,,\n",
+                          url = hulu.url().unwrap(),
+                          user = hulu.ainfo().unwrap());
+        let rows = read_rows(&mut csv.as_bytes()).unwrap();
+        let preset = Preset::detect(&rows[0]).unwrap();
+        assert_eq!(preset.name, "firefox");
+        let actions = plan_import(&vault, &rows[1..], &preset.columns(&rows[0]).unwrap());
+        match actions[0] {
+            ImportAction::Skip { ref uuid, .. } => assert_eq!(uuid, hulu.uuid()),
+            _ => panic!("existing login must be skipped"),
+        }
+        match actions[1] {
+            ImportAction::CreateItem(ref new_item) => {
+                assert_eq!(new_item.title, "new.example.com");
+                assert_eq!(new_item.created, Some(1488603967));
+                assert_eq!(new_item.urls, vec!["https://new.example.com/"]);
+            }
+            _ => panic!("new login must be created"),
+        }
+        match actions[2] {
+            ImportAction::Skip { ref reason, .. } => assert_eq!(reason, "duplicate in file"),
+            _ => panic!("repeated row must be skipped"),
+        }
+    }
+
+    #[test]
+    fn test_write_row() {
+        let mut bytes = Vec::new();
+        write_row(&mut bytes,
+                  &["plain", "a,b", "say \"hi\"", "two\nlines", " padded", ""],
+                  true)
+            .unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(),
+                   "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\" padded\",\r\n");
+    }
+
+    #[test]
+    fn test_write_row_formulas() {
+        let row = ["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx", "a=b"];
+        let mut bytes = Vec::new();
+        write_row(&mut bytes, &row, true).unwrap();
+        let csv = String::from_utf8(bytes).unwrap();
+        assert_eq!(csv, "'=1+1,'+1,'-1,'@SUM(A1),'\tx,\"'\rx\",a=b\r\n");
+        // Reading takes the fields as written.
+        assert_eq!(read_rows(&mut csv.as_bytes()).unwrap()[0],
+                   vec!["'=1+1", "'+1", "'-1", "'@SUM(A1)", "'\tx", "'\rx", "a=b"]);
+
+        let mut bytes = Vec::new();
+        write_row(&mut bytes, &row, false).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(),
+                   "=1+1,+1,-1,@SUM(A1),\tx,\"\rx\",a=b\r\n");
+    }
+
+    #[test]
+    fn test_export() {
+        let vault = sample_vault();
+        let columns: Vec<String> = DEFAULT_EXPORT_COLUMNS.iter().map(|c| c.to_string()).collect();
+        let mut filter = ExportFilter::new();
+        filter.categories.push("login".to_string());
+        let mut bytes = Vec::new();
+        export(&vault, &mut bytes, &columns, &filter, true).unwrap();
+        let csv = String::from_utf8(bytes).unwrap();
+        let rows = read_rows(&mut csv.as_bytes()).unwrap();
+        assert_eq!(rows[0], columns);
+        let hulu = rows.iter().find(|row| row[0] == "Hulu").unwrap();
+        assert!(!hulu[2].is_empty());
+        assert!(!hulu[3].is_empty());
+        // Only logins are selected.
+        let logins = vault.items()
+            .into_iter()
+            .filter(|item| item.category().name() == "Login" && !item.is_trashed())
+            .count();
+        assert_eq!(rows.len(), logins + 1);
+
+        let bad_columns = vec!["title".to_string(), "secret".to_string()];
+        assert!(export(&vault, &mut Vec::new(), &bad_columns, &filter, true).is_err());
+    }
+}
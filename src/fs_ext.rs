@@ -0,0 +1,25 @@
+//! Creating files that may hold secrets.
+
+use std::fs::{DirBuilder, File, OpenOptions, Permissions};
+use std::io::Result as IoResult;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// Opens `path` for writing with permissions for the owner only, tightening them if the file
+/// already exists.
+pub fn create_private_file<P: AsRef<Path>>(path: P) -> IoResult<File> {
+    let file = try!(OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path));
+    try!(file.set_permissions(Permissions::from_mode(0o600)));
+    Ok(file)
+}
+
+/// Creates `path` and its missing parents with permissions for the owner only. Directories that
+/// exist already are left as they are.
+pub fn create_private_dir_all<P: AsRef<Path>>(path: P) -> IoResult<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
@@ -0,0 +1,279 @@
+//! Random passwords made to a recipe, in the manner of the 1Password generator.
+
+use openssl::crypto::rand;
+use std::io::{self, Result as IoResult};
+
+/// The words for `Mode::Words`, one per line.
+const WORDLIST: &'static str = include_str!("wordlist.txt");
+
+const LOWERCASE: &'static str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &'static str = "0123456789";
+const SYMBOLS: &'static str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Characters that are easily mistaken for one another in some fonts.
+pub const AMBIGUOUS: &'static str = "0O1Il|`'\"";
+
+const CONSONANTS: &'static str = "bcdfghjklmnprstvwz";
+const VOWELS: &'static str = "aeiou";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Random characters from the enabled classes.
+    Characters,
+    /// Syllables of a consonant and a vowel, sometimes followed by another consonant.
+    Syllables,
+    /// Words from the embedded wordlist.
+    Words,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chars" | "characters" => Some(Mode::Characters),
+            "syllables" | "pronounceable" => Some(Mode::Syllables),
+            "words" | "diceware" => Some(Mode::Words),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digits,
+    Symbols,
+}
+
+impl CharClass {
+    pub fn chars(&self) -> &'static str {
+        match *self {
+            CharClass::Lowercase => LOWERCASE,
+            CharClass::Uppercase => UPPERCASE,
+            CharClass::Digits => DIGITS,
+            CharClass::Symbols => SYMBOLS,
+        }
+    }
+}
+
+/// Describes the passwords to generate.
+#[derive(Clone, Debug)]
+pub struct Recipe {
+    pub mode: Mode,
+    /// The number of characters, syllables or words.
+    pub length: usize,
+    /// The character classes to draw from in `Mode::Characters`, each with the least number of
+    /// characters the password must have from it.
+    pub classes: Vec<(CharClass, usize)>,
+    /// Leaves out the characters of `AMBIGUOUS`.
+    pub exclude_ambiguous: bool,
+    /// Characters never to use.
+    pub exclude: String,
+    /// Goes between syllables or words.
+    pub separator: String,
+    /// Capitalizes the first letter of each syllable or word.
+    pub capitalize: bool,
+}
+
+impl Recipe {
+    /// A recipe for 20 characters of any class.
+    pub fn new() -> Self {
+        Recipe {
+            mode: Mode::Characters,
+            length: 20,
+            classes: vec![(CharClass::Lowercase, 0),
+                          (CharClass::Uppercase, 0),
+                          (CharClass::Digits, 0),
+                          (CharClass::Symbols, 0)],
+            exclude_ambiguous: false,
+            exclude: String::new(),
+            separator: "-".to_string(),
+            capitalize: false,
+        }
+    }
+
+    /// A recipe for `count` words separated by hyphens.
+    pub fn words(count: usize) -> Self {
+        let mut recipe = Recipe::new();
+        recipe.mode = Mode::Words;
+        recipe.length = count;
+        recipe
+    }
+
+    fn allows(&self, c: char) -> bool {
+        !self.exclude.contains(c) && !(self.exclude_ambiguous && AMBIGUOUS.contains(c))
+    }
+
+    /// Returns the characters of `class` that the recipe allows.
+    fn pool(&self, class: CharClass) -> Vec<char> {
+        class.chars().chars().filter(|&c| self.allows(c)).collect()
+    }
+
+    /// Estimates the entropy in bits of the passwords the recipe makes, ignoring the small loss
+    /// from required class counts.
+    pub fn entropy(&self) -> f64 {
+        let choices = match self.mode {
+            Mode::Characters => {
+                self.classes.iter().map(|&(class, _)| self.pool(class).len()).sum::<usize>() as f64
+            }
+            Mode::Syllables => {
+                let consonants = CONSONANTS.len() as f64;
+                // Each syllable is either open or closed by one more consonant.
+                consonants * VOWELS.len() as f64 * (consonants + 1.0)
+            }
+            Mode::Words => wordlist().len() as f64,
+        };
+        choices.log2() * self.length as f64
+    }
+}
+
+/// Generates a password to `recipe`.
+pub fn generate(recipe: &Recipe) -> IoResult<String> {
+    if recipe.length == 0 {
+        return Err(invalid_input("length must be positive"));
+    }
+    match recipe.mode {
+        Mode::Characters => generate_characters(recipe),
+        Mode::Syllables => {
+            let consonants: Vec<char> = CONSONANTS.chars().collect();
+            let vowels: Vec<char> = VOWELS.chars().collect();
+            let syllables: Vec<String> = (0..recipe.length)
+                .map(|_| {
+                    let mut syllable = String::new();
+                    syllable.push(choose(&consonants));
+                    syllable.push(choose(&vowels));
+                    let index = random_below(consonants.len() + 1);
+                    if index < consonants.len() {
+                        syllable.push(consonants[index]);
+                    }
+                    syllable
+                })
+                .collect();
+            Ok(join(recipe, syllables))
+        }
+        Mode::Words => {
+            let words = wordlist();
+            Ok(join(recipe, (0..recipe.length).map(|_| choose(&words).to_string()).collect()))
+        }
+    }
+}
+
+fn generate_characters(recipe: &Recipe) -> IoResult<String> {
+    let mut all = Vec::new();
+    let mut password = Vec::new();
+    for &(class, min) in &recipe.classes {
+        let pool = recipe.pool(class);
+        if pool.is_empty() {
+            if min > 0 {
+                return Err(invalid_input("a required character class has every character \
+                                          excluded"));
+            }
+            continue;
+        }
+        for _ in 0..min {
+            password.push(choose(&pool));
+        }
+        all.extend(pool);
+    }
+    if all.is_empty() {
+        return Err(invalid_input("no characters to choose from"));
+    }
+    if password.len() > recipe.length {
+        return Err(invalid_input("required characters exceed the length"));
+    }
+    while password.len() < recipe.length {
+        password.push(choose(&all));
+    }
+    // Required characters were added first, so shuffle them into place (Fisher-Yates).
+    for i in (1..password.len()).rev() {
+        password.swap(i, random_below(i + 1));
+    }
+    Ok(password.into_iter().collect())
+}
+
+fn join(recipe: &Recipe, parts: Vec<String>) -> String {
+    let parts: Vec<String> = parts.into_iter()
+        .map(|part| if recipe.capitalize { capitalize(&part) } else { part })
+        .collect();
+    parts.join(&recipe.separator)
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+pub fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect()
+}
+
+fn choose<T: Clone>(choices: &[T]) -> T {
+    choices[random_below(choices.len())].clone()
+}
+
+/// Returns a uniformly random number below `n`, rejecting the values that would bias the
+/// remainder.
+fn random_below(n: usize) -> usize {
+    let n = n as u64;
+    let limit = u32::max_value() as u64 + 1;
+    loop {
+        let bytes = rand::rand_bytes(4);
+        let value = bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+        if value < limit - limit % n {
+            return (value % n) as usize;
+        }
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AMBIGUOUS, CharClass, Mode, Recipe, generate, wordlist};
+
+    #[test]
+    fn test_characters() {
+        let mut recipe = Recipe::new();
+        recipe.length = 12;
+        recipe.classes = vec![(CharClass::Lowercase, 0), (CharClass::Digits, 4)];
+        recipe.exclude_ambiguous = true;
+        recipe.exclude = "xyz".to_string();
+        for _ in 0..100 {
+            let password = generate(&recipe).unwrap();
+            assert_eq!(password.chars().count(), 12);
+            assert!(password.chars().filter(|c| c.is_digit(10)).count() >= 4);
+            assert!(password.chars().all(|c| c.is_digit(10) || (c >= 'a' && c <= 'w')));
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+        }
+        recipe.classes = vec![(CharClass::Digits, 13)];
+        assert!(generate(&recipe).is_err());
+        recipe.classes = vec![(CharClass::Digits, 0)];
+        recipe.exclude = "0123456789".to_string();
+        assert!(generate(&recipe).is_err());
+    }
+
+    #[test]
+    fn test_words() {
+        assert!(wordlist().len() >= 1296);
+        let mut recipe = Recipe::words(5);
+        recipe.separator = ".".to_string();
+        recipe.capitalize = true;
+        let password = generate(&recipe).unwrap();
+        let words: Vec<&str> = password.split('.').collect();
+        assert_eq!(words.len(), 5);
+        assert!(words.iter().all(|word| word.chars().next().unwrap().is_uppercase()));
+        assert!(recipe.entropy() > 50.0);
+
+        recipe.mode = Mode::Syllables;
+        recipe.capitalize = false;
+        let password = generate(&recipe).unwrap();
+        assert_eq!(password.split('.').count(), 5);
+        assert!(password.split('.').all(|s| s.len() == 2 || s.len() == 3));
+    }
+}
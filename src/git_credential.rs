@@ -0,0 +1,250 @@
+//! A git credential helper answering from the logins of a vault.
+//!
+//! Git writes `key=value` lines describing the remote, ended by a blank line, and reads back the
+//! same format. See gitcredentials(7).
+//!
+//! `get` answers with the login that `find_login` picks, and `store` saves a login for remotes
+//! that have none. `erase` intentionally does nothing, so that a rejected password never
+//! deletes a login that other tools than git may use.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Result as IoResult, Write};
+
+use import;
+use op_vault::category::Category;
+use op_vault::item::{Item, NewItem};
+use op_vault::vault::Vault;
+use url::Url;
+
+/// Reads the attributes git sends, splitting `url` into `protocol`, `host` and `path` unless git
+/// sent those too.
+pub fn read_attributes<R: BufRead>(reader: R) -> IoResult<BTreeMap<String, String>> {
+    let mut attributes = BTreeMap::new();
+    for line in reader.lines() {
+        let line = try!(line);
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").to_string();
+        let value = try!(parts.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{} is not key=value", line))
+        }));
+        attributes.insert(key, value.to_string());
+    }
+    let url = attributes.get("url").and_then(|url| Url::parse(url));
+    if let Some(url) = url {
+        let host = host_port(&url);
+        attributes.entry("protocol".to_string()).or_insert(url.scheme);
+        attributes.entry("host".to_string()).or_insert(host);
+        if url.path != "/" {
+            attributes.entry("path".to_string()).or_insert(url.path[1..].to_string());
+        }
+    }
+    Ok(attributes)
+}
+
+pub fn write_attributes<W: Write>(mut writer: W, attributes: &[(&str, &str)]) -> IoResult<()> {
+    for &(key, value) in attributes {
+        // A newline would let the value smuggle in attributes of its own.
+        if value.contains('\n') || value.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{} contains a newline", key)));
+        }
+        try!(writeln!(writer, "{}={}", key, value));
+    }
+    Ok(())
+}
+
+/// Finds the login for the remote described by `attributes`.
+///
+/// A login matches if one of its URLs has the same protocol, host and port, and a path that is
+/// empty or a leading part of the remote path, and if git named a user, its username is that
+/// user. The login with the longest matching path wins, then the one updated last.
+pub fn find_login(vault: &Vault, attributes: &BTreeMap<String, String>) -> Option<Item> {
+    let protocol = match attributes.get("protocol") {
+        Some(protocol) => protocol.to_lowercase(),
+        None => return None,
+    };
+    let host = match attributes.get("host") {
+        Some(host) => host.to_lowercase(),
+        None => return None,
+    };
+    let path = attributes.get("path").map(|path| trim_path(path)).unwrap_or("");
+    let username = attributes.get("username");
+
+    let mut best: Option<(usize, Item)> = None;
+    for item in vault.items() {
+        if item.category() != Category::Login || item.is_trashed() {
+            continue;
+        }
+        if username.is_some() && item.ainfo() != username.map(|u| &u[..]) {
+            continue;
+        }
+        let score = item.urls()
+            .iter()
+            .filter_map(|url| Url::parse(url))
+            .filter(|url| url.scheme == protocol && host_port(url) == host)
+            .filter_map(|url| {
+                let login_path = trim_path(&url.path);
+                if login_path.is_empty() || path == login_path ||
+                   path.starts_with(&format!("{}/", login_path)) {
+                    Some(login_path.len())
+                } else {
+                    None
+                }
+            })
+            .max();
+        let score = match score {
+            Some(score) => score,
+            None => continue,
+        };
+        let better = match best {
+            Some((best_score, ref best_item)) => {
+                score > best_score || (score == best_score && item.updated() > best_item.updated())
+            }
+            None => true,
+        };
+        if better {
+            best = Some((score, item));
+        }
+    }
+    best.map(|(_, item)| item)
+}
+
+/// Builds a login for credentials git has seen work.
+pub fn new_login(attributes: &BTreeMap<String, String>) -> Option<NewItem> {
+    let (protocol, host, username, password) = match (attributes.get("protocol"),
+                                                       attributes.get("host"),
+                                                       attributes.get("username"),
+                                                       attributes.get("password")) {
+        (Some(protocol), Some(host), Some(username), Some(password)) => {
+            (protocol, host, username, password)
+        }
+        _ => return None,
+    };
+    let mut url = format!("{}://{}/", protocol, host);
+    if let Some(path) = attributes.get("path") {
+        url.push_str(trim_path(path));
+    }
+    let mut details = BTreeMap::new();
+    details.insert("fields".to_string(),
+                   Value::Array(vec![import::login_field("username", "T", username),
+                                     import::login_field("password", "P", password)]));
+    let mut new_item = NewItem::new(Category::Login, host, Value::Object(details));
+    new_item.urls = vec![url];
+    Some(new_item)
+}
+
+fn host_port(url: &Url) -> String {
+    match url.port {
+        Some(port) => format!("{}:{}", url.host, port),
+        None => url.host.clone(),
+    }
+}
+
+/// Strips the slashes around a path and the `.git` git may or may not have been given.
+fn trim_path(path: &str) -> &str {
+    let path = path.trim_matches('/');
+    if path.ends_with(".git") { &path[..path.len() - 4] } else { path }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use op_vault::vault::LockedVault;
+    use testing::{copy_sample_vault, sample_vault, SAMPLE_VAULT_PASSWORD};
+    use super::{find_login, new_login, read_attributes, trim_path, write_attributes};
+
+    const HULU_UUID: &'static str = "13C8E12AC8E54B1F873BAB0824E521BC";
+
+    fn attributes(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|&(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_read_attributes() {
+        let input = b"url=https://git.example.com:8443/org/repo.git\nusername=bob\n\nignored=1\n";
+        let attributes = read_attributes(&input[..]).unwrap();
+        let mut expected = BTreeMap::new();
+        for &(key, value) in &[("url", "https://git.example.com:8443/org/repo.git"),
+                               ("protocol", "https"),
+                               ("host", "git.example.com:8443"),
+                               ("path", "org/repo.git"),
+                               ("username", "bob")] {
+            expected.insert(key.to_string(), value.to_string());
+        }
+        assert_eq!(attributes, expected);
+        assert!(read_attributes(&b"protocol\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_write_attributes() {
+        let mut output = Vec::new();
+        write_attributes(&mut output, &[("username", "bob"), ("password", "hunter2")]).unwrap();
+        assert_eq!(output, b"username=bob\npassword=hunter2\n".to_vec());
+        assert!(write_attributes(&mut Vec::new(), &[("password", "a\nhost=evil")]).is_err());
+    }
+
+    #[test]
+    fn test_find_login() {
+        let vault = sample_vault();
+        let uuid = |pairs: &[(&str, &str)]| {
+            find_login(&vault, &attributes(pairs)).map(|item| item.uuid().to_string())
+        };
+        let hulu = Some(HULU_UUID.to_string());
+        assert_eq!(uuid(&[("protocol", "http"), ("host", "www.hulu.com")]), hulu);
+        assert_eq!(uuid(&[("protocol", "HTTP"), ("host", "WWW.Hulu.com"), ("path", "a/b.git")]),
+                   hulu);
+        assert_eq!(uuid(&[("protocol", "http"),
+                          ("host", "www.hulu.com"),
+                          ("username", "wendy@appleseed.com")]),
+                   hulu);
+        assert_eq!(uuid(&[("protocol", "http"), ("host", "www.hulu.com"), ("username", "bob")]),
+                   None);
+        assert_eq!(uuid(&[("protocol", "https"), ("host", "www.hulu.com")]), None);
+        assert_eq!(uuid(&[("protocol", "http"), ("host", "www.hulu.com:8080")]), None);
+        assert_eq!(uuid(&[("protocol", "http"), ("host", "hulu.com")]), None);
+        assert_eq!(uuid(&[("host", "www.hulu.com")]), None);
+    }
+
+    #[test]
+    fn test_find_login_path() {
+        let path = copy_sample_vault("git-credential");
+        let mut vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        let mut create = |pairs: &[(&str, &str)]| {
+            let new_item = new_login(&attributes(pairs)).unwrap();
+            vault.create_item(&new_item).unwrap().uuid().to_string()
+        };
+        let host_login = create(&[("protocol", "https"),
+                                  ("host", "git.example.com"),
+                                  ("username", "bob"),
+                                  ("password", "a")]);
+        let repo_login = create(&[("protocol", "https"),
+                                  ("host", "git.example.com"),
+                                  ("path", "org/repo.git"),
+                                  ("username", "bob"),
+                                  ("password", "b")]);
+
+        let vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        let uuid = |path: &str| {
+            let pairs = [("protocol", "https"), ("host", "git.example.com"), ("path", path)];
+            find_login(&vault, &attributes(&pairs)).map(|item| item.uuid().to_string())
+        };
+        assert_eq!(uuid("org/repo.git"), Some(repo_login.clone()));
+        assert_eq!(uuid("/org/repo/"), Some(repo_login));
+        assert_eq!(uuid("org/repository"), Some(host_login.clone()));
+        assert_eq!(uuid("org"), Some(host_login));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_trim_path() {
+        assert_eq!(trim_path("/org/repo.git"), "org/repo");
+        assert_eq!(trim_path("org/repo/"), "org/repo");
+        assert_eq!(trim_path("/"), "");
+    }
+}
@@ -0,0 +1,130 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Result as IoResult};
+
+use op_vault::item::NewItem;
+use op_vault::vault::Vault;
+
+/// A change that importing a file makes to a vault.
+#[derive(Clone, Debug)]
+pub enum ImportAction {
+    CreateFolder {
+        parent: Option<String>,
+        title: String,
+        uuid: String,
+    },
+    CreateItem(NewItem),
+    Skip {
+        reason: String,
+        title: String,
+        uuid: String,
+    },
+}
+
+impl fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportAction::CreateFolder { ref title, ref uuid, .. } => {
+                write!(f, "create folder {} {}", uuid, title)
+            }
+            ImportAction::CreateItem(ref new_item) => {
+                try!(write!(f,
+                            "create item {} [{}] {}",
+                            new_item.uuid.as_ref().map(|s| &s[..]).unwrap_or("-"),
+                            new_item.category.name(),
+                            new_item.title));
+                match new_item.attachments.len() {
+                    0 => Ok(()),
+                    1 => write!(f, " (1 attachment)"),
+                    n => write!(f, " ({} attachments)", n),
+                }
+            }
+            ImportAction::Skip { ref reason, ref title, ref uuid } => {
+                write!(f, "skip {} {} ({})", uuid, title, reason)
+            }
+        }
+    }
+}
+
+/// Carries out `actions`, which importers work out without changing the vault so that they
+/// can be shown first. Nothing is written unless `check` accepts all of them.
+pub fn apply(vault: &mut Vault, actions: &[ImportAction]) -> IoResult<()> {
+    try!(check(vault, actions));
+    for action in actions {
+        match *action {
+            ImportAction::CreateFolder { ref parent, ref title, ref uuid } => {
+                try!(vault.create_folder(Some(uuid), title, parent.as_ref().map(|s| &s[..])));
+            }
+            ImportAction::CreateItem(ref new_item) => {
+                try!(vault.create_item(new_item));
+            }
+            ImportAction::Skip { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `actions` can all be carried out, so that a bad plan fails before it leaves a
+/// vault half imported: nothing is created twice or over something in the vault, and folders
+/// and items go into folders that exist or are created before them.
+pub fn check(vault: &Vault, actions: &[ImportAction]) -> IoResult<()> {
+    let mut folders: BTreeSet<String> =
+        vault.folders().iter().map(|folder| folder.uuid().to_string()).collect();
+    let mut items = BTreeSet::new();
+    for action in actions {
+        let (uuid, folder) = match *action {
+            ImportAction::CreateFolder { ref parent, ref uuid, .. } => (Some(uuid), parent),
+            ImportAction::CreateItem(ref new_item) => (new_item.uuid.as_ref(), &new_item.folder),
+            ImportAction::Skip { .. } => continue,
+        };
+        if let Some(ref folder) = *folder {
+            if !folders.contains(folder) {
+                return Err(io::Error::new(io::ErrorKind::NotFound,
+                                          format!("cannot {}: no folder {}", action, folder)));
+            }
+        }
+        let new = match (action, uuid) {
+            (&ImportAction::CreateFolder { .. }, Some(uuid)) => {
+                vault.folder(uuid).is_none() && folders.insert(uuid.clone())
+            }
+            (_, Some(uuid)) => vault.item(uuid).is_none() && items.insert(uuid.clone()),
+            (_, None) => true,
+        };
+        if !new {
+            let message = format!("cannot {}: it exists already", action);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+        }
+    }
+    Ok(())
+}
+
+/// Returns a login field of item details, such as the username (of type `T`) or the password
+/// (of type `P`).
+pub fn login_field(designation: &str, kind: &str, value: &str) -> Value {
+    let mut field = BTreeMap::new();
+    field.insert("designation".to_string(), Value::String(designation.to_string()));
+    field.insert("name".to_string(), Value::String(designation.to_string()));
+    field.insert("type".to_string(), Value::String(kind.to_string()));
+    field.insert("value".to_string(), Value::String(value.to_string()));
+    Value::Object(field)
+}
+
+/// Returns a section field of item details. `kind` is `string`, `concealed` and so on.
+pub fn section_field(name: &str, title: &str, kind: &str, value: &str) -> Value {
+    let mut field = BTreeMap::new();
+    field.insert("k".to_string(), Value::String(kind.to_string()));
+    field.insert("n".to_string(), Value::String(name.to_string()));
+    field.insert("t".to_string(), Value::String(title.to_string()));
+    field.insert("v".to_string(), Value::String(value.to_string()));
+    Value::Object(field)
+}
+
+/// Returns the section that holds fields which have no place of their own in an item.
+pub fn custom_section(fields: Vec<Value>) -> Value {
+    let mut section = BTreeMap::new();
+    section.insert("name".to_string(), Value::String("custom".to_string()));
+    section.insert("title".to_string(), Value::String("Custom Fields".to_string()));
+    section.insert("fields".to_string(), Value::Array(fields));
+    Value::Object(section)
+}
@@ -0,0 +1,71 @@
+//! Rendering templates that refer to secrets in the vault.
+//!
+//! A reference is written `{{ vault://<vault>/<item>/<section>/<field> }}`, with the URI as
+//! described in the `reference` module. Other text between braces is left alone so that
+//! templates can be fed to other tools afterwards.
+
+use std::io::{self, Result as IoResult};
+
+/// Replaces each `{{ vault://... }}` in `template` with what `resolve` returns for it.
+///
+/// Every reference is tried before failing, and the error lists all that did not resolve with
+/// their line numbers.
+pub fn render<F>(template: &str, mut resolve: F) -> IoResult<String>
+    where F: FnMut(&str) -> IoResult<String>
+{
+    let mut output = String::with_capacity(template.len());
+    let mut errors = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        let reference = rest[start + 2..end].trim();
+        output.push_str(&rest[..start]);
+        if reference.starts_with("vault://") {
+            match resolve(reference) {
+                Ok(value) => output.push_str(&value),
+                Err(e) => {
+                    let offset = template.len() - rest.len() + start;
+                    let line = template[..offset].matches('\n').count() + 1;
+                    errors.push(format!("line {}: {}: {}", line, reference, e));
+                }
+            }
+        } else {
+            output.push_str(&rest[start..end + 2]);
+        }
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    if !errors.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+                                  format!("unresolved references:\n{}", errors.join("\n"))));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::render;
+
+    #[test]
+    fn test_render() {
+        let resolve = |reference: &str| {
+            match reference {
+                "vault://v/db/password" => Ok("hunter2".to_string()),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such item")),
+            }
+        };
+        assert_eq!(render("user = app\npass = {{vault://v/db/password}} {{ name }}\n", &resolve)
+                       .unwrap(),
+                   "user = app\npass = hunter2 {{ name }}\n");
+        assert_eq!(render("{{ vault://v/db/password }}{{", &resolve).unwrap(), "hunter2{{");
+
+        let e = render("a\n{{ vault://v/x/y }}\n{{ vault://v/z/y }}", &resolve).unwrap_err();
+        assert_eq!(format!("{}", e),
+                   "unresolved references:\nline 2: vault://v/x/y: no such item\n\
+                    line 3: vault://v/z/y: no such item");
+    }
+}
@@ -0,0 +1,82 @@
+//! Conversion between Unix time and ISO 8601 timestamps in UTC such as
+//! "2017-03-04T05:06:07Z".
+
+/// Formats a Unix time with second precision.
+pub fn format(unix_time: u64) -> String {
+    let days = (unix_time / 86400) as i64;
+    let seconds = unix_time % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60)
+}
+
+/// Parses a timestamp, ignoring fractional seconds and assuming UTC. Returns Unix time, or
+/// `None` if `s` is not a timestamp.
+pub fn parse(s: &str) -> Option<u64> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 19 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' {
+        return None;
+    }
+    let fields: Option<Vec<u64>> = [0..4, 5..7, 8..10, 11..13, 14..16, 17..19]
+        .iter()
+        .map(|range| {
+            ::std::str::from_utf8(&bytes[range.clone()]).ok().and_then(|f| f.parse().ok())
+        })
+        .collect();
+    fields.and_then(|f| {
+        days_from_civil(f[0], f[1], f[2])
+            .map(|days| (days * 86400 + (f[3] * 3600 + f[4] * 60 + f[5]) as i64).max(0) as u64)
+    })
+}
+
+/// Returns the days between 1970-01-01 and the given date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<i64> {
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return None;
+    }
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+/// Reverses `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse};
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(format(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format(951868801), "2000-03-01T00:00:01Z");
+        assert_eq!(parse("2000-03-01T00:00:01Z"), Some(951868801));
+        assert_eq!(parse("2017-03-04T05:06:07.123Z"), Some(1488603967));
+        for &t in &[0, 68169599, 951782400, 1488603967, 4102444800] {
+            assert_eq!(parse(&format(t)), Some(t));
+        }
+        assert_eq!(parse("garbage"), None);
+    }
+}
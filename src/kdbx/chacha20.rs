@@ -0,0 +1,100 @@
+use kdbx::{StreamCipher, read_u32_le};
+
+/// The ChaCha20 stream cipher of RFC 7539 with a 96-bit nonce, starting at block 0.
+///
+/// KDBX 4 uses it both for the payload and for the inner stream that hides protected values.
+pub struct ChaCha20 {
+    keystream: [u8; 64],
+    offset: usize,
+    state: [u32; 16],
+}
+
+impl ChaCha20 {
+    pub fn new(key: &[u8], nonce: &[u8]) -> Self {
+        assert!(key.len() == 32 && nonce.len() == 12);
+        let mut state = [0u32; 16];
+        state[0] = 0x61707865;
+        state[1] = 0x3320646e;
+        state[2] = 0x79622d32;
+        state[3] = 0x6b206574;
+        for i in 0..8 {
+            state[4 + i] = read_u32_le(&key[i * 4..i * 4 + 4]);
+        }
+        state[12] = 0;
+        for i in 0..3 {
+            state[13 + i] = read_u32_le(&nonce[i * 4..i * 4 + 4]);
+        }
+        ChaCha20 {
+            keystream: [0; 64],
+            offset: 64,
+            state: state,
+        }
+    }
+
+    fn next_block(&mut self) {
+        let mut x = self.state;
+        for _ in 0..10 {
+            quarter_round(&mut x, 0, 4, 8, 12);
+            quarter_round(&mut x, 1, 5, 9, 13);
+            quarter_round(&mut x, 2, 6, 10, 14);
+            quarter_round(&mut x, 3, 7, 11, 15);
+            quarter_round(&mut x, 0, 5, 10, 15);
+            quarter_round(&mut x, 1, 6, 11, 12);
+            quarter_round(&mut x, 2, 7, 8, 13);
+            quarter_round(&mut x, 3, 4, 9, 14);
+        }
+        for i in 0..16 {
+            let word = x[i].wrapping_add(self.state[i]);
+            for j in 0..4 {
+                self.keystream[i * 4 + j] = (word >> (8 * j)) as u8;
+            }
+        }
+        self.state[12] = self.state[12].wrapping_add(1);
+        self.offset = 0;
+    }
+}
+
+impl StreamCipher for ChaCha20 {
+    fn apply_keystream(&mut self, bytes: &mut [u8]) {
+        for byte in bytes.iter_mut() {
+            if self.offset == 64 {
+                self.next_block();
+            }
+            *byte ^= self.keystream[self.offset];
+            self.offset += 1;
+        }
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+#[cfg(test)]
+mod tests {
+    use kdbx::StreamCipher;
+    use super::ChaCha20;
+
+    #[test]
+    fn test_rfc_7539_encryption() {
+        let key: Vec<u8> = (0..32).collect();
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        // The test vector starts at block 1.
+        cipher.process(&[0; 64]);
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only \
+                          one tip for the future, sunscreen would be it.";
+        let ciphertext = cipher.process(plaintext);
+        assert_eq!(&ciphertext[..16],
+                   &[0x6e, 0x2e, 0x35, 0x9a, 0x25, 0x68, 0xf9, 0x80, 0x41, 0xba, 0x07, 0x28,
+                     0xdd, 0x0d, 0x69, 0x81]);
+        assert_eq!(&ciphertext[ciphertext.len() - 4..], &[0x5e, 0x42, 0x87, 0x4d]);
+    }
+}
@@ -0,0 +1,84 @@
+use openssl::crypto::symm;
+
+use kdbx::StreamCipher;
+use kdbx::chacha20::ChaCha20;
+
+const AES_256_UUID: [u8; 16] = [0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05,
+                                0x21, 0x6a, 0xfc, 0x5a, 0xff];
+const CHACHA20_UUID: [u8; 16] = [0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24,
+                                 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a];
+
+/// The cipher that encrypts the payload of a database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256,
+    ChaCha20,
+}
+
+impl Cipher {
+    pub fn from_uuid(uuid: &[u8]) -> Option<Self> {
+        if uuid == &AES_256_UUID[..] {
+            Some(Cipher::Aes256)
+        } else if uuid == &CHACHA20_UUID[..] {
+            Some(Cipher::ChaCha20)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "aes" => Some(Cipher::Aes256),
+            "chacha20" => Some(Cipher::ChaCha20),
+            _ => None,
+        }
+    }
+
+    pub fn uuid(&self) -> &'static [u8] {
+        match *self {
+            Cipher::Aes256 => &AES_256_UUID,
+            Cipher::ChaCha20 => &CHACHA20_UUID,
+        }
+    }
+
+    pub fn iv_len(&self) -> usize {
+        match *self {
+            Cipher::Aes256 => 16,
+            Cipher::ChaCha20 => 12,
+        }
+    }
+
+    /// Encrypts `bytes` with AES-256-CBC and PKCS #7 padding, or with ChaCha20.
+    pub fn encrypt(&self, key: &[u8], iv: &[u8], bytes: &[u8]) -> Vec<u8> {
+        match *self {
+            Cipher::Aes256 => symm::encrypt(symm::Type::AES_256_CBC, key, iv, bytes),
+            Cipher::ChaCha20 => ChaCha20::new(key, iv).process(bytes),
+        }
+    }
+
+    /// Reverses `encrypt`. Returns `None` if the padding is malformed, which usually means
+    /// the key is wrong.
+    pub fn decrypt(&self, key: &[u8], iv: &[u8], bytes: &[u8]) -> Option<Vec<u8>> {
+        match *self {
+            Cipher::Aes256 => {
+                if bytes.is_empty() || bytes.len() % 16 != 0 {
+                    return None;
+                }
+                let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+                crypter.init(symm::Mode::Decrypt, key, iv);
+                crypter.pad(false);
+                let mut plaintext = crypter.update(bytes);
+                plaintext.extend(crypter.finalize());
+                let padding = plaintext[plaintext.len() - 1] as usize;
+                if padding == 0 || padding > 16 ||
+                   plaintext[plaintext.len() - padding..].iter().any(|&b| b as usize != padding) {
+                    return None;
+                }
+                let len = plaintext.len() - padding;
+                plaintext.truncate(len);
+                Some(plaintext)
+            }
+            Cipher::ChaCha20 => Some(ChaCha20::new(key, iv).process(bytes)),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/// A KeePass database as a tree of groups and entries.
+#[derive(Clone, Debug)]
+pub struct Database {
+    /// Attachment contents, which entries refer to by index.
+    pub binaries: Vec<Vec<u8>>,
+    pub name: String,
+    /// The group that holds deleted entries, if any.
+    pub recycle_bin: Option<Vec<u8>>,
+    pub root: Group,
+}
+
+impl Database {
+    pub fn new(name: &str, root: Group) -> Self {
+        Database {
+            binaries: Vec::new(),
+            name: name.to_string(),
+            recycle_bin: None,
+            root: root,
+        }
+    }
+
+    /// Adds attachment contents to the pool and returns their index.
+    pub fn add_binary(&mut self, contents: Vec<u8>) -> usize {
+        self.binaries.push(contents);
+        self.binaries.len() - 1
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub entries: Vec<Entry>,
+    pub groups: Vec<Group>,
+    pub name: String,
+    pub times: Times,
+    pub uuid: Vec<u8>,
+}
+
+impl Group {
+    pub fn new(uuid: Vec<u8>, name: &str, times: Times) -> Self {
+        Group {
+            entries: Vec::new(),
+            groups: Vec::new(),
+            name: name.to_string(),
+            times: times,
+            uuid: uuid,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub binaries: Vec<EntryBinary>,
+    pub strings: Vec<EntryString>,
+    pub tags: Vec<String>,
+    pub times: Times,
+    pub uuid: Vec<u8>,
+}
+
+impl Entry {
+    pub fn new(uuid: Vec<u8>, times: Times) -> Self {
+        Entry {
+            binaries: Vec::new(),
+            strings: Vec::new(),
+            tags: Vec::new(),
+            times: times,
+            uuid: uuid,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings.iter().find(|s| s.key == key).map(|s| &s.value[..])
+    }
+
+    /// Adds a string field, renaming the key with a numeric suffix if it is already taken.
+    pub fn add_string(&mut self, key: &str, value: &str, protected: bool) {
+        let mut unique_key = key.to_string();
+        let mut n = 2;
+        while self.get(&unique_key).is_some() {
+            unique_key = format!("{} ({})", key, n);
+            n += 1;
+        }
+        self.strings.push(EntryString {
+            key: unique_key,
+            protected: protected,
+            value: value.to_string(),
+        });
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryString {
+    pub key: String,
+    pub protected: bool,
+    pub value: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryBinary {
+    pub index: usize,
+    pub key: String,
+}
+
+/// Timestamps in seconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Times {
+    pub created: u64,
+    pub modified: u64,
+}
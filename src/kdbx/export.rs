@@ -0,0 +1,279 @@
+use openssl::crypto::rand;
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult, Write};
+
+use kdbx::Settings;
+use kdbx::database::{Database, Entry, EntryBinary, Group, Times};
+use kdbx::key::CompositeKey;
+use kdbx::writer;
+use op_vault::details::ItemDetails;
+use op_vault::folder::Folder;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+
+const RECYCLE_BIN_NAME: &'static str = "Recycle Bin";
+
+/// Writes `vault` as a KDBX 4 database.
+pub fn export<W: Write + ?Sized>(vault: &Vault,
+                                 writer: &mut W,
+                                 key: &CompositeKey,
+                                 settings: &Settings)
+                                 -> IoResult<()> {
+    let db = try!(to_database(vault));
+    writer::write(&db, key, settings, writer)
+}
+
+/// Converts `vault` into a database whose groups mirror the folder tree. Smart folders are
+/// left out and trashed items go to the recycle bin.
+pub fn to_database(vault: &Vault) -> IoResult<Database> {
+    let name = vault.path()
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "decone".to_string());
+    let folders: Vec<Folder> = vault.folders().into_iter().filter(|f| !f.is_smart()).collect();
+    let mut db = Database::new(&name, Group::new(rand::rand_bytes(16), &name, Times::default()));
+
+    let mut entries_by_folder: BTreeMap<Option<String>, Vec<Entry>> = BTreeMap::new();
+    let mut trashed_entries = Vec::new();
+    for item in vault.items() {
+        let details = try!(vault.item_details(&item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let entry = try!(to_entry(vault, &mut db, &item, &details));
+        if item.is_trashed() {
+            trashed_entries.push(entry);
+            continue;
+        }
+        let folder = item.folder()
+            .and_then(|uuid| if has_folder(&folders, uuid) { Some(uuid) } else { None })
+            .map(|uuid| uuid.to_string());
+        entries_by_folder.entry(folder).or_insert_with(Vec::new).push(entry);
+    }
+
+    db.root.entries = entries_by_folder.remove(&None).unwrap_or_else(Vec::new);
+    db.root.groups = child_groups(None, &folders, &mut entries_by_folder);
+    for (_, entries) in entries_by_folder {
+        db.root.entries.extend(entries);
+    }
+    if !trashed_entries.is_empty() {
+        let mut recycle_bin = Group::new(rand::rand_bytes(16), RECYCLE_BIN_NAME, Times::default());
+        recycle_bin.entries = trashed_entries;
+        db.recycle_bin = Some(recycle_bin.uuid.clone());
+        db.root.groups.push(recycle_bin);
+    }
+    Ok(db)
+}
+
+/// Returns the groups of the folders under `parent`. Folders whose parent is missing or that
+/// are among their own ancestors are put at the top level.
+fn child_groups(parent: Option<&str>,
+                folders: &[Folder],
+                entries_by_folder: &mut BTreeMap<Option<String>, Vec<Entry>>)
+                -> Vec<Group> {
+    let mut groups = Vec::new();
+    for folder in folders {
+        if group_parent(folder, folders) != parent {
+            continue;
+        }
+        let times = Times {
+            created: folder.created(),
+            modified: folder.updated(),
+        };
+        let mut group = Group::new(uuid_bytes(folder.uuid()), folder.title(), times);
+        group.entries = entries_by_folder.remove(&Some(folder.uuid().to_string()))
+            .unwrap_or_else(Vec::new);
+        group.groups = child_groups(Some(folder.uuid()), folders, entries_by_folder);
+        groups.push(group);
+    }
+    groups
+}
+
+/// Returns the parent of `folder` in `folders`, or `None` if the parent is missing or its
+/// ancestors lead back to `folder`.
+fn group_parent<'a>(folder: &'a Folder, folders: &[Folder]) -> Option<&'a str> {
+    let parent = match folder.parent() {
+        Some(parent) if has_folder(folders, parent) => parent,
+        _ => return None,
+    };
+    let mut ancestor = Some(parent);
+    // Cycles that do not include `folder` would otherwise never end.
+    for _ in 0..folders.len() {
+        match ancestor {
+            Some(uuid) if uuid == folder.uuid() => return None,
+            Some(uuid) => {
+                ancestor = folders.iter().find(|f| f.uuid() == uuid).and_then(|f| f.parent())
+            }
+            None => break,
+        }
+    }
+    Some(parent)
+}
+
+fn has_folder(folders: &[Folder], uuid: &str) -> bool {
+    folders.iter().any(|f| f.uuid() == uuid)
+}
+
+fn to_entry(vault: &Vault,
+            db: &mut Database,
+            item: &Item,
+            details: &ItemDetails)
+            -> IoResult<Entry> {
+    let times = Times {
+        created: item.created(),
+        modified: item.updated(),
+    };
+    let mut entry = Entry::new(uuid_bytes(item.uuid()), times);
+    let sections = details.sections();
+    let section_value = |name: &str| {
+        sections.iter()
+            .flat_map(|section| section.fields().iter())
+            .find(|field| field.name() == name)
+            .and_then(|field| field.value_string())
+    };
+    let username = details.username().or_else(|| section_value("username"));
+    let password = details.password().or_else(|| section_value("password"));
+    let urls = item.urls();
+
+    entry.add_string("Title", item.title().unwrap_or(""), false);
+    entry.add_string("UserName", username.as_ref().map(|s| &s[..]).unwrap_or(""), false);
+    entry.add_string("Password", password.as_ref().map(|s| &s[..]).unwrap_or(""), true);
+    entry.add_string("URL", urls.first().map(|s| &s[..]).unwrap_or(""), false);
+    entry.add_string("Notes", details.notes().unwrap_or(""), false);
+    for (i, url) in urls.iter().enumerate().skip(1) {
+        entry.add_string(&format!("KP2A_URL_{}", i), url, false);
+    }
+
+    for section in &sections {
+        for field in section.fields() {
+            let value = match field.value_string() {
+                Some(value) => value,
+                None => continue,
+            };
+            if value.starts_with("otpauth://") || field.name().starts_with("TOTP_") {
+                entry.add_string("otp", &value, true);
+                continue;
+            }
+            if (field.name() == "username" && Some(&value) == username.as_ref()) ||
+               (field.name() == "password" && Some(&value) == password.as_ref()) {
+                continue;
+            }
+            let key = if field.title().is_empty() { field.name() } else { field.title() };
+            entry.add_string(key, &value, field.is_concealed());
+        }
+    }
+    entry.tags = item.tags();
+
+    for attachment in try!(vault.attachments(item)) {
+        let contents = try!(vault.attachment_contents(item, &attachment).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt attachment {}", attachment.uuid()))
+        }));
+        entry.binaries.push(EntryBinary {
+            index: db.add_binary(contents),
+            key: unique_binary_key(&entry, attachment.file_name()),
+        });
+    }
+    Ok(entry)
+}
+
+fn unique_binary_key(entry: &Entry, file_name: &str) -> String {
+    let mut key = file_name.to_string();
+    let mut n = 2;
+    while entry.binaries.iter().any(|b| b.key == key) {
+        key = format!("{} ({})", file_name, n);
+        n += 1;
+    }
+    key
+}
+
+/// Converts a hex UUID such as an OPVault item UUID into 16 bytes, or returns random bytes
+/// if it is malformed.
+fn uuid_bytes(uuid: &str) -> Vec<u8> {
+    if uuid.len() != 32 || !uuid.chars().all(|c| c.is_digit(16)) {
+        return rand::rand_bytes(16);
+    }
+    (0..16).map(|i| u8::from_str_radix(&uuid[i * 2..i * 2 + 2], 16).unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use kdbx::database::Group;
+    use op_vault::category::Category;
+    use op_vault::item::NewItem;
+    use op_vault::vault::{LockedVault, Vault};
+    use testing::{copy_sample_vault, sample_vault, SAMPLE_VAULT_PASSWORD};
+
+    const LOOP_A: &'static str = "A0000000000000000000000000000001";
+    const LOOP_B: &'static str = "B0000000000000000000000000000002";
+    const SELF_PARENTED: &'static str = "C0000000000000000000000000000003";
+
+    fn create_note(vault: &mut Vault, title: &str, folder: &str) {
+        let details = Value::Object(BTreeMap::new());
+        let mut new_item = NewItem::new(Category::SecureNote, title, details);
+        new_item.folder = Some(folder.to_string());
+        vault.create_item(&new_item).unwrap();
+    }
+
+    fn group<'a>(groups: &'a [Group], name: &str) -> &'a Group {
+        groups.iter().find(|g| g.name == name).expect("group must be at this level")
+    }
+
+    fn has_entry(group: &Group, title: &str) -> bool {
+        group.entries.iter().any(|e| e.get("Title") == Some(title))
+    }
+
+    #[test]
+    fn test_to_database() {
+        let vault = sample_vault();
+        let db = super::to_database(&vault).unwrap();
+        let hulu = db.root
+            .entries
+            .iter()
+            .chain(db.root.groups.iter().flat_map(|g| g.entries.iter()))
+            .find(|e| e.get("Title") == Some("Hulu"))
+            .unwrap();
+        assert_eq!(hulu.get("UserName"), Some("wendy@appleseed.com"));
+        assert_eq!(hulu.get("Password"), Some("frirp7i1ob7wig4d"));
+        assert!(db.recycle_bin.is_some());
+        assert!(!db.binaries.is_empty());
+    }
+    #[test]
+    fn test_to_database_self_parented_folder() {
+        let path = copy_sample_vault("kdbx-export-self-parented");
+        let mut vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        vault.create_folder(Some(SELF_PARENTED), "Self", Some(SELF_PARENTED)).unwrap();
+        create_note(&mut vault, "In Self", SELF_PARENTED);
+
+        let vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        let db = super::to_database(&vault).unwrap();
+        let folder = group(&db.root.groups, "Self");
+        assert!(folder.groups.is_empty());
+        assert!(has_entry(folder, "In Self"));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_to_database_folder_cycle() {
+        let path = copy_sample_vault("kdbx-export-cycle");
+        let mut vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        vault.create_folder(Some(LOOP_A), "Loop A", Some(LOOP_B)).unwrap();
+        vault.create_folder(Some(LOOP_B), "Loop B", Some(LOOP_A)).unwrap();
+        let inside = vault.create_folder(None, "Inside", Some(LOOP_A)).unwrap();
+        create_note(&mut vault, "In A", LOOP_A);
+        create_note(&mut vault, "In B", LOOP_B);
+        create_note(&mut vault, "Inside A", inside.uuid());
+
+        let vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        let db = super::to_database(&vault).unwrap();
+        let loop_a = group(&db.root.groups, "Loop A");
+        assert!(has_entry(loop_a, "In A"));
+        assert!(has_entry(group(&loop_a.groups, "Inside"), "Inside A"));
+        assert!(has_entry(group(&db.root.groups, "Loop B"), "In B"));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
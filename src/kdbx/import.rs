@@ -0,0 +1,211 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+use import::{self, ImportAction};
+use kdbx::database::{Database, Entry, Group};
+use op_vault::attachment::NewAttachment;
+use op_vault::category::Category;
+use op_vault::item::NewItem;
+use op_vault::uuid;
+use op_vault::vault::Vault;
+
+/// The entry strings that map to item fields rather than custom fields.
+const STANDARD_KEYS: [&'static str; 6] = ["Title", "UserName", "Password", "URL", "Notes", "otp"];
+
+/// Works out how to add the groups and entries of `db` to `vault` without changing it.
+///
+/// Groups become folders and entries become items, both keeping their UUIDs, so those already
+/// in the vault are skipped and importing the same database twice adds nothing. Entries in the
+/// recycle bin are imported as trashed items.
+pub fn plan_import(vault: &Vault, db: &Database) -> Vec<ImportAction> {
+    let mut actions = Vec::new();
+    let mut folder_uuids: BTreeSet<String> =
+        vault.folders().iter().map(|folder| folder.uuid().to_string()).collect();
+    plan_group(vault, db, &db.root, None, false, &mut folder_uuids, &mut actions);
+    actions
+}
+
+/// Plans the entries of `group` and its subgroups, which go in the folder `folder`.
+fn plan_group(vault: &Vault,
+              db: &Database,
+              group: &Group,
+              folder: Option<&str>,
+              trashed: bool,
+              folder_uuids: &mut BTreeSet<String>,
+              actions: &mut Vec<ImportAction>) {
+    for entry in &group.entries {
+        let uuid = hex_uuid(&entry.uuid);
+        let title = entry.get("Title").unwrap_or("").to_string();
+        if vault.item(&uuid).is_some() {
+            actions.push(ImportAction::Skip {
+                reason: "already in vault".to_string(),
+                title: title,
+                uuid: uuid,
+            });
+            continue;
+        }
+        let mut new_item = new_item(db, entry, uuid);
+        new_item.folder = folder.map(|folder| folder.to_string());
+        new_item.trashed = trashed;
+        actions.push(ImportAction::CreateItem(new_item));
+    }
+    for child in &group.groups {
+        // The recycle bin holds trashed entries rather than being a folder of its own.
+        if db.recycle_bin.as_ref() == Some(&child.uuid) {
+            plan_group(vault, db, child, None, true, folder_uuids, actions);
+            continue;
+        }
+        let uuid = hex_uuid(&child.uuid);
+        if vault.folder(&uuid).is_some() {
+            actions.push(ImportAction::Skip {
+                reason: "already in vault".to_string(),
+                title: child.name.clone(),
+                uuid: uuid.clone(),
+            });
+        } else if !folder_uuids.contains(&uuid) {
+            folder_uuids.insert(uuid.clone());
+            actions.push(ImportAction::CreateFolder {
+                parent: folder.map(|folder| folder.to_string()),
+                title: child.name.clone(),
+                uuid: uuid.clone(),
+            });
+        }
+        plan_group(vault, db, child, Some(&uuid), trashed, folder_uuids, actions);
+    }
+}
+
+fn new_item(db: &Database, entry: &Entry, uuid: String) -> NewItem {
+    let field = |key| entry.get(key).and_then(|s| if s.is_empty() { None } else { Some(s) });
+    let username = field("UserName");
+    let password = field("Password");
+
+    let mut details = BTreeMap::new();
+    let mut fields = Vec::new();
+    if let Some(username) = username {
+        fields.push(import::login_field("username", "T", username));
+    }
+    if let Some(password) = password {
+        fields.push(import::login_field("password", "P", password));
+    }
+    if !fields.is_empty() {
+        details.insert("fields".to_string(), Value::Array(fields));
+    }
+    if let Some(notes) = field("Notes") {
+        details.insert("notesPlain".to_string(), Value::String(notes.to_string()));
+    }
+
+    let mut urls: Vec<String> = field("URL").into_iter().map(|s| s.to_string()).collect();
+    let mut section_fields = Vec::new();
+    if let Some(otp) = field("otp") {
+        section_fields.push(import::section_field(&format!("TOTP_{}", uuid::generate()),
+                                                  "one-time password",
+                                                  "concealed",
+                                                  otp));
+    }
+    for string in &entry.strings {
+        if STANDARD_KEYS.contains(&&string.key[..]) || string.value.is_empty() {
+            continue;
+        }
+        if string.key.starts_with("KP2A_URL") {
+            if !urls.contains(&string.value) {
+                urls.push(string.value.clone());
+            }
+            continue;
+        }
+        let kind = if string.protected { "concealed" } else { "string" };
+        section_fields.push(import::section_field(&uuid::generate(),
+                                                  &string.key,
+                                                  kind,
+                                                  &string.value));
+    }
+    if !section_fields.is_empty() {
+        details.insert("sections".to_string(),
+                       Value::Array(vec![import::custom_section(section_fields)]));
+    }
+
+    let category = if username.is_some() || password.is_some() || !urls.is_empty() {
+        Category::Login
+    } else {
+        Category::SecureNote
+    };
+    let mut new_item = NewItem::new(category,
+                                    entry.get("Title").unwrap_or(""),
+                                    Value::Object(details));
+    new_item.attachments = entry.binaries
+        .iter()
+        .filter_map(|binary| {
+            db.binaries
+                .get(binary.index)
+                .map(|contents| NewAttachment::new(&binary.key, contents.clone()))
+        })
+        .collect();
+    new_item.created = nonzero(entry.times.created);
+    new_item.updated = nonzero(entry.times.modified);
+    new_item.tags = entry.tags.clone();
+    new_item.urls = urls;
+    new_item.uuid = Some(uuid);
+    new_item
+}
+
+/// Converts a KDBX UUID into the uppercase hex form of OPVault, or returns a new UUID if it is
+/// malformed.
+fn hex_uuid(bytes: &[u8]) -> String {
+    if bytes.len() != 16 {
+        return uuid::generate();
+    }
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn nonzero(time: u64) -> Option<u64> {
+    if time == 0 { None } else { Some(time) }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_round_trip() {
+        use import::ImportAction;
+        use kdbx::{Settings, export, reader};
+        use kdbx::key::{CompositeKey, Kdf};
+        use op_vault::category::Category;
+        use testing::sample_vault;
+        use super::plan_import;
+
+        let vault = sample_vault();
+        let key = CompositeKey::from_password("hunter2");
+        let mut settings = Settings::new();
+        settings.kdf = Kdf::argon2id(2, 1024 * 1024, 1);
+        let mut bytes = Vec::new();
+        export::export(&vault, &mut bytes, &key, &settings).unwrap();
+        assert!(reader::read(&mut &bytes[..], &CompositeKey::from_password("wrong")).is_err());
+        let db = reader::read(&mut &bytes[..], &key).unwrap();
+
+        // Everything in the database came from the vault, so nothing is new.
+        let actions = plan_import(&vault, &db);
+        assert!(actions.iter().all(|action| match *action {
+            ImportAction::Skip { .. } => true,
+            _ => false,
+        }));
+
+        let hulu = db.root
+            .entries
+            .iter()
+            .chain(db.root.groups.iter().flat_map(|g| g.entries.iter()))
+            .find(|entry| entry.get("Title") == Some("Hulu"))
+            .unwrap();
+        let mut renamed = db.clone();
+        renamed.root = ::kdbx::database::Group::new(vec![0; 16], "Root", hulu.times);
+        let mut entry = hulu.clone();
+        entry.uuid = vec![1; 16];
+        renamed.root.entries.push(entry);
+        match plan_import(&vault, &renamed)[0] {
+            ImportAction::CreateItem(ref new_item) => {
+                assert_eq!(new_item.category, Category::Login);
+                assert_eq!(new_item.uuid.as_ref().unwrap(), "01010101010101010101010101010101");
+                assert!(new_item.details.find("fields").is_some());
+                assert!(!new_item.urls.is_empty());
+            }
+            _ => panic!("new entry must be created"),
+        }
+    }
+}
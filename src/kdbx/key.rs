@@ -0,0 +1,312 @@
+use argon2;
+use base64;
+use openssl::crypto::{hash, rand, symm};
+use std::io::{self, Result as IoResult};
+
+use kdbx::variant_dictionary::{Variant, VariantDictionary};
+use kdbx::xml::Element;
+use util::invalid_data;
+
+const AES_KDF_UUID: [u8; 16] = [0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d,
+                                0x08, 0xc1, 0x8a, 0x4f, 0xea];
+const ARGON2D_UUID: [u8; 16] = [0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9,
+                                0xa4, 0x03, 0xe3, 0x0a, 0x0c];
+const ARGON2ID_UUID: [u8; 16] = [0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc,
+                                 0x3e, 0xc6, 0xf0, 0xa1, 0xe6];
+const ARGON2_VERSION_10: u32 = 0x10;
+const ARGON2_VERSION_13: u32 = 0x13;
+
+/// Limits on the Argon2 parameters a database may ask for, well above what KeePass clients
+/// write, so that a crafted file cannot take all memory or hours to open.
+const MAX_ARGON2_ITERATIONS: u64 = 10000;
+const MAX_ARGON2_MEMORY_KIB: u64 = 4 * 1024 * 1024;
+const MAX_ARGON2_PARALLELISM: u64 = 256;
+
+/// The hash of the credentials that unlock a database.
+#[derive(Clone)]
+pub struct CompositeKey {
+    hash: Vec<u8>,
+}
+
+impl CompositeKey {
+    pub fn from_password(password: &str) -> Self {
+        let password_hash = hash::hash(hash::Type::SHA256, password.as_bytes());
+        CompositeKey { hash: hash::hash(hash::Type::SHA256, &password_hash) }
+    }
+
+    /// Combines a password and the contents of a key file, either of which may be absent.
+    pub fn new(password: Option<&str>, key_file: Option<&[u8]>) -> IoResult<Self> {
+        let mut bytes = Vec::new();
+        if let Some(password) = password {
+            bytes.extend(hash::hash(hash::Type::SHA256, password.as_bytes()));
+        }
+        if let Some(key_file) = key_file {
+            bytes.extend(try!(key_file_key(key_file)));
+        }
+        if bytes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "a password or a key file is required"));
+        }
+        Ok(CompositeKey { hash: hash::hash(hash::Type::SHA256, &bytes) })
+    }
+
+    /// Derives the key from which the payload keys are made.
+    pub fn transform(&self, kdf: &Kdf) -> IoResult<Vec<u8>> {
+        kdf.derive(&self.hash)
+    }
+}
+
+/// Returns the 32-byte key of a key file. XML key files carry the key in base64 (version 1)
+/// or hex (version 2), 32-byte files are the key itself, 64-byte files are the key in hex,
+/// and any other file is hashed.
+fn key_file_key(bytes: &[u8]) -> IoResult<Vec<u8>> {
+    if let Ok(root) = Element::parse(bytes) {
+        if root.name == "KeyFile" {
+            let version = root.find("Meta/Version").map(|e| e.text.trim()).unwrap_or("");
+            let data = try!(root.find("Key/Data")
+                .ok_or_else(|| invalid_data("key file has no data")));
+            if version.starts_with("2.") {
+                let hex: String = data.text.chars().filter(|c| !c.is_whitespace()).collect();
+                let key = try!(decode_hex(&hex).ok_or_else(|| invalid_data("malformed key")));
+                let checksum = data.attribute("Hash").and_then(decode_hex);
+                let digest = hash::hash(hash::Type::SHA256, &key);
+                if checksum.map_or(false, |checksum| checksum[..] != digest[..checksum.len()]) {
+                    return Err(invalid_data("key file checksum does not match"));
+                }
+                return Ok(key);
+            }
+            return base64::u8de(data.text.trim().as_bytes())
+                .map_err(|_| invalid_data("malformed key"));
+        }
+    }
+    if bytes.len() == 32 {
+        return Ok(bytes.to_vec());
+    }
+    if bytes.len() == 64 {
+        if let Some(key) = ::std::str::from_utf8(bytes).ok().and_then(decode_hex) {
+            return Ok(key);
+        }
+    }
+    Ok(hash::hash(hash::Type::SHA256, bytes))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+    (0..s.len() / 2).map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()).collect()
+}
+
+/// The key derivation function, along with its parameters.
+#[derive(Clone, Debug)]
+pub enum Kdf {
+    /// AES-KDF, which encrypts the key `rounds` times with AES-256-ECB.
+    Aes {
+        rounds: u64,
+        seed: Vec<u8>,
+    },
+    Argon2 {
+        /// Argon2id if true, Argon2d otherwise.
+        id: bool,
+        iterations: u64,
+        /// Memory in bytes.
+        memory: u64,
+        parallelism: u32,
+        salt: Vec<u8>,
+        version: u32,
+    },
+}
+
+impl Kdf {
+    /// Returns Argon2id with a random salt.
+    pub fn argon2id(iterations: u64, memory: u64, parallelism: u32) -> Self {
+        Kdf::Argon2 {
+            id: true,
+            iterations: iterations,
+            memory: memory,
+            parallelism: parallelism,
+            salt: rand::rand_bytes(32),
+            version: ARGON2_VERSION_13,
+        }
+    }
+
+    pub fn from_variant_dictionary(dict: &VariantDictionary) -> IoResult<Self> {
+        let uuid = match dict.get("$UUID") {
+            Some(&Variant::Bytes(ref uuid)) => uuid.clone(),
+            _ => return Err(invalid_data("KDF parameters have no UUID")),
+        };
+        let bytes = |key| {
+            match dict.get(key) {
+                Some(&Variant::Bytes(ref bytes)) => Ok(bytes.clone()),
+                _ => Err(invalid_data(&format!("KDF parameter {} is missing", key))),
+            }
+        };
+        let uint = |key| {
+            match dict.get(key) {
+                Some(&Variant::UInt32(n)) => Ok(n as u64),
+                Some(&Variant::UInt64(n)) => Ok(n),
+                _ => Err(invalid_data(&format!("KDF parameter {} is missing", key))),
+            }
+        };
+        if uuid[..] == AES_KDF_UUID[..] {
+            Ok(Kdf::Aes {
+                rounds: try!(uint("R")),
+                seed: try!(bytes("S")),
+            })
+        } else if uuid[..] == ARGON2D_UUID[..] || uuid[..] == ARGON2ID_UUID[..] {
+            Ok(Kdf::Argon2 {
+                id: uuid[..] == ARGON2ID_UUID[..],
+                iterations: try!(uint("I")),
+                memory: try!(uint("M")),
+                parallelism: try!(uint("P")) as u32,
+                salt: try!(bytes("S")),
+                version: try!(uint("V")) as u32,
+            })
+        } else {
+            Err(invalid_data("unsupported KDF"))
+        }
+    }
+
+    /// Returns the same function with a new random salt, so that a database written twice
+    /// does not share derived keys.
+    pub fn reseeded(&self) -> Self {
+        match *self {
+            Kdf::Aes { rounds, .. } => {
+                Kdf::Aes {
+                    rounds: rounds,
+                    seed: rand::rand_bytes(32),
+                }
+            }
+            Kdf::Argon2 { id, iterations, memory, parallelism, version, .. } => {
+                Kdf::Argon2 {
+                    id: id,
+                    iterations: iterations,
+                    memory: memory,
+                    parallelism: parallelism,
+                    salt: rand::rand_bytes(32),
+                    version: version,
+                }
+            }
+        }
+    }
+
+    pub fn to_variant_dictionary(&self) -> VariantDictionary {
+        let mut dict = VariantDictionary::new();
+        match *self {
+            Kdf::Aes { rounds, ref seed } => {
+                dict.insert("$UUID", Variant::Bytes(AES_KDF_UUID.to_vec()));
+                dict.insert("R", Variant::UInt64(rounds));
+                dict.insert("S", Variant::Bytes(seed.clone()));
+            }
+            Kdf::Argon2 { id, iterations, memory, parallelism, ref salt, version } => {
+                let uuid = if id { &ARGON2ID_UUID } else { &ARGON2D_UUID };
+                dict.insert("$UUID", Variant::Bytes(uuid.to_vec()));
+                dict.insert("I", Variant::UInt64(iterations));
+                dict.insert("M", Variant::UInt64(memory));
+                dict.insert("P", Variant::UInt32(parallelism));
+                dict.insert("S", Variant::Bytes(salt.clone()));
+                dict.insert("V", Variant::UInt32(version));
+            }
+        }
+        dict
+    }
+
+    fn derive(&self, key: &[u8]) -> IoResult<Vec<u8>> {
+        match *self {
+            Kdf::Aes { rounds, ref seed } => {
+                if key.len() != 32 || seed.len() != 32 {
+                    return Err(invalid_data("malformed AES-KDF parameters"));
+                }
+                let crypter = symm::Crypter::new(symm::Type::AES_256_ECB);
+                crypter.init(symm::Mode::Encrypt, seed, &[]);
+                crypter.pad(false);
+                let mut block = key.to_vec();
+                for _ in 0..rounds {
+                    block = crypter.update(&block);
+                }
+                Ok(hash::hash(hash::Type::SHA256, &block))
+            }
+            Kdf::Argon2 { id, iterations, memory, parallelism, ref salt, version } => {
+                let version = match version {
+                    ARGON2_VERSION_10 => argon2::Version::Version10,
+                    ARGON2_VERSION_13 => argon2::Version::Version13,
+                    _ => return Err(invalid_data("unsupported Argon2 version")),
+                };
+                let time_cost = try!(argon2_param("iterations", iterations, MAX_ARGON2_ITERATIONS));
+                // Memory is given in bytes but used in KiB.
+                let mem_cost = try!(argon2_param("memory in KiB",
+                                                 memory / 1024,
+                                                 MAX_ARGON2_MEMORY_KIB));
+                let lanes = try!(argon2_param("parallelism",
+                                              parallelism as u64,
+                                              MAX_ARGON2_PARALLELISM));
+                let config = argon2::Config {
+                    ad: &[],
+                    hash_length: 32,
+                    lanes: lanes,
+                    mem_cost: mem_cost,
+                    secret: &[],
+                    thread_mode: argon2::ThreadMode::from_threads(lanes),
+                    time_cost: time_cost,
+                    variant: if id { argon2::Variant::Argon2id } else { argon2::Variant::Argon2d },
+                    version: version,
+                };
+                argon2::hash_raw(key, salt, &config)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            }
+        }
+    }
+}
+
+/// Returns `value` as a `u32` if it is at most `max`, which must fit in a `u32`.
+fn argon2_param(name: &str, value: u64, max: u64) -> IoResult<u32> {
+    if value > max {
+        return Err(invalid_data(&format!("Argon2 {} of {} is above the limit of {}",
+                                         name,
+                                         value,
+                                         max)));
+    }
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{CompositeKey, Kdf, key_file_key};
+
+    #[test]
+    fn test_key_file_key() {
+        let key: Vec<u8> = (0..32).collect();
+        assert_eq!(key_file_key(&key).unwrap(), key);
+        let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(key_file_key(hex.as_bytes()).unwrap(), key);
+        let v2 = format!("<?xml version=\"1.0\"?><KeyFile><Meta><Version>2.0</Version></Meta>\
+                          <Key><Data Hash=\"630DCD29\">{}</Data></Key></KeyFile>",
+                         hex);
+        assert_eq!(key_file_key(v2.as_bytes()).unwrap(), key);
+        assert!(key_file_key(v2.replace("630DCD29", "00000000").as_bytes()).is_err());
+        assert!(CompositeKey::new(None, None).is_err());
+    }
+    #[test]
+    fn test_argon2_limits() {
+        let key = CompositeKey::from_password("password");
+        let kdf = |iterations: u64, memory: u64, parallelism: u32| {
+            Kdf::Argon2 {
+                id: true,
+                iterations: iterations,
+                memory: memory,
+                parallelism: parallelism,
+                salt: vec![0; 32],
+                version: super::ARGON2_VERSION_13,
+            }
+        };
+        assert_eq!(key.transform(&kdf(2, 64 * 1024, 2)).unwrap().len(), 32);
+        for kdf in &[kdf(1 << 40, 64 * 1024, 2),
+                     kdf(2, 1 << 42, 2),
+                     kdf(2, 1 << 45, 2),
+                     kdf(2, 64 * 1024, 1 << 20)] {
+            assert_eq!(key.transform(kdf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! KeePass KDBX databases.
+
+use openssl::crypto::hash;
+
+use self::chacha20::ChaCha20;
+use self::cipher::Cipher;
+use self::key::Kdf;
+
+pub mod chacha20;
+pub mod cipher;
+pub mod database;
+pub mod export;
+pub mod import;
+pub mod key;
+pub mod reader;
+pub mod salsa20;
+pub mod variant_dictionary;
+pub mod writer;
+mod xml;
+
+pub const SIGNATURE_1: u32 = 0x9AA2D903;
+pub const SIGNATURE_2: u32 = 0xB54BFB67;
+pub const VERSION_4: u32 = 0x00040000;
+
+/// Seconds between 0001-01-01 and 1970-01-01, the epochs of KDBX 4 and Unix.
+pub const UNIX_EPOCH_IN_KDBX_SECONDS: u64 = 62135596800;
+
+pub const HEADER_END: u8 = 0;
+pub const HEADER_CIPHER_ID: u8 = 2;
+pub const HEADER_COMPRESSION_FLAGS: u8 = 3;
+pub const HEADER_MASTER_SEED: u8 = 4;
+pub const HEADER_TRANSFORM_SEED: u8 = 5;
+pub const HEADER_TRANSFORM_ROUNDS: u8 = 6;
+pub const HEADER_ENCRYPTION_IV: u8 = 7;
+pub const HEADER_PROTECTED_STREAM_KEY: u8 = 8;
+pub const HEADER_STREAM_START_BYTES: u8 = 9;
+pub const HEADER_INNER_RANDOM_STREAM_ID: u8 = 10;
+pub const HEADER_KDF_PARAMETERS: u8 = 11;
+
+pub const INNER_HEADER_END: u8 = 0;
+pub const INNER_HEADER_STREAM_ID: u8 = 1;
+pub const INNER_HEADER_STREAM_KEY: u8 = 2;
+pub const INNER_HEADER_BINARY: u8 = 3;
+
+pub const INNER_STREAM_SALSA20: u32 = 2;
+pub const INNER_STREAM_CHACHA20: u32 = 3;
+
+/// A cipher that XORs data with a keystream, so that encryption and decryption are the same.
+pub trait StreamCipher {
+    /// XORs `bytes` with the next bytes of the keystream.
+    fn apply_keystream(&mut self, bytes: &mut [u8]);
+
+    fn process(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut output = bytes.to_vec();
+        self.apply_keystream(&mut output);
+        output
+    }
+}
+
+/// How a database is encrypted.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub cipher: Cipher,
+    pub compress: bool,
+    pub kdf: Kdf,
+}
+
+impl Settings {
+    /// Returns AES-256 with Argon2id at 64 MiB and 10 iterations, close to what KeePassXC
+    /// picks on a typical machine.
+    pub fn new() -> Self {
+        Settings {
+            cipher: Cipher::Aes256,
+            compress: true,
+            kdf: Kdf::argon2id(10, 64 * 1024 * 1024, 2),
+        }
+    }
+}
+
+/// Returns the key that authenticates the payload block `index`. The header uses the index
+/// `u64::max_value()`.
+pub fn block_hmac_key(hmac_key: &[u8], index: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_u64_le(&mut bytes, index);
+    bytes.extend_from_slice(hmac_key);
+    hash::hash(hash::Type::SHA512, &bytes)
+}
+
+/// Returns the ChaCha20 stream that protects values such as passwords inside the XML.
+pub fn inner_stream(inner_stream_key: &[u8]) -> ChaCha20 {
+    let hash = hash::hash(hash::Type::SHA512, inner_stream_key);
+    ChaCha20::new(&hash[0..32], &hash[32..44])
+}
+
+pub fn read_u16_le(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) | (bytes[1] as u16) << 8
+}
+
+pub fn read_u32_le(bytes: &[u8]) -> u32 {
+    (0..4).fold(0, |n, i| n | (bytes[i] as u32) << (8 * i))
+}
+
+pub fn read_u64_le(bytes: &[u8]) -> u64 {
+    (0..8).fold(0, |n, i| n | (bytes[i] as u64) << (8 * i))
+}
+
+pub fn write_u16_le(bytes: &mut Vec<u8>, n: u16) {
+    bytes.push(n as u8);
+    bytes.push((n >> 8) as u8);
+}
+
+pub fn write_u32_le(bytes: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        bytes.push((n >> (8 * i)) as u8);
+    }
+}
+
+pub fn write_u64_le(bytes: &mut Vec<u8>, n: u64) {
+    for i in 0..8 {
+        bytes.push((n >> (8 * i)) as u8);
+    }
+}
@@ -0,0 +1,432 @@
+use base64;
+use flate2::read::GzDecoder;
+use openssl::crypto::{hash, hmac};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Result as IoResult};
+
+use iso8601;
+use kdbx::{self, StreamCipher, read_u16_le, read_u32_le, read_u64_le,
+           write_u64_le};
+use kdbx::cipher::Cipher;
+use kdbx::database::{Database, Entry, EntryBinary, EntryString, Group, Times};
+use kdbx::key::{CompositeKey, Kdf};
+use kdbx::salsa20::Salsa20;
+use kdbx::variant_dictionary::VariantDictionary;
+use kdbx::xml::Element;
+use util::{encode_base64, invalid_data};
+
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+/// The header fields that both KDBX 3.1 and KDBX 4 need to decrypt the payload.
+struct Header {
+    cipher: Cipher,
+    compress: bool,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    master_seed: Vec<u8>,
+    /// KDBX 3.1 only; KDBX 4 keeps these in the inner header.
+    inner_stream: Option<(u32, Vec<u8>)>,
+    /// KDBX 3.1 only.
+    stream_start: Vec<u8>,
+}
+
+/// Reads a KDBX 3.1 or KDBX 4 database encrypted with `key`.
+pub fn read<R: Read + ?Sized>(reader: &mut R, key: &CompositeKey) -> IoResult<Database> {
+    let mut bytes = Vec::new();
+    try!(reader.read_to_end(&mut bytes));
+    let mut pos = 0;
+    let signature = try!(take(&bytes, &mut pos, 12));
+    if read_u32_le(&signature[0..]) != kdbx::SIGNATURE_1 ||
+       read_u32_le(&signature[4..]) != kdbx::SIGNATURE_2 {
+        return Err(invalid_data("not a KeePass database"));
+    }
+    let major_version = read_u32_le(&signature[8..]) >> 16;
+    if major_version != 3 && major_version != 4 {
+        return Err(invalid_data("unsupported KDBX version"));
+    }
+    let header = try!(read_header(&bytes, &mut pos, major_version));
+    let header_bytes = &bytes[..pos];
+
+    let transformed_key = try!(key.transform(&header.kdf));
+    let mut seeded_key = header.master_seed.clone();
+    seeded_key.extend_from_slice(&transformed_key);
+    let enc_key = hash::hash(hash::Type::SHA256, &seeded_key);
+
+    let (inner_stream_id, inner_stream_key, binaries, xml) = if major_version == 4 {
+        let header_hash = try!(take(&bytes, &mut pos, 32));
+        if header_hash != &hash::hash(hash::Type::SHA256, header_bytes)[..] {
+            return Err(invalid_data("header is corrupt"));
+        }
+        seeded_key.push(1);
+        let hmac_key = hash::hash(hash::Type::SHA512, &seeded_key);
+        let header_hmac = try!(take(&bytes, &mut pos, 32));
+        let expected = hmac::hmac(hash::Type::SHA256,
+                                  &kdbx::block_hmac_key(&hmac_key, u64::max_value()),
+                                  header_bytes);
+        if header_hmac != &expected[..] {
+            return Err(wrong_key());
+        }
+        let ciphertext = try!(read_hmac_blocks(&bytes, &mut pos, &hmac_key));
+        let mut payload = try!(header.cipher
+            .decrypt(&enc_key, &header.iv, &ciphertext)
+            .ok_or_else(|| invalid_data("payload is corrupt")));
+        if header.compress {
+            payload = try!(gunzip(&payload));
+        }
+        try!(read_inner_header(&payload))
+    } else {
+        let ciphertext = &bytes[pos..];
+        let plaintext = try!(header.cipher
+            .decrypt(&enc_key, &header.iv, ciphertext)
+            .ok_or_else(wrong_key));
+        let start_len = header.stream_start.len();
+        if plaintext.len() < start_len || plaintext[..start_len] != header.stream_start[..] {
+            return Err(wrong_key());
+        }
+        let mut payload = try!(read_hashed_blocks(&plaintext[start_len..]));
+        if header.compress {
+            payload = try!(gunzip(&payload));
+        }
+        let (id, key) = try!(header.inner_stream
+            .ok_or_else(|| invalid_data("inner stream is missing")));
+        (id, key, Vec::new(), payload)
+    };
+
+    let mut inner_stream: Box<StreamCipher> = match inner_stream_id {
+        kdbx::INNER_STREAM_SALSA20 => {
+            Box::new(Salsa20::new(&hash::hash(hash::Type::SHA256, &inner_stream_key),
+                                  &SALSA20_NONCE))
+        }
+        kdbx::INNER_STREAM_CHACHA20 => Box::new(kdbx::inner_stream(&inner_stream_key)),
+        _ => return Err(invalid_data("unsupported inner stream cipher")),
+    };
+    let mut root = try!(Element::parse(&xml));
+    try!(unprotect(&mut root, &mut *inner_stream));
+    to_database(&root, binaries)
+}
+
+fn wrong_key() -> io::Error {
+    invalid_data("wrong password or key file")
+}
+
+/// Returns the next `n` bytes, or an error if the database ends before them.
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, n: usize) -> IoResult<&'a [u8]> {
+    if bytes.len() - *pos < n {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "database is truncated"));
+    }
+    *pos += n;
+    Ok(&bytes[*pos - n..*pos])
+}
+
+/// Reads the outer header. Field sizes are 16 bits in KDBX 3.1 and 32 bits in KDBX 4.
+fn read_header(bytes: &[u8], pos: &mut usize, major_version: u32) -> IoResult<Header> {
+    let mut fields = BTreeMap::new();
+    loop {
+        let id = try!(take(bytes, pos, 1))[0];
+        let size = if major_version == 4 {
+            read_u32_le(try!(take(bytes, pos, 4))) as usize
+        } else {
+            read_u16_le(try!(take(bytes, pos, 2))) as usize
+        };
+        let data = try!(take(bytes, pos, size));
+        if id == kdbx::HEADER_END {
+            break;
+        }
+        fields.insert(id, data.to_vec());
+    }
+    let field = |id| fields.get(&id).ok_or_else(|| invalid_data("header field is missing"));
+    let cipher = try!(Cipher::from_uuid(try!(field(kdbx::HEADER_CIPHER_ID)))
+        .ok_or_else(|| invalid_data("unsupported cipher")));
+    let compression = try!(field(kdbx::HEADER_COMPRESSION_FLAGS));
+    let kdf = if major_version == 4 {
+        let params = try!(field(kdbx::HEADER_KDF_PARAMETERS));
+        try!(Kdf::from_variant_dictionary(&try!(VariantDictionary::from_bytes(params))))
+    } else {
+        let rounds = try!(field(kdbx::HEADER_TRANSFORM_ROUNDS));
+        if rounds.len() != 8 {
+            return Err(invalid_data("malformed transform rounds"));
+        }
+        Kdf::Aes {
+            rounds: read_u64_le(rounds),
+            seed: try!(field(kdbx::HEADER_TRANSFORM_SEED)).clone(),
+        }
+    };
+    let inner_stream = match (fields.get(&kdbx::HEADER_INNER_RANDOM_STREAM_ID),
+                              fields.get(&kdbx::HEADER_PROTECTED_STREAM_KEY)) {
+        (Some(id), Some(key)) if id.len() == 4 => Some((read_u32_le(id), key.clone())),
+        _ => None,
+    };
+    Ok(Header {
+        cipher: cipher,
+        compress: compression.len() == 4 && read_u32_le(compression) != 0,
+        iv: try!(field(kdbx::HEADER_ENCRYPTION_IV)).clone(),
+        kdf: kdf,
+        master_seed: try!(field(kdbx::HEADER_MASTER_SEED)).clone(),
+        inner_stream: inner_stream,
+        stream_start: fields.get(&kdbx::HEADER_STREAM_START_BYTES).cloned().unwrap_or(vec![]),
+    })
+}
+
+/// Joins the KDBX 4 payload blocks, checking the HMAC of each.
+fn read_hmac_blocks(bytes: &[u8], pos: &mut usize, hmac_key: &[u8]) -> IoResult<Vec<u8>> {
+    let mut ciphertext = Vec::new();
+    for index in 0.. {
+        let block_hmac = try!(take(bytes, pos, 32));
+        let size = try!(take(bytes, pos, 4));
+        let data = try!(take(bytes, pos, read_u32_le(size) as usize));
+        let mut message = Vec::new();
+        write_u64_le(&mut message, index);
+        message.extend_from_slice(size);
+        message.extend_from_slice(data);
+        let block_key = kdbx::block_hmac_key(hmac_key, index);
+        if block_hmac != &hmac::hmac(hash::Type::SHA256, &block_key, &message)[..] {
+            return Err(invalid_data("payload block is corrupt"));
+        }
+        if data.is_empty() {
+            break;
+        }
+        ciphertext.extend_from_slice(data);
+    }
+    Ok(ciphertext)
+}
+
+/// Joins the KDBX 3.1 payload blocks, checking the SHA-256 of each.
+fn read_hashed_blocks(bytes: &[u8]) -> IoResult<Vec<u8>> {
+    let mut payload = Vec::new();
+    let mut pos = 0;
+    for index in 0.. {
+        let block_index = try!(take(bytes, &mut pos, 4));
+        let block_hash = try!(take(bytes, &mut pos, 32));
+        let size = read_u32_le(try!(take(bytes, &mut pos, 4))) as usize;
+        if read_u32_le(block_index) != index {
+            return Err(invalid_data("payload blocks are out of order"));
+        }
+        if size == 0 {
+            break;
+        }
+        let data = try!(take(bytes, &mut pos, size));
+        if block_hash != &hash::hash(hash::Type::SHA256, data)[..] {
+            return Err(invalid_data("payload block is corrupt"));
+        }
+        payload.extend_from_slice(data);
+    }
+    Ok(payload)
+}
+
+fn gunzip(bytes: &[u8]) -> IoResult<Vec<u8>> {
+    let mut decoder = try!(GzDecoder::new(bytes));
+    let mut output = Vec::new();
+    try!(decoder.read_to_end(&mut output));
+    Ok(output)
+}
+
+/// Splits the decrypted KDBX 4 payload into the inner stream id and key, the binaries and
+/// the XML.
+fn read_inner_header(payload: &[u8]) -> IoResult<(u32, Vec<u8>, Vec<Vec<u8>>, Vec<u8>)> {
+    let mut pos = 0;
+    let mut stream_id = None;
+    let mut stream_key = None;
+    let mut binaries = Vec::new();
+    loop {
+        let id = try!(take(payload, &mut pos, 1))[0];
+        let size = read_u32_le(try!(take(payload, &mut pos, 4))) as usize;
+        let data = try!(take(payload, &mut pos, size));
+        match id {
+            kdbx::INNER_HEADER_END => break,
+            kdbx::INNER_HEADER_STREAM_ID if data.len() == 4 => stream_id = Some(read_u32_le(data)),
+            kdbx::INNER_HEADER_STREAM_KEY => stream_key = Some(data.to_vec()),
+            // The first byte holds flags such as whether the binary is protected in memory.
+            kdbx::INNER_HEADER_BINARY if !data.is_empty() => binaries.push(data[1..].to_vec()),
+            _ => {}
+        }
+    }
+    match (stream_id, stream_key) {
+        (Some(id), Some(key)) => Ok((id, key, binaries, payload[pos..].to_vec())),
+        _ => Err(invalid_data("inner stream is missing")),
+    }
+}
+
+/// Decrypts the values marked as protected. They were XORed with the inner stream in
+/// document order, including those in entry history, so every one of them has to be
+/// visited. String values keep the attribute so that they can be told apart afterwards.
+fn unprotect(root: &mut Element, inner_stream: &mut StreamCipher) -> IoResult<()> {
+    let mut result = Ok(());
+    root.walk_mut(&mut |element: &mut Element| {
+        if result.is_err() || element.attribute("Protected") != Some("True") {
+            return;
+        }
+        let mut value = match base64::u8de(element.text.trim().as_bytes()) {
+            Ok(value) => value,
+            Err(_) => {
+                result = Err(invalid_data("malformed protected value"));
+                return;
+            }
+        };
+        inner_stream.apply_keystream(&mut value);
+        element.attributes.retain(|&(ref name, _)| name != "Protected");
+        if element.name == "Value" {
+            element.attributes.push(("Protected".to_string(), "Decrypted".to_string()));
+            element.text = String::from_utf8_lossy(&value).into_owned();
+        } else {
+            // Binaries in the KDBX 3.1 pool stay base64 like their unprotected siblings.
+            element.text = encode_base64(&value);
+        }
+    });
+    result
+}
+
+fn to_database(root: &Element, mut binaries: Vec<Vec<u8>>) -> IoResult<Database> {
+    let meta = try!(root.child("Meta").ok_or_else(|| invalid_data("Meta is missing")));
+    // KDBX 3.1 keeps binaries in Meta under IDs, which entries refer to.
+    let mut binary_ids = BTreeMap::new();
+    if let Some(pool) = meta.child("Binaries") {
+        for binary in pool.children_named("Binary") {
+            let mut contents = try!(decode_base64(&binary.text));
+            if binary.attribute("Compressed") == Some("True") {
+                contents = try!(gunzip(&contents));
+            }
+            if let Some(id) = binary.attribute("ID") {
+                binary_ids.insert(id.to_string(), binaries.len());
+            }
+            binaries.push(contents);
+        }
+    }
+    let root_group = try!(root.find("Root/Group")
+        .ok_or_else(|| invalid_data("root group is missing")));
+    let recycle_bin = if meta.child_text("RecycleBinEnabled") == Some("True") {
+        meta.child_text("RecycleBinUUID")
+            .and_then(|uuid| decode_base64(uuid).ok())
+            .and_then(|uuid| if uuid.iter().any(|&b| b != 0) { Some(uuid) } else { None })
+    } else {
+        None
+    };
+    let mut db = Database::new(meta.child_text("DatabaseName").unwrap_or(""),
+                               try!(read_group(root_group, &binary_ids)));
+    db.binaries = binaries;
+    db.recycle_bin = recycle_bin;
+    Ok(db)
+}
+
+fn read_group(element: &Element, binary_ids: &BTreeMap<String, usize>) -> IoResult<Group> {
+    let mut group = Group::new(try!(decode_base64(element.child_text("UUID").unwrap_or(""))),
+                               element.child_text("Name").unwrap_or(""),
+                               read_times(element));
+    for child in &element.children {
+        match &child.name[..] {
+            "Entry" => group.entries.push(try!(read_entry(child, binary_ids))),
+            "Group" => group.groups.push(try!(read_group(child, binary_ids))),
+            _ => {}
+        }
+    }
+    Ok(group)
+}
+
+fn read_entry(element: &Element, binary_ids: &BTreeMap<String, usize>) -> IoResult<Entry> {
+    let mut entry = Entry::new(try!(decode_base64(element.child_text("UUID").unwrap_or(""))),
+                               read_times(element));
+    entry.tags = element.child_text("Tags")
+        .unwrap_or("")
+        .split(|c| c == ';' || c == ',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    for string in element.children_named("String") {
+        let value = string.child("Value");
+        entry.strings.push(EntryString {
+            key: string.child_text("Key").unwrap_or("").to_string(),
+            protected: value.and_then(|v| v.attribute("Protected")) == Some("Decrypted"),
+            value: value.map(|v| v.text.clone()).unwrap_or_else(String::new),
+        });
+    }
+    for binary in element.children_named("Binary") {
+        let reference = binary.child("Value").and_then(|v| v.attribute("Ref"));
+        let index = match reference {
+            Some(r) if binary_ids.is_empty() => r.parse().ok(),
+            Some(r) => binary_ids.get(r).cloned(),
+            None => None,
+        };
+        let index = try!(index.ok_or_else(|| invalid_data("attachment reference is invalid")));
+        entry.binaries.push(EntryBinary {
+            index: index,
+            key: binary.child_text("Key").unwrap_or("").to_string(),
+        });
+    }
+    Ok(entry)
+}
+
+fn read_times(element: &Element) -> Times {
+    let time = |name| {
+        element.find(&format!("Times/{}", name))
+            .and_then(|e| parse_time(e.text.trim()))
+            .unwrap_or(0)
+    };
+    Times {
+        created: time("CreationTime"),
+        modified: time("LastModificationTime"),
+    }
+}
+
+/// Parses a time as either KDBX 4 writes it, the base64 of the seconds since 0001-01-01, or
+/// as KDBX 3.1 does, in ISO 8601. Returns Unix time.
+fn parse_time(s: &str) -> Option<u64> {
+    if let Some(time) = iso8601::parse(s) {
+        return Some(time);
+    }
+    decode_base64(s).ok().and_then(|bytes| {
+        if bytes.len() == 8 {
+            Some(read_u64_le(&bytes).saturating_sub(kdbx::UNIX_EPOCH_IN_KDBX_SECONDS))
+        } else {
+            None
+        }
+    })
+}
+
+fn decode_base64(s: &str) -> IoResult<Vec<u8>> {
+    base64::u8de(s.trim().as_bytes()).map_err(|_| invalid_data("malformed base64"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use kdbx::key::CompositeKey;
+    use super::{parse_time, read};
+
+    #[test]
+    fn test_read_kdbx_3_1() {
+        let mut key_file = Vec::new();
+        File::open("test/Sample31.key").unwrap().read_to_end(&mut key_file).unwrap();
+        let password_only = CompositeKey::from_password("decone");
+        assert!(read(&mut File::open("test/Sample31.kdbx").unwrap(), &password_only).is_err());
+
+        let key = CompositeKey::new(Some("decone"), Some(&key_file)).unwrap();
+        let db = read(&mut File::open("test/Sample31.kdbx").unwrap(), &key).unwrap();
+        assert_eq!(db.name, "Sample");
+        assert_eq!(db.recycle_bin, Some(vec![0x33; 16]));
+        assert_eq!(db.binaries, vec![b"hello, world\n".to_vec(), b"\x00\x01\x02binary".to_vec()]);
+
+        let mail = &db.root.entries[0];
+        assert_eq!(mail.get("Password"), Some("s3cr3t pass"));
+        assert_eq!(mail.get("PIN"), Some("1234"));
+        assert_eq!(mail.get("otp"), Some("otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP"));
+        assert_eq!(mail.get("Notes"), Some("line 1\nline 2"));
+        assert!(mail.strings.iter().find(|s| s.key == "PIN").unwrap().protected);
+        assert_eq!(mail.tags, vec!["work", "mail"]);
+        assert_eq!(mail.times.created, 1488603967);
+        assert_eq!(mail.binaries[0].key, "hello.txt");
+
+        // The value after the entry history decrypts only if the history was visited.
+        let recipe = &db.root.groups[0].entries[0];
+        assert_eq!(recipe.get("Secret"), Some("the sauce"));
+        assert_eq!(db.binaries[recipe.binaries[0].index], b"\x00\x01\x02binary");
+        assert_eq!(db.root.groups[1].entries[0].get("Password"), Some("gone"));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("2000-03-01T00:00:01Z"), Some(951868801));
+        assert_eq!(parse_time("AAAAAAAAAAA="), Some(0));
+        assert_eq!(parse_time("garbage"), None);
+    }
+}
@@ -0,0 +1,94 @@
+use kdbx::{StreamCipher, read_u32_le};
+
+/// The Salsa20/20 stream cipher with a 64-bit nonce, starting at block 0.
+///
+/// KDBX 3.1 uses it for the inner stream that hides protected values.
+pub struct Salsa20 {
+    keystream: [u8; 64],
+    offset: usize,
+    state: [u32; 16],
+}
+
+impl Salsa20 {
+    pub fn new(key: &[u8], nonce: &[u8]) -> Self {
+        assert!(key.len() == 32 && nonce.len() == 8);
+        let mut state = [0u32; 16];
+        state[0] = 0x61707865;
+        state[5] = 0x3320646e;
+        state[10] = 0x79622d32;
+        state[15] = 0x6b206574;
+        for i in 0..4 {
+            state[1 + i] = read_u32_le(&key[i * 4..]);
+            state[11 + i] = read_u32_le(&key[16 + i * 4..]);
+        }
+        state[6] = read_u32_le(&nonce[0..]);
+        state[7] = read_u32_le(&nonce[4..]);
+        Salsa20 {
+            keystream: [0; 64],
+            offset: 64,
+            state: state,
+        }
+    }
+
+    fn next_block(&mut self) {
+        let mut x = self.state;
+        for _ in 0..10 {
+            quarter_round(&mut x, 0, 4, 8, 12);
+            quarter_round(&mut x, 5, 9, 13, 1);
+            quarter_round(&mut x, 10, 14, 2, 6);
+            quarter_round(&mut x, 15, 3, 7, 11);
+            quarter_round(&mut x, 0, 1, 2, 3);
+            quarter_round(&mut x, 5, 6, 7, 4);
+            quarter_round(&mut x, 10, 11, 8, 9);
+            quarter_round(&mut x, 15, 12, 13, 14);
+        }
+        for i in 0..16 {
+            let word = x[i].wrapping_add(self.state[i]);
+            for j in 0..4 {
+                self.keystream[i * 4 + j] = (word >> (8 * j)) as u8;
+            }
+        }
+        self.state[8] = self.state[8].wrapping_add(1);
+        if self.state[8] == 0 {
+            self.state[9] = self.state[9].wrapping_add(1);
+        }
+        self.offset = 0;
+    }
+}
+
+impl StreamCipher for Salsa20 {
+    fn apply_keystream(&mut self, bytes: &mut [u8]) {
+        for byte in bytes.iter_mut() {
+            if self.offset == 64 {
+                self.next_block();
+            }
+            *byte ^= self.keystream[self.offset];
+            self.offset += 1;
+        }
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+    x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+    x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+    x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+#[cfg(test)]
+mod tests {
+    use kdbx::StreamCipher;
+    use super::Salsa20;
+
+    #[test]
+    fn test_ecrypt_set_1_vector_0() {
+        let mut key = [0; 32];
+        key[0] = 0x80;
+        let mut cipher = Salsa20::new(&key, &[0; 8]);
+        let keystream = cipher.process(&[0; 64]);
+        assert_eq!(&keystream[..16],
+                   &[0xe3, 0xbe, 0x8f, 0xdd, 0x8b, 0xec, 0xa2, 0xe3, 0xea, 0x8e, 0xf9, 0x47,
+                     0x5b, 0x29, 0xa6, 0xe7]);
+        assert_eq!(&keystream[60..], &[0x7e, 0xf1, 0xa1, 0x17]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult};
+
+use kdbx::{read_u16_le, read_u32_le, read_u64_le, write_u16_le, write_u32_le, write_u64_le};
+use util::invalid_data;
+
+const VERSION: u16 = 0x0100;
+
+const TYPE_END: u8 = 0x00;
+const TYPE_UINT32: u8 = 0x04;
+const TYPE_UINT64: u8 = 0x05;
+const TYPE_BOOL: u8 = 0x08;
+const TYPE_INT32: u8 = 0x0C;
+const TYPE_INT64: u8 = 0x0D;
+const TYPE_STRING: u8 = 0x18;
+const TYPE_BYTES: u8 = 0x42;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Variant {
+    UInt32(u32),
+    UInt64(u64),
+    Bool(bool),
+    Int32(i32),
+    Int64(i64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+/// The typed key-value map that KDBX 4 uses for KDF parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantDictionary {
+    entries: BTreeMap<String, Variant>,
+}
+
+impl VariantDictionary {
+    pub fn new() -> Self {
+        VariantDictionary { entries: BTreeMap::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Variant> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: &str, value: Variant) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> IoResult<Self> {
+        if bytes.len() < 2 || read_u16_le(bytes) >> 8 != VERSION >> 8 {
+            return Err(invalid_data("unsupported variant dictionary version"));
+        }
+        let mut dict = VariantDictionary::new();
+        let mut rest = &bytes[2..];
+        loop {
+            let kind = match rest.first() {
+                Some(&TYPE_END) => return Ok(dict),
+                Some(&kind) => kind,
+                None => return Err(invalid_data("variant dictionary is truncated")),
+            };
+            let (key, next) = try!(read_sized(&rest[1..]));
+            let (data, next) = try!(read_sized(next));
+            rest = next;
+            let key = try!(String::from_utf8(key.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+            let value = match (kind, data.len()) {
+                (TYPE_UINT32, 4) => Variant::UInt32(read_u32_le(data)),
+                (TYPE_UINT64, 8) => Variant::UInt64(read_u64_le(data)),
+                (TYPE_BOOL, 1) => Variant::Bool(data[0] != 0),
+                (TYPE_INT32, 4) => Variant::Int32(read_u32_le(data) as i32),
+                (TYPE_INT64, 8) => Variant::Int64(read_u64_le(data) as i64),
+                (TYPE_STRING, _) => {
+                    Variant::String(try!(String::from_utf8(data.to_vec())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))))
+                }
+                (TYPE_BYTES, _) => Variant::Bytes(data.to_vec()),
+                _ => return Err(invalid_data("malformed variant dictionary entry")),
+            };
+            dict.entries.insert(key, value);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_u16_le(&mut bytes, VERSION);
+        for (key, value) in &self.entries {
+            let (kind, data) = match *value {
+                Variant::UInt32(n) => (TYPE_UINT32, le_bytes(n as u64, 4)),
+                Variant::UInt64(n) => (TYPE_UINT64, le_bytes(n, 8)),
+                Variant::Bool(b) => (TYPE_BOOL, vec![b as u8]),
+                Variant::Int32(n) => (TYPE_INT32, le_bytes(n as u32 as u64, 4)),
+                Variant::Int64(n) => (TYPE_INT64, le_bytes(n as u64, 8)),
+                Variant::String(ref s) => (TYPE_STRING, s.as_bytes().to_vec()),
+                Variant::Bytes(ref b) => (TYPE_BYTES, b.clone()),
+            };
+            bytes.push(kind);
+            write_u32_le(&mut bytes, key.len() as u32);
+            bytes.extend_from_slice(key.as_bytes());
+            write_u32_le(&mut bytes, data.len() as u32);
+            bytes.extend(data);
+        }
+        bytes.push(TYPE_END);
+        bytes
+    }
+}
+
+/// Splits a 32-bit length-prefixed field off `bytes`.
+fn read_sized(bytes: &[u8]) -> IoResult<(&[u8], &[u8])> {
+    if bytes.len() < 4 {
+        return Err(invalid_data("variant dictionary is truncated"));
+    }
+    let len = read_u32_le(bytes) as usize;
+    if bytes.len() - 4 < len {
+        return Err(invalid_data("variant dictionary is truncated"));
+    }
+    Ok((&bytes[4..4 + len], &bytes[4 + len..]))
+}
+
+fn le_bytes(n: u64, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_u64_le(&mut bytes, n);
+    bytes.truncate(len);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Variant, VariantDictionary};
+
+    #[test]
+    fn test_round_trip() {
+        let mut dict = VariantDictionary::new();
+        dict.insert("I", Variant::UInt64(10));
+        dict.insert("P", Variant::UInt32(2));
+        dict.insert("S", Variant::Bytes(vec![1, 2, 3]));
+        dict.insert("N", Variant::String("name".to_string()));
+        dict.insert("B", Variant::Bool(true));
+        dict.insert("X", Variant::Int64(-1));
+        assert_eq!(VariantDictionary::from_bytes(&dict.to_bytes()).unwrap(), dict);
+        assert!(VariantDictionary::from_bytes(&dict.to_bytes()[..20]).is_err());
+    }
+}
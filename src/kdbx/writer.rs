@@ -0,0 +1,194 @@
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use openssl::crypto::{hash, hmac, rand};
+use std::io::{Result as IoResult, Write};
+
+use kdbx::{self, Settings, StreamCipher, write_u32_le, write_u64_le};
+use kdbx::chacha20::ChaCha20;
+use kdbx::database::{Database, Entry, Group, Times};
+use kdbx::key::CompositeKey;
+use kdbx::xml::XmlWriter;
+use util::encode_base64;
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Writes `db` as a KDBX 4 database encrypted with `key`.
+pub fn write<W: Write + ?Sized>(db: &Database,
+                                key: &CompositeKey,
+                                settings: &Settings,
+                                writer: &mut W)
+                                -> IoResult<()> {
+    let kdf = settings.kdf.reseeded();
+    let master_seed = rand::rand_bytes(32);
+    let iv = rand::rand_bytes(settings.cipher.iv_len());
+
+    let mut header = Vec::new();
+    write_u32_le(&mut header, kdbx::SIGNATURE_1);
+    write_u32_le(&mut header, kdbx::SIGNATURE_2);
+    write_u32_le(&mut header, kdbx::VERSION_4);
+    write_header_field(&mut header, kdbx::HEADER_CIPHER_ID, settings.cipher.uuid());
+    let mut compression = Vec::new();
+    write_u32_le(&mut compression, if settings.compress { 1 } else { 0 });
+    write_header_field(&mut header, kdbx::HEADER_COMPRESSION_FLAGS, &compression);
+    write_header_field(&mut header, kdbx::HEADER_MASTER_SEED, &master_seed);
+    write_header_field(&mut header, kdbx::HEADER_ENCRYPTION_IV, &iv);
+    write_header_field(&mut header,
+                       kdbx::HEADER_KDF_PARAMETERS,
+                       &kdf.to_variant_dictionary().to_bytes());
+    write_header_field(&mut header, kdbx::HEADER_END, b"\r\n\r\n");
+
+    let transformed_key = try!(key.transform(&kdf));
+    let mut seeded_key = master_seed.clone();
+    seeded_key.extend_from_slice(&transformed_key);
+    let enc_key = hash::hash(hash::Type::SHA256, &seeded_key);
+    seeded_key.push(1);
+    let hmac_key = hash::hash(hash::Type::SHA512, &seeded_key);
+
+    try!(writer.write_all(&header));
+    try!(writer.write_all(&hash::hash(hash::Type::SHA256, &header)));
+    try!(writer.write_all(&hmac::hmac(hash::Type::SHA256,
+                                      &kdbx::block_hmac_key(&hmac_key, u64::max_value()),
+                                      &header)));
+
+    let inner_stream_key = rand::rand_bytes(64);
+    let mut payload = inner_header(db, &inner_stream_key);
+    payload.extend(to_xml(db, &mut kdbx::inner_stream(&inner_stream_key)).into_bytes());
+    if settings.compress {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        try!(encoder.write_all(&payload));
+        payload = try!(encoder.finish());
+    }
+    let ciphertext = settings.cipher.encrypt(&enc_key, &iv, &payload);
+    write_blocks(writer, &hmac_key, &ciphertext)
+}
+
+fn write_header_field(header: &mut Vec<u8>, id: u8, data: &[u8]) {
+    header.push(id);
+    write_u32_le(header, data.len() as u32);
+    header.extend_from_slice(data);
+}
+
+/// Splits `ciphertext` into blocks, each authenticated by its index, followed by an empty
+/// block that marks the end.
+fn write_blocks<W: Write + ?Sized>(writer: &mut W,
+                                   hmac_key: &[u8],
+                                   ciphertext: &[u8])
+                                   -> IoResult<()> {
+    let mut chunks: Vec<&[u8]> = ciphertext.chunks(BLOCK_SIZE).collect();
+    chunks.push(&[]);
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let mut message = Vec::new();
+        write_u64_le(&mut message, index as u64);
+        write_u32_le(&mut message, chunk.len() as u32);
+        message.extend_from_slice(chunk);
+        let block_key = kdbx::block_hmac_key(hmac_key, index as u64);
+        try!(writer.write_all(&hmac::hmac(hash::Type::SHA256, &block_key, &message)));
+        try!(writer.write_all(&message[8..]));
+    }
+    Ok(())
+}
+
+fn inner_header(db: &Database, inner_stream_key: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut stream_id = Vec::new();
+    write_u32_le(&mut stream_id, kdbx::INNER_STREAM_CHACHA20);
+    write_header_field(&mut header, kdbx::INNER_HEADER_STREAM_ID, &stream_id);
+    write_header_field(&mut header, kdbx::INNER_HEADER_STREAM_KEY, inner_stream_key);
+    for binary in &db.binaries {
+        let mut data = vec![0];
+        data.extend_from_slice(binary);
+        write_header_field(&mut header, kdbx::INNER_HEADER_BINARY, &data);
+    }
+    write_header_field(&mut header, kdbx::INNER_HEADER_END, &[]);
+    header
+}
+
+fn to_xml(db: &Database, inner_stream: &mut ChaCha20) -> String {
+    let mut xml = XmlWriter::new();
+    xml.start("KeePassFile");
+    xml.start("Meta");
+    xml.text("Generator", "decone");
+    xml.text("DatabaseName", &db.name);
+    xml.start("MemoryProtection");
+    xml.text("ProtectTitle", "False");
+    xml.text("ProtectUserName", "False");
+    xml.text("ProtectPassword", "True");
+    xml.text("ProtectURL", "False");
+    xml.text("ProtectNotes", "False");
+    xml.end();
+    match db.recycle_bin {
+        Some(ref uuid) => {
+            xml.text("RecycleBinEnabled", "True");
+            xml.text("RecycleBinUUID", &encode_base64(uuid));
+        }
+        None => xml.text("RecycleBinEnabled", "False"),
+    }
+    xml.end();
+    xml.start("Root");
+    write_group(&mut xml, &db.root, inner_stream);
+    xml.end();
+    xml.end();
+    xml.into_string()
+}
+
+fn write_group(xml: &mut XmlWriter, group: &Group, inner_stream: &mut ChaCha20) {
+    xml.start("Group");
+    xml.text("UUID", &encode_base64(&group.uuid));
+    xml.text("Name", &group.name);
+    write_times(xml, &group.times);
+    for entry in &group.entries {
+        write_entry(xml, entry, inner_stream);
+    }
+    for child in &group.groups {
+        write_group(xml, child, inner_stream);
+    }
+    xml.end();
+}
+
+fn write_entry(xml: &mut XmlWriter, entry: &Entry, inner_stream: &mut ChaCha20) {
+    xml.start("Entry");
+    xml.text("UUID", &encode_base64(&entry.uuid));
+    if !entry.tags.is_empty() {
+        xml.text("Tags", &entry.tags.join(";"));
+    }
+    write_times(xml, &entry.times);
+    for string in &entry.strings {
+        xml.start("String");
+        xml.text("Key", &string.key);
+        if string.protected {
+            // Protected values are XORed with the inner stream in document order.
+            let value = encode_base64(&inner_stream.process(string.value.as_bytes()));
+            xml.text_with_attr("Value", Some(("Protected", "True")), &value);
+        } else {
+            xml.text("Value", &string.value);
+        }
+        xml.end();
+    }
+    for binary in &entry.binaries {
+        xml.start("Binary");
+        xml.text("Key", &binary.key);
+        xml.text_with_attr("Value", Some(("Ref", &binary.index.to_string())), "");
+        xml.end();
+    }
+    xml.end();
+}
+
+fn write_times(xml: &mut XmlWriter, times: &Times) {
+    xml.start("Times");
+    xml.text("CreationTime", &format_time(times.created));
+    xml.text("LastModificationTime", &format_time(times.modified));
+    xml.text("LastAccessTime", &format_time(times.modified));
+    xml.text("ExpiryTime", &format_time(times.modified));
+    xml.text("Expires", "False");
+    xml.text("UsageCount", "0");
+    xml.text("LocationChanged", &format_time(times.modified));
+    xml.end();
+}
+
+/// Formats a Unix time as KDBX 4 does: the base64 of the seconds since 0001-01-01 in
+/// little endian.
+fn format_time(unix_time: u64) -> String {
+    let mut bytes = Vec::new();
+    write_u64_le(&mut bytes, unix_time + kdbx::UNIX_EPOCH_IN_KDBX_SECONDS);
+    encode_base64(&bytes)
+}
@@ -0,0 +1,180 @@
+use std::fmt::Write;
+use std::io::{self, Result as IoResult};
+use xml::reader::{EventReader, XmlEvent};
+
+/// An element of a parsed XML document.
+#[derive(Clone, Debug)]
+pub struct Element {
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub name: String,
+    pub text: String,
+}
+
+impl Element {
+    /// Parses `bytes` into the tree of the root element.
+    pub fn parse(bytes: &[u8]) -> IoResult<Element> {
+        let mut stack: Vec<Element> = Vec::new();
+        for event in EventReader::new(bytes).into_iter() {
+            let event = try!(event.map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))
+            }));
+            match event {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    stack.push(Element {
+                        attributes: attributes.into_iter()
+                            .map(|a| (a.name.local_name, a.value))
+                            .collect(),
+                        children: Vec::new(),
+                        name: name.local_name,
+                        text: String::new(),
+                    });
+                }
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop().expect("unbalanced XML");
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                XmlEvent::Characters(s) | XmlEvent::CData(s) | XmlEvent::Whitespace(s) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&s);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "XML has no root element"))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|&&(ref k, _)| k == name).map(|&(_, ref v)| &v[..])
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|e| e.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &str) -> Vec<&'a Element> {
+        self.children.iter().filter(|e| e.name == name).collect()
+    }
+
+    /// Returns the text of the child `name`, or `None` if there is no such child.
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|e| &e.text[..])
+    }
+
+    /// Returns the element at a slash-separated path below this one.
+    pub fn find(&self, path: &str) -> Option<&Element> {
+        path.split('/').fold(Some(self), |e, name| e.and_then(|e| e.child(name)))
+    }
+
+    /// Calls `f` on this element and all its descendants in document order.
+    pub fn walk_mut<F: FnMut(&mut Element)>(&mut self, f: &mut F) {
+        f(self);
+        for child in &mut self.children {
+            child.walk_mut(f);
+        }
+    }
+}
+
+/// Builds an indented XML document.
+pub struct XmlWriter {
+    buf: String,
+    stack: Vec<String>,
+}
+
+impl XmlWriter {
+    pub fn new() -> Self {
+        XmlWriter {
+            buf: "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n".to_string(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn start(&mut self, name: &str) {
+        self.indent();
+        let _ = writeln!(self.buf, "<{}>", name);
+        self.stack.push(name.to_string());
+    }
+
+    pub fn end(&mut self) {
+        let name = self.stack.pop().expect("no element to end");
+        self.indent();
+        let _ = writeln!(self.buf, "</{}>", name);
+    }
+
+    /// Writes an element that contains only text.
+    pub fn text(&mut self, name: &str, text: &str) {
+        self.text_with_attr(name, None, text);
+    }
+
+    pub fn text_with_attr(&mut self, name: &str, attr: Option<(&str, &str)>, text: &str) {
+        self.indent();
+        let attr = attr.map(|(k, v)| format!(" {}=\"{}\"", k, escape(v)))
+            .unwrap_or_else(String::new);
+        if text.is_empty() {
+            let _ = writeln!(self.buf, "<{}{}/>", name, attr);
+        } else {
+            let _ = writeln!(self.buf, "<{}{}>{}</{}>", name, attr, escape(text), name);
+        }
+    }
+
+    pub fn into_string(self) -> String {
+        assert!(self.stack.is_empty(), "unclosed elements");
+        self.buf
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.stack.len() {
+            self.buf.push('\t');
+        }
+    }
+}
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 cannot represent most control characters at all.
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, XmlWriter};
+
+    #[test]
+    fn test_parse() {
+        let xml = b"<?xml version=\"1.0\"?><A><B x=\"1\">t &amp; u</B><B/><C><D>d</D></C></A>";
+        let root = Element::parse(xml).unwrap();
+        assert_eq!(root.name, "A");
+        assert_eq!(root.child("B").unwrap().attribute("x"), Some("1"));
+        assert_eq!(root.child_text("B"), Some("t & u"));
+        assert_eq!(root.children_named("B").len(), 2);
+        assert_eq!(root.find("C/D").map(|e| &e.text[..]), Some("d"));
+    }
+
+    #[test]
+    fn test_xml_writer() {
+        let mut xml = XmlWriter::new();
+        xml.start("Root");
+        xml.text("Name", "a & <b>");
+        xml.text_with_attr("Value", Some(("Ref", "0")), "");
+        xml.end();
+        let s = xml.into_string();
+        assert!(s.ends_with("<Root>\n\t<Name>a &amp; &lt;b&gt;</Name>\n\t<Value Ref=\"0\"/>\n\
+                             </Root>\n"));
+    }
+}
@@ -1,16 +1,47 @@
 #![allow(dead_code)] // until we release 0.1.0...
 
+extern crate argon2;
 extern crate base64;
 #[macro_use(crate_version)]
 extern crate clap;
+extern crate flate2;
+extern crate libc;
 extern crate openssl;
+extern crate ring;
 extern crate rpassword;
 extern crate serde_json;
+extern crate xml;
 
+mod agent;
+mod api;
 mod agile_keychain;
+mod audit;
+mod bitwarden;
 mod cli;
+mod clipboard;
+mod csv;
+mod fs_ext;
+mod generator;
+mod git_credential;
+mod import;
+mod inject;
+mod iso8601;
 mod json_value_ext;
+mod kdbx;
+mod native_host;
+mod one_pif;
 mod op_vault;
+mod reference;
+mod run;
+mod search;
+mod secret_service;
+mod ssh_agent;
+#[cfg(test)]
+mod testing;
+mod totp;
+mod tui;
+mod url;
+mod util;
 
 use cli::Cli;
 
@@ -0,0 +1,355 @@
+//! A native messaging host filling logins into web pages for a companion browser extension.
+//!
+//! The browser starts the host and exchanges JSON objects with it over stdin and stdout, each
+//! preceded by its length as a 32-bit integer in native byte order. The browser starts the
+//! program named in the host manifest with arguments of its own, so the manifest should name a
+//! script running `decone native-host "$@"`.
+//!
+//! Requests carry an `action`, and answers an `ok` flag and an `error` message when it is false.
+//! An `id` in a request is copied to its answer.
+//!
+//! * `find` with a `url` lists the logins for the page without their secrets, the closest
+//!   matches first.
+//! * `fill` with a `uuid` and the `url` of the page returns the `fields` of the login, each with
+//!   its `designation` (`username` or `password`), `name`, `type` and `value`. The login must
+//!   belong to the page, so that a page cannot be sent the secrets of another site.
+//! * `save` with a `url`, `fields` like those `fill` returns and an optional `title` adds a login.
+
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Result as IoResult, Write};
+
+use op_vault::category::Category;
+use op_vault::item::{Item, NewItem};
+use op_vault::vault::Vault;
+use url::Url;
+use url::matcher::{MatchMode, Matcher};
+
+/// Browsers send at most 4 GiB but never anything this host needs more than this for.
+const MAX_MESSAGE_LEN: u32 = 1024 * 1024;
+
+/// Reads a message, returning `None` when the browser has closed stdin.
+pub fn read_message<R: Read>(mut reader: R) -> IoResult<Option<Value>> {
+    let mut len = [0; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match try!(reader.read(&mut len[read..])) {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message is incomplete")),
+            n => read += n,
+        }
+    }
+    let len = decode_len(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("message of {} bytes is too long", len)));
+    }
+    let mut message = vec![0; len as usize];
+    try!(reader.read_exact(&mut message));
+    let message = try!(String::from_utf8(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    serde_json::from_str(&message)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(mut writer: W, message: &Value) -> IoResult<()> {
+    let s = try!(serde_json::to_string(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    if s.len() as u64 > MAX_MESSAGE_LEN as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "answer is too long"));
+    }
+    try!(writer.write_all(&encode_len(s.len() as u32)));
+    try!(writer.write_all(s.as_bytes()));
+    writer.flush()
+}
+
+/// Answers the messages on `reader` until the browser closes it. `unlock` is called for each
+/// request until it returns the vault, so that starting the agent later is enough. Logins belong
+/// to a page if their URLs match it by `mode`.
+pub fn run<R, W, F>(mut reader: R, mut writer: W, mode: MatchMode, mut unlock: F) -> IoResult<()>
+    where R: Read,
+          W: Write,
+          F: FnMut() -> Option<Vault>
+{
+    let mut vault = None;
+    while let Some(request) = try!(read_message(&mut reader)) {
+        if vault.is_none() {
+            vault = unlock();
+        }
+        let mut response = match vault {
+            Some(ref mut vault) => handle(vault, &request, mode),
+            None => error_response("start `decone agent` to unlock the vault"),
+        };
+        if let (Some(id), Some(response)) = (request.find("id"), response.as_object_mut()) {
+            response.insert("id".to_string(), id.clone());
+        }
+        try!(write_message(&mut writer, &response));
+    }
+    Ok(())
+}
+
+pub fn handle(vault: &mut Vault, request: &Value, mode: MatchMode) -> Value {
+    let action = request.find("action").and_then(|v| v.as_string()).unwrap_or("");
+    let url = request.find("url").and_then(|v| v.as_string());
+    let matcher = match url.and_then(|url| Matcher::new(url, mode)) {
+        Some(matcher) => matcher,
+        None if action == "find" || action == "fill" || action == "save" => {
+            return error_response("url is missing or invalid");
+        }
+        None => return error_response(&format!("unknown action {}", action)),
+    };
+    match action {
+        "find" => {
+            let logins = find_logins(vault, &matcher)
+                .iter()
+                .map(|item| login_summary(vault, item))
+                .collect();
+            let mut response = ok_response();
+            response.insert("logins".to_string(), Value::Array(logins));
+            Value::Object(response)
+        }
+        "fill" => {
+            let uuid = request.find("uuid").and_then(|v| v.as_string()).unwrap_or("");
+            let item = match find_logins(vault, &matcher).into_iter().find(|i| i.uuid() == uuid) {
+                Some(item) => item,
+                None => {
+                    return error_response(&format!("no login {} for {}", uuid, matcher.url().host))
+                }
+            };
+            let details = match vault.item_details(&item) {
+                Some(details) => details,
+                None => return error_response("could not decrypt the login"),
+            };
+            let fields = details.fields()
+                .iter()
+                .map(|field| {
+                    let mut json = BTreeMap::new();
+                    if let Some(designation) = field.designation() {
+                        json.insert("designation".to_string(),
+                                    Value::String(designation.to_string()));
+                    }
+                    json.insert("name".to_string(), Value::String(field.name().to_string()));
+                    json.insert("type".to_string(), Value::String(field.kind().to_string()));
+                    json.insert("value".to_string(), Value::String(field.value().to_string()));
+                    Value::Object(json)
+                })
+                .collect();
+            let mut response = ok_response();
+            response.insert("fields".to_string(), Value::Array(fields));
+            Value::Object(response)
+        }
+        "save" => {
+            let new_item = match new_login(request, matcher.url()) {
+                Ok(new_item) => new_item,
+                Err(message) => return error_response(message),
+            };
+            match vault.create_item(&new_item) {
+                Ok(item) => {
+                    let mut response = ok_response();
+                    response.insert("uuid".to_string(), Value::String(item.uuid().to_string()));
+                    Value::Object(response)
+                }
+                Err(e) => error_response(&format!("{}", e)),
+            }
+        }
+        _ => error_response(&format!("unknown action {}", action)),
+    }
+}
+
+/// Returns the logins that belong to the page, the closest matches first.
+fn find_logins(vault: &Vault, matcher: &Matcher) -> Vec<Item> {
+    let logins = vault.items().into_iter().filter(|item| item.category() == Category::Login);
+    matcher.rank(logins).into_iter().map(|m| m.item).collect()
+}
+
+fn login_summary(vault: &Vault, item: &Item) -> Value {
+    let mut json = BTreeMap::new();
+    json.insert("uuid".to_string(), Value::String(item.uuid().to_string()));
+    json.insert("title".to_string(),
+                Value::String(item.title().unwrap_or("").to_string()));
+    // The overview shows the username, so it is not a secret here.
+    let username = item.ainfo()
+        .map(|s| s.to_string())
+        .or_else(|| vault.item_details(item).and_then(|details| details.username()));
+    if let Some(username) = username {
+        json.insert("username".to_string(), Value::String(username));
+    }
+    if let Some(url) = item.url() {
+        json.insert("url".to_string(), Value::String(url.to_string()));
+    }
+    Value::Object(json)
+}
+
+/// Builds a login for the page at `url` from the fields of a `save` request. A password field
+/// is required; the login is named after the host unless the request gives a title.
+fn new_login(request: &Value, url: &Url) -> Result<NewItem, &'static str> {
+    let fields = match request.find("fields").and_then(|v| v.as_array()) {
+        Some(fields) => fields,
+        None => return Err("fields are missing"),
+    };
+    let mut login_fields = Vec::new();
+    for field in fields {
+        let get = |key| field.find(key).and_then(|v| v.as_string());
+        let value = match get("value") {
+            Some(value) => value,
+            None => return Err("a field has no value"),
+        };
+        let designation = get("designation");
+        let mut json = BTreeMap::new();
+        if let Some(designation) = designation {
+            if designation != "username" && designation != "password" {
+                return Err("designation must be username or password");
+            }
+            json.insert("designation".to_string(), Value::String(designation.to_string()));
+        }
+        let name = get("name").or(designation).unwrap_or("");
+        let kind = get("type").unwrap_or(if designation == Some("password") { "P" } else { "T" });
+        json.insert("name".to_string(), Value::String(name.to_string()));
+        json.insert("type".to_string(), Value::String(kind.to_string()));
+        json.insert("value".to_string(), Value::String(value.to_string()));
+        login_fields.push(Value::Object(json));
+    }
+    let has_password = login_fields.iter().any(|field| {
+        field.find("designation").and_then(|v| v.as_string()) == Some("password")
+    });
+    if !has_password {
+        return Err("no field is designated password");
+    }
+    let title = request.find("title").and_then(|v| v.as_string()).unwrap_or(&url.host);
+    let mut details = BTreeMap::new();
+    details.insert("fields".to_string(), Value::Array(login_fields));
+    let mut new_item = NewItem::new(Category::Login, title, Value::Object(details));
+    new_item.urls = vec![match url.port {
+                             Some(port) => format!("{}://{}:{}/", url.scheme, url.host, port),
+                             None => format!("{}://{}/", url.scheme, url.host),
+                         }];
+    Ok(new_item)
+}
+
+#[cfg(target_endian = "little")]
+fn decode_len(bytes: [u8; 4]) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+#[cfg(target_endian = "big")]
+fn decode_len(bytes: [u8; 4]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | (bytes[3] as u32)
+}
+
+#[cfg(target_endian = "little")]
+fn encode_len(len: u32) -> [u8; 4] {
+    [len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]
+}
+
+#[cfg(target_endian = "big")]
+fn encode_len(len: u32) -> [u8; 4] {
+    [(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]
+}
+
+fn ok_response() -> BTreeMap<String, Value> {
+    let mut response = BTreeMap::new();
+    response.insert("ok".to_string(), Value::Bool(true));
+    response
+}
+
+fn error_response(message: &str) -> Value {
+    let mut response = BTreeMap::new();
+    response.insert("ok".to_string(), Value::Bool(false));
+    response.insert("error".to_string(), Value::String(message.to_string()));
+    Value::Object(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use std::fs;
+
+    use op_vault::vault::LockedVault;
+    use testing::{copy_sample_vault, SAMPLE_VAULT_PASSWORD};
+    use url::matcher::MatchMode;
+    use super::{encode_len, handle, read_message, run};
+
+    const HULU_UUID: &'static str = "13C8E12AC8E54B1F873BAB0824E521BC";
+
+    fn message(json: &str) -> Vec<u8> {
+        let mut message = encode_len(json.len() as u32).to_vec();
+        message.extend_from_slice(json.as_bytes());
+        message
+    }
+
+    fn request(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_read_message() {
+        let input = message("{\"action\":\"find\"}");
+        let mut reader = &input[..];
+        assert_eq!(read_message(&mut reader).unwrap(), Some(request("{\"action\":\"find\"}")));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+        assert!(read_message(&input[..6]).is_err());
+        assert!(read_message(&encode_len(2 * 1024 * 1024)[..]).is_err());
+    }
+
+    #[test]
+    fn test_handle() {
+        let path = copy_sample_vault("native-host");
+        let mut vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+
+        let find = "{\"action\":\"find\",\"url\":\"https://secure.hulu.com/\"}";
+        let response = handle(&mut vault, &request(find), MatchMode::Domain);
+        let logins = response.find("logins").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(logins[0].find("uuid").and_then(|v| v.as_string()), Some(HULU_UUID));
+        assert_eq!(logins[0].find("username").and_then(|v| v.as_string()),
+                   Some("wendy@appleseed.com"));
+        assert!(logins[0].find("value").is_none());
+
+        let fill = format!("{{\"action\":\"fill\",\"uuid\":\"{}\",\"url\":\"{}\"}}",
+                           HULU_UUID,
+                           "https://www.hulu.com/");
+        let response = handle(&mut vault, &request(&fill), MatchMode::Domain);
+        let fields = response.find("fields").and_then(|v| v.as_array()).unwrap();
+        let password = fields.iter()
+            .find(|f| f.find("designation").and_then(|v| v.as_string()) == Some("password"))
+            .and_then(|f| f.find("value"))
+            .and_then(|v| v.as_string());
+        assert_eq!(password, Some("frirp7i1ob7wig4d"));
+        let response = handle(&mut vault, &request(&fill), MatchMode::Prefix);
+        assert_eq!(response.find("ok").and_then(|v| v.as_boolean()), Some(true));
+        let evil = fill.replace("hulu.com", "evil.com");
+        let response = handle(&mut vault, &request(&evil), MatchMode::Domain);
+        assert_eq!(response.find("ok").and_then(|v| v.as_boolean()), Some(false));
+
+        let save = "{\"action\":\"save\",\"url\":\"https://shop.example.com/signup\",\
+                    \"fields\":[{\"designation\":\"username\",\"value\":\"bob\"},\
+                    {\"designation\":\"password\",\"name\":\"pw\",\"value\":\"hunter2\"}]}";
+        let response = handle(&mut vault, &request(save), MatchMode::Domain);
+        let uuid = response.find("uuid").and_then(|v| v.as_string()).unwrap();
+        let item = vault.item(uuid).unwrap();
+        assert_eq!(item.title(), Some("shop.example.com"));
+        assert_eq!(item.urls(), vec!["https://shop.example.com/".to_string()]);
+        let details = vault.item_details(&item).unwrap();
+        assert_eq!(details.username(), Some("bob".to_string()));
+        assert_eq!(details.password(), Some("hunter2".to_string()));
+        let response = handle(&mut vault, &request(&save.replace("password", "other")),
+                              MatchMode::Domain);
+        assert_eq!(response.find("ok").and_then(|v| v.as_boolean()), Some(false));
+
+        let mut input = message("{\"action\":\"list\",\"id\":7}");
+        input.extend(message("{\"action\":\"find\",\"url\":\"example.net\",\"id\":8}"));
+        let mut output = Vec::new();
+        let mut vault = Some(vault);
+        run(&input[..], &mut output, MatchMode::Domain, || vault.take()).unwrap();
+        let mut reader = &output[..];
+        let response = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(response.find("error").and_then(|v| v.as_string()),
+                   Some("unknown action list"));
+        assert_eq!(response.find("id").and_then(|v| v.as_u64()), Some(7));
+        let response = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(response.find("logins").and_then(|v| v.as_array()).map(|l| l.len()),
+                   Some(0));
+        assert_eq!(response.find("id").and_then(|v| v.as_u64()), Some(8));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,537 @@
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Read, Result as IoResult, Write};
+use std::path::{Component, Path, PathBuf};
+
+use fs_ext;
+use import::ImportAction;
+use json_value_ext::JsonValueExt;
+use op_vault::attachment::{Attachment, NewAttachment};
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::folder::Folder;
+use op_vault::item::{Item, NewItem};
+use op_vault::uuid;
+use op_vault::vault::Vault;
+
+/// The line that separates records in a 1Password Interchange Format file.
+pub const SEPARATOR: &'static str = "***5642bee8-a5ff-11dc-8314-0800200c9a66***";
+
+const FOLDER_TYPE_NAME: &'static str = "system.folder.Regular";
+const SMART_FOLDER_TYPE_NAME: &'static str = "system.folder.SavedSearch";
+
+/// Writes every folder and item of `vault` as 1PIF records.
+///
+/// If `attachments_dir` is given, attachments are decrypted into
+/// `<attachments_dir>/<item uuid>/<attachment uuid>/<file name>` and the records refer to
+/// them by that relative path.
+///
+/// Records hold `openContents` and `secureContents` as 1Password writes them. There is no
+/// `securityContents`: 1Password records carry only a `securityLevel` next to `secureContents`,
+/// and importers read nothing else, so no such object is made up.
+pub fn export<W: Write + ?Sized>(vault: &Vault,
+                                 writer: &mut W,
+                                 attachments_dir: Option<&Path>)
+                                 -> IoResult<()> {
+    for folder in vault.folders() {
+        try!(write_record(writer, &folder_record(&folder)));
+    }
+    for item in vault.items() {
+        let details = try!(vault.item_details(&item).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", item.uuid()))
+        }));
+        let attachments = try!(vault.attachments(&item));
+        let mut references = Vec::new();
+        for attachment in &attachments {
+            let path = match attachments_dir {
+                Some(dir) => Some(try!(export_attachment(vault, &item, attachment, dir))),
+                None => None,
+            };
+            references.push(attachment_reference(attachment, path));
+        }
+        try!(write_record(writer, &item_record(&item, &details, references)));
+    }
+    Ok(())
+}
+
+/// Reads the records of a 1PIF file, which are JSON objects separated by `SEPARATOR` lines.
+pub fn read_records<R: Read + ?Sized>(reader: &mut R) -> IoResult<Vec<Value>> {
+    let mut buf = String::new();
+    try!(reader.read_to_string(&mut buf));
+    let mut records = Vec::new();
+    for chunk in buf.split(SEPARATOR) {
+        let chunk = chunk.trim();
+        if chunk.is_empty() {
+            continue;
+        }
+        let v = serde_json::de::from_str(chunk)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        records.push(try!(v));
+    }
+    Ok(records)
+}
+
+/// Works out how to add `records` to `vault` without changing it.
+///
+/// Records keep their UUIDs, so those already in the vault or earlier in the file are skipped
+/// and importing the same file twice adds nothing. Tombstones and smart folders are skipped, and
+/// items of types that OPVault does not know are imported as secure notes.
+///
+/// Attachments are read from `attachments_dir` as `export` lays them out, by the `path` of
+/// their reference or else `<item uuid>/<attachment uuid>/<file name>`. Those that cannot be
+/// read are skipped.
+pub fn plan_import(vault: &Vault,
+                   records: &[Value],
+                   attachments_dir: Option<&Path>)
+                   -> Vec<ImportAction> {
+    let mut actions = Vec::new();
+    let mut item_uuids = BTreeSet::new();
+    let mut folder_uuids: BTreeSet<String> =
+        vault.folders().iter().map(|folder| folder.uuid().to_string()).collect();
+
+    let mut folder_records: Vec<&Value> = records.iter()
+        .filter(|record| type_name(record) == FOLDER_TYPE_NAME)
+        .collect();
+    // Parents must be created before their children.
+    while !folder_records.is_empty() {
+        let (ready, pending): (Vec<&Value>, Vec<&Value>) =
+            folder_records.iter().cloned().partition(|record| {
+                record_str(record, "folderUuid").map_or(true, |parent| {
+                    folder_uuids.contains(parent) ||
+                    !folder_records.iter().any(|r| record_str(r, "uuid") == Some(parent))
+                })
+            });
+        if ready.is_empty() {
+            // The remaining folders form a cycle, so import them without parents.
+            for record in pending {
+                actions.push(folder_action(vault, record, &mut folder_uuids, false));
+            }
+            break;
+        }
+        for record in ready {
+            actions.push(folder_action(vault, record, &mut folder_uuids, true));
+        }
+        folder_records = pending;
+    }
+
+    for record in records {
+        let type_name = type_name(record);
+        if type_name == FOLDER_TYPE_NAME {
+            continue;
+        }
+        let uuid = record_str(record, "uuid").unwrap_or("").to_string();
+        let title = record_str(record, "title").unwrap_or("").to_string();
+        let reason = if type_name == SMART_FOLDER_TYPE_NAME {
+            Some("smart folder")
+        } else if Category::from_type_name(type_name) == Some(Category::Tombstone) {
+            Some("tombstone")
+        } else if uuid.is_empty() {
+            None
+        } else if vault.item(&uuid).is_some() {
+            Some("already in vault")
+        } else if !item_uuids.insert(uuid.clone()) {
+            Some("duplicate in file")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            actions.push(ImportAction::Skip {
+                reason: reason.to_string(),
+                title: title,
+                uuid: uuid,
+            });
+            continue;
+        }
+        let mut new_item = new_item(record, &folder_uuids);
+        let mut skipped = Vec::new();
+        let references = record.find("attachments").and_then(|v| v.as_array());
+        for reference in references.into_iter().flat_map(|references| references.iter()) {
+            match read_attachment(record, reference, attachments_dir) {
+                Ok(attachment) => new_item.attachments.push(attachment),
+                Err(e) => {
+                    skipped.push(ImportAction::Skip {
+                        reason: format!("attachment of {}: {}", uuid, e),
+                        title: record_str(reference, "fileName").unwrap_or("").to_string(),
+                        uuid: record_str(reference, "uuid").unwrap_or("-").to_string(),
+                    })
+                }
+            }
+        }
+        actions.push(ImportAction::CreateItem(new_item));
+        actions.extend(skipped);
+    }
+    actions
+}
+
+/// Reads the attachment that `reference` in `record` refers to from `attachments_dir`.
+fn read_attachment(record: &Value,
+                   reference: &Value,
+                   attachments_dir: Option<&Path>)
+                   -> IoResult<NewAttachment> {
+    let dir = try!(attachments_dir.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no attachments directory given")
+    }));
+    let file_name = record_str(reference, "fileName").unwrap_or("");
+    let rel_path = match record_str(reference, "path") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            Path::new(record_str(record, "uuid").unwrap_or(""))
+                .join(record_str(reference, "uuid").unwrap_or(""))
+                .join(sanitize_file_name(file_name))
+        }
+    };
+    // A path from the file must not reach out of the directory.
+    let inside = rel_path.components().all(|component| match component {
+        Component::Normal(_) => true,
+        _ => false,
+    });
+    if !inside {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("path {} leaves the attachments directory",
+                                          rel_path.display())));
+    }
+    let mut contents = Vec::new();
+    try!(File::open(dir.join(&rel_path)).and_then(|mut file| file.read_to_end(&mut contents)));
+    let file_name = if file_name.is_empty() {
+        rel_path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(String::new)
+    } else {
+        file_name.to_string()
+    };
+    Ok(NewAttachment::new(&file_name, contents))
+}
+
+fn folder_action(vault: &Vault,
+                 record: &Value,
+                 folder_uuids: &mut BTreeSet<String>,
+                 keep_parent: bool)
+                 -> ImportAction {
+    let uuid = record_str(record, "uuid")
+        .and_then(|s| if s.is_empty() { None } else { Some(s.to_string()) })
+        .unwrap_or_else(uuid::generate);
+    let title = record_str(record, "title").unwrap_or("").to_string();
+    let reason = if vault.folder(&uuid).is_some() {
+        Some("already in vault")
+    } else if folder_uuids.contains(&uuid) {
+        Some("duplicate in file")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        return ImportAction::Skip {
+            reason: reason.to_string(),
+            title: title,
+            uuid: uuid,
+        };
+    }
+    let parent = record_str(record, "folderUuid")
+        .and_then(|parent| known_folder(parent, folder_uuids))
+        .and_then(|parent| if keep_parent { Some(parent) } else { None });
+    folder_uuids.insert(uuid.clone());
+    ImportAction::CreateFolder {
+        parent: parent,
+        title: title,
+        uuid: uuid,
+    }
+}
+
+fn new_item(record: &Value, folder_uuids: &BTreeSet<String>) -> NewItem {
+    let category = Category::from_type_name(type_name(record)).unwrap_or(Category::SecureNote);
+    let mut details = match record.find("secureContents") {
+        Some(&Value::Object(ref map)) => map.clone(),
+        _ => BTreeMap::new(),
+    };
+    let mut urls = Vec::new();
+    if let Some(location) = record_str(record, "location") {
+        if !location.is_empty() {
+            urls.push(location.to_string());
+        }
+    }
+    if let Some(Value::Array(entries)) = details.remove("URLs") {
+        for entry in entries {
+            if let Some(url) = entry.find("url").and_then(|v| v.as_owned_string()) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+    }
+    let mut new_item = NewItem::new(category,
+                                    record_str(record, "title").unwrap_or(""),
+                                    Value::Object(details));
+    new_item.created = record.find("createdAt").and_then(|v| v.as_u64());
+    new_item.updated = record.find("updatedAt").and_then(|v| v.as_u64());
+    new_item.fave = record.find("faveIndex").and_then(|v| v.as_u64());
+    new_item.folder = record_str(record, "folderUuid")
+        .and_then(|folder| known_folder(folder, folder_uuids));
+    new_item.tags = record.lookup("openContents.tags")
+        .and_then(|v| v.as_array())
+        .map(|array| array.iter().filter_map(|v| v.as_owned_string()).collect())
+        .unwrap_or_else(Vec::new);
+    new_item.trashed = record.find("trashed").and_then(|v| v.as_boolean()).unwrap_or(false);
+    new_item.urls = urls;
+    new_item.uuid = record_str(record, "uuid")
+        .and_then(|uuid| if uuid.is_empty() { None } else { Some(uuid.to_string()) });
+    new_item
+}
+
+fn known_folder(uuid: &str, folder_uuids: &BTreeSet<String>) -> Option<String> {
+    if folder_uuids.contains(uuid) {
+        Some(uuid.to_string())
+    } else {
+        None
+    }
+}
+
+fn type_name(record: &Value) -> &str {
+    record_str(record, "typeName").unwrap_or("")
+}
+
+fn record_str<'a>(record: &'a Value, key: &str) -> Option<&'a str> {
+    record.find(key).and_then(|v| v.as_string())
+}
+
+fn write_record<W: Write + ?Sized>(writer: &mut W, record: &Value) -> IoResult<()> {
+    let json = try!(serde_json::to_string(record)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    try!(writeln!(writer, "{}", json));
+    writeln!(writer, "{}", SEPARATOR)
+}
+
+fn folder_record(folder: &Folder) -> Value {
+    let mut record = BTreeMap::new();
+    record.insert("uuid".to_string(), Value::String(folder.uuid().to_string()));
+    record.insert("title".to_string(), Value::String(folder.title().to_string()));
+    let type_name = if folder.is_smart() { SMART_FOLDER_TYPE_NAME } else { FOLDER_TYPE_NAME };
+    record.insert("typeName".to_string(), Value::String(type_name.to_string()));
+    record.insert("createdAt".to_string(), Value::U64(folder.created()));
+    record.insert("updatedAt".to_string(), Value::U64(folder.updated()));
+    record.insert("txTimestamp".to_string(), Value::U64(folder.tx()));
+    if let Some(parent) = folder.parent() {
+        record.insert("folderUuid".to_string(), Value::String(parent.to_string()));
+    }
+    Value::Object(record)
+}
+
+fn item_record(item: &Item, details: &ItemDetails, attachments: Vec<Value>) -> Value {
+    let mut record = BTreeMap::new();
+    record.insert("uuid".to_string(), Value::String(item.uuid().to_string()));
+    record.insert("title".to_string(), Value::String(item.title().unwrap_or("").to_string()));
+    record.insert("typeName".to_string(), Value::String(item.category().type_name().to_string()));
+    record.insert("createdAt".to_string(), Value::U64(item.created()));
+    record.insert("updatedAt".to_string(), Value::U64(item.updated()));
+    record.insert("txTimestamp".to_string(), Value::U64(item.tx()));
+    record.insert("securityLevel".to_string(), Value::String("SL5".to_string()));
+    if let Some(url) = item.url() {
+        record.insert("location".to_string(), Value::String(url.to_string()));
+        if let Some(host) = location_key(url) {
+            record.insert("locationKey".to_string(), Value::String(host));
+        }
+    }
+    if let Some(folder) = item.folder() {
+        record.insert("folderUuid".to_string(), Value::String(folder.to_string()));
+    }
+    if let Some(fave) = item.fave() {
+        record.insert("faveIndex".to_string(), Value::U64(fave));
+    }
+    if item.is_trashed() {
+        record.insert("trashed".to_string(), Value::Bool(true));
+    }
+
+    let mut open_contents = BTreeMap::new();
+    let tags = item.tags();
+    if !tags.is_empty() {
+        let tags = tags.into_iter().map(Value::String).collect();
+        open_contents.insert("tags".to_string(), Value::Array(tags));
+    }
+    if !open_contents.is_empty() {
+        record.insert("openContents".to_string(), Value::Object(open_contents));
+    }
+
+    let mut secure_contents = match *details.value() {
+        Value::Object(ref map) => map.clone(),
+        _ => BTreeMap::new(),
+    };
+    let urls = item.urls();
+    if !urls.is_empty() {
+        let urls = urls.into_iter()
+            .map(|url| {
+                let mut entry = BTreeMap::new();
+                entry.insert("label".to_string(), Value::String(String::new()));
+                entry.insert("url".to_string(), Value::String(url));
+                Value::Object(entry)
+            })
+            .collect();
+        secure_contents.insert("URLs".to_string(), Value::Array(urls));
+    }
+    record.insert("secureContents".to_string(), Value::Object(secure_contents));
+
+    if !attachments.is_empty() {
+        record.insert("attachments".to_string(), Value::Array(attachments));
+    }
+    Value::Object(record)
+}
+
+fn attachment_reference(attachment: &Attachment, path: Option<String>) -> Value {
+    let mut reference = BTreeMap::new();
+    reference.insert("uuid".to_string(), Value::String(attachment.uuid().to_string()));
+    reference.insert("fileName".to_string(), Value::String(attachment.file_name().to_string()));
+    reference.insert("size".to_string(), Value::U64(attachment.size()));
+    reference.insert("createdAt".to_string(), Value::U64(attachment.created_at()));
+    reference.insert("updatedAt".to_string(), Value::U64(attachment.updated_at()));
+    if let Some(path) = path {
+        reference.insert("path".to_string(), Value::String(path));
+    }
+    Value::Object(reference)
+}
+
+fn export_attachment(vault: &Vault,
+                     item: &Item,
+                     attachment: &Attachment,
+                     dir: &Path)
+                     -> IoResult<String> {
+    let contents = try!(vault.attachment_contents(item, attachment).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("could not decrypt attachment {}", attachment.uuid()))
+    }));
+    let file_name = sanitize_file_name(attachment.file_name());
+    let rel_dir = Path::new(item.uuid()).join(attachment.uuid());
+    try!(fs_ext::create_private_dir_all(dir.join(&rel_dir)));
+    let rel_path = rel_dir.join(&file_name);
+    let mut file = try!(fs_ext::create_private_file(dir.join(&rel_path)));
+    try!(file.write_all(&contents));
+    Ok(rel_path.to_string_lossy().into_owned())
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c == '/' || c == '\\' || c == '\0' { '_' } else { c })
+        .collect();
+    if name.is_empty() || name == "." || name == ".." {
+        "attachment".to_string()
+    } else {
+        name
+    }
+}
+
+/// Returns the host of `url` without a leading `www.`, as 1Password does for `locationKey`.
+pub fn location_key(url: &str) -> Option<String> {
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    let host = rest.split(|c| c == '/' || c == '?' || c == '#' || c == ':')
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    // Only one `www.`, so that `www.www.example.com` stays apart from `example.com`.
+    let host = if host.starts_with("www.") { host[4..].to_string() } else { host };
+    if host.is_empty() { None } else { Some(host) }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_location_key() {
+        use super::location_key;
+        assert_eq!(location_key("http://WWW.hulu.com/"), Some("hulu.com".to_string()));
+        assert_eq!(location_key("ftp://ftp.dreamhost.com"), Some("ftp.dreamhost.com".to_string()));
+        assert_eq!(location_key("https://Example.com:8443/a?b"), Some("example.com".to_string()));
+        assert_eq!(location_key("https://www.www.example.com/"),
+                   Some("www.example.com".to_string()));
+        assert_eq!(location_key(""), None);
+    }
+
+    #[test]
+    fn test_plan_import() {
+        use import::ImportAction;
+        use testing::sample_vault;
+        use super::{plan_import, read_records};
+
+        let vault = sample_vault();
+        let input = format!("{}\n{}\n{}\n{}\n",
+                            r#"{"uuid":"13C8E12AC8E54B1F873BAB0824E521BC","title":"Hulu",
+                                "typeName":"webforms.WebForm","secureContents":{}}"#,
+                            super::SEPARATOR,
+                            r#"{"uuid":"00000000000000000000000000000001","title":"New",
+                                "typeName":"webforms.WebForm","location":"https://a.example/",
+                                "openContents":{"tags":["x"]},
+                                "secureContents":{"URLs":[{"url":"https://b.example/"}]}}"#,
+                            super::SEPARATOR);
+        let records = read_records(&mut input.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        let actions = plan_import(&vault, &records, None);
+        match actions[0] {
+            ImportAction::Skip { ref uuid, .. } => {
+                assert_eq!(uuid, "13C8E12AC8E54B1F873BAB0824E521BC")
+            }
+            _ => panic!("existing item must be skipped"),
+        }
+        match actions[1] {
+            ImportAction::CreateItem(ref new_item) => {
+                assert_eq!(new_item.urls, vec!["https://a.example/", "https://b.example/"]);
+                assert_eq!(new_item.tags, vec!["x"]);
+                assert!(new_item.details.find("URLs").is_none());
+            }
+            _ => panic!("new item must be created"),
+        }
+    }
+
+    #[test]
+    fn test_plan_import_attachments_and_duplicates() {
+        use import::{self, ImportAction};
+        use op_vault::uuid;
+        use testing::sample_vault;
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::{plan_import, read_records, SEPARATOR};
+
+        let dir = env::temp_dir().join(format!("decone-test-1pif-{}", uuid::generate()));
+        fs::create_dir_all(dir.join("A")).unwrap();
+        File::create(dir.join("A/hello.txt")).unwrap().write_all(b"hello").unwrap();
+        let vault = sample_vault();
+        let input = format!("{}\n{}\n{}\n{}\n",
+                            r#"{"uuid":"00000000000000000000000000000001","title":"New",
+                                "typeName":"securenotes.SecureNote","secureContents":{},
+                                "attachments":[{"uuid":"A1","fileName":"hello.txt",
+                                                "path":"A/hello.txt"},
+                                               {"uuid":"A2","fileName":"gone.txt"},
+                                               {"uuid":"A3","fileName":"x","path":"../x"}]}"#,
+                            SEPARATOR,
+                            r#"{"uuid":"00000000000000000000000000000001","title":"Again",
+                                "typeName":"securenotes.SecureNote","secureContents":{}}"#,
+                            SEPARATOR);
+        let records = read_records(&mut input.as_bytes()).unwrap();
+        let actions = plan_import(&vault, &records, Some(&dir));
+        assert_eq!(actions.len(), 4);
+        match actions[0] {
+            ImportAction::CreateItem(ref new_item) => {
+                assert_eq!(new_item.attachments.len(), 1);
+                assert_eq!(new_item.attachments[0].file_name, "hello.txt");
+                assert_eq!(new_item.attachments[0].contents, b"hello");
+            }
+            _ => panic!("new item must be created"),
+        }
+        let skipped: Vec<(&str, &str)> = actions[1..]
+            .iter()
+            .filter_map(|action| match *action {
+                ImportAction::Skip { ref uuid, ref reason, .. } => Some((&uuid[..], &reason[..])),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(skipped[0].0, "A2");
+        assert_eq!(skipped[1].0, "A3");
+        assert_eq!(skipped[2], ("00000000000000000000000000000001", "duplicate in file"));
+        assert!(import::check(&vault, &actions).is_ok());
+
+        // A plan that would create the same item twice fails before anything is written.
+        let twice = vec![actions[0].clone(), actions[0].clone()];
+        assert!(import::check(&vault, &twice).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+use json_value_ext::JsonValueExt;
+use op_vault::key::{ItemKey, MainKey};
+use op_vault::op_data_01::OpData01;
+use op_vault::uuid;
+use util::invalid_data;
+
+const HEADER: &'static [u8] = b"OPCLDAT";
+const HEADER_LEN: usize = 16;
+const VERSION: u8 = 1;
+
+/// Loads the attachments of the item `item_uuid` from the profile directory `path`.
+pub fn load_attachments<P: AsRef<Path>>(path: P,
+                                        item_uuid: &str)
+                                        -> IoResult<Vec<LockedAttachment>> {
+    let prefix = format!("{}_", item_uuid);
+    let mut attachments = Vec::new();
+    for dir_entry in try!(fs::read_dir(path)) {
+        let dir_entry = try!(dir_entry);
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with(&prefix) && file_name.ends_with(".attachment") {
+            attachments.push(try!(LockedAttachment::from_file(dir_entry.path())));
+        }
+    }
+    Ok(attachments)
+}
+
+/// Encrypts `new_attachment` and writes it as an attachment of the item `item_uuid` into the
+/// profile directory `path`.
+pub fn save_attachment<P: AsRef<Path>>(path: P,
+                                       item_uuid: &str,
+                                       new_attachment: &NewAttachment,
+                                       item_key: &ItemKey,
+                                       overview_key: &MainKey,
+                                       now: u64)
+                                       -> IoResult<LockedAttachment> {
+    let uuid = uuid::generate();
+    let mut overview = BTreeMap::new();
+    overview.insert("filename".to_string(),
+                    Value::String(new_attachment.file_name.clone()));
+    let overview = try!(serde_json::to_string(&Value::Object(overview))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    let overview = overview_key.encrypt_op_data(overview.as_bytes());
+
+    let mut metadata = BTreeMap::new();
+    metadata.insert("contentsSize".to_string(),
+                    Value::U64(new_attachment.contents.len() as u64));
+    metadata.insert("createdAt".to_string(), Value::U64(now));
+    metadata.insert("external".to_string(), Value::Bool(false));
+    metadata.insert("itemUUID".to_string(), Value::String(item_uuid.to_string()));
+    metadata.insert("overview".to_string(), Value::String(overview.to_base64_string()));
+    metadata.insert("txTimestamp".to_string(), Value::U64(now));
+    metadata.insert("updatedAt".to_string(), Value::U64(now));
+    metadata.insert("uuid".to_string(), Value::String(uuid.clone()));
+    let metadata = try!(serde_json::to_string(&Value::Object(metadata))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    if metadata.len() > 0xffff {
+        return Err(invalid_data("attachment metadata is too large"));
+    }
+
+    let mut bytes = HEADER.to_vec();
+    bytes.push(VERSION);
+    bytes.push(metadata.len() as u8);
+    bytes.push((metadata.len() >> 8) as u8);
+    bytes.extend_from_slice(&[0, 0]);
+    // No icon.
+    bytes.extend_from_slice(&[0, 0, 0, 0]);
+    bytes.extend_from_slice(metadata.as_bytes());
+    bytes.extend_from_slice(item_key.encrypt_op_data(&new_attachment.contents).as_bytes());
+
+    let file_path = path.as_ref().join(format!("{}_{}.attachment", item_uuid, uuid));
+    {
+        let mut file = try!(File::create(&file_path));
+        try!(file.write_all(&bytes));
+    }
+    LockedAttachment::from_file(file_path)
+}
+
+/// The plaintext of an attachment to be added to an item.
+#[derive(Clone, Debug)]
+pub struct NewAttachment {
+    pub contents: Vec<u8>,
+    pub file_name: String,
+}
+
+impl NewAttachment {
+    pub fn new(file_name: &str, contents: Vec<u8>) -> Self {
+        NewAttachment {
+            contents: contents,
+            file_name: file_name.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LockedAttachment {
+    contents: OpData01,
+    contents_size: u64,
+    created_at: u64,
+    icon: Option<OpData01>,
+    item_uuid: String,
+    overview: OpData01,
+    path: PathBuf,
+    updated_at: u64,
+    uuid: String,
+}
+
+impl LockedAttachment {
+    /// Reads an attachment file, which consists of a 16-byte header (`OPCLDAT`, version,
+    /// metadata size, padding and icon size), JSON metadata, an opdata01 icon and opdata01
+    /// contents.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let mut file = try!(File::open(path.as_ref()));
+        let mut bytes = Vec::new();
+        try!(file.read_to_end(&mut bytes));
+        if bytes.len() < HEADER_LEN || &bytes[0..HEADER.len()] != HEADER {
+            return Err(invalid_data("not an attachment file"));
+        }
+        let metadata_len = (bytes[8] as usize) | (bytes[9] as usize) << 8;
+        let icon_len = (bytes[12] as usize) |
+                       (bytes[13] as usize) << 8 |
+                       (bytes[14] as usize) << 16 |
+                       (bytes[15] as usize) << 24;
+        let icon_start = HEADER_LEN + metadata_len;
+        let contents_start = icon_start + icon_len;
+        if bytes.len() < contents_start {
+            return Err(invalid_data("attachment file is truncated"));
+        }
+        let metadata = String::from_utf8(bytes[HEADER_LEN..icon_start].to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        let metadata = try!(metadata);
+        let v = serde_json::de::from_str(&metadata)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        let v: Value = try!(v);
+        let icon = if icon_len > 0 {
+            OpData01::new(bytes[icon_start..contents_start].to_vec())
+        } else {
+            None
+        };
+        let contents = try!(OpData01::new(bytes[contents_start..].to_vec())
+            .ok_or_else(|| invalid_data("could not retrieve contents")));
+        Ok(LockedAttachment {
+            contents: contents,
+            contents_size: try!(v.retrieve("contentsSize", |v| v.as_u64())),
+            created_at: try!(v.retrieve("createdAt", |v| v.as_u64())),
+            icon: icon,
+            item_uuid: try!(v.retrieve("itemUUID", |v| v.as_owned_string())),
+            overview: try!(v.retrieve("overview", |v| v.as_op_data_01())),
+            path: path.as_ref().to_path_buf(),
+            updated_at: try!(v.retrieve("updatedAt", |v| v.as_u64())),
+            uuid: try!(v.retrieve("uuid", |v| v.as_owned_string())),
+        })
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn item_uuid(&self) -> &str {
+        &self.item_uuid
+    }
+
+    pub fn unlock(&self, overview_key: &MainKey) -> Option<Attachment> {
+        overview_key.decrypt_op_data(&self.overview)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|s| serde_json::de::from_str::<Value>(&s).ok())
+            .and_then(|overview| {
+                Some(Attachment {
+                    file_name: overview.find("filename").and_then(|v| v.as_owned_string()),
+                    locked: self.clone(),
+                })
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    file_name: Option<String>,
+    locked: LockedAttachment,
+}
+
+impl Attachment {
+    pub fn uuid(&self) -> &str {
+        &self.locked.uuid
+    }
+
+    pub fn item_uuid(&self) -> &str {
+        &self.locked.item_uuid
+    }
+
+    /// Returns the original file name, or the attachment UUID if it is unknown.
+    pub fn file_name(&self) -> &str {
+        self.file_name.as_ref().map(|s| &s[..]).unwrap_or(&self.locked.uuid[..])
+    }
+
+    pub fn size(&self) -> u64 {
+        self.locked.contents_size
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.locked.created_at
+    }
+
+    pub fn updated_at(&self) -> u64 {
+        self.locked.updated_at
+    }
+
+    pub fn decrypt_icon(&self, item_key: &ItemKey) -> Option<Vec<u8>> {
+        self.locked.icon.as_ref().and_then(|icon| item_key.decrypt_op_data(icon))
+    }
+
+    pub fn decrypt_contents(&self, item_key: &ItemKey) -> Option<Vec<u8>> {
+        item_key.decrypt_op_data(&self.locked.contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockedAttachment;
+
+    fn get_attachment_file_path() -> &'static str {
+        "test/SampleVault.opvault/default/\
+         2A632FDD32F5445E91EB5636C7580447_8FA293F2B001459D8F8F78C21E6BF9F6.attachment"
+    }
+
+    #[test]
+    fn test_from_file() {
+        let attachment = LockedAttachment::from_file(get_attachment_file_path()).unwrap();
+        assert_eq!(attachment.uuid(), "8FA293F2B001459D8F8F78C21E6BF9F6");
+        assert_eq!(attachment.item_uuid(), "2A632FDD32F5445E91EB5636C7580447");
+    }
+}
@@ -0,0 +1,147 @@
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Login,
+    CreditCard,
+    SecureNote,
+    Identity,
+    Password,
+    Tombstone,
+    SoftwareLicense,
+    BankAccount,
+    Database,
+    DriverLicense,
+    OutdoorLicense,
+    Membership,
+    Passport,
+    Rewards,
+    SocialSecurityNumber,
+    Router,
+    Server,
+    Email,
+    Unknown(String),
+}
+
+impl Category {
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "001" => Category::Login,
+            "002" => Category::CreditCard,
+            "003" => Category::SecureNote,
+            "004" => Category::Identity,
+            "005" => Category::Password,
+            "099" => Category::Tombstone,
+            "100" => Category::SoftwareLicense,
+            "101" => Category::BankAccount,
+            "102" => Category::Database,
+            "103" => Category::DriverLicense,
+            "104" => Category::OutdoorLicense,
+            "105" => Category::Membership,
+            "106" => Category::Passport,
+            "107" => Category::Rewards,
+            "108" => Category::SocialSecurityNumber,
+            "109" => Category::Router,
+            "110" => Category::Server,
+            "111" => Category::Email,
+            _ => Category::Unknown(code.to_string()),
+        }
+    }
+
+    /// Returns the category of a 1PIF `typeName`, or `None` if it is not an item type.
+    pub fn from_type_name(type_name: &str) -> Option<Self> {
+        let category = match type_name {
+            "webforms.WebForm" => Category::Login,
+            "wallet.financial.CreditCard" => Category::CreditCard,
+            "securenotes.SecureNote" => Category::SecureNote,
+            "identities.Identity" => Category::Identity,
+            "passwords.Password" => Category::Password,
+            "system.Tombstone" => Category::Tombstone,
+            "wallet.computer.License" => Category::SoftwareLicense,
+            "wallet.financial.BankAccountUS" => Category::BankAccount,
+            "wallet.computer.Database" => Category::Database,
+            "wallet.government.DriversLicense" => Category::DriverLicense,
+            "wallet.government.HuntingLicense" => Category::OutdoorLicense,
+            "wallet.membership.Membership" => Category::Membership,
+            "wallet.government.Passport" => Category::Passport,
+            "wallet.membership.RewardProgram" => Category::Rewards,
+            "wallet.government.SsnUS" => Category::SocialSecurityNumber,
+            "wallet.computer.Router" => Category::Router,
+            "wallet.computer.UnixServer" => Category::Server,
+            "wallet.onlineservices.Email.v2" => Category::Email,
+            _ => return None,
+        };
+        Some(category)
+    }
+
+    pub fn code(&self) -> &str {
+        match *self {
+            Category::Login => "001",
+            Category::CreditCard => "002",
+            Category::SecureNote => "003",
+            Category::Identity => "004",
+            Category::Password => "005",
+            Category::Tombstone => "099",
+            Category::SoftwareLicense => "100",
+            Category::BankAccount => "101",
+            Category::Database => "102",
+            Category::DriverLicense => "103",
+            Category::OutdoorLicense => "104",
+            Category::Membership => "105",
+            Category::Passport => "106",
+            Category::Rewards => "107",
+            Category::SocialSecurityNumber => "108",
+            Category::Router => "109",
+            Category::Server => "110",
+            Category::Email => "111",
+            Category::Unknown(ref code) => code,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Category::Login => "Login",
+            Category::CreditCard => "Credit Card",
+            Category::SecureNote => "Secure Note",
+            Category::Identity => "Identity",
+            Category::Password => "Password",
+            Category::Tombstone => "Tombstone",
+            Category::SoftwareLicense => "Software License",
+            Category::BankAccount => "Bank Account",
+            Category::Database => "Database",
+            Category::DriverLicense => "Driver License",
+            Category::OutdoorLicense => "Outdoor License",
+            Category::Membership => "Membership",
+            Category::Passport => "Passport",
+            Category::Rewards => "Reward Program",
+            Category::SocialSecurityNumber => "Social Security Number",
+            Category::Router => "Wireless Router",
+            Category::Server => "Server",
+            Category::Email => "Email Account",
+            Category::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// Returns the `typeName` that 1Password uses for this category in 1PIF files.
+    pub fn type_name(&self) -> &str {
+        match *self {
+            Category::Login => "webforms.WebForm",
+            Category::CreditCard => "wallet.financial.CreditCard",
+            Category::SecureNote => "securenotes.SecureNote",
+            Category::Identity => "identities.Identity",
+            Category::Password => "passwords.Password",
+            Category::Tombstone => "system.Tombstone",
+            Category::SoftwareLicense => "wallet.computer.License",
+            Category::BankAccount => "wallet.financial.BankAccountUS",
+            Category::Database => "wallet.computer.Database",
+            Category::DriverLicense => "wallet.government.DriversLicense",
+            Category::OutdoorLicense => "wallet.government.HuntingLicense",
+            Category::Membership => "wallet.membership.Membership",
+            Category::Passport => "wallet.government.Passport",
+            Category::Rewards => "wallet.membership.RewardProgram",
+            Category::SocialSecurityNumber => "wallet.government.SsnUS",
+            Category::Router => "wallet.computer.Router",
+            Category::Server => "wallet.computer.UnixServer",
+            Category::Email => "wallet.onlineservices.Email.v2",
+            Category::Unknown(_) => "securenotes.SecureNote",
+        }
+    }
+}
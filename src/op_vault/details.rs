@@ -0,0 +1,231 @@
+use serde_json::Value;
+
+use json_value_ext::JsonValueExt;
+
+#[derive(Clone, Debug)]
+pub struct ItemDetails {
+    value: Value,
+}
+
+impl ItemDetails {
+    pub fn new(value: Value) -> Self {
+        ItemDetails { value: value }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.value.find("notesPlain").and_then(|v| v.as_string())
+    }
+
+    /// Returns the web form fields of a login.
+    pub fn fields(&self) -> Vec<LoginField> {
+        self.value.find("fields")
+            .and_then(|v| v.as_array())
+            .map(|array| array.iter().map(|v| LoginField::from_json(v)).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    pub fn sections(&self) -> Vec<Section> {
+        self.value.find("sections")
+            .and_then(|v| v.as_array())
+            .map(|array| array.iter().map(|v| Section::from_json(v)).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Returns the value of the login field designated as `designation`.
+    pub fn designated_field(&self, designation: &str) -> Option<String> {
+        self.fields()
+            .into_iter()
+            .find(|f| f.designation() == Some(designation))
+            .map(|f| f.value().to_string())
+    }
+
+    pub fn username(&self) -> Option<String> {
+        self.designated_field("username")
+    }
+
+    /// Returns the password of a login, or the `password` attribute of other categories
+    /// such as Password.
+    pub fn password(&self) -> Option<String> {
+        self.designated_field("password")
+            .or_else(|| self.value.find("password").and_then(|v| v.as_owned_string()))
+    }
+
+    /// Returns the first one-time password of the sections, usually an `otpauth://` URI.
+    pub fn totp(&self) -> Option<String> {
+        self.sections()
+            .iter()
+            .flat_map(|section| section.fields().iter())
+            .filter_map(|field| {
+                field.value_string().and_then(|value| {
+                    if field.name().starts_with("TOTP_") || value.starts_with("otpauth://") {
+                        Some(value)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .next()
+    }
+
+    pub fn password_history(&self) -> Vec<PasswordHistoryEntry> {
+        self.value.find("passwordHistory")
+            .and_then(|v| v.as_array())
+            .map(|array| {
+                array.iter()
+                    .map(|v| {
+                        PasswordHistoryEntry {
+                            time: v.find("time").and_then(|v| v.as_u64()).unwrap_or(0),
+                            value: v.find("value")
+                                .and_then(|v| v.as_owned_string())
+                                .unwrap_or_else(String::new),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginField {
+    designation: Option<String>,
+    kind: String,
+    name: String,
+    value: String,
+}
+
+impl LoginField {
+    fn from_json(v: &Value) -> Self {
+        LoginField {
+            designation: v.find("designation").and_then(|v| v.as_owned_string()),
+            kind: v.find("type").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            name: v.find("name").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            value: v.find("value").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+        }
+    }
+
+    pub fn designation(&self) -> Option<&str> {
+        self.designation.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the HTML input type code: `T` (text), `P` (password), `E` (email),
+    /// `C` (checkbox), `B` (button) and so on.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    fields: Vec<SectionField>,
+    name: String,
+    title: String,
+}
+
+impl Section {
+    fn from_json(v: &Value) -> Self {
+        Section {
+            fields: v.find("fields")
+                .and_then(|v| v.as_array())
+                .map(|array| array.iter().map(|v| SectionField::from_json(v)).collect())
+                .unwrap_or_else(Vec::new),
+            name: v.find("name").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            title: v.find("title").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+        }
+    }
+
+    pub fn fields(&self) -> &[SectionField] {
+        &self.fields
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SectionField {
+    kind: String,
+    name: String,
+    title: String,
+    value: Value,
+}
+
+impl SectionField {
+    fn from_json(v: &Value) -> Self {
+        SectionField {
+            kind: v.find("k").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            name: v.find("n").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            title: v.find("t").and_then(|v| v.as_owned_string()).unwrap_or_else(String::new),
+            value: v.find("v").cloned().unwrap_or(Value::Null),
+        }
+    }
+
+    /// Returns the field kind: `string`, `concealed`, `email`, `URL`, `date`, `monthYear`,
+    /// `menu`, `phone`, `address` and so on.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn is_concealed(&self) -> bool {
+        self.kind == "concealed"
+    }
+
+    /// Returns the value as a string, or `None` if the field is empty.
+    pub fn value_string(&self) -> Option<String> {
+        match self.value {
+            Value::Null => None,
+            Value::String(ref s) if s.is_empty() => None,
+            Value::String(ref s) => Some(s.clone()),
+            Value::I64(n) => Some(n.to_string()),
+            Value::U64(n) => Some(n.to_string()),
+            Value::F64(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            ref v => Some(format!("{}", v)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordHistoryEntry {
+    time: u64,
+    value: String,
+}
+
+impl PasswordHistoryEntry {
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
@@ -1,10 +1,12 @@
 use serde_json::{self, Value};
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{self, Read, Result as IoResult};
+use std::fs::{self, File};
+use std::io::{self, Read, Result as IoResult, Write};
 use std::path::Path;
 
 use json_value_ext::JsonValueExt;
+use op_vault::key::MainKey;
+use op_vault::uuid;
 
 pub fn load_folder_map<P: AsRef<Path>>(path: P) -> IoResult<BTreeMap<String, LockedFolder>> {
     let mut file = try!(File::open(path));
@@ -19,6 +21,7 @@ pub fn load_folder_map<P: AsRef<Path>>(path: P) -> IoResult<BTreeMap<String, Loc
         map.insert((*k).clone(), LockedFolder {
             created: try!(v.retrieve("created", |v| v.as_u64())),
             overview: try!(v.retrieve("overview", |v| v.as_owned_string())),
+            parent: v.retrieve("parent", |v| v.as_owned_string()).ok(),
             smart: v.retrieve("smart", |v| v.as_boolean()).unwrap_or(false),
             tx: try!(v.retrieve("tx", |v| v.as_u64())),
             updated: try!(v.retrieve("updated", |v| v.as_u64())),
@@ -28,16 +31,153 @@ pub fn load_folder_map<P: AsRef<Path>>(path: P) -> IoResult<BTreeMap<String, Loc
     Ok(map)
 }
 
+/// Rewrites `folders.js` at `path` with the folders of `map`.
+pub fn save_folder_map<P: AsRef<Path>>(path: P,
+                                       map: &BTreeMap<String, LockedFolder>)
+                                       -> IoResult<()> {
+    let mut folders = BTreeMap::new();
+    for (uuid, folder) in map {
+        folders.insert(uuid.clone(), folder.to_json());
+    }
+    let json = try!(serde_json::to_string(&Value::Object(folders))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    let tmp_path = path.as_ref().with_extension("js.tmp");
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(write!(file, "loadFolders({});", json));
+        try!(file.sync_all());
+    }
+    fs::rename(&tmp_path, path)
+}
+
 #[derive(Clone, Debug)]
 pub struct LockedFolder {
     created: u64,
     overview: String,
+    parent: Option<String>,
     smart: bool,
     tx: u64,
     updated: u64,
     uuid: String,
 }
 
+impl LockedFolder {
+    /// Creates a regular folder titled `title`, generating a UUID unless one is given.
+    pub fn create(uuid: Option<&str>,
+                  title: &str,
+                  parent: Option<&str>,
+                  overview_key: &MainKey,
+                  now: u64)
+                  -> IoResult<Self> {
+        let mut overview = BTreeMap::new();
+        overview.insert("title".to_string(), Value::String(title.to_string()));
+        let overview = try!(serde_json::to_string(&Value::Object(overview))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        Ok(LockedFolder {
+            created: now,
+            overview: overview_key.encrypt_op_data(overview.as_bytes()).to_base64_string(),
+            parent: parent.map(|s| s.to_string()),
+            smart: false,
+            tx: now,
+            updated: now,
+            uuid: uuid.map(|s| s.to_string()).unwrap_or_else(uuid::generate),
+        })
+    }
+
+    /// Returns the folder as stored in `folders.js`.
+    pub fn to_json(&self) -> Value {
+        let mut map = BTreeMap::new();
+        map.insert("created".to_string(), Value::U64(self.created));
+        map.insert("overview".to_string(), Value::String(self.overview.clone()));
+        if let Some(ref parent) = self.parent {
+            map.insert("parent".to_string(), Value::String(parent.clone()));
+        }
+        if self.smart {
+            map.insert("smart".to_string(), Value::Bool(true));
+        }
+        map.insert("tx".to_string(), Value::U64(self.tx));
+        map.insert("updated".to_string(), Value::U64(self.updated));
+        map.insert("uuid".to_string(), Value::String(self.uuid.clone()));
+        Value::Object(map)
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn unlock(&self, overview_key: &MainKey) -> Option<Folder> {
+        overview_key.decrypt_op_data_str(&self.overview)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|s| serde_json::de::from_str::<Value>(trim_padding(&s)).ok())
+            .and_then(|overview| {
+                Some(Folder {
+                    locked: self.clone(),
+                    title: overview.find("title").and_then(|v| v.as_owned_string()),
+                })
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Folder {
+    locked: LockedFolder,
+    title: Option<String>,
+}
+
+impl Folder {
+    pub fn uuid(&self) -> &str {
+        &self.locked.uuid
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_ref().map(|s| &s[..]).unwrap_or("")
+    }
+
+    pub fn parent(&self) -> Option<&str> {
+        self.locked.parent.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the title prefixed by those of the ancestors found in `folders`, separated by
+    /// slashes.
+    pub fn path(&self, folders: &[Folder]) -> String {
+        let mut titles = vec![self.title().to_string()];
+        let mut parent = self.parent();
+        while let Some(folder) = parent.and_then(|uuid| folders.iter().find(|f| f.uuid() == uuid)) {
+            // Parents that form a cycle would otherwise never end.
+            if titles.len() > folders.len() {
+                break;
+            }
+            titles.insert(0, folder.title().to_string());
+            parent = folder.parent();
+        }
+        titles.join("/")
+    }
+
+    pub fn is_smart(&self) -> bool {
+        self.locked.smart
+    }
+
+    pub fn created(&self) -> u64 {
+        self.locked.created
+    }
+
+    pub fn updated(&self) -> u64 {
+        self.locked.updated
+    }
+
+    pub fn tx(&self) -> u64 {
+        self.locked.tx
+    }
+}
+
 fn strip_js(s: &str) -> &str {
     s.trim_left_matches("loadFolders(").trim_right_matches(");")
 }
+
+/// Smart folder overviews may carry stray bytes after the closing brace.
+fn trim_padding(s: &str) -> &str {
+    match s.rfind('}') {
+        Some(i) => &s[..i + 1],
+        None => s,
+    }
+}
@@ -0,0 +1,450 @@
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Result as IoResult, Write};
+use std::path::Path;
+
+use json_value_ext::JsonValueExt;
+use op_vault::attachment::NewAttachment;
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::key::{ItemKey, Key, MainKey};
+use op_vault::op_data_01::OpData01;
+use op_vault::uuid;
+use util::encode_base64;
+
+pub const BAND_NAMES: &'static str = "0123456789ABCDEF";
+
+pub fn load_item_map<P: AsRef<Path>>(path: P) -> IoResult<BTreeMap<String, LockedItem>> {
+    let mut map = BTreeMap::new();
+    for band in BAND_NAMES.chars() {
+        let band_path = path.as_ref().join(format!("band_{}.js", band));
+        if !band_path.exists() {
+            continue;
+        }
+        for (uuid, item) in try!(load_band(&band_path)) {
+            map.insert(uuid, item);
+        }
+    }
+    Ok(map)
+}
+
+/// Returns the band an item belongs to, which is the first digit of its UUID.
+pub fn band_name(uuid: &str) -> char {
+    uuid.chars().next().unwrap_or('0')
+}
+
+/// Rewrites `band_<band>.js` in the profile directory `path` with the items of `map` that
+/// belong to the band.
+pub fn save_band<P: AsRef<Path>>(path: P,
+                                 band: char,
+                                 map: &BTreeMap<String, LockedItem>)
+                                 -> IoResult<()> {
+    let mut band_map = BTreeMap::new();
+    for (uuid, item) in map.iter().filter(|&(uuid, _)| band_name(uuid) == band) {
+        band_map.insert(uuid.clone(), item.to_json());
+    }
+    let json = try!(serde_json::to_string(&Value::Object(band_map))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    let band_path = path.as_ref().join(format!("band_{}.js", band));
+    let tmp_path = path.as_ref().join(format!("band_{}.js.tmp", band));
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(write!(file, "ld({});", json));
+        try!(file.sync_all());
+    }
+    fs::rename(&tmp_path, &band_path)
+}
+
+fn load_band(path: &Path) -> IoResult<BTreeMap<String, LockedItem>> {
+    let mut file = try!(File::open(path));
+    let mut buf = String::new();
+    try!(file.read_to_string(&mut buf));
+    let json = strip_js(&buf);
+    let v = serde_json::de::from_str(json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    let v: serde_json::Value = try!(v);
+    let mut map = BTreeMap::new();
+    for (k, v) in v.as_object().expect("root must be object").iter() {
+        map.insert((*k).clone(), LockedItem {
+            category: try!(v.retrieve("category", |v| v.as_owned_string())),
+            created: try!(v.retrieve("created", |v| v.as_u64())),
+            details: try!(v.retrieve("d", |v| v.as_op_data_01())),
+            fave: v.retrieve("fave", |v| v.as_u64()).ok(),
+            folder: v.retrieve("folder", |v| v.as_owned_string()).ok(),
+            hmac: try!(v.retrieve("hmac", |v| v.decode_base64())),
+            key_data: try!(v.retrieve("k", |v| v.decode_base64())),
+            overview: try!(v.retrieve("o", |v| v.as_op_data_01())),
+            trashed: v.retrieve("trashed", |v| v.as_boolean()).unwrap_or(false),
+            tx: try!(v.retrieve("tx", |v| v.as_u64())),
+            updated: try!(v.retrieve("updated", |v| v.as_u64())),
+            uuid: try!(v.retrieve("uuid", |v| v.as_owned_string())),
+        });
+    }
+    Ok(map)
+}
+
+/// The plaintext attributes of an item to be added to a vault.
+#[derive(Clone, Debug)]
+pub struct NewItem {
+    pub attachments: Vec<NewAttachment>,
+    pub category: Category,
+    pub created: Option<u64>,
+    pub details: Value,
+    pub fave: Option<u64>,
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+    pub title: String,
+    pub trashed: bool,
+    pub updated: Option<u64>,
+    pub urls: Vec<String>,
+    pub uuid: Option<String>,
+}
+
+impl NewItem {
+    pub fn new(category: Category, title: &str, details: Value) -> Self {
+        NewItem {
+            attachments: Vec::new(),
+            category: category,
+            created: None,
+            details: details,
+            fave: None,
+            folder: None,
+            tags: Vec::new(),
+            title: title.to_string(),
+            trashed: false,
+            updated: None,
+            urls: Vec::new(),
+            uuid: None,
+        }
+    }
+
+    /// Builds the overview, which holds what 1Password shows without decrypting details.
+    pub fn overview(&self) -> Value {
+        let mut overview = BTreeMap::new();
+        overview.insert("title".to_string(), Value::String(self.title.clone()));
+        let ainfo = summarize(&self.category, &ItemDetails::new(self.details.clone()));
+        overview.insert("ainfo".to_string(), Value::String(ainfo));
+        if let Some(url) = self.urls.first() {
+            overview.insert("url".to_string(), Value::String(url.clone()));
+            let urls = self.urls
+                .iter()
+                .map(|url| {
+                    let mut entry = BTreeMap::new();
+                    entry.insert("u".to_string(), Value::String(url.clone()));
+                    Value::Object(entry)
+                })
+                .collect();
+            overview.insert("URLs".to_string(), Value::Array(urls));
+        }
+        if !self.tags.is_empty() {
+            let tags = self.tags.iter().cloned().map(Value::String).collect();
+            overview.insert("tags".to_string(), Value::Array(tags));
+        }
+        Value::Object(overview)
+    }
+}
+
+/// Returns the short description shown under the title, such as the username of a login.
+fn summarize(category: &Category, details: &ItemDetails) -> String {
+    match *category {
+        Category::Login => details.username().unwrap_or_else(String::new),
+        Category::Password => String::new(),
+        Category::SecureNote => {
+            details.notes()
+                .and_then(|notes| notes.lines().next())
+                .map(|line| line.chars().take(80).collect())
+                .unwrap_or_else(String::new)
+        }
+        _ => {
+            details.sections()
+                .iter()
+                .flat_map(|section| section.fields().iter())
+                .filter(|field| !field.is_concealed())
+                .filter_map(|field| field.value_string())
+                .next()
+                .unwrap_or_else(String::new)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LockedItem {
+    category: String,
+    created: u64,
+    details: OpData01,
+    fave: Option<u64>,
+    folder: Option<String>,
+    hmac: Vec<u8>,
+    key_data: Vec<u8>,
+    overview: OpData01,
+    trashed: bool,
+    tx: u64,
+    updated: u64,
+    uuid: String,
+}
+
+impl LockedItem {
+    /// Encrypts `new_item` under a freshly generated item key.
+    pub fn create(new_item: &NewItem,
+                  master_key: &MainKey,
+                  overview_key: &MainKey,
+                  now: u64)
+                  -> IoResult<Self> {
+        let overview = try!(serde_json::to_string(&new_item.overview())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        let details = try!(serde_json::to_string(&new_item.details)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        let item_key = ItemKey::generate();
+        let mut item = LockedItem {
+            category: new_item.category.code().to_string(),
+            created: new_item.created.unwrap_or(now),
+            details: item_key.encrypt_op_data(details.as_bytes()),
+            fave: new_item.fave,
+            folder: new_item.folder.clone(),
+            hmac: Vec::new(),
+            key_data: item_key.to_key_data(master_key),
+            overview: overview_key.encrypt_op_data(overview.as_bytes()),
+            trashed: new_item.trashed,
+            tx: now,
+            updated: new_item.updated.unwrap_or(now),
+            uuid: new_item.uuid.clone().unwrap_or_else(uuid::generate),
+        };
+        item.hmac = item.compute_hmac(overview_key);
+        Ok(item)
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn category(&self) -> Category {
+        Category::from_code(&self.category)
+    }
+
+    pub fn folder(&self) -> Option<&str> {
+        self.folder.as_ref().map(|s| &s[..])
+    }
+
+    pub fn is_trashed(&self) -> bool {
+        self.trashed
+    }
+
+    pub fn verify_hmac(&self, overview_key: &MainKey) -> bool {
+        self.compute_hmac(overview_key) == self.hmac
+    }
+
+    /// Computes the `hmac` attribute, which authenticates every other attribute. The message
+    /// is the concatenation of each attribute name and value in the order of the names.
+    fn compute_hmac(&self, overview_key: &MainKey) -> Vec<u8> {
+        let mut message = Vec::new();
+        if let Value::Object(map) = self.to_json() {
+            for (k, v) in map.iter().filter(|&(k, _)| k != "hmac") {
+                message.extend_from_slice(k.as_bytes());
+                let v = match *v {
+                    Value::String(ref s) => s.clone(),
+                    Value::Bool(b) => if b { "1" } else { "0" }.to_string(),
+                    Value::I64(n) => n.to_string(),
+                    Value::U64(n) => n.to_string(),
+                    ref v => format!("{}", v),
+                };
+                message.extend_from_slice(v.as_bytes());
+            }
+        }
+        overview_key.compute_mac(&message)
+    }
+
+    /// Returns the item as stored in a band file.
+    pub fn to_json(&self) -> Value {
+        let mut map = BTreeMap::new();
+        map.insert("category".to_string(), Value::String(self.category.clone()));
+        map.insert("created".to_string(), Value::U64(self.created));
+        map.insert("d".to_string(), Value::String(self.details.to_base64_string()));
+        if let Some(fave) = self.fave {
+            map.insert("fave".to_string(), Value::U64(fave));
+        }
+        if let Some(ref folder) = self.folder {
+            map.insert("folder".to_string(), Value::String(folder.clone()));
+        }
+        map.insert("hmac".to_string(), Value::String(encode_base64(&self.hmac)));
+        map.insert("k".to_string(), Value::String(encode_base64(&self.key_data)));
+        map.insert("o".to_string(), Value::String(self.overview.to_base64_string()));
+        if self.trashed {
+            map.insert("trashed".to_string(), Value::Bool(true));
+        }
+        map.insert("tx".to_string(), Value::U64(self.tx));
+        map.insert("updated".to_string(), Value::U64(self.updated));
+        map.insert("uuid".to_string(), Value::String(self.uuid.clone()));
+        Value::Object(map)
+    }
+
+    pub fn unlock(&self, overview_key: &MainKey) -> Option<Item> {
+        overview_key.decrypt_op_data(&self.overview)
+            .and_then(|bytes| parse_json(bytes))
+            .and_then(|overview| {
+                Some(Item {
+                    locked: self.clone(),
+                    overview: overview,
+                })
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Item {
+    locked: LockedItem,
+    overview: Value,
+}
+
+impl Item {
+    pub fn uuid(&self) -> &str {
+        &self.locked.uuid
+    }
+
+    pub fn category(&self) -> Category {
+        self.locked.category()
+    }
+
+    pub fn created(&self) -> u64 {
+        self.locked.created
+    }
+
+    pub fn updated(&self) -> u64 {
+        self.locked.updated
+    }
+
+    pub fn tx(&self) -> u64 {
+        self.locked.tx
+    }
+
+    pub fn fave(&self) -> Option<u64> {
+        self.locked.fave
+    }
+
+    pub fn folder(&self) -> Option<&str> {
+        self.locked.folder()
+    }
+
+    pub fn is_trashed(&self) -> bool {
+        self.locked.trashed
+    }
+
+    pub fn overview(&self) -> &Value {
+        &self.overview
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.overview.find("title").and_then(|v| v.as_string())
+    }
+
+    pub fn ainfo(&self) -> Option<&str> {
+        self.overview.find("ainfo").and_then(|v| v.as_string())
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.overview.find("url").and_then(|v| v.as_string())
+    }
+
+    /// Returns the `URLs` of the overview, falling back to the single `url`.
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls = Vec::new();
+        if let Some(array) = self.overview.find("URLs").and_then(|v| v.as_array()) {
+            for v in array {
+                if let Some(u) = v.find("u").and_then(|v| v.as_string()) {
+                    urls.push(u.to_string());
+                }
+            }
+        }
+        if let Some(url) = self.url() {
+            if !urls.iter().any(|u| u == url) {
+                urls.insert(0, url.to_string());
+            }
+        }
+        urls
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.overview.find("tags")
+            .and_then(|v| v.as_array())
+            .map(|array| array.iter().filter_map(|v| v.as_owned_string()).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    pub fn item_key(&self, master_key: &MainKey) -> Option<ItemKey> {
+        ItemKey::from_key_data(master_key, &self.locked.key_data)
+    }
+
+    pub fn decrypt_details(&self, master_key: &MainKey) -> Option<ItemDetails> {
+        self.item_key(master_key)
+            .and_then(|item_key| item_key.decrypt_op_data(&self.locked.details))
+            .and_then(|bytes| parse_json(bytes))
+            .map(|details| ItemDetails::new(details))
+    }
+}
+
+fn parse_json(bytes: Vec<u8>) -> Option<Value> {
+    String::from_utf8(bytes)
+        .ok()
+        .and_then(|s| serde_json::de::from_str(&s).ok())
+}
+
+fn strip_js(s: &str) -> &str {
+    s.trim().trim_left_matches("ld(").trim_right_matches(");")
+}
+
+#[cfg(test)]
+mod tests {
+    use op_vault::profile::{LockedProfile, Profile};
+
+    fn get_profile_dir_path() -> &'static str {
+        "test/SampleVault.opvault/default"
+    }
+
+    fn unlock_sample_profile() -> Profile {
+        let path = "test/SampleVault.opvault/default/profile.js";
+        LockedProfile::from_file(path).unwrap().unlock("freddy").unwrap()
+    }
+
+    #[test]
+    fn test_load_item_map() {
+        let map = super::load_item_map(get_profile_dir_path()).unwrap();
+        assert_eq!(map.len(), 29);
+        let item = map.get("0C4F27910A64488BB339AED63565D148").unwrap();
+        assert!(item.is_trashed());
+    }
+
+    #[test]
+    fn test_verify_hmac() {
+        let profile = unlock_sample_profile();
+        let map = super::load_item_map(get_profile_dir_path()).unwrap();
+        for item in map.values() {
+            assert!(item.verify_hmac(profile.overview_key()), "{}", item.uuid());
+        }
+    }
+
+    #[test]
+    fn test_create_round_trips() {
+        use op_vault::category::Category;
+        use serde_json::Value;
+        use std::collections::BTreeMap;
+        use super::{LockedItem, NewItem};
+
+        let profile = unlock_sample_profile();
+        let mut details = BTreeMap::new();
+        details.insert("password".to_string(), Value::String("hunter2".to_string()));
+        let mut new_item = NewItem::new(Category::Password, "Example", Value::Object(details));
+        new_item.urls.push("https://example.com/".to_string());
+        let locked = LockedItem::create(&new_item,
+                                        profile.master_key().unwrap(),
+                                        profile.overview_key(),
+                                        1400000000)
+            .unwrap();
+        assert!(locked.verify_hmac(profile.overview_key()));
+        let item = locked.unlock(profile.overview_key()).unwrap();
+        assert_eq!(item.title(), Some("Example"));
+        assert_eq!(item.url(), Some("https://example.com/"));
+        assert_eq!(item.created(), 1400000000);
+        let details = item.decrypt_details(profile.master_key().unwrap()).unwrap();
+        assert_eq!(details.password(), Some("hunter2".to_string()));
+    }
+}
@@ -1,4 +1,4 @@
-use openssl::crypto::{hash, hmac, pkcs5, symm};
+use openssl::crypto::{hash, hmac, pkcs5, rand, symm};
 
 use op_vault::op_data_01::OpData01;
 
@@ -18,6 +18,15 @@ pub trait Key {
         plaintext.extend(crypter.finalize());
         plaintext
     }
+
+    fn encrypt_aes(&self, iv: &[u8], bytes: &[u8]) -> Vec<u8> {
+        let crypter = symm::Crypter::new(symm::Type::AES_256_CBC);
+        crypter.init(symm::Mode::Encrypt, self.enc_key(), iv);
+        crypter.pad(false);
+        let mut ciphertext = crypter.update(bytes);
+        ciphertext.extend(crypter.finalize());
+        ciphertext
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -44,6 +53,21 @@ impl MainKey {
         }
     }
 
+    /// Restores a key from the 64 bytes of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 64 {
+            return None;
+        }
+        Some(MainKey::new(bytes[0..32].to_vec(), bytes[32..64].to_vec()))
+    }
+
+    /// Returns the encryption key followed by the MAC key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.enc_key.clone();
+        bytes.extend_from_slice(&self.mac_key);
+        bytes
+    }
+
     pub fn from_op_data(key: &DerivedKey, op_data: &OpData01) -> Option<Self> {
         if let Some(plaintext) = op_data.decrypt_with_key(key) {
             let bytes = hash::hash(hash::Type::SHA512, &plaintext);
@@ -68,6 +92,10 @@ impl MainKey {
         OpData01::from_base64_str(b64str)
             .and_then(|op_data| op_data.decrypt_with_key(self))
     }
+
+    pub fn encrypt_op_data(&self, plaintext: &[u8]) -> OpData01 {
+        OpData01::encrypt_with_key(self, plaintext)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -103,6 +131,80 @@ impl DerivedKey {
     }
 }
 
+const ITEM_KEY_IV_LEN: usize = 16;
+const ITEM_KEY_CIPHERTEXT_LEN: usize = 64;
+const ITEM_KEY_MAC_LEN: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct ItemKey {
+    enc_key: Vec<u8>,
+    mac_key: Vec<u8>,
+}
+
+impl Key for ItemKey {
+    fn enc_key(&self) -> &[u8] {
+        &self.enc_key
+    }
+
+    fn mac_key(&self) -> &[u8] {
+        &self.mac_key
+    }
+}
+
+impl ItemKey {
+    pub fn new(enc_key: Vec<u8>, mac_key: Vec<u8>) -> Self {
+        ItemKey {
+            enc_key: enc_key,
+            mac_key: mac_key,
+        }
+    }
+
+    /// Decrypts the `k` attribute of an item, which is an IV, two AES blocks worth of key
+    /// material and a MAC, with no opdata01 header.
+    pub fn from_key_data(master_key: &MainKey, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ITEM_KEY_IV_LEN + ITEM_KEY_CIPHERTEXT_LEN + ITEM_KEY_MAC_LEN {
+            return None;
+        }
+        let payload_end = bytes.len() - ITEM_KEY_MAC_LEN;
+        if master_key.compute_mac(&bytes[..payload_end]) != &bytes[payload_end..] {
+            return None;
+        }
+        let plaintext = master_key.decrypt_aes(&bytes[..ITEM_KEY_IV_LEN],
+                                               &bytes[ITEM_KEY_IV_LEN..payload_end]);
+        let enc_key = plaintext[0..32].to_vec();
+        let mac_key = plaintext[32..64].to_vec();
+        Some(ItemKey::new(enc_key, mac_key))
+    }
+
+    /// Generates a fresh random key for a new item.
+    pub fn generate() -> Self {
+        let bytes = rand::rand_bytes(64);
+        let enc_key = bytes[0..32].to_vec();
+        let mac_key = bytes[32..64].to_vec();
+        ItemKey::new(enc_key, mac_key)
+    }
+
+    /// Encrypts the key with `master_key` into the format of the `k` attribute.
+    pub fn to_key_data(&self, master_key: &MainKey) -> Vec<u8> {
+        let iv = rand::rand_bytes(ITEM_KEY_IV_LEN);
+        let mut plaintext = self.enc_key.clone();
+        plaintext.extend_from_slice(&self.mac_key);
+        let mut bytes = iv.clone();
+        bytes.extend(master_key.encrypt_aes(&iv, &plaintext));
+        let mac = master_key.compute_mac(&bytes);
+        bytes.extend(mac);
+        bytes
+    }
+
+    pub fn decrypt_op_data(&self, op_data: &OpData01) -> Option<Vec<u8>> {
+        op_data.decrypt_with_key(self)
+    }
+
+    pub fn encrypt_op_data(&self, plaintext: &[u8]) -> OpData01 {
+        OpData01::encrypt_with_key(self, plaintext)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use base64;
@@ -132,4 +234,16 @@ mod tests {
         let overview = overview_key.decrypt_op_data_str(DEMO_OVERVIEW_OP_DATA).unwrap();
         assert_eq!(overview, DEMO_OVERVIEW.as_bytes());
     }
+
+    #[test]
+    fn test_item_key_round_trips_through_key_data() {
+        let salt = base64::u8de(DEMO_SALT.as_bytes()).unwrap();
+        let derived_key = super::DerivedKey::from_password(&DEMO_PASSWORD, &salt, DEMO_ITERATIONS);
+        let master_key = super::MainKey::from_op_data_str(&derived_key, DEMO_OVERVIEW_KEY_OP_DATA).unwrap();
+        let item_key = super::ItemKey::generate();
+        let key_data = item_key.to_key_data(&master_key);
+        let decrypted_item_key = super::ItemKey::from_key_data(&master_key, &key_data).unwrap();
+        let op_data = item_key.encrypt_op_data(DEMO_OVERVIEW.as_bytes());
+        assert_eq!(decrypted_item_key.decrypt_op_data(&op_data).unwrap(), DEMO_OVERVIEW.as_bytes());
+    }
 }
@@ -1,5 +1,10 @@
+pub mod attachment;
+pub mod category;
+pub mod details;
 pub mod folder;
+pub mod item;
 pub mod key;
 pub mod op_data_01;
 pub mod profile;
+pub mod uuid;
 pub mod vault;
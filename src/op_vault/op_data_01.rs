@@ -1,10 +1,12 @@
 use base64;
+use openssl::crypto::rand;
 
 use op_vault::key::Key;
 
 const HEADER_LEN: usize = 8;
 const PLAINTEXT_LEN_LEN: usize = 8;
 const IV_LEN: usize = 16;
+const BLOCK_LEN: usize = 16;
 const MAC_LEN: usize = 32;
 const MIN_OP_DATA_01_LEN: usize = HEADER_LEN + PLAINTEXT_LEN_LEN + IV_LEN + MAC_LEN;
 
@@ -37,6 +39,31 @@ impl OpData01 {
             .and_then(|bytes| OpData01::new(bytes))
     }
 
+    /// Encrypts `plaintext` with a random IV. The plaintext is prefixed with 1 to 16 random
+    /// bytes so that it fills whole AES blocks.
+    pub fn encrypt_with_key(key: &Key, plaintext: &[u8]) -> Self {
+        let padding_len = BLOCK_LEN - plaintext.len() % BLOCK_LEN;
+        let mut padded_plaintext = rand::rand_bytes(padding_len);
+        padded_plaintext.extend_from_slice(plaintext);
+        let iv = rand::rand_bytes(IV_LEN);
+        let mut bytes = b"opdata01".to_vec();
+        bytes.extend(u64_to_bytes_le(plaintext.len() as u64));
+        bytes.extend_from_slice(&iv);
+        bytes.extend(key.encrypt_aes(&iv, &padded_plaintext));
+        let mac = key.compute_mac(&bytes);
+        bytes.extend(mac);
+        OpData01::from_bytes_unchecked(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn to_base64_string(&self) -> String {
+        let b64 = base64::u8en(&self.bytes).expect("base64 encoding never fails");
+        String::from_utf8(b64).expect("base64 is ASCII")
+    }
+
     pub fn validate_with_key(&self, key: &Key) -> bool {
         let payload_end = self.bytes.len().saturating_sub(MAC_LEN);
         let payload = &self.bytes[..payload_end];
@@ -87,3 +114,24 @@ fn u64_from_bytes_le(bytes: &[u8]) -> u64 {
         (bytes[0] as u64);
     u64::from_le(u64_le)
 }
+
+fn u64_to_bytes_le(n: u64) -> Vec<u8> {
+    (0..8).map(|i| (n >> 8 * i) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use op_vault::key::ItemKey;
+    use super::OpData01;
+
+    #[test]
+    fn test_encrypt_with_key_round_trips() {
+        let key = ItemKey::generate();
+        for len in 0..40 {
+            let plaintext = vec![b'x'; len];
+            let op_data = OpData01::encrypt_with_key(&key, &plaintext);
+            let op_data = OpData01::from_base64_str(&op_data.to_base64_string()).unwrap();
+            assert_eq!(op_data.decrypt_with_key(&key).unwrap(), plaintext);
+        }
+    }
+}
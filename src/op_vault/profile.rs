@@ -1,3 +1,4 @@
+use openssl::crypto::hash;
 use serde_json::{self, Value};
 use std::fs::File;
 use std::io::{self, Read, Result as IoResult};
@@ -47,27 +48,84 @@ impl LockedProfile {
         let master_key = MainKey::from_op_data(&derived_key, &self.master_key);
         let overview_key = MainKey::from_op_data(&derived_key, &self.overview_key);
         if let (Some(master_key), Some(overview_key)) = (master_key, overview_key) {
-            return Some(Profile {
-                created_at: self.created_at,
-                iterations: self.iterations,
-                last_updated_by: self.last_updated_by.clone(),
-                master_key: master_key,
-                overview_key: overview_key,
-                profile_name: self.profile_name.clone(),
-                salt: self.salt.clone(),
-                updated_at: self.updated_at,
-                uuid: self.uuid.clone(),
-            });
+            return Some(self.profile(Some(master_key), overview_key));
         }
         None
     }
+
+    /// Unlocks only the overview key, leaving the master key encrypted, so that overviews and
+    /// folders can be read but not item details, item keys or attachment contents.
+    pub fn unlock_overview(&self, password: &str) -> Option<Profile> {
+        let derived_key = DerivedKey::from_password(password, &self.salt, self.iterations);
+        MainKey::from_op_data(&derived_key, &self.overview_key)
+            .map(|overview_key| self.profile(None, overview_key))
+    }
+
+    /// Returns a digest of the encrypted master and overview keys in profile.js. Keys unlocked
+    /// from a profile with the same fingerprint were checked against these very keys, and a new
+    /// password or a recreated vault changes it.
+    pub fn key_fingerprint(&self) -> Vec<u8> {
+        let mut bytes = self.master_key.as_bytes().to_vec();
+        bytes.extend_from_slice(self.overview_key.as_bytes());
+        hash::hash(hash::Type::SHA256, &bytes)
+    }
+
+    /// Unlocks the profile with keys from an earlier unlock, without deriving them again.
+    /// `key_fingerprint` is the `key_fingerprint` of the profile they were unlocked from, and
+    /// `None` is returned unless it is the fingerprint of this one.
+    pub fn unlock_with_keys(&self,
+                            master_key: MainKey,
+                            overview_key: MainKey,
+                            key_fingerprint: &[u8])
+                            -> Option<Profile> {
+        self.profile_with_keys(Some(master_key), overview_key, key_fingerprint)
+    }
+
+    /// Like `unlock_with_keys`, with the overview key alone.
+    pub fn unlock_overview_with_key(&self,
+                                    overview_key: MainKey,
+                                    key_fingerprint: &[u8])
+                                    -> Option<Profile> {
+        self.profile_with_keys(None, overview_key, key_fingerprint)
+    }
+
+    /// Checks that keys of an earlier unlock belong to this profile. The keys themselves cannot
+    /// be checked without the password, as nothing in profile.js is encrypted with them, but
+    /// the unlock that produced them checked them against the encrypted keys it had.
+    fn profile_with_keys(&self,
+                         master_key: Option<MainKey>,
+                         overview_key: MainKey,
+                         key_fingerprint: &[u8])
+                         -> Option<Profile> {
+        if key_fingerprint != &self.key_fingerprint()[..] {
+            return None;
+        }
+        Some(self.profile(master_key, overview_key))
+    }
+
+    fn profile(&self, master_key: Option<MainKey>, overview_key: MainKey) -> Profile {
+        Profile {
+            created_at: self.created_at,
+            iterations: self.iterations,
+            key_fingerprint: self.key_fingerprint(),
+            last_updated_by: self.last_updated_by.clone(),
+            master_key: master_key,
+            overview_key: overview_key,
+            profile_name: self.profile_name.clone(),
+            salt: self.salt.clone(),
+            updated_at: self.updated_at,
+            uuid: self.uuid.clone(),
+        }
+    }
 }
 
 pub struct Profile {
     created_at: u64,
     iterations: usize,
+    key_fingerprint: Vec<u8>,
     last_updated_by: String,
-    master_key: MainKey,
+    /// `None` when only the overview key was unlocked.
+    master_key: Option<MainKey>,
     overview_key: MainKey,
     profile_name: String,
     salt: Vec<u8>,
@@ -76,11 +134,17 @@ pub struct Profile {
 }
 
 impl Profile {
-    fn master_key(&self) -> &MainKey {
-        &self.master_key
+    /// Returns the `key_fingerprint` of the profile this was unlocked from.
+    pub fn key_fingerprint(&self) -> &[u8] {
+        &self.key_fingerprint
+    }
+
+    /// Returns the master key, or `None` if only the overview key was unlocked.
+    pub fn master_key(&self) -> Option<&MainKey> {
+        self.master_key.as_ref()
     }
 
-    fn overview_key(&self) -> &MainKey {
+    pub fn overview_key(&self) -> &MainKey {
         &self.overview_key
     }
 }
@@ -0,0 +1,17 @@
+use openssl::crypto::rand;
+
+/// Generates a random UUID in the form 1Password uses: 32 uppercase hex digits without dashes.
+pub fn generate() -> String {
+    rand::rand_bytes(16).iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_generate() {
+        let uuid = super::generate();
+        assert_eq!(uuid.len(), 32);
+        assert!(uuid.chars().all(|c| c.is_digit(16) && !c.is_lowercase()));
+        assert!(uuid != super::generate());
+    }
+}
@@ -1,44 +1,386 @@
-use std::io::{Result as IoResult};
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult};
 use std::path::{Path, PathBuf};
 
+use op_vault::attachment::{self, Attachment};
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::folder::{self, Folder, LockedFolder};
+use op_vault::item::{self, Item, LockedItem, NewItem};
+use op_vault::key::MainKey;
 use op_vault::profile::{LockedProfile, Profile};
+use util::now;
 
+const PROFILE_DIR_REL_PATH: &'static str = "default";
 const PROFILE_REL_PATH: &'static str = "default/profile.js";
+const FOLDERS_REL_PATH: &'static str = "default/folders.js";
 
 pub struct LockedVault {
+    folders: BTreeMap<String, LockedFolder>,
+    items: BTreeMap<String, LockedItem>,
     path: PathBuf,
     profile: LockedProfile,
 }
 
 impl LockedVault {
     pub fn new<P: AsRef<Path>>(path: P) -> IoResult<LockedVault> {
-        load_locked_profile(path.as_ref())
-            .and_then(|profile| {
-                Ok(LockedVault {
-                    path: path.as_ref().to_path_buf(),
-                    profile: profile,
-                })
-            })
+        let profile = try!(load_locked_profile(path.as_ref()));
+        let folders = try!(load_locked_folders(path.as_ref()));
+        let items = try!(item::load_item_map(path.as_ref().join(PROFILE_DIR_REL_PATH)));
+        Ok(LockedVault {
+            folders: folders,
+            items: items,
+            path: path.as_ref().to_path_buf(),
+            profile: profile,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
     pub fn unlock(&self, password: &str) -> Option<Vault> {
-        self.profile.unlock(password).and_then(|profile| {
-            Some(Vault {
-                path: self.path.clone(),
-                profile: profile,
-            })
-        })
+        self.profile.unlock(password).map(|profile| self.vault(profile))
+    }
+
+    /// Unlocks only what the overview key decrypts: folders, item overviews and attachment
+    /// overviews. The master key is never decrypted, so item details, item keys and attachment
+    /// contents stay out of reach of the returned vault.
+    pub fn unlock_overview(&self, password: &str) -> Option<Vault> {
+        self.profile.unlock_overview(password).map(|profile| self.vault(profile))
+    }
+
+    /// Unlocks the vault with the keys of an earlier unlock, skipping the slow key derivation.
+    /// Returns `None` unless `key_fingerprint`, the `Profile::key_fingerprint` of that unlock,
+    /// shows that the keys belong to this vault.
+    pub fn unlock_with_keys(&self,
+                            master_key: MainKey,
+                            overview_key: MainKey,
+                            key_fingerprint: &[u8])
+                            -> Option<Vault> {
+        self.profile
+            .unlock_with_keys(master_key, overview_key, key_fingerprint)
+            .map(|profile| self.vault(profile))
+    }
+
+    /// Like `unlock_overview`, with the overview key of an earlier unlock. Returns `None` unless
+    /// `key_fingerprint` shows that the key belongs to this vault, as for `unlock_with_keys`.
+    pub fn unlock_overview_with_key(&self,
+                                    overview_key: MainKey,
+                                    key_fingerprint: &[u8])
+                                    -> Option<Vault> {
+        self.profile
+            .unlock_overview_with_key(overview_key, key_fingerprint)
+            .map(|profile| self.vault(profile))
+    }
+
+    fn vault(&self, profile: Profile) -> Vault {
+        Vault {
+            folders: self.folders.clone(),
+            items: self.items.clone(),
+            path: self.path.clone(),
+            profile: profile,
+        }
     }
 }
 
 pub struct Vault {
+    folders: BTreeMap<String, LockedFolder>,
+    items: BTreeMap<String, LockedItem>,
     path: PathBuf,
     profile: Profile,
 }
 
+impl Vault {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the name of the vault, which is its directory name without `.opvault`.
+    pub fn name(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(String::new)
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Returns whether only the overview key was unlocked, so that item details and attachment
+    /// contents cannot be decrypted.
+    pub fn is_overview_only(&self) -> bool {
+        self.profile.master_key().is_none()
+    }
+
+    pub fn folders(&self) -> Vec<Folder> {
+        self.folders
+            .values()
+            .filter_map(|folder| folder.unlock(self.profile.overview_key()))
+            .collect()
+    }
+
+    pub fn folder(&self, uuid: &str) -> Option<Folder> {
+        self.folders.get(uuid).and_then(|folder| folder.unlock(self.profile.overview_key()))
+    }
+
+    /// Returns all items except tombstones of deleted items.
+    pub fn items(&self) -> Vec<Item> {
+        self.items
+            .values()
+            .filter(|item| item.category() != Category::Tombstone)
+            .filter_map(|item| item.unlock(self.profile.overview_key()))
+            .collect()
+    }
+
+    pub fn item(&self, uuid: &str) -> Option<Item> {
+        self.items.get(uuid).and_then(|item| item.unlock(self.profile.overview_key()))
+    }
+
+    /// Finds the item whose UUID is `query` or, failing that, the item outside the trash whose
+    /// title is `query` ignoring case. It is an error for no item or several items to match.
+    pub fn find_item(&self, query: &str) -> IoResult<Item> {
+        if let Some(item) = self.item(query) {
+            return Ok(item);
+        }
+        let title = query.to_lowercase();
+        let mut items: Vec<Item> = self.items()
+            .into_iter()
+            .filter(|item| {
+                !item.is_trashed() && item.title().map(|t| t.to_lowercase()) == Some(title.clone())
+            })
+            .collect();
+        match items.len() {
+            0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("no item matches {}", query))),
+            1 => Ok(items.remove(0)),
+            n => {
+                let uuids: Vec<&str> = items.iter().map(|item| item.uuid()).collect();
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   format!("{} items are titled {} ({}); use a UUID instead",
+                                           n,
+                                           query,
+                                           uuids.join(", "))))
+            }
+        }
+    }
+
+    /// Returns `None` if the details do not decrypt or the vault is unlocked for overviews only.
+    pub fn item_details(&self, item: &Item) -> Option<ItemDetails> {
+        self.profile.master_key().and_then(|master_key| item.decrypt_details(master_key))
+    }
+
+    pub fn attachments(&self, item: &Item) -> IoResult<Vec<Attachment>> {
+        let locked_attachments = try!(attachment::load_attachments(self.profile_dir_path(),
+                                                                   item.uuid()));
+        Ok(locked_attachments
+            .iter()
+            .filter_map(|attachment| attachment.unlock(self.profile.overview_key()))
+            .collect())
+    }
+
+    pub fn attachment_contents(&self, item: &Item, attachment: &Attachment) -> Option<Vec<u8>> {
+        self.profile
+            .master_key()
+            .and_then(|master_key| item.item_key(master_key))
+            .and_then(|item_key| attachment.decrypt_contents(&item_key))
+    }
+
+    /// Encrypts `new_item` and writes it to its band file, along with its attachments.
+    pub fn create_item(&mut self, new_item: &NewItem) -> IoResult<Item> {
+        if let Some(ref uuid) = new_item.uuid {
+            if self.items.contains_key(uuid) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                          format!("item {} already exists", uuid)));
+            }
+        }
+        let master_key = try!(self.profile.master_key().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::PermissionDenied,
+                           "the vault is unlocked for overviews only")
+        }));
+        let now = now();
+        let locked = try!(LockedItem::create(new_item,
+                                             master_key,
+                                             self.profile.overview_key(),
+                                             now));
+        let uuid = locked.uuid().to_string();
+        self.items.insert(uuid.clone(), locked);
+        try!(item::save_band(self.profile_dir_path(), item::band_name(&uuid), &self.items));
+        let item = try!(self.item(&uuid).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt item {}", uuid))
+        }));
+        if !new_item.attachments.is_empty() {
+            let item_key = try!(item.item_key(master_key).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData,
+                               format!("could not decrypt key of item {}", uuid))
+            }));
+            for new_attachment in &new_item.attachments {
+                try!(attachment::save_attachment(self.profile_dir_path(),
+                                                 &uuid,
+                                                 new_attachment,
+                                                 &item_key,
+                                                 self.profile.overview_key(),
+                                                 now));
+            }
+        }
+        Ok(item)
+    }
+
+    /// Creates a regular folder and writes it to `folders.js`.
+    pub fn create_folder(&mut self,
+                         uuid: Option<&str>,
+                         title: &str,
+                         parent: Option<&str>)
+                         -> IoResult<Folder> {
+        if let Some(uuid) = uuid {
+            if self.folders.contains_key(uuid) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                          format!("folder {} already exists", uuid)));
+            }
+        }
+        let locked = try!(LockedFolder::create(uuid,
+                                               title,
+                                               parent,
+                                               self.profile.overview_key(),
+                                               now()));
+        let uuid = locked.uuid().to_string();
+        self.folders.insert(uuid.clone(), locked);
+        try!(folder::save_folder_map(self.path.join(FOLDERS_REL_PATH), &self.folders));
+        self.folder(&uuid).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("could not decrypt folder {}", uuid))
+        })
+    }
+
+    fn profile_dir_path(&self) -> PathBuf {
+        self.path.join(PROFILE_DIR_REL_PATH)
+    }
+}
+
 fn load_locked_profile(path: &Path) -> IoResult<LockedProfile> {
     let mut path = path.to_path_buf();
     path.push(PROFILE_REL_PATH);
-    println!("Loading {:?}", path);
     LockedProfile::from_file(path)
 }
+
+fn load_locked_folders(path: &Path) -> IoResult<BTreeMap<String, LockedFolder>> {
+    let path = path.join(FOLDERS_REL_PATH);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    folder::load_folder_map(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use op_vault::key::MainKey;
+    use testing::{copy_sample_vault, sample_vault, SAMPLE_VAULT_PASSWORD, SAMPLE_VAULT_PATH};
+    use super::LockedVault;
+
+    const HULU_UUID: &'static str = "13C8E12AC8E54B1F873BAB0824E521BC";
+
+    #[test]
+    fn test_unlock_decrypts_items() {
+        let vault = sample_vault();
+        let item = vault.item(HULU_UUID).unwrap();
+        assert_eq!(item.title(), Some("Hulu"));
+        let details = vault.item_details(&item).unwrap();
+        assert_eq!(details.username(), Some("wendy@appleseed.com".to_string()));
+        assert_eq!(details.password(), Some("frirp7i1ob7wig4d".to_string()));
+    }
+
+    #[test]
+    fn test_unlock_decrypts_attachments() {
+        let vault = sample_vault();
+        let item = vault.item("2A632FDD32F5445E91EB5636C7580447").unwrap();
+        let attachments = vault.attachments(&item).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].file_name(), "skype-logo-placeholder.png");
+        let contents = vault.attachment_contents(&item, &attachments[0]).unwrap();
+        assert_eq!(contents.len() as u64, attachments[0].size());
+    }
+
+    #[test]
+    fn test_unlock_with_keys() {
+        let full = sample_vault();
+        let keys = || {
+            (MainKey::from_bytes(&full.profile().master_key().unwrap().to_bytes()).unwrap(),
+             MainKey::from_bytes(&full.profile().overview_key().to_bytes()).unwrap())
+        };
+        // Without items to try the keys on, only the fingerprint tells them apart.
+        let path = copy_sample_vault("unlock-with-keys");
+        for entry in fs::read_dir(path.join("default")).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_name().to_string_lossy().starts_with("band_") {
+                fs::remove_file(entry.path()).unwrap();
+            }
+        }
+        let locked_vault = LockedVault::new(&path).unwrap();
+        let (master_key, overview_key) = keys();
+        assert!(locked_vault.unlock_with_keys(master_key, overview_key, b"wrong").is_none());
+        let (master_key, overview_key) = keys();
+        let fingerprint = full.profile().key_fingerprint();
+        let vault = locked_vault.unlock_with_keys(master_key, overview_key, fingerprint).unwrap();
+        assert!(vault.items().is_empty());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unlock_overview() {
+        let locked_vault = LockedVault::new(SAMPLE_VAULT_PATH).unwrap();
+        assert!(locked_vault.unlock_overview("wrong").is_none());
+        let vault = locked_vault.unlock_overview(SAMPLE_VAULT_PASSWORD).unwrap();
+        assert!(vault.is_overview_only());
+        assert!(!vault.folders().is_empty());
+        let item = vault.item(HULU_UUID).unwrap();
+        assert_eq!(item.title(), Some("Hulu"));
+        assert!(vault.item_details(&item).is_none());
+
+        let item = vault.item("2A632FDD32F5445E91EB5636C7580447").unwrap();
+        let attachments = vault.attachments(&item).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert!(vault.attachment_contents(&item, &attachments[0]).is_none());
+
+        let full = sample_vault();
+        assert!(!full.is_overview_only());
+        let overview_key = || MainKey::from_bytes(&full.profile().overview_key().to_bytes());
+        assert!(locked_vault.unlock_overview_with_key(overview_key().unwrap(), b"wrong").is_none());
+        let overview_key = overview_key().unwrap();
+        let fingerprint = full.profile().key_fingerprint();
+        let vault = locked_vault.unlock_overview_with_key(overview_key, fingerprint).unwrap();
+        assert!(vault.profile().master_key().is_none());
+    }
+
+    #[test]
+    fn test_create_item_and_folder() {
+        use op_vault::attachment::NewAttachment;
+        use op_vault::category::Category;
+        use op_vault::item::NewItem;
+        use serde_json::Value;
+        use std::collections::BTreeMap;
+
+        let path = copy_sample_vault("create");
+        let mut vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        let folder = vault.create_folder(None, "Imported", None).unwrap();
+        let mut details = BTreeMap::new();
+        details.insert("notesPlain".to_string(), Value::String("hello".to_string()));
+        let mut new_item = NewItem::new(Category::SecureNote, "Note", Value::Object(details));
+        new_item.folder = Some(folder.uuid().to_string());
+        new_item.attachments.push(NewAttachment::new("hello.txt", b"hello, world".to_vec()));
+        let uuid = vault.create_item(&new_item).unwrap().uuid().to_string();
+
+        let vault = LockedVault::new(&path).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap();
+        assert_eq!(vault.folder(folder.uuid()).unwrap().title(), "Imported");
+        let item = vault.item(&uuid).unwrap();
+        assert_eq!(item.folder(), Some(folder.uuid()));
+        assert_eq!(item.ainfo(), Some("hello"));
+        assert_eq!(vault.item_details(&item).unwrap().notes(), Some("hello"));
+        let attachments = vault.attachments(&item).unwrap();
+        assert_eq!(attachments[0].file_name(), "hello.txt");
+        assert_eq!(vault.attachment_contents(&item, &attachments[0]).unwrap(), b"hello, world");
+        assert!(vault.item(HULU_UUID).is_some());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
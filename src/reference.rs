@@ -0,0 +1,322 @@
+//! Looking up single values of items for commands that hand secrets to other programs.
+//!
+//! The canonical way to point at a value is a reference URI:
+//!
+//! ```text
+//! vault://<vault>/<item>/[<section>/]<field>[?attribute=<attribute>]
+//! ```
+//!
+//! `<vault>` is the name of the vault directory without `.opvault`, `<item>` is a UUID or the
+//! title of exactly one item outside the trash, and `<section>` and `<field>` are labels as shown
+//! in 1Password. Everything but the UUID matches ignoring case. A segment containing `/`, `?` or
+//! `%` escapes it as `%2F`, `%3F` or `%25`.
+//!
+//! `?attribute=otp` turns the field into the current one-time password, and then the field may
+//! be left out to use the one-time password of the item.
+
+use std::fmt;
+use std::io::{self, Result as IoResult};
+
+use op_vault::details::ItemDetails;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use totp::{self, percent_decode};
+use util;
+
+const SCHEME: &'static str = "vault://";
+
+/// What a reference yields of the field it points at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attribute {
+    /// The value of the field.
+    Value,
+    /// The current one-time password generated from the field.
+    Otp,
+}
+
+impl Attribute {
+    pub fn from_name(name: &str) -> Option<Attribute> {
+        match &name.to_lowercase()[..] {
+            "value" => Some(Attribute::Value),
+            "otp" | "totp" => Some(Attribute::Otp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Attribute::Value => "value",
+            Attribute::Otp => "otp",
+        }
+    }
+}
+
+/// A parsed reference URI. See the module documentation for its syntax.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub vault: String,
+    pub item: String,
+    pub section: Option<String>,
+    /// `None` only for the one-time password of the item.
+    pub field: Option<String>,
+    pub attribute: Attribute,
+}
+
+impl Reference {
+    pub fn parse(s: &str) -> IoResult<Reference> {
+        let invalid = |reason: &str| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           format!("invalid reference {}: {}", s, reason))
+        };
+        if !s.starts_with(SCHEME) {
+            return Err(invalid("it does not start with vault://"));
+        }
+        let mut parts = s[SCHEME.len()..].splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let mut attribute = Attribute::Value;
+        for option in parts.next().unwrap_or("").split('&').filter(|o| !o.is_empty()) {
+            let mut option = option.splitn(2, '=');
+            let name = percent_decode(option.next().unwrap_or(""));
+            let value = percent_decode(option.next().unwrap_or(""));
+            match &name[..] {
+                "attribute" => {
+                    attribute = try!(Attribute::from_name(&value)
+                        .ok_or_else(|| invalid(&format!("unknown attribute {}", value))));
+                }
+                _ => return Err(invalid(&format!("unknown option {}", name))),
+            }
+        }
+
+        let segments: Vec<String> = path.split('/').map(percent_decode).collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(invalid("it has an empty segment"));
+        }
+        let (section, field) = match (segments.len(), attribute) {
+            (2, Attribute::Otp) => (None, None),
+            (3, _) => (None, Some(segments[2].clone())),
+            (4, _) => (Some(segments[2].clone()), Some(segments[3].clone())),
+            _ => return Err(invalid("expected vault://<vault>/<item>/[<section>/]<field>")),
+        };
+        Ok(Reference {
+            vault: segments[0].clone(),
+            item: segments[1].clone(),
+            section: section,
+            field: field,
+            attribute: attribute,
+        })
+    }
+
+    /// Returns the value the reference points at in `vault`, which must be the vault it names.
+    pub fn resolve(&self, vault: &Vault) -> IoResult<String> {
+        let now = util::now();
+        self.resolve_at(vault, now)
+    }
+
+    /// Like `resolve`, but generates one-time passwords for Unix time `time`.
+    pub fn resolve_at(&self, vault: &Vault, time: u64) -> IoResult<String> {
+        if self.vault.to_lowercase() != vault.name().to_lowercase() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                                      format!("{} refers to vault {}, but {} is open",
+                                              self,
+                                              self.vault,
+                                              vault.name())));
+        }
+        let item = try!(vault.find_item(&self.item));
+        let details = try!(decrypt_details(vault, &item));
+        let value = match self.field {
+            Some(ref field) => {
+                try!(section_field_value(&item,
+                                         &details,
+                                         self.section.as_ref().map(|section| &section[..]),
+                                         field))
+            }
+            None => {
+                try!(details.totp().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound,
+                                   format!("item {} has no one-time password", item.uuid()))
+                }))
+            }
+        };
+        match self.attribute {
+            Attribute::Value => Ok(value),
+            Attribute::Otp => totp::Otp::parse(&value).map(|otp| otp.code(time)),
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    /// Writes the reference in canonical form.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}{}/{}", SCHEME, escape(&self.vault), escape(&self.item)));
+        if let Some(ref section) = self.section {
+            try!(write!(f, "/{}", escape(section)));
+        }
+        if let Some(ref field) = self.field {
+            try!(write!(f, "/{}", escape(field)));
+        }
+        if self.attribute != Attribute::Value {
+            try!(write!(f, "?attribute={}", self.attribute.name()));
+        }
+        Ok(())
+    }
+}
+
+fn escape(segment: &str) -> String {
+    segment.replace("%", "%25").replace("/", "%2F").replace("?", "%3F")
+}
+
+/// Splits an `<item>/<field>` reference at its last slash, since titles may contain slashes
+/// but field labels rarely do.
+pub fn split_item_field(reference: &str) -> IoResult<(&str, &str)> {
+    match reference.rfind('/') {
+        Some(i) if i > 0 && i + 1 < reference.len() => Ok((&reference[..i], &reference[i + 1..])),
+        _ => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               format!("{} is not of the form <item>/<field>", reference)))
+        }
+    }
+}
+
+/// Returns the value of `field` of the item whose UUID or title is `item`.
+pub fn resolve(vault: &Vault, item: &str, field: &str) -> IoResult<String> {
+    let item = try!(vault.find_item(item));
+    let details = try!(decrypt_details(vault, &item));
+    field_value(&item, &details, field)
+}
+
+fn decrypt_details(vault: &Vault, item: &Item) -> IoResult<ItemDetails> {
+    vault.item_details(item).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("could not decrypt item {}", item.uuid()))
+    })
+}
+
+/// Returns the value of `field` of `item`, matching its label ignoring case.
+///
+/// `username`, `password`, `notes` and `url` name what they say for every category. Other
+/// labels match the names of login form fields and then the titles or names of section fields.
+/// A label shared by fields of different values is an error.
+pub fn field_value(item: &Item, details: &ItemDetails, field: &str) -> IoResult<String> {
+    section_field_value(item, details, None, field)
+}
+
+/// Returns the value of `field` in `section` of `item`, or of `field` anywhere in the item if no
+/// section is given. Sections match their titles or names ignoring case.
+pub fn section_field_value(item: &Item,
+                           details: &ItemDetails,
+                           section: Option<&str>,
+                           field: &str)
+                           -> IoResult<String> {
+    let label = field.to_lowercase();
+    let special = match &label[..] {
+        "username" => details.username(),
+        "password" => details.password(),
+        "notes" => details.notes().map(|notes| notes.to_string()),
+        "url" => item.url().map(|url| url.to_string()),
+        _ => None,
+    };
+    let section_label = section.map(|section| section.to_lowercase());
+    if section_label.is_none() {
+        if let Some(value) = special {
+            return Ok(value);
+        }
+        let login_field = details.fields().into_iter().find(|f| f.name().to_lowercase() == label);
+        if let Some(field) = login_field {
+            return Ok(field.value().to_string());
+        }
+    }
+
+    // Each distinct value with the titles of the sections it was found in.
+    let mut values: Vec<(String, Vec<String>)> = Vec::new();
+    for section in details.sections() {
+        if let Some(ref section_label) = section_label {
+            if section.title().to_lowercase() != *section_label &&
+               section.name().to_lowercase() != *section_label {
+                continue;
+            }
+        }
+        for field in section.fields() {
+            if field.title().to_lowercase() != label && field.name().to_lowercase() != label {
+                continue;
+            }
+            if let Some(value) = field.value_string() {
+                let section_title = if section.title().is_empty() {
+                    section.name().to_string()
+                } else {
+                    section.title().to_string()
+                };
+                match values.iter().position(|&(ref v, _)| *v == value) {
+                    Some(i) => values[i].1.push(section_title),
+                    None => values.push((value, vec![section_title])),
+                }
+            }
+        }
+    }
+    match values.len() {
+        0 => {
+            Err(io::Error::new(io::ErrorKind::NotFound,
+                               format!("item {} has no field {}", item.uuid(), field)))
+        }
+        1 => Ok(values.remove(0).0),
+        n => {
+            let sections: Vec<String> = values.into_iter().flat_map(|(_, s)| s).collect();
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               format!("item {} has {} different fields labeled {} (in sections \
+                                        {}); name the section",
+                                       item.uuid(),
+                                       n,
+                                       field,
+                                       sections.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::sample_vault;
+    use super::{Attribute, Reference, resolve, split_item_field};
+
+    #[test]
+    fn test_resolve() {
+        let vault = sample_vault();
+        assert_eq!(resolve(&vault, "hulu", "username").unwrap(), "wendy@appleseed.com");
+        let password = resolve(&vault, "13C8E12AC8E54B1F873BAB0824E521BC", "PASSWORD").unwrap();
+        assert!(!password.is_empty());
+        assert!(resolve(&vault, "Hulu", "no such field").is_err());
+        assert!(resolve(&vault, "No such item", "password").is_err());
+
+        assert_eq!(split_item_field("a/b/password").unwrap(), ("a/b", "password"));
+        assert!(split_item_field("password").is_err());
+        assert!(split_item_field("item/").is_err());
+    }
+
+    #[test]
+    fn test_reference() {
+        assert_eq!(Reference::parse("vault://Work/A%2FB/Database/password").unwrap(),
+                   Reference {
+                       vault: "Work".to_string(),
+                       item: "A/B".to_string(),
+                       section: Some("Database".to_string()),
+                       field: Some("password".to_string()),
+                       attribute: Attribute::Value,
+                   });
+        assert_eq!(Reference::parse("vault://Work/Item/password").unwrap().section, None);
+        let otp = Reference::parse("vault://Work/Item?attribute=otp").unwrap();
+        assert_eq!((otp.field, otp.attribute), (None, Attribute::Otp));
+        assert!(Reference::parse("vault://Work/Item").is_err());
+        assert!(Reference::parse("vault://Work/Item/password?attribute=color").is_err());
+        assert!(Reference::parse("vault://Work/Item/password?colour=red").is_err());
+        assert!(Reference::parse("vault://Work//password").is_err());
+        assert!(Reference::parse("Work/Item/password").is_err());
+
+        for s in &["vault://Work/A%2FB%3F/Database/password",
+                   "vault://Work/Item/one-time password?attribute=otp"] {
+            assert_eq!(Reference::parse(s).unwrap().to_string(), *s);
+        }
+
+        let vault = sample_vault();
+        let reference = Reference::parse("vault://samplevault/Hulu/username").unwrap();
+        assert_eq!(reference.resolve(&vault).unwrap(), "wendy@appleseed.com");
+        assert!(Reference::parse("vault://Other/Hulu/username").unwrap().resolve(&vault).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Running commands with secrets from the vault in their environment.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Result as IoResult, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Replaces secrets in the output of the command when masking.
+const MASK: &'static [u8] = b"<concealed by decone>";
+
+/// Parses a `NAME=<reference>` assignment.
+pub fn parse_assignment(s: &str) -> IoResult<(String, String)> {
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    let reference = parts.next().unwrap_or("").trim();
+    let valid_name = !name.is_empty() &&
+                     !name.starts_with(|c: char| c.is_digit(10)) &&
+                     name.chars().all(|c| c == '_' || c.is_alphanumeric());
+    if !valid_name || reference.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("{} is not of the form NAME=<reference>", s)));
+    }
+    Ok((name.to_string(), reference.to_string()))
+}
+
+/// Reads assignments from an env file, one per line. Blank lines, lines starting with `#` and
+/// an `export` keyword before the name are ignored, as shells would.
+pub fn read_env_file<P: AsRef<Path>>(path: P) -> IoResult<Vec<(String, String)>> {
+    let file = try!(File::open(path));
+    let mut assignments = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = try!(line);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = if line.starts_with("export ") { line["export ".len()..].trim() } else { line };
+        assignments.push(try!(parse_assignment(line)));
+    }
+    Ok(assignments)
+}
+
+/// Replaces the current process with `command`, with `env` added to its environment. Returns
+/// only if the command could not be run.
+pub fn exec(command: &[String], env: &[(String, String)]) -> io::Error {
+    build_command(command, env).exec()
+}
+
+/// Runs `command` with `env` added to its environment, replacing each of `secrets` in its
+/// output, and returns its exit code.
+///
+/// Output is passed on as soon as it is read, except for trailing bytes that may be the start
+/// of a secret, which wait for the next read.
+pub fn run_masked(command: &[String],
+                  env: &[(String, String)],
+                  secrets: &[String])
+                  -> IoResult<i32> {
+    let mut child = try!(build_command(command, env)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn());
+    // Longer secrets first, so that one containing another is masked whole.
+    let mut secrets: Vec<Vec<u8>> = secrets.iter()
+        .filter(|secret| !secret.is_empty())
+        .map(|secret| secret.as_bytes().to_vec())
+        .collect();
+    secrets.sort_by(|a, b| b.len().cmp(&a.len()));
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stdout_secrets = secrets.clone();
+    let stdout_thread = thread::spawn(move || copy_masked(stdout, io::stdout(), &stdout_secrets));
+    let stderr_thread = thread::spawn(move || copy_masked(stderr, io::stderr(), &secrets));
+    let status = try!(child.wait());
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+    // Follow the shell convention for children killed by a signal.
+    Ok(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+
+fn build_command(command: &[String], env: &[(String, String)]) -> Command {
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    for &(ref name, ref value) in env {
+        cmd.env(name, value);
+    }
+    cmd
+}
+
+fn copy_masked<R: Read, W: Write>(mut reader: R,
+                                  mut writer: W,
+                                  secrets: &[Vec<u8>])
+                                  -> IoResult<()> {
+    let mut buf = [0; 4096];
+    let mut pending = Vec::new();
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&buf[..n]);
+        let (masked, consumed) = mask(&pending, secrets, n == 0);
+        pending.drain(..consumed);
+        try!(writer.write_all(&masked));
+        try!(writer.flush());
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Replaces every occurrence of `secrets`, longest first, in `bytes` and returns the result
+/// with the number of bytes it covers. Unless `at_end`, it stops at the first byte from which
+/// the rest of `bytes` is the start of a secret.
+fn mask(bytes: &[u8], secrets: &[Vec<u8>], at_end: bool) -> (Vec<u8>, usize) {
+    let mut masked = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    'scan: while i < bytes.len() {
+        for secret in secrets {
+            if bytes[i..].starts_with(secret) {
+                masked.extend_from_slice(MASK);
+                i += secret.len();
+                continue 'scan;
+            }
+            if !at_end && secret.starts_with(&bytes[i..]) {
+                break 'scan;
+            }
+        }
+        masked.push(bytes[i]);
+        i += 1;
+    }
+    (masked, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Result as IoResult};
+
+    use super::{copy_masked, mask, parse_assignment, run_masked};
+
+    /// Hands out a few bytes at a time, as a pipe might.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(parse_assignment("DB_PASSWORD=Database/password").unwrap(),
+                   ("DB_PASSWORD".to_string(), "Database/password".to_string()));
+        assert!(parse_assignment("1X=a/b").is_err());
+        assert!(parse_assignment("X=").is_err());
+        assert!(parse_assignment("A-B=a/b").is_err());
+    }
+
+    #[test]
+    fn test_mask() {
+        let secrets = vec![b"hunter22".to_vec(), b"hunter2".to_vec()];
+        assert_eq!(mask(b"pw hunter22 and hunter2\n", &secrets, true).0,
+                   b"pw <concealed by decone> and <concealed by decone>\n".to_vec());
+        assert_eq!(mask(b"Password: ", &secrets, false),
+                   (b"Password: ".to_vec(), "Password: ".len()));
+        assert_eq!(mask(b"pw hunter2", &secrets, false), (b"pw ".to_vec(), 3));
+        assert_eq!(mask(b"pw hunter2", &secrets, true).0, b"pw <concealed by decone>".to_vec());
+    }
+
+    #[test]
+    fn test_copy_masked() {
+        let secrets = vec![b"-----BEGIN KEY-----\nc2VjcmV0\n-----END KEY-----".to_vec()];
+        let input = b"key:\n-----BEGIN KEY-----\nc2VjcmV0\n-----END KEY-----\ndone\n-----BEGIN";
+        let mut output = Vec::new();
+        copy_masked(Trickle(input), &mut output, &secrets).unwrap();
+        assert_eq!(output, b"key:\n<concealed by decone>\ndone\n-----BEGIN".to_vec());
+    }
+
+    #[test]
+    fn test_run_masked() {
+        let command = vec!["sh".to_string(), "-c".to_string(), "exit $CODE".to_string()];
+        let env = vec![("CODE".to_string(), "3".to_string())];
+        assert_eq!(run_masked(&command, &env, &[]).unwrap(), 3);
+    }
+}
@@ -0,0 +1,347 @@
+//! Fuzzy search over what item overviews show: titles, usernames, URLs, tags and folders.
+//!
+//! Only overviews and folders are read, which the overview key decrypts, so searching never
+//! touches item details.
+//!
+//! A query is made of words matched fuzzily and filters of the form `key:value`, where the value
+//! may be quoted to hold spaces:
+//!
+//! * `category:login` keeps items of a category, named as `Category::name` or `type_name` names it
+//!   with or without its spaces, such as `secure-note` or `"Secure Note"`.
+//! * `tag:prod` keeps items with the tag.
+//! * `folder:Social` keeps items in the folder or one inside it, given by title, slash-separated
+//!   path or UUID.
+//! * `trashed:true` searches the trash instead of the other items, and `trashed:any` both.
+//!
+//! All filters must hold, and every word must match one of the fields of an item.
+
+use std::cmp::{self, Ordering};
+use std::io::{self, Result as IoResult};
+
+use op_vault::folder::Folder;
+use op_vault::item::Item;
+use url::Url;
+
+const WORD_START_BONUS: i64 = 8;
+const CONSECUTIVE_BONUS: i64 = 4;
+const WHOLE_FIELD_BONUS: i64 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Filter {
+    Category(String),
+    Tag(String),
+    Folder(String),
+    /// `None` for either.
+    Trashed(Option<bool>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    /// Lowercase.
+    words: Vec<Vec<char>>,
+    filters: Vec<Filter>,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> IoResult<Query> {
+        let mut query = Query {
+            words: Vec::new(),
+            filters: Vec::new(),
+        };
+        for token in try!(tokenize(s)) {
+            let colon = match token.find(':') {
+                Some(colon) => colon,
+                None => {
+                    query.words.push(token.to_lowercase().chars().collect());
+                    continue;
+                }
+            };
+            let (key, value) = (&token[..colon], token[colon + 1..].to_lowercase());
+            let filter = match key {
+                "category" => Filter::Category(compact(&value)),
+                "tag" => Filter::Tag(value),
+                "folder" => Filter::Folder(value),
+                "trashed" if value == "true" => Filter::Trashed(Some(true)),
+                "trashed" if value == "false" => Filter::Trashed(Some(false)),
+                "trashed" if value == "any" => Filter::Trashed(None),
+                "trashed" => {
+                    let message = format!("trashed:{} is not true, false or any", value);
+                    return Err(invalid_input(message));
+                }
+                // A colon in a word, as in a URL, is part of the word.
+                _ => {
+                    query.words.push(token.to_lowercase().chars().collect());
+                    continue;
+                }
+            };
+            query.filters.push(filter);
+        }
+        Ok(query)
+    }
+
+    /// Returns the score of `item` in the folders `folders`, or `None` if it does not match.
+    pub fn score(&self, item: &Item, folders: &[Folder]) -> Option<u32> {
+        let folder_names = folder_names(item, folders);
+        let trashed = self.filters
+            .iter()
+            .filter_map(|filter| match *filter {
+                Filter::Trashed(trashed) => Some(trashed),
+                _ => None,
+            })
+            .last()
+            .unwrap_or(Some(false));
+        if trashed.map_or(false, |trashed| trashed != item.is_trashed()) {
+            return None;
+        }
+        for filter in &self.filters {
+            let holds = match *filter {
+                Filter::Category(ref name) => {
+                    let category = item.category();
+                    *name == compact(category.name()) || *name == compact(category.type_name())
+                }
+                Filter::Tag(ref tag) => item.tags().iter().any(|t| t.to_lowercase() == *tag),
+                Filter::Folder(ref name) => folder_names.iter().any(|n| n == name),
+                Filter::Trashed(_) => true,
+            };
+            if !holds {
+                return None;
+            }
+        }
+
+        // Fields with their weights; a match in the title counts the most.
+        let mut fields = vec![(item.title().unwrap_or("").to_string(), 3),
+                              (item.ainfo().unwrap_or("").to_string(), 2)];
+        for url in item.urls() {
+            let host = Url::parse(&url).map(|url| url.host).unwrap_or(url);
+            fields.push((host, 1));
+        }
+        for tag in item.tags() {
+            fields.push((tag, 2));
+        }
+        // The title of the folder, not its path, so that parents do not match.
+        if let Some(title) = folder_names.get(1) {
+            fields.push((title.clone(), 1));
+        }
+        let fields: Vec<(Vec<char>, u32)> = fields.into_iter()
+            .map(|(text, weight)| (text.to_lowercase().chars().collect(), weight))
+            .collect();
+        let mut total = 0;
+        for word in &self.words {
+            let best = fields.iter()
+                .filter_map(|&(ref text, weight)| fuzzy_score(word, text).map(|s| s * weight))
+                .max();
+            match best {
+                Some(score) => total += score,
+                None => return None,
+            }
+        }
+        Some(total)
+    }
+}
+
+pub struct SearchResult {
+    pub item: Item,
+    pub score: u32,
+}
+
+/// Returns the items among `items` that match `query`, the best matches first, then by title.
+/// `folders` are the folders of the vault, to match folder filters and names.
+pub fn search(items: Vec<Item>, folders: &[Folder], query: &Query) -> Vec<SearchResult> {
+    let mut results: Vec<SearchResult> = items.into_iter()
+        .filter_map(|item| {
+            query.score(&item, folders).map(|score| {
+                SearchResult {
+                    item: item,
+                    score: score,
+                }
+            })
+        })
+        .collect();
+    results.sort_by(|a, b| {
+        match b.score.cmp(&a.score) {
+            Ordering::Equal => {
+                let title = |r: &SearchResult| r.item.title().unwrap_or("").to_lowercase();
+                title(a).cmp(&title(b))
+            }
+            ordering => ordering,
+        }
+    });
+    results
+}
+
+/// Scores how well the characters of `word` appear in order in `text`, both lowercase, or
+/// returns `None` if they do not. Characters that start words count more, as do the characters
+/// that follow them in a run, and characters skipped between matches count against. The best
+/// placement of the first character is taken.
+fn fuzzy_score(word: &[char], text: &[char]) -> Option<u32> {
+    if word.is_empty() {
+        return Some(0);
+    }
+    let mut best = None;
+    for start in 0..text.len() {
+        if text[start] != word[0] {
+            continue;
+        }
+        let mut score: i64 = 0;
+        let mut previous: Option<(usize, i64)> = None;
+        let mut i = start;
+        let mut matched = true;
+        for &c in word {
+            while i < text.len() && text[i] != c {
+                i += 1;
+            }
+            if i == text.len() {
+                matched = false;
+                break;
+            }
+            let mut bonus = if i == 0 || !text[i - 1].is_alphanumeric() {
+                WORD_START_BONUS
+            } else {
+                0
+            };
+            match previous {
+                Some((j, previous_bonus)) if j + 1 == i => {
+                    bonus = cmp::max(bonus, cmp::max(previous_bonus, CONSECUTIVE_BONUS));
+                }
+                Some((j, _)) => score -= (i - j - 1) as i64,
+                None => {}
+            }
+            score += 1 + bonus;
+            previous = Some((i, bonus));
+            i += 1;
+        }
+        if !matched {
+            // Later starts leave fewer characters to match the rest in.
+            break;
+        }
+        if word.len() == text.len() {
+            score += WHOLE_FIELD_BONUS;
+        }
+        let score = cmp::max(score, 1) as u32;
+        if best.map_or(true, |best| score > best) {
+            best = Some(score);
+        }
+    }
+    best
+}
+
+/// Returns the lowercase UUID, title and path of the folder of `item` and of each of its
+/// ancestors, or nothing if it is in no folder.
+fn folder_names(item: &Item, folders: &[Folder]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut uuid = item.folder();
+    while let Some(folder) = uuid.and_then(|uuid| folders.iter().find(|f| f.uuid() == uuid)) {
+        // Parents that form a cycle would otherwise never end.
+        if names.len() > 3 * folders.len() {
+            break;
+        }
+        names.push(folder.uuid().to_lowercase());
+        names.push(folder.title().to_lowercase());
+        names.push(folder.path(folders).to_lowercase());
+        uuid = folder.parent();
+    }
+    names
+}
+
+/// Lowercases `name` and drops its spaces, hyphens and underscores.
+fn compact(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != ' ' && c != '-' && c != '_')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Splits `s` at whitespace outside double quotes, dropping the quotes.
+fn tokenize(s: &str) -> IoResult<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    for c in s.chars() {
+        if c == '"' {
+            quoted = !quoted;
+            started = true;
+        } else if c.is_whitespace() && !quoted {
+            if started {
+                tokens.push(token.clone());
+                token.clear();
+                started = false;
+            }
+        } else {
+            token.push(c);
+            started = true;
+        }
+    }
+    if quoted {
+        return Err(invalid_input("unterminated quote".to_string()));
+    }
+    if started {
+        tokens.push(token);
+    }
+    Ok(tokens.into_iter().filter(|token| !token.is_empty()).collect())
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::sample_vault;
+    use super::{fuzzy_score, search, tokenize, Filter, Query};
+
+    fn score(word: &str, text: &str) -> Option<u32> {
+        let word: Vec<char> = word.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        fuzzy_score(&word, &text)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(tokenize("a  \"b c\" folder:\"Social Media\" \"\"").unwrap(),
+                   vec!["a", "b c", "folder:Social Media"]);
+        assert!(tokenize("\"open").is_err());
+        let query = Query::parse("Git category:\"Secure Note\" tag:Prod https://x trashed:any")
+            .unwrap();
+        assert_eq!(query.words,
+                   vec!["git".chars().collect::<Vec<_>>(), "https://x".chars().collect()]);
+        assert_eq!(query.filters,
+                   vec![Filter::Category("securenote".to_string()),
+                        Filter::Tag("prod".to_string()),
+                        Filter::Trashed(None)]);
+        assert!(Query::parse("trashed:maybe").is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(score("ghub", "github").is_some());
+        assert!(score("hubg", "github").is_none());
+        assert!(score("gh", "git hub").unwrap() > score("gh", "github").unwrap());
+        assert!(score("git", "git").unwrap() > score("git", "github").unwrap());
+        assert!(score("lab", "gitlab labs").unwrap() > score("lab", "gitlab").unwrap());
+        assert!(score("hul", "hulu").unwrap() > score("hul", "hunting license").unwrap());
+        assert_eq!(score("", "anything"), Some(0));
+    }
+
+    #[test]
+    fn test_search() {
+        let vault = sample_vault();
+        let folders = vault.folders();
+        let titles = |query: &str| {
+            search(vault.items(), &folders, &Query::parse(query).unwrap())
+                .iter()
+                .map(|r| r.item.title().unwrap_or("").to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles("hulu")[0], "Hulu");
+        assert_eq!(titles("wendy hulu")[0], "Hulu");
+        assert_eq!(titles("hulu category:login"), vec!["Hulu"]);
+        assert!(titles("hulu category:password").is_empty());
+        assert!(titles("hulu tag:sample").contains(&"Hulu".to_string()));
+        assert!(titles("xyzzyq").is_empty());
+        let social = titles("folder:social");
+        assert!(!social.is_empty());
+        assert!(social.len() < titles("").len());
+        assert!(titles("trashed:true").len() < titles("trashed:any").len());
+    }
+}
@@ -0,0 +1,777 @@
+//! Just enough of the D-Bus protocol to offer a service on the session bus: the wire format of
+//! messages and a connection authenticated with the EXTERNAL mechanism.
+
+use libc;
+use std::env;
+use std::io::{self, Read, Result as IoResult, Write};
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use totp;
+use util::invalid_data;
+
+pub const METHOD_CALL: u8 = 1;
+pub const METHOD_RETURN: u8 = 2;
+pub const ERROR: u8 = 3;
+pub const SIGNAL: u8 = 4;
+
+/// Set on calls whose caller does not want a reply.
+pub const NO_REPLY_EXPECTED: u8 = 1;
+
+const BUS_NAME: &'static str = "org.freedesktop.DBus";
+const BUS_PATH: &'static str = "/org/freedesktop/DBus";
+
+/// The largest message the specification allows.
+const MAX_MESSAGE_LEN: usize = 128 * 1024 * 1024;
+
+/// How deeply containers may nest, which keeps hostile messages from exhausting the stack.
+const MAX_DEPTH: usize = 64;
+
+const HEADER_PATH: u8 = 1;
+const HEADER_INTERFACE: u8 = 2;
+const HEADER_MEMBER: u8 = 3;
+const HEADER_ERROR_NAME: u8 = 4;
+const HEADER_REPLY_SERIAL: u8 = 5;
+const HEADER_DESTINATION: u8 = 6;
+const HEADER_SENDER: u8 = 7;
+const HEADER_SIGNATURE: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    /// An array with the signature of its elements, which is needed when it is empty.
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match *self {
+            Value::Byte(_) => "y".to_string(),
+            Value::Bool(_) => "b".to_string(),
+            Value::I32(_) => "i".to_string(),
+            Value::U32(_) => "u".to_string(),
+            Value::I64(_) => "x".to_string(),
+            Value::U64(_) => "t".to_string(),
+            Value::Str(_) => "s".to_string(),
+            Value::ObjectPath(_) => "o".to_string(),
+            Value::Signature(_) => "g".to_string(),
+            Value::Array(ref element, _) => format!("a{}", element),
+            Value::Struct(ref fields) => format!("({})", signature_of(fields)),
+            Value::DictEntry(ref key, ref value) => {
+                format!("{{{}{}}}", key.signature(), value.signature())
+            }
+            Value::Variant(_) => "v".to_string(),
+        }
+    }
+
+    /// Returns the contents of strings, object paths and signatures.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref s) | Value::ObjectPath(ref s) | Value::Signature(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            Value::U32(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(_, ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(bytes: &[u8]) -> Value {
+        Value::Array("y".to_string(), bytes.iter().map(|&b| Value::Byte(b)).collect())
+    }
+
+    pub fn object_paths(paths: Vec<String>) -> Value {
+        Value::Array("o".to_string(), paths.into_iter().map(Value::ObjectPath).collect())
+    }
+
+    pub fn dict_entry(key: Value, value: Value) -> Value {
+        Value::DictEntry(Box::new(key), Box::new(value))
+    }
+}
+
+/// Returns the signature of `values` in a row, as in a message body.
+pub fn signature_of(values: &[Value]) -> String {
+    values.iter().map(Value::signature).collect::<Vec<_>>().concat()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub kind: u8,
+    pub flags: u8,
+    pub serial: u32,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub body: Vec<Value>,
+}
+
+impl Message {
+    fn new(kind: u8) -> Message {
+        Message {
+            kind: kind,
+            flags: 0,
+            serial: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn method_call(destination: &str,
+                       path: &str,
+                       interface: &str,
+                       member: &str,
+                       body: Vec<Value>)
+                       -> Message {
+        let mut message = Message::new(METHOD_CALL);
+        message.destination = Some(destination.to_string());
+        message.path = Some(path.to_string());
+        message.interface = Some(interface.to_string());
+        message.member = Some(member.to_string());
+        message.body = body;
+        message
+    }
+
+    pub fn method_return(call: &Message, body: Vec<Value>) -> Message {
+        let mut message = Message::new(METHOD_RETURN);
+        message.reply_serial = Some(call.serial);
+        message.destination = call.sender.clone();
+        message.body = body;
+        message
+    }
+
+    pub fn error(call: &Message, name: &str, text: &str) -> Message {
+        let mut message = Message::new(ERROR);
+        message.reply_serial = Some(call.serial);
+        message.destination = call.sender.clone();
+        message.error_name = Some(name.to_string());
+        message.body = vec![Value::Str(text.to_string())];
+        message
+    }
+
+    /// Encodes the message in little-endian byte order with `serial`.
+    pub fn encode(&self, serial: u32) -> Vec<u8> {
+        let mut body = Encoder::new();
+        for value in &self.body {
+            body.value(value);
+        }
+        let mut fields = Vec::new();
+        {
+            let mut field = |code: u8, value: Value| {
+                fields.push(Value::Struct(vec![Value::Byte(code),
+                                               Value::Variant(Box::new(value))]));
+            };
+            if let Some(ref path) = self.path {
+                field(HEADER_PATH, Value::ObjectPath(path.clone()));
+            }
+            if let Some(ref interface) = self.interface {
+                field(HEADER_INTERFACE, Value::Str(interface.clone()));
+            }
+            if let Some(ref member) = self.member {
+                field(HEADER_MEMBER, Value::Str(member.clone()));
+            }
+            if let Some(ref error_name) = self.error_name {
+                field(HEADER_ERROR_NAME, Value::Str(error_name.clone()));
+            }
+            if let Some(reply_serial) = self.reply_serial {
+                field(HEADER_REPLY_SERIAL, Value::U32(reply_serial));
+            }
+            if let Some(ref destination) = self.destination {
+                field(HEADER_DESTINATION, Value::Str(destination.clone()));
+            }
+            if let Some(ref sender) = self.sender {
+                field(HEADER_SENDER, Value::Str(sender.clone()));
+            }
+            if !self.body.is_empty() {
+                field(HEADER_SIGNATURE, Value::Signature(signature_of(&self.body)));
+            }
+        }
+        let mut message = Encoder::new();
+        message.buf.extend_from_slice(&[b'l', self.kind, self.flags, 1]);
+        message.u32(body.buf.len() as u32);
+        message.u32(serial);
+        message.value(&Value::Array("(yv)".to_string(), fields));
+        message.pad(8);
+        message.buf.extend_from_slice(&body.buf);
+        message.buf
+    }
+
+    /// Decodes a whole message, as read by `message_len`.
+    pub fn decode(data: &[u8]) -> IoResult<Message> {
+        if data.len() < 16 {
+            return Err(invalid_data("message is truncated"));
+        }
+        let big_endian = match data[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(invalid_data("unknown byte order")),
+        };
+        if data[3] != 1 {
+            return Err(invalid_data("unknown protocol version"));
+        }
+        let mut message = Message::new(data[1]);
+        message.flags = data[2];
+        let mut header = Decoder::new(data, big_endian);
+        header.pos = 4;
+        let body_len = try!(header.u32()) as usize;
+        message.serial = try!(header.u32());
+        let mut signature = String::new();
+        let fields = try!(header.value("a(yv)", 0));
+        for field in fields.as_array().unwrap_or(&[]) {
+            let (code, value) = match *field {
+                Value::Struct(ref pair) => {
+                    match (&pair[0], &pair[1]) {
+                        (&Value::Byte(code), &Value::Variant(ref value)) => (code, &**value),
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let string = value.as_str().map(|s| s.to_string());
+            match code {
+                HEADER_PATH => message.path = string,
+                HEADER_INTERFACE => message.interface = string,
+                HEADER_MEMBER => message.member = string,
+                HEADER_ERROR_NAME => message.error_name = string,
+                HEADER_REPLY_SERIAL => message.reply_serial = value.as_u32(),
+                HEADER_DESTINATION => message.destination = string,
+                HEADER_SENDER => message.sender = string,
+                HEADER_SIGNATURE => signature = string.unwrap_or_else(String::new),
+                _ => {}
+            }
+        }
+        try!(header.pad(8));
+        if data.len() != header.pos + body_len {
+            return Err(invalid_data("body length does not match"));
+        }
+        // The body starts on an 8-byte boundary, so it aligns as if it began the message.
+        let mut body = Decoder::new(&data[header.pos..], big_endian);
+        for value_signature in try!(split_signature(&signature)) {
+            message.body.push(try!(body.value(value_signature, 0)));
+        }
+        if body.pos != body_len {
+            return Err(invalid_data("body is longer than its signature"));
+        }
+        Ok(message)
+    }
+}
+
+/// Returns the length of the message whose first 16 bytes are `header`.
+pub fn message_len(header: &[u8]) -> IoResult<usize> {
+    let mut decoder = Decoder::new(header, header[0] == b'B');
+    decoder.pos = 4;
+    let body_len = try!(decoder.u32()) as usize;
+    decoder.pos = 12;
+    let fields_len = try!(decoder.u32()) as usize;
+    let len = (16 + fields_len + 7) / 8 * 8 + body_len;
+    if len > MAX_MESSAGE_LEN {
+        return Err(invalid_data("message is too long"));
+    }
+    Ok(len)
+}
+
+/// Splits a signature into its complete types.
+fn split_signature(signature: &str) -> IoResult<Vec<&str>> {
+    let mut types = Vec::new();
+    let mut rest = signature;
+    while !rest.is_empty() {
+        let len = try!(complete_type_len(rest.as_bytes()));
+        types.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Ok(types)
+}
+
+fn complete_type_len(signature: &[u8]) -> IoResult<usize> {
+    let close = match signature.first() {
+        None => return Err(invalid_data("malformed signature")),
+        Some(&b'a') => return complete_type_len(&signature[1..]).map(|len| len + 1),
+        Some(&b'(') => b')',
+        Some(&b'{') => b'}',
+        Some(_) => return Ok(1),
+    };
+    let mut len = 1;
+    while signature.get(len) != Some(&close) {
+        len += try!(complete_type_len(&signature[len..]));
+    }
+    Ok(len + 1)
+}
+
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(&b'b') | Some(&b'i') | Some(&b'u') | Some(&b's') | Some(&b'o') | Some(&b'a') => 4,
+        Some(&b'x') | Some(&b't') | Some(&b'(') | Some(&b'{') => 8,
+        _ => 1,
+    }
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Encoder {
+        Encoder { buf: Vec::new() }
+    }
+
+    fn pad(&mut self, alignment: usize) {
+        while self.buf.len() % alignment != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.pad(8);
+        for i in 0..8 {
+            self.buf.push((n >> (8 * i)) as u8);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match *value {
+            Value::Byte(b) => self.buf.push(b),
+            Value::Bool(b) => self.u32(b as u32),
+            Value::I32(n) => self.u32(n as u32),
+            Value::U32(n) => self.u32(n),
+            Value::I64(n) => self.u64(n as u64),
+            Value::U64(n) => self.u64(n),
+            Value::Str(ref s) | Value::ObjectPath(ref s) => self.string(s),
+            Value::Signature(ref s) => {
+                self.buf.push(s.len() as u8);
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            Value::Array(ref element, ref values) => {
+                self.u32(0);
+                let len_pos = self.buf.len() - 4;
+                self.pad(alignment(element));
+                let start = self.buf.len();
+                for value in values {
+                    self.value(value);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_pos..len_pos + 4]
+                    .copy_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8,
+                                       (len >> 24) as u8]);
+            }
+            Value::Struct(ref fields) => {
+                self.pad(8);
+                for field in fields {
+                    self.value(field);
+                }
+            }
+            Value::DictEntry(ref key, ref value) => {
+                self.pad(8);
+                self.value(key);
+                self.value(value);
+            }
+            Value::Variant(ref value) => {
+                self.value(&Value::Signature(value.signature()));
+                self.value(value);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], big_endian: bool) -> Decoder<'a> {
+        Decoder {
+            data: data,
+            pos: 0,
+            big_endian: big_endian,
+        }
+    }
+
+    fn pad(&mut self, alignment: usize) -> IoResult<()> {
+        let pos = (self.pos + alignment - 1) / alignment * alignment;
+        if pos > self.data.len() {
+            return Err(invalid_data("message is truncated"));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) -> IoResult<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid_data("message is truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> IoResult<u64> {
+        try!(self.pad(len));
+        let bytes = try!(self.bytes(len));
+        let big_endian = self.big_endian;
+        Ok((0..len).fold(0, |n, i| {
+            let byte = if big_endian { bytes[i] } else { bytes[len - 1 - i] };
+            n << 8 | byte as u64
+        }))
+    }
+
+    fn u32(&mut self) -> IoResult<u32> {
+        self.uint(4).map(|n| n as u32)
+    }
+
+    fn string(&mut self, len: usize) -> IoResult<String> {
+        let bytes = try!(self.bytes(len + 1));
+        if bytes[len] != 0 {
+            return Err(invalid_data("string is not terminated"));
+        }
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| invalid_data("string is not UTF-8"))
+    }
+
+    /// Decodes a value of the single complete type `signature`.
+    fn value(&mut self, signature: &str, depth: usize) -> IoResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("values are nested too deeply"));
+        }
+        let value = match signature.as_bytes()[0] {
+            b'y' => Value::Byte(try!(self.bytes(1))[0]),
+            b'b' => Value::Bool(try!(self.u32()) != 0),
+            b'i' => Value::I32(try!(self.u32()) as i32),
+            b'u' => Value::U32(try!(self.u32())),
+            b'x' => Value::I64(try!(self.uint(8)) as i64),
+            b't' => Value::U64(try!(self.uint(8))),
+            b's' => {
+                let len = try!(self.u32()) as usize;
+                Value::Str(try!(self.string(len)))
+            }
+            b'o' => {
+                let len = try!(self.u32()) as usize;
+                Value::ObjectPath(try!(self.string(len)))
+            }
+            b'g' => {
+                let len = try!(self.bytes(1))[0] as usize;
+                Value::Signature(try!(self.string(len)))
+            }
+            b'v' => {
+                let len = try!(self.bytes(1))[0] as usize;
+                let signature = try!(self.string(len));
+                if try!(split_signature(&signature)).len() != 1 {
+                    return Err(invalid_data("variant holds no single value"));
+                }
+                Value::Variant(Box::new(try!(self.value(&signature, depth + 1))))
+            }
+            b'a' => {
+                let element = &signature[1..];
+                let len = try!(self.u32()) as usize;
+                try!(self.pad(alignment(element)));
+                if self.data.len() - self.pos < len {
+                    return Err(invalid_data("message is truncated"));
+                }
+                let end = self.pos + len;
+                let mut values = Vec::new();
+                while self.pos < end {
+                    values.push(try!(self.value(element, depth + 1)));
+                }
+                if self.pos != end {
+                    return Err(invalid_data("array overruns its length"));
+                }
+                Value::Array(element.to_string(), values)
+            }
+            b'(' | b'{' => {
+                try!(self.pad(8));
+                let mut fields = Vec::new();
+                for field in try!(split_signature(&signature[1..signature.len() - 1])) {
+                    fields.push(try!(self.value(field, depth + 1)));
+                }
+                if signature.starts_with('(') {
+                    Value::Struct(fields)
+                } else if fields.len() == 2 {
+                    let value = fields.pop().unwrap();
+                    Value::dict_entry(fields.pop().unwrap(), value)
+                } else {
+                    return Err(invalid_data("dict entry is not a pair"));
+                }
+            }
+            _ => return Err(invalid_data(&format!("unsupported type {}", signature))),
+        };
+        Ok(value)
+    }
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+    unique_name: String,
+}
+
+impl Connection {
+    /// Connects to the bus at `$DBUS_SESSION_BUS_ADDRESS`.
+    pub fn session() -> IoResult<Connection> {
+        let address = try!(env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| {
+            io::Error::new(io::ErrorKind::NotFound, "DBUS_SESSION_BUS_ADDRESS is not set")
+        }));
+        Connection::open(&address)
+    }
+
+    /// Connects to the first address of `addresses` that can be reached, authenticates and says
+    /// hello to the bus.
+    pub fn open(addresses: &str) -> IoResult<Connection> {
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "no bus address");
+        for address in addresses.split(';').filter(|a| !a.is_empty()) {
+            match connect(address) {
+                Ok(stream) => {
+                    let mut connection = Connection {
+                        stream: stream,
+                        serial: 0,
+                        unique_name: String::new(),
+                    };
+                    try!(connection.authenticate());
+                    let reply = try!(connection.call(Message::method_call(BUS_NAME,
+                                                                          BUS_PATH,
+                                                                          BUS_NAME,
+                                                                          "Hello",
+                                                                          Vec::new())));
+                    connection.unique_name = reply.body
+                        .first()
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("")
+                        .to_string();
+                    return Ok(connection);
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    /// Takes `name` on the bus, failing if another connection owns it.
+    pub fn request_name(&mut self, name: &str) -> IoResult<()> {
+        const DO_NOT_QUEUE: u32 = 4;
+        const PRIMARY_OWNER: u32 = 1;
+        const ALREADY_OWNER: u32 = 4;
+        let reply = try!(self.call(Message::method_call(BUS_NAME,
+                                                        BUS_PATH,
+                                                        BUS_NAME,
+                                                        "RequestName",
+                                                        vec![Value::Str(name.to_string()),
+                                                             Value::U32(DO_NOT_QUEUE)])));
+        match reply.body.first().and_then(|v| v.as_u32()) {
+            Some(PRIMARY_OWNER) | Some(ALREADY_OWNER) => Ok(()),
+            _ => {
+                Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                   format!("{} is owned by another process", name)))
+            }
+        }
+    }
+
+    /// Sends `message` and returns its serial.
+    pub fn send(&mut self, message: &Message) -> IoResult<u32> {
+        self.serial += 1;
+        try!(self.stream.write_all(&message.encode(self.serial)));
+        Ok(self.serial)
+    }
+
+    pub fn receive(&mut self) -> IoResult<Message> {
+        let mut data = vec![0; 16];
+        try!(self.stream.read_exact(&mut data));
+        let len = try!(message_len(&data));
+        data.resize(len, 0);
+        try!(self.stream.read_exact(&mut data[16..]));
+        Message::decode(&data)
+    }
+
+    /// Sends `message` and waits for its reply, dropping whatever arrives before it.
+    fn call(&mut self, message: Message) -> IoResult<Message> {
+        let serial = try!(self.send(&message));
+        loop {
+            let reply = try!(self.receive());
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            if reply.kind == ERROR {
+                let text = reply.body.first().and_then(|v| v.as_str()).unwrap_or("");
+                return Err(io::Error::new(io::ErrorKind::Other,
+                                          format!("{}: {}",
+                                                  reply.error_name.unwrap_or_else(String::new),
+                                                  text)));
+            }
+            return Ok(reply);
+        }
+    }
+
+    fn authenticate(&mut self) -> IoResult<()> {
+        let uid = unsafe { libc::geteuid() }.to_string();
+        let hex = uid.bytes().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().concat();
+        try!(self.stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes()));
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut byte = [0];
+            try!(self.stream.read_exact(&mut byte));
+            line.push(byte[0]);
+            if line.len() > 1024 {
+                return Err(invalid_data("the bus sent a malformed line"));
+            }
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("the bus refused us: {}",
+                                              String::from_utf8_lossy(&line).trim())));
+        }
+        self.stream.write_all(b"BEGIN\r\n")
+    }
+}
+
+/// Connects to a `unix:` address with a `path` or, on Linux, an `abstract` name.
+fn connect(address: &str) -> IoResult<UnixStream> {
+    let unsupported = || {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       format!("unsupported bus address {}", address))
+    };
+    if !address.starts_with("unix:") {
+        return Err(unsupported());
+    }
+    for pair in address["unix:".len()..].split(',') {
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = totp::percent_decode(parts.next().unwrap_or(""));
+        match key {
+            "path" => return UnixStream::connect(value),
+            "abstract" => return connect_abstract(&value),
+            "runtime" if value == "yes" => {
+                let dir = try!(env::var_os("XDG_RUNTIME_DIR").ok_or_else(&unsupported));
+                return UnixStream::connect(PathBuf::from(dir).join("bus"));
+            }
+            _ => {}
+        }
+    }
+    Err(unsupported())
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> IoResult<UnixStream> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // The name follows a NUL byte that marks it as abstract.
+    if name.len() + 1 > addr.sun_path.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "abstract name is too long"));
+    }
+    for (i, &b) in name.as_bytes().iter().enumerate() {
+        addr.sun_path[i + 1] = b as libc::c_char;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = UnixStream::from_raw_fd(fd);
+        if libc::connect(fd,
+                         &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                         len as libc::socklen_t) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_name: &str) -> IoResult<UnixStream> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, "abstract sockets need Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Value, message_len};
+
+    #[test]
+    fn test_round_trip() {
+        let mut call = Message::method_call("org.freedesktop.secrets",
+                                            "/org/freedesktop/secrets",
+                                            "org.freedesktop.Secret.Service",
+                                            "SearchItems",
+                                            vec![Value::Array("{ss}".to_string(),
+                                                              vec![Value::dict_entry(
+                Value::Str("title".to_string()),
+                Value::Str("Hulu".to_string()))])]);
+        call.sender = Some(":1.7".to_string());
+        let data = call.encode(3);
+        assert_eq!(message_len(&data[..16]).unwrap(), data.len());
+        let decoded = Message::decode(&data).unwrap();
+        assert_eq!(decoded.serial, 3);
+        assert_eq!(decoded.body[0].signature(), "a{ss}");
+        assert_eq!(Message { serial: 0, ..decoded.clone() }, call);
+
+        let reply = Message::method_return(&decoded,
+                                           vec![Value::Variant(Box::new(Value::U64(1))),
+                                                Value::object_paths(Vec::new()),
+                                                Value::Struct(vec![Value::Bool(true),
+                                                                   Value::bytes(b"ab")])]);
+        let decoded = Message::decode(&reply.encode(4)).unwrap();
+        assert_eq!(decoded.reply_serial, Some(3));
+        assert_eq!(decoded.destination, Some(":1.7".to_string()));
+        assert_eq!(Message { serial: 0, ..decoded }, reply);
+    }
+
+    #[test]
+    fn test_decode() {
+        // A call to Ping as dbus-send writes it on a big-endian machine.
+        let data = b"B\x01\x00\x01\x00\x00\x00\x00\x00\x00\x00\x02\x00\x00\x00\x45\
+                     \x01\x01o\x00\x00\x00\x00\x01/\x00\x00\x00\x00\x00\x00\x00\
+                     \x02\x01s\x00\x00\x00\x00\x19org.freedesktop.DBus.Peer\x00\
+                     \x00\x00\x00\x00\x00\x00\
+                     \x03\x01s\x00\x00\x00\x00\x04Ping\x00\x00\x00\x00";
+        let message = Message::decode(&data[..]).unwrap();
+        assert_eq!(message.serial, 2);
+        assert_eq!(message.path, Some("/".to_string()));
+        assert_eq!(message.interface, Some("org.freedesktop.DBus.Peer".to_string()));
+        assert_eq!(message.member, Some("Ping".to_string()));
+        assert!(Message::decode(&data[..data.len() - 1]).is_err());
+    }
+}
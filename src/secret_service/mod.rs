@@ -0,0 +1,622 @@
+//! A provider of the freedesktop.org Secret Service API backed by a vault.
+//!
+//! Registered as `org.freedesktop.secrets` on the session bus, the service lets libsecret and
+//! the tools built on it read the vault. Each folder is a collection, and items outside any
+//! folder are in a collection named after the vault, which is also the `default` alias. Items
+//! can be looked up by attributes taken from their overviews; see `attributes`. The vault stays
+//! read-only: creating, changing and deleting items fail with `NotSupported`.
+//!
+//! Only `plain` sessions are offered, so secrets cross the bus unencrypted. libsecret falls back
+//! to them by itself, and the bus is private to the user anyway.
+
+pub mod dbus;
+
+use std::collections::BTreeMap;
+use std::io::{self, Result as IoResult};
+
+use op_vault::category::Category;
+use op_vault::details::ItemDetails;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use secret_service::dbus::{Connection, Message, Value};
+use url::Url;
+
+/// The name the service owns on the bus.
+pub const BUS_NAME: &'static str = "org.freedesktop.secrets";
+
+const SERVICE_PATH: &'static str = "/org/freedesktop/secrets";
+const COLLECTION_PREFIX: &'static str = "/org/freedesktop/secrets/collection/";
+const SESSION_PREFIX: &'static str = "/org/freedesktop/secrets/session/";
+const DEFAULT_ALIAS_PATH: &'static str = "/org/freedesktop/secrets/aliases/default";
+
+const SERVICE_INTERFACE: &'static str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &'static str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &'static str = "org.freedesktop.Secret.Item";
+const SESSION_INTERFACE: &'static str = "org.freedesktop.Secret.Session";
+const PROPERTIES_INTERFACE: &'static str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE_INTERFACE: &'static str = "org.freedesktop.DBus.Introspectable";
+const PEER_INTERFACE: &'static str = "org.freedesktop.DBus.Peer";
+
+const NOT_SUPPORTED: &'static str = "org.freedesktop.DBus.Error.NotSupported";
+const INVALID_ARGS: &'static str = "org.freedesktop.DBus.Error.InvalidArgs";
+const UNKNOWN_METHOD: &'static str = "org.freedesktop.DBus.Error.UnknownMethod";
+const UNKNOWN_OBJECT: &'static str = "org.freedesktop.DBus.Error.UnknownObject";
+const UNKNOWN_PROPERTY: &'static str = "org.freedesktop.DBus.Error.UnknownProperty";
+const PROPERTY_READ_ONLY: &'static str = "org.freedesktop.DBus.Error.PropertyReadOnly";
+const NO_SESSION: &'static str = "org.freedesktop.Secret.Error.NoSession";
+const NO_SUCH_OBJECT: &'static str = "org.freedesktop.Secret.Error.NoSuchObject";
+
+/// The content type of every secret, which is text from a password or notes.
+const CONTENT_TYPE: &'static str = "text/plain; charset=utf8";
+
+/// A D-Bus error to send back, with its name and text.
+struct Error {
+    name: &'static str,
+    text: String,
+}
+
+impl Error {
+    fn new(name: &'static str, text: &str) -> Error {
+        Error {
+            name: name,
+            text: text.to_string(),
+        }
+    }
+}
+
+type CallResult = Result<Vec<Value>, Error>;
+
+struct Collection {
+    path: String,
+    label: String,
+    created: u64,
+    modified: u64,
+    items: Vec<Entry>,
+}
+
+/// What the service tells about an item without decrypting its details.
+struct Entry {
+    path: String,
+    uuid: String,
+    label: String,
+    attributes: BTreeMap<String, String>,
+    created: u64,
+    modified: u64,
+}
+
+enum Object<'a> {
+    Service,
+    Collection(&'a Collection),
+    Item(&'a Entry),
+    Session(&'a str),
+}
+
+pub struct Service {
+    vault: Vault,
+    collections: Vec<Collection>,
+    sessions: Vec<String>,
+    next_session: u64,
+}
+
+impl Service {
+    /// Lays out the items of `vault` in collections. Items added to the vault later are not
+    /// seen until the service starts again.
+    pub fn new(vault: Vault) -> Service {
+        let folders = vault.folders().into_iter().filter(|f| !f.is_smart()).collect::<Vec<_>>();
+        let mut collections = vec![Collection {
+                                       path: format!("{}{}",
+                                                     COLLECTION_PREFIX,
+                                                     path_element(&vault.name())),
+                                       label: vault.name(),
+                                       created: 0,
+                                       modified: 0,
+                                       items: Vec::new(),
+                                   }];
+        for folder in &folders {
+            collections.push(Collection {
+                path: format!("{}{}", COLLECTION_PREFIX, path_element(folder.uuid())),
+                label: folder.path(&folders),
+                created: folder.created(),
+                modified: folder.updated(),
+                items: Vec::new(),
+            });
+        }
+        for item in vault.items() {
+            if item.is_trashed() {
+                continue;
+            }
+            let index = item.folder()
+                .and_then(|uuid| folders.iter().position(|folder| folder.uuid() == uuid))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            let collection = &mut collections[index];
+            collection.items.push(Entry {
+                path: format!("{}/{}", collection.path, path_element(item.uuid())),
+                uuid: item.uuid().to_string(),
+                label: item.title().unwrap_or("").to_string(),
+                attributes: attributes(&item),
+                created: item.created(),
+                modified: item.updated(),
+            });
+        }
+        Service {
+            vault: vault,
+            collections: collections,
+            sessions: Vec::new(),
+            next_session: 1,
+        }
+    }
+
+    /// Answers calls from `connection` until the bus goes away.
+    pub fn run(&mut self, connection: &mut Connection) -> IoResult<()> {
+        loop {
+            let message = match connection.receive() {
+                Ok(message) => message,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if message.kind != dbus::METHOD_CALL {
+                continue;
+            }
+            let reply = self.handle(&message);
+            if message.flags & dbus::NO_REPLY_EXPECTED == 0 {
+                try!(connection.send(&reply));
+            }
+        }
+    }
+
+    /// Returns the reply to the method call `call`.
+    pub fn handle(&mut self, call: &Message) -> Message {
+        let path = call.path.as_ref().map(|s| &s[..]).unwrap_or("");
+        let interface = call.interface.as_ref().map(|s| &s[..]).unwrap_or("");
+        let member = call.member.as_ref().map(|s| &s[..]).unwrap_or("");
+        match self.call(path, interface, member, &call.body) {
+            Ok(body) => Message::method_return(call, body),
+            Err(e) => Message::error(call, e.name, &e.text),
+        }
+    }
+
+    fn call(&mut self, path: &str, interface: &str, member: &str, args: &[Value]) -> CallResult {
+        match interface {
+            PROPERTIES_INTERFACE => return self.call_properties(path, member, args),
+            INTROSPECTABLE_INTERFACE if member == "Introspect" => {
+                return self.introspect(path).map(|xml| vec![Value::Str(xml)]);
+            }
+            PEER_INTERFACE if member == "Ping" => return Ok(Vec::new()),
+            _ => {}
+        }
+        // Callers may leave out the interface, in which case the object's own is meant.
+        let object_interface = match try!(self.object(path)) {
+            Object::Service => SERVICE_INTERFACE,
+            Object::Collection(_) => COLLECTION_INTERFACE,
+            Object::Item(_) => ITEM_INTERFACE,
+            Object::Session(_) => SESSION_INTERFACE,
+        };
+        if !interface.is_empty() && interface != object_interface {
+            return Err(unknown_method(interface, member));
+        }
+        match (object_interface, member) {
+            (SERVICE_INTERFACE, "OpenSession") => {
+                try!(check_args(args, "sv"));
+                if args[0].as_str() != Some("plain") {
+                    return Err(Error::new(NOT_SUPPORTED, "only plain sessions are supported"));
+                }
+                let session = format!("{}{}", SESSION_PREFIX, self.next_session);
+                self.next_session += 1;
+                self.sessions.push(session.clone());
+                Ok(vec![Value::Variant(Box::new(Value::Str(String::new()))),
+                        Value::ObjectPath(session)])
+            }
+            (SERVICE_INTERFACE, "SearchItems") => {
+                try!(check_args(args, "a{ss}"));
+                let query = string_dict(&args[0]);
+                let paths = self.collections
+                    .iter()
+                    .flat_map(|collection| search(collection, &query))
+                    .collect();
+                Ok(vec![Value::object_paths(paths), Value::object_paths(Vec::new())])
+            }
+            (SERVICE_INTERFACE, "Unlock") => {
+                try!(check_args(args, "ao"));
+                // Everything is unlocked already.
+                Ok(vec![args[0].clone(), Value::ObjectPath("/".to_string())])
+            }
+            (SERVICE_INTERFACE, "Lock") => {
+                try!(check_args(args, "ao"));
+                Ok(vec![Value::object_paths(Vec::new()), Value::ObjectPath("/".to_string())])
+            }
+            (SERVICE_INTERFACE, "GetSecrets") => {
+                try!(check_args(args, "aoo"));
+                let session = args[1].as_str().unwrap_or("");
+                try!(self.check_session(session));
+                let mut secrets = Vec::new();
+                for path in args[0].as_array().unwrap_or(&[]) {
+                    let path = path.as_str().unwrap_or("");
+                    // Paths that are not items are left out, as other services do.
+                    if let Ok(Object::Item(entry)) = self.object(path) {
+                        let secret = try!(self.secret(entry, session));
+                        secrets.push(Value::dict_entry(Value::ObjectPath(path.to_string()),
+                                                       secret));
+                    }
+                }
+                Ok(vec![Value::Array("{o(oayays)}".to_string(), secrets)])
+            }
+            (SERVICE_INTERFACE, "ReadAlias") => {
+                try!(check_args(args, "s"));
+                let path = if args[0].as_str() == Some("default") {
+                    self.collections[0].path.clone()
+                } else {
+                    "/".to_string()
+                };
+                Ok(vec![Value::ObjectPath(path)])
+            }
+            (COLLECTION_INTERFACE, "SearchItems") => {
+                try!(check_args(args, "a{ss}"));
+                match try!(self.object(path)) {
+                    Object::Collection(collection) => {
+                        Ok(vec![Value::object_paths(search(collection, &string_dict(&args[0])))])
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            (ITEM_INTERFACE, "GetSecret") => {
+                try!(check_args(args, "o"));
+                let session = args[0].as_str().unwrap_or("");
+                try!(self.check_session(session));
+                match try!(self.object(path)) {
+                    Object::Item(entry) => self.secret(entry, session).map(|s| vec![s]),
+                    _ => unreachable!(),
+                }
+            }
+            (SESSION_INTERFACE, "Close") => {
+                self.sessions.retain(|session| session != path);
+                Ok(Vec::new())
+            }
+            (SERVICE_INTERFACE, "CreateCollection") |
+            (SERVICE_INTERFACE, "SetAlias") |
+            (COLLECTION_INTERFACE, "CreateItem") |
+            (COLLECTION_INTERFACE, "Delete") |
+            (ITEM_INTERFACE, "SetSecret") |
+            (ITEM_INTERFACE, "Delete") => Err(Error::new(NOT_SUPPORTED, "the vault is read-only")),
+            _ => Err(unknown_method(object_interface, member)),
+        }
+    }
+
+    fn call_properties(&self, path: &str, member: &str, args: &[Value]) -> CallResult {
+        let (interface, properties) = try!(self.properties(path));
+        match member {
+            "Get" => {
+                try!(check_args(args, "ss"));
+                if args[0].as_str() != Some(interface) {
+                    return Err(Error::new(UNKNOWN_PROPERTY, "no such interface"));
+                }
+                let name = args[1].as_str().unwrap_or("");
+                properties.into_iter()
+                    .find(|&(property, _)| property == name)
+                    .map(|(_, value)| vec![Value::Variant(Box::new(value))])
+                    .ok_or_else(|| Error::new(UNKNOWN_PROPERTY, &format!("no property {}", name)))
+            }
+            "GetAll" => {
+                try!(check_args(args, "s"));
+                let entries = if args[0].as_str() == Some(interface) {
+                    properties.into_iter()
+                        .map(|(name, value)| {
+                            Value::dict_entry(Value::Str(name.to_string()),
+                                              Value::Variant(Box::new(value)))
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                Ok(vec![Value::Array("{sv}".to_string(), entries)])
+            }
+            "Set" => Err(Error::new(PROPERTY_READ_ONLY, "the vault is read-only")),
+            _ => Err(unknown_method(PROPERTIES_INTERFACE, member)),
+        }
+    }
+
+    /// Returns the interface of the object at `path` with its properties.
+    fn properties(&self, path: &str) -> Result<(&'static str, Vec<(&'static str, Value)>), Error> {
+        let properties = match try!(self.object(path)) {
+            Object::Service => {
+                let paths = self.collections.iter().map(|c| c.path.clone()).collect();
+                (SERVICE_INTERFACE, vec![("Collections", Value::object_paths(paths))])
+            }
+            Object::Collection(collection) => {
+                let paths = collection.items.iter().map(|entry| entry.path.clone()).collect();
+                (COLLECTION_INTERFACE,
+                 vec![("Items", Value::object_paths(paths)),
+                      ("Label", Value::Str(collection.label.clone())),
+                      ("Locked", Value::Bool(false)),
+                      ("Created", Value::U64(collection.created)),
+                      ("Modified", Value::U64(collection.modified))])
+            }
+            Object::Item(entry) => {
+                let attributes = entry.attributes
+                    .iter()
+                    .map(|(key, value)| {
+                        Value::dict_entry(Value::Str(key.clone()), Value::Str(value.clone()))
+                    })
+                    .collect();
+                (ITEM_INTERFACE,
+                 vec![("Locked", Value::Bool(false)),
+                      ("Attributes", Value::Array("{ss}".to_string(), attributes)),
+                      ("Label", Value::Str(entry.label.clone())),
+                      ("Created", Value::U64(entry.created)),
+                      ("Modified", Value::U64(entry.modified))])
+            }
+            Object::Session(_) => (SESSION_INTERFACE, Vec::new()),
+        };
+        Ok(properties)
+    }
+
+    /// Describes the object at `path` just far enough for tools to find their way through the
+    /// objects: by its interface and children.
+    fn introspect(&self, path: &str) -> Result<String, Error> {
+        let mut xml = "<!DOCTYPE node PUBLIC \"-//freedesktop//DTD D-BUS Object Introspection \
+                       1.0//EN\"\n\"http://www.freedesktop.org/standards/dbus/1.0/\
+                       introspect.dtd\">\n<node>\n"
+            .to_string();
+        let is_object = match self.properties(path) {
+            Ok((interface, _)) => {
+                for name in &[interface, PROPERTIES_INTERFACE, INTROSPECTABLE_INTERFACE] {
+                    xml.push_str(&format!("  <interface name=\"{}\"/>\n", name));
+                }
+                true
+            }
+            Err(_) => false,
+        };
+        let prefix = if path == "/" { "/".to_string() } else { format!("{}/", path) };
+        let mut children = Vec::new();
+        for object_path in self.object_paths() {
+            if object_path.starts_with(&prefix) {
+                let child = object_path[prefix.len()..].split('/').next().unwrap_or("");
+                if !children.contains(&child.to_string()) {
+                    children.push(child.to_string());
+                }
+            }
+        }
+        if !is_object && children.is_empty() {
+            return Err(Error::new(UNKNOWN_OBJECT, &format!("no object at {}", path)));
+        }
+        for child in children {
+            xml.push_str(&format!("  <node name=\"{}\"/>\n", child));
+        }
+        xml.push_str("</node>\n");
+        Ok(xml)
+    }
+
+    fn object_paths(&self) -> Vec<String> {
+        let mut paths = vec![SERVICE_PATH.to_string(), DEFAULT_ALIAS_PATH.to_string()];
+        for collection in &self.collections {
+            paths.push(collection.path.clone());
+            paths.extend(collection.items.iter().map(|entry| entry.path.clone()));
+        }
+        paths.extend(self.sessions.iter().cloned());
+        paths
+    }
+
+    fn object(&self, path: &str) -> Result<Object, Error> {
+        if path == SERVICE_PATH {
+            return Ok(Object::Service);
+        }
+        if path == DEFAULT_ALIAS_PATH {
+            return Ok(Object::Collection(&self.collections[0]));
+        }
+        if let Some(session) = self.sessions.iter().find(|session| *session == path) {
+            return Ok(Object::Session(session));
+        }
+        for collection in &self.collections {
+            if collection.path == path {
+                return Ok(Object::Collection(collection));
+            }
+            if let Some(entry) = collection.items.iter().find(|entry| entry.path == path) {
+                return Ok(Object::Item(entry));
+            }
+        }
+        Err(Error::new(UNKNOWN_OBJECT, &format!("no object at {}", path)))
+    }
+
+    fn check_session(&self, session: &str) -> Result<(), Error> {
+        if self.sessions.iter().any(|s| s == session) {
+            Ok(())
+        } else {
+            Err(Error::new(NO_SESSION, &format!("no session at {}", session)))
+        }
+    }
+
+    /// Decrypts the secret of `entry` as a Secret struct for `session`.
+    fn secret(&self, entry: &Entry, session: &str) -> Result<Value, Error> {
+        let details = try!(self.vault
+            .item(&entry.uuid)
+            .and_then(|item| self.vault.item_details(&item))
+            .ok_or_else(|| Error::new(NO_SUCH_OBJECT, "the item cannot be decrypted")));
+        Ok(Value::Struct(vec![Value::ObjectPath(session.to_string()),
+                              Value::bytes(b""),
+                              Value::bytes(secret(&details).as_bytes()),
+                              Value::Str(CONTENT_TYPE.to_string())]))
+    }
+}
+
+/// Returns the lookup attributes of `item`, all from its overview: `uuid`, `title`, `category`
+/// (such as `Login`), and if it has them `url` and its `host`, and `username` for logins.
+fn attributes(item: &Item) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    attributes.insert("uuid".to_string(), item.uuid().to_string());
+    attributes.insert("category".to_string(), item.category().name().to_string());
+    if let Some(title) = item.title() {
+        attributes.insert("title".to_string(), title.to_string());
+    }
+    if let Some(url) = item.url() {
+        attributes.insert("url".to_string(), url.to_string());
+        if let Some(url) = Url::parse(url) {
+            attributes.insert("host".to_string(), url.host);
+        }
+    }
+    if item.category() == Category::Login {
+        if let Some(username) = item.ainfo() {
+            attributes.insert("username".to_string(), username.to_string());
+        }
+    }
+    attributes
+}
+
+/// The secret of an item is its password, or for items without one, such as secure notes, its
+/// notes.
+fn secret(details: &ItemDetails) -> String {
+    details.password()
+        .or_else(|| details.notes().map(|notes| notes.to_string()))
+        .unwrap_or_else(String::new)
+}
+
+/// Returns the paths of the items in `collection` that have all the attributes in `query`.
+fn search(collection: &Collection, query: &BTreeMap<String, String>) -> Vec<String> {
+    collection.items
+        .iter()
+        .filter(|entry| query.iter().all(|(key, value)| entry.attributes.get(key) == Some(value)))
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+fn string_dict(value: &Value) -> BTreeMap<String, String> {
+    let mut dict = BTreeMap::new();
+    for entry in value.as_array().unwrap_or(&[]) {
+        if let Value::DictEntry(ref key, ref value) = *entry {
+            if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+                dict.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    dict
+}
+
+fn check_args(args: &[Value], signature: &str) -> Result<(), Error> {
+    let actual = dbus::signature_of(args);
+    if actual == signature {
+        Ok(())
+    } else {
+        Err(Error::new(INVALID_ARGS,
+                       &format!("expected arguments of type {}, not {}", signature, actual)))
+    }
+}
+
+fn unknown_method(interface: &str, member: &str) -> Error {
+    Error::new(UNKNOWN_METHOD, &format!("no method {}.{}", interface, member))
+}
+
+/// Replaces the characters object paths do not allow.
+fn path_element(s: &str) -> String {
+    s.chars().map(|c| if (c as u32) < 0x80 && c.is_alphanumeric() { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use secret_service::dbus::{self, Message, Value};
+    use testing::sample_vault;
+    use super::Service;
+
+    fn call(service: &mut Service, path: &str, interface: &str, member: &str, body: Vec<Value>)
+            -> Message {
+        let mut call = Message::method_call(super::BUS_NAME, path, interface, member, body);
+        call.sender = Some(":1.1".to_string());
+        service.handle(&call)
+    }
+
+    fn attributes(pairs: &[(&str, &str)]) -> Value {
+        Value::Array("{ss}".to_string(),
+                     pairs.iter()
+                         .map(|&(key, value)| {
+                             Value::dict_entry(Value::Str(key.to_string()),
+                                               Value::Str(value.to_string()))
+                         })
+                         .collect())
+    }
+
+    #[test]
+    fn test_service() {
+        let vault = sample_vault();
+        let mut service = Service::new(vault);
+        let secrets = "/org/freedesktop/secrets";
+        let service_interface = "org.freedesktop.Secret.Service";
+
+        let reply = call(&mut service,
+                         secrets,
+                         service_interface,
+                         "OpenSession",
+                         vec![Value::Str("dh-ietf1024-sha256-aes128-cbc-pkcs7".to_string()),
+                              Value::Variant(Box::new(Value::bytes(b"")))]);
+        assert_eq!(reply.error_name,
+                   Some("org.freedesktop.DBus.Error.NotSupported".to_string()));
+        let reply = call(&mut service,
+                         secrets,
+                         service_interface,
+                         "OpenSession",
+                         vec![Value::Str("plain".to_string()),
+                              Value::Variant(Box::new(Value::Str(String::new())))]);
+        assert_eq!(reply.kind, dbus::METHOD_RETURN);
+        let session = reply.body[1].clone();
+
+        let reply = call(&mut service,
+                         secrets,
+                         service_interface,
+                         "SearchItems",
+                         vec![attributes(&[("title", "Hulu"), ("category", "Login")])]);
+        let found = reply.body[0].as_array().unwrap().to_vec();
+        assert_eq!(found.len(), 1);
+        let item = found[0].as_str().unwrap().to_string();
+
+        let reply = call(&mut service,
+                         &item,
+                         "org.freedesktop.DBus.Properties",
+                         "Get",
+                         vec![Value::Str("org.freedesktop.Secret.Item".to_string()),
+                              Value::Str("Attributes".to_string())]);
+        let attributes = match reply.body[0] {
+            Value::Variant(ref value) => super::string_dict(value),
+            _ => panic!("not a variant"),
+        };
+        assert_eq!(attributes.get("username").map(|s| &s[..]), Some("wendy@appleseed.com"));
+
+        let reply = call(&mut service,
+                         &item,
+                         "org.freedesktop.Secret.Item",
+                         "GetSecret",
+                         vec![session.clone()]);
+        let secret = match reply.body[0] {
+            Value::Struct(ref fields) => fields[2].clone(),
+            _ => panic!("not a secret"),
+        };
+        let details = service.vault
+            .item_details(&service.vault.item(&attributes["uuid"]).unwrap())
+            .unwrap();
+        assert_eq!(secret, Value::bytes(details.password().unwrap().as_bytes()));
+
+        let reply = call(&mut service,
+                         secrets,
+                         service_interface,
+                         "GetSecrets",
+                         vec![Value::object_paths(vec![item.clone()]), session.clone()]);
+        assert_eq!(reply.body[0].as_array().unwrap().len(), 1);
+
+        let reply = call(&mut service,
+                         &item,
+                         "org.freedesktop.Secret.Item",
+                         "Delete",
+                         Vec::new());
+        assert_eq!(reply.error_name,
+                   Some("org.freedesktop.DBus.Error.NotSupported".to_string()));
+
+        call(&mut service,
+             session.as_str().unwrap(),
+             "org.freedesktop.Secret.Session",
+             "Close",
+             Vec::new());
+        let reply = call(&mut service,
+                         &item,
+                         "org.freedesktop.Secret.Item",
+                         "GetSecret",
+                         vec![session]);
+        assert_eq!(reply.error_name,
+                   Some("org.freedesktop.Secret.Error.NoSession".to_string()));
+    }
+}
@@ -0,0 +1,234 @@
+//! Unsigned integers of any size, as key files and the agent protocol carry them.
+//!
+//! Signing is left to ring. The only arithmetic here derives the RSA exponents that OpenSSH key
+//! files leave out, once when a key is loaded and never while answering a client.
+
+use std::cmp::Ordering;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigUint {
+    /// Little-endian 32-bit limbs without leading zeros, so zero has none.
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> BigUint {
+        BigUint { limbs: Vec::new() }
+    }
+
+    pub fn from_u32(n: u32) -> BigUint {
+        normalize(vec![n])
+    }
+
+    pub fn from_bytes_be(bytes: &[u8]) -> BigUint {
+        let mut limbs = vec![0u32; (bytes.len() + 3) / 4];
+        for (i, &b) in bytes.iter().rev().enumerate() {
+            limbs[i / 4] |= (b as u32) << (8 * (i % 4));
+        }
+        normalize(limbs)
+    }
+
+    /// Returns the big-endian bytes without leading zeros.
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.limbs.len() * 4);
+        for limb in self.limbs.iter().rev() {
+            bytes.extend_from_slice(&[(limb >> 24) as u8, (limb >> 16) as u8, (limb >> 8) as u8,
+                                      *limb as u8]);
+        }
+        let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+        bytes.split_off(zeros)
+    }
+
+    /// Returns the big-endian bytes left-padded with zeros to `len`, or `None` if they do not
+    /// fit.
+    pub fn to_bytes_be_padded(&self, len: usize) -> Option<Vec<u8>> {
+        let bytes = self.to_bytes_be();
+        if bytes.len() > len {
+            return None;
+        }
+        let mut padded = vec![0; len - bytes.len()];
+        padded.extend_from_slice(&bytes);
+        Some(padded)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// Returns the number of significant bits.
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    /// Subtracts `other`, which must not be greater.
+    pub fn sub(&self, other: &BigUint) -> BigUint {
+        assert!(*self >= *other, "subtraction underflow");
+        let mut difference = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0i64;
+        for i in 0..self.limbs.len() {
+            let d = self.limbs[i] as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            difference.push(d as u32);
+            borrow = if d < 0 { 1 } else { 0 };
+        }
+        normalize(difference)
+    }
+
+    /// Divides by `divisor` with Knuth's algorithm D, returning the quotient and remainder.
+    pub fn div_rem(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        assert!(!divisor.is_zero(), "division by zero");
+        if *self < *divisor {
+            return (BigUint::zero(), self.clone());
+        }
+        if divisor.limbs.len() == 1 {
+            let d = divisor.limbs[0] as u64;
+            let mut quotient = vec![0u32; self.limbs.len()];
+            let mut r = 0u64;
+            for i in (0..self.limbs.len()).rev() {
+                let n = (r << 32) | self.limbs[i] as u64;
+                quotient[i] = (n / d) as u32;
+                r = n % d;
+            }
+            return (normalize(quotient), BigUint::from_u32(r as u32));
+        }
+
+        // Normalize so that the top bit of the divisor is set, which keeps the estimates of
+        // quotient digits off by at most two.
+        let shift = divisor.limbs.last().unwrap().leading_zeros();
+        let v = shift_left(&divisor.limbs, shift);
+        let mut u = shift_left(&self.limbs, shift);
+        if u.len() == self.limbs.len() {
+            u.push(0);
+        }
+        let n = v.len();
+        let m = u.len() - n - 1;
+        let mut quotient = vec![0u32; m + 1];
+        for j in (0..m + 1).rev() {
+            let numerator = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+            let mut q = numerator / v[n - 1] as u64;
+            let mut r = numerator % v[n - 1] as u64;
+            while q >> 32 != 0 || q * v[n - 2] as u64 > ((r << 32) | u[j + n - 2] as u64) {
+                q -= 1;
+                r += v[n - 1] as u64;
+                if r >> 32 != 0 {
+                    break;
+                }
+            }
+
+            let mut borrow = 0i64;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let p = q * v[i] as u64 + carry;
+                carry = p >> 32;
+                let t = u[i + j] as i64 - borrow - (p & 0xffffffff) as i64;
+                u[i + j] = t as u32;
+                borrow = if t < 0 { 1 } else { 0 };
+            }
+            let t = u[j + n] as i64 - borrow - carry as i64;
+            u[j + n] = t as u32;
+            if t < 0 {
+                // The estimate was one too large; add the divisor back.
+                q -= 1;
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let s = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = s as u32;
+                    carry = s >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = q as u32;
+        }
+        (normalize(quotient), normalize(shift_right(&u[..n], shift)))
+    }
+
+    pub fn rem(&self, modulus: &BigUint) -> BigUint {
+        self.div_rem(modulus).1
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        if self.limbs.len() != other.limbs.len() {
+            return self.limbs.len().cmp(&other.limbs.len());
+        }
+        for (a, b) in self.limbs.iter().rev().zip(other.limbs.iter().rev()) {
+            if a != b {
+                return a.cmp(b);
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+fn normalize(mut limbs: Vec<u32>) -> BigUint {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    BigUint { limbs: limbs }
+}
+
+/// Shifts left by less than a limb, growing by a limb if bits are shifted out.
+fn shift_left(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    let mut shifted = Vec::with_capacity(limbs.len() + 1);
+    let mut carry = 0u32;
+    for &limb in limbs {
+        shifted.push(limb << shift | carry);
+        carry = limb >> (32 - shift);
+    }
+    if carry != 0 {
+        shifted.push(carry);
+    }
+    shifted
+}
+
+/// Shifts right by less than a limb.
+fn shift_right(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    let mut shifted = vec![0u32; limbs.len()];
+    for i in 0..limbs.len() {
+        let high = if i + 1 < limbs.len() { limbs[i + 1] << (32 - shift) } else { 0 };
+        shifted[i] = limbs[i] >> shift | high;
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BigUint;
+
+    fn from_hex(s: &str) -> BigUint {
+        let bytes: Vec<u8> = (0..s.len() / 2)
+            .map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap())
+            .collect();
+        BigUint::from_bytes_be(&bytes)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = from_hex("123456789abcdef0fedcba9876543210");
+        let b = from_hex("00fedcba98765432100123456789");
+        assert_eq!(a.sub(&b), from_hex("12345579be02467aaaaaaa97530eca87"));
+        assert_eq!(a.to_bytes_be(),
+                   vec![0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0xfe, 0xdc, 0xba, 0x98,
+                        0x76, 0x54, 0x32, 0x10]);
+        assert_eq!(b.to_bytes_be_padded(14).unwrap()[..2], [0, 0xfe]);
+        assert!(a.to_bytes_be_padded(15).is_none());
+        let product = from_hex("121fa00ad77d7423213d0003e2285cffffc1dcb6c1170dcccce1833a97");
+        assert_eq!(product.div_rem(&b), (a, BigUint::from_u32(7)));
+        assert_eq!(BigUint::from_bytes_be(&[0, 0, 1]).bits(), 1);
+    }
+}
@@ -0,0 +1,65 @@
+//! ECDSA over the NIST curves both OpenSSH and ring support, signed by ring.
+
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, EcdsaSigningAlgorithm};
+use std::io::{self, Result as IoResult};
+
+use ssh_agent::bignum::BigUint;
+
+pub struct Curve {
+    /// The name OpenSSH uses, such as `nistp256`.
+    pub name: &'static str,
+    algorithm: &'static EcdsaSigningAlgorithm,
+    /// The size of field elements and scalars in bytes.
+    field_len: usize,
+}
+
+impl Curve {
+    /// Returns the curve OpenSSH calls `name`, or `None` for `nistp521`, which ring lacks.
+    pub fn from_name(name: &str) -> Option<Curve> {
+        let (name, algorithm, field_len) = match name {
+            "nistp256" => ("nistp256", &signature::ECDSA_P256_SHA256_FIXED_SIGNING, 32),
+            "nistp384" => ("nistp384", &signature::ECDSA_P384_SHA384_FIXED_SIGNING, 48),
+            _ => return None,
+        };
+        Some(Curve {
+            name: name,
+            algorithm: algorithm,
+            field_len: field_len,
+        })
+    }
+}
+
+pub struct EcdsaKey {
+    pub curve: Curve,
+    /// The public point, uncompressed.
+    pub public_key: Vec<u8>,
+    key_pair: EcdsaKeyPair,
+}
+
+impl EcdsaKey {
+    /// Builds a key from the private scalar `d` and the public point, which must match.
+    pub fn new(curve: Curve, d: &BigUint, public_key: Vec<u8>) -> IoResult<EcdsaKey> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed ECDSA key");
+        let d = try!(d.to_bytes_be_padded(curve.field_len).ok_or_else(|| malformed()));
+        let key_pair = try!(EcdsaKeyPair::from_private_key_and_public_key(curve.algorithm,
+                                                                          &d,
+                                                                          &public_key,
+                                                                          &SystemRandom::new())
+            .map_err(|_| malformed()));
+        Ok(EcdsaKey {
+            curve: curve,
+            public_key: public_key,
+            key_pair: key_pair,
+        })
+    }
+
+    /// Signs `message`, returning `r` and `s`.
+    pub fn sign(&self, message: &[u8]) -> IoResult<(BigUint, BigUint)> {
+        let signature = try!(self.key_pair
+            .sign(&SystemRandom::new(), message)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "ECDSA signing failed")));
+        let (r, s) = signature.as_ref().split_at(self.curve.field_len);
+        Ok((BigUint::from_bytes_be(r), BigUint::from_bytes_be(s)))
+    }
+}
@@ -0,0 +1,303 @@
+//! An SSH agent serving the private keys kept in a vault.
+//!
+//! The agent speaks the protocol of OpenSSH's `ssh-agent` on a Unix domain socket: it lists
+//! its keys and signs with them, but refuses to add or remove keys since those live in the vault.
+//! Keys are read from concealed fields and attachments holding a PEM or OpenSSH private key.
+//! ring signs with them: Ed25519 keys, ECDSA keys on nistp256 and nistp384, and RSA keys of 2048
+//! to 4096 bits, with SHA-2 only.
+
+pub mod bignum;
+pub mod ecdsa;
+pub mod key;
+pub mod rsa;
+pub mod wire;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Result as IoResult, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use agent;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use ssh_agent::key::PrivateKey;
+use ssh_agent::wire::Reader;
+use util::encode_base64;
+
+/// The variable clients such as `ssh` find the agent by.
+pub const SOCKET_ENV: &'static str = "SSH_AUTH_SOCK";
+
+const FAILURE: u8 = 5;
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+const SIGN_REQUEST: u8 = 13;
+const SIGN_RESPONSE: u8 = 14;
+
+/// Larger messages are not requests OpenSSH would send, so the connection is dropped.
+const MAX_MESSAGE_LEN: u32 = 256 * 1024;
+
+/// Attachments larger than this are not read in search of keys.
+const MAX_KEY_FILE_LEN: u64 = 64 * 1024;
+
+pub struct Identity {
+    key: PrivateKey,
+    blob: Vec<u8>,
+    comment: String,
+}
+
+impl Identity {
+    pub fn new(key: PrivateKey, comment: &str) -> Identity {
+        Identity {
+            blob: key.public_key_blob(),
+            key: key,
+            comment: comment.to_string(),
+        }
+    }
+
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Returns the public key as a line of `authorized_keys`.
+    pub fn public_key_line(&self) -> String {
+        format!("{} {} {}",
+                self.key.algorithm(),
+                encode_base64(&self.blob),
+                self.comment)
+    }
+}
+
+/// Finds the private keys in items outside the trash. Keys that cannot be read are reported in
+/// the second list rather than failing the whole vault.
+pub fn load_identities(vault: &Vault) -> (Vec<Identity>, Vec<String>) {
+    let mut identities = Vec::new();
+    let mut errors = Vec::new();
+    for item in vault.items() {
+        if item.is_trashed() {
+            continue;
+        }
+        for (label, text) in key_texts(vault, &item) {
+            let comment = format!("{} ({})", item.title().unwrap_or(item.uuid()), label);
+            match PrivateKey::parse(&text) {
+                Ok(key) => identities.push(Identity::new(key, &comment)),
+                Err(e) => errors.push(format!("{}: {}", comment, e)),
+            }
+        }
+    }
+    (identities, errors)
+}
+
+/// Returns the concealed fields and attachments of `item` that look like private keys, with the
+/// labels they go by.
+fn key_texts(vault: &Vault, item: &Item) -> Vec<(String, String)> {
+    let mut texts = Vec::new();
+    if let Some(details) = vault.item_details(item) {
+        for section in details.sections() {
+            for field in section.fields() {
+                if !field.is_concealed() {
+                    continue;
+                }
+                if let Some(value) = field.value_string() {
+                    if key::contains_private_key(&value) {
+                        texts.push((field.title().to_string(), value));
+                    }
+                }
+            }
+        }
+    }
+    for attachment in vault.attachments(item).unwrap_or_else(|_| Vec::new()) {
+        if attachment.size() > MAX_KEY_FILE_LEN {
+            continue;
+        }
+        let contents = vault.attachment_contents(item, &attachment)
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+        if let Some(contents) = contents {
+            if key::contains_private_key(&contents) {
+                texts.push((attachment.file_name().to_string(), contents));
+            }
+        }
+    }
+    texts
+}
+
+/// Returns where the agent listens unless told otherwise.
+pub fn default_socket_path() -> IoResult<PathBuf> {
+    agent::runtime_path("ssh-agent.sock")
+}
+
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    identities: Arc<Vec<Identity>>,
+    confirm: bool,
+}
+
+impl Server {
+    /// Listens at `path`. With `confirm`, every signature must be allowed through
+    /// `$SSH_ASKPASS` first, as with `ssh-add -c`.
+    pub fn bind<P: AsRef<Path>>(path: P,
+                                identities: Vec<Identity>,
+                                confirm: bool)
+                                -> IoResult<Server> {
+        let listener = try!(agent::bind_socket(path.as_ref()));
+        Ok(Server {
+            listener: listener,
+            path: path.as_ref().to_path_buf(),
+            identities: Arc::new(identities),
+            confirm: confirm,
+        })
+    }
+
+    /// Serves clients until the process is killed, each connection on a thread of its own
+    /// since `ssh` keeps its connection open for the whole session.
+    pub fn run(&self) -> IoResult<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            if agent::check_peer(&stream).is_err() {
+                continue;
+            }
+            let identities = self.identities.clone();
+            let confirm = self.confirm;
+            thread::spawn(move || serve(stream, &identities, confirm));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve(mut stream: UnixStream, identities: &[Identity], confirm: bool) {
+    loop {
+        let mut len = [0u8; 4];
+        if stream.read_exact(&mut len).is_err() {
+            return;
+        }
+        let len = Reader::new(&len).u32().unwrap_or(0);
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return;
+        }
+        let mut message = vec![0; len as usize];
+        if stream.read_exact(&mut message).is_err() {
+            return;
+        }
+        let response = handle(&message, identities, confirm).unwrap_or_else(|_| vec![FAILURE]);
+        let mut framed = Vec::with_capacity(response.len() + 4);
+        wire::put_string(&mut framed, &response);
+        if stream.write_all(&framed).is_err() {
+            return;
+        }
+    }
+}
+
+/// Answers a request, failing for those the agent does not support.
+pub fn handle(message: &[u8], identities: &[Identity], confirm: bool) -> IoResult<Vec<u8>> {
+    let mut reader = Reader::new(message);
+    match try!(reader.u8()) {
+        REQUEST_IDENTITIES => {
+            let mut response = vec![IDENTITIES_ANSWER];
+            wire::put_u32(&mut response, identities.len() as u32);
+            for identity in identities {
+                wire::put_string(&mut response, &identity.blob);
+                wire::put_string(&mut response, identity.comment.as_bytes());
+            }
+            Ok(response)
+        }
+        SIGN_REQUEST => {
+            let blob = try!(reader.string());
+            let data = try!(reader.string());
+            let flags = try!(reader.u32());
+            let identity = try!(identities.iter()
+                .find(|identity| identity.blob == blob)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such key")));
+            if confirm && !confirm_use(identity) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "use not allowed"));
+            }
+            let signature = try!(identity.key.sign(data, flags));
+            let mut response = vec![SIGN_RESPONSE];
+            wire::put_string(&mut response, &signature);
+            Ok(response)
+        }
+        _ => Ok(vec![FAILURE]),
+    }
+}
+
+/// Asks whether to sign with `identity` through `$SSH_ASKPASS`, or `ssh-askpass` if it is not
+/// set, as `ssh-agent` does for keys added with `ssh-add -c`.
+fn confirm_use(identity: &Identity) -> bool {
+    let askpass = env::var("SSH_ASKPASS").unwrap_or_else(|_| "ssh-askpass".to_string());
+    Command::new(askpass)
+        .arg(format!("Allow use of key {}?", identity.comment))
+        .env("SSH_ASKPASS_PROMPT", "confirm")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use ssh_agent::key::PrivateKey;
+    use ssh_agent::wire::{self, Reader};
+    use super::{Identity, handle};
+
+    fn identity(name: &str) -> Identity {
+        let mut text = String::new();
+        File::open(format!("test/ssh/{}", name)).unwrap().read_to_string(&mut text).unwrap();
+        Identity::new(PrivateKey::parse(&text).unwrap(), name)
+    }
+
+    #[test]
+    fn test_handle() {
+        let identities = vec![identity("id_ed25519"), identity("id_rsa")];
+
+        let response = handle(&[11], &identities, false).unwrap();
+        let mut reader = Reader::new(&response);
+        assert_eq!(reader.u8().unwrap(), 12);
+        assert_eq!(reader.u32().unwrap(), 2);
+        assert_eq!(reader.string().unwrap(), &identities[0].blob[..]);
+        assert_eq!(reader.string().unwrap(), b"id_ed25519");
+        assert_eq!(reader.string().unwrap(), &identities[1].blob[..]);
+        assert_eq!(reader.string().unwrap(), b"id_rsa");
+        assert!(reader.is_empty());
+
+        let mut request = vec![13];
+        wire::put_string(&mut request, &identities[1].blob);
+        wire::put_string(&mut request, b"data");
+        wire::put_u32(&mut request, 4);
+        let response = handle(&request, &identities, false).unwrap();
+        let mut reader = Reader::new(&response);
+        assert_eq!(reader.u8().unwrap(), 14);
+        let mut signature = Reader::new(reader.string().unwrap());
+        assert_eq!(signature.string().unwrap(), b"rsa-sha2-512");
+        assert_eq!(signature.string().unwrap().len(), 256);
+
+        // SHA-1 signatures are refused rather than made.
+        let mut request = vec![13];
+        wire::put_string(&mut request, &identities[1].blob);
+        wire::put_string(&mut request, b"data");
+        wire::put_u32(&mut request, 0);
+        assert!(handle(&request, &identities, false).is_err());
+
+        // Keys cannot be added through the agent, and unknown keys cannot sign.
+        assert_eq!(handle(&[17], &identities, false).unwrap(), vec![5]);
+        let mut request = vec![13];
+        wire::put_string(&mut request, b"unknown");
+        wire::put_string(&mut request, b"data");
+        wire::put_u32(&mut request, 0);
+        assert!(handle(&request, &identities, false).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! RSA signatures with PKCS #1 v1.5 padding, made by ring.
+
+use openssl::crypto::hash;
+use ring::rand::SystemRandom;
+use ring::rsa::{KeyPair, KeyPairComponents, PublicKeyComponents};
+use ring::signature;
+use std::io::{self, Result as IoResult};
+
+use ssh_agent::bignum::BigUint;
+
+/// The sizes of moduli ring signs with.
+const MIN_BITS: usize = 2048;
+const MAX_BITS: usize = 4096;
+
+pub struct RsaKey {
+    pub n: BigUint,
+    pub e: BigUint,
+    key_pair: KeyPair,
+}
+
+impl RsaKey {
+    /// Builds a key from its modulus, exponents, primes and `q_inv`, the inverse of `q` modulo
+    /// `p`, deriving the other exponents the Chinese remainder theorem needs.
+    pub fn new(n: BigUint,
+               e: BigUint,
+               d: BigUint,
+               p: BigUint,
+               q: BigUint,
+               q_inv: BigUint)
+               -> IoResult<RsaKey> {
+        if n.bits() < MIN_BITS || n.bits() > MAX_BITS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{}-bit RSA keys are not supported", n.bits())));
+        }
+        let one = BigUint::from_u32(1);
+        if p <= one || q <= one {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed RSA key"));
+        }
+        let d_p = d.rem(&p.sub(&one));
+        let d_q = d.rem(&q.sub(&one));
+        let key_pair = try!(KeyPair::from_components(&KeyPairComponents {
+                public_key: PublicKeyComponents {
+                    n: n.to_bytes_be(),
+                    e: e.to_bytes_be(),
+                },
+                d: d.to_bytes_be(),
+                p: p.to_bytes_be(),
+                q: q.to_bytes_be(),
+                dP: d_p.to_bytes_be(),
+                dQ: d_q.to_bytes_be(),
+                qInv: q_inv.to_bytes_be(),
+            })
+            .map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("unusable RSA key: {}", e))
+            }));
+        Ok(RsaKey {
+            n: n,
+            e: e,
+            key_pair: key_pair,
+        })
+    }
+
+    /// Signs `message` hashed with `hash_type`, which must be SHA-256 or SHA-512.
+    pub fn sign(&self, hash_type: hash::Type, message: &[u8]) -> IoResult<Vec<u8>> {
+        let padding = match hash_type {
+            hash::Type::SHA256 => &signature::RSA_PKCS1_SHA256,
+            hash::Type::SHA512 => &signature::RSA_PKCS1_SHA512,
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("{:?} RSA signatures are not supported",
+                                                  hash_type)))
+            }
+        };
+        let mut signature = vec![0; self.key_pair.public().modulus_len()];
+        try!(self.key_pair
+            .sign(padding, &SystemRandom::new(), message, &mut signature)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "RSA signing failed")));
+        Ok(signature)
+    }
+}
@@ -0,0 +1,90 @@
+//! The data types of the SSH wire format, RFC 4251 section 5.
+
+use std::io::{self, Result as IoResult};
+
+use ssh_agent::bignum::BigUint;
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> IoResult<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message is truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> IoResult<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    pub fn u32(&mut self) -> IoResult<u32> {
+        self.bytes(4).map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 |
+                              b[3] as u32)
+    }
+
+    pub fn string(&mut self) -> IoResult<&'a [u8]> {
+        let len = try!(self.u32());
+        self.bytes(len as usize)
+    }
+
+    /// Reads a non-negative `mpint`.
+    pub fn mpint(&mut self) -> IoResult<BigUint> {
+        let bytes = try!(self.string());
+        if bytes.first().map(|&b| b & 0x80 != 0) == Some(true) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "negative mpint"));
+        }
+        Ok(BigUint::from_bytes_be(bytes))
+    }
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+}
+
+pub fn put_string(buf: &mut Vec<u8>, s: &[u8]) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s);
+}
+
+pub fn put_mpint(buf: &mut Vec<u8>, n: &BigUint) {
+    let mut bytes = n.to_bytes_be();
+    // A set top bit would make it negative.
+    if bytes.first().map(|&b| b & 0x80 != 0) == Some(true) {
+        bytes.insert(0, 0);
+    }
+    put_string(buf, &bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_agent::bignum::BigUint;
+    use super::{Reader, put_mpint, put_string};
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = Vec::new();
+        put_string(&mut buf, b"ssh-rsa");
+        put_mpint(&mut buf, &BigUint::from_u32(0x80));
+        put_mpint(&mut buf, &BigUint::zero());
+        assert_eq!(buf, b"\0\0\0\x07ssh-rsa\0\0\0\x02\0\x80\0\0\0\0".to_vec());
+        let mut reader = Reader::new(&buf);
+        assert_eq!(reader.string().unwrap(), b"ssh-rsa");
+        assert_eq!(reader.mpint().unwrap(), BigUint::from_u32(0x80));
+        assert_eq!(reader.mpint().unwrap(), BigUint::zero());
+        assert!(reader.is_empty());
+        assert!(reader.u32().is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Helpers shared by the tests of several modules.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use op_vault::uuid;
+use op_vault::vault::{LockedVault, Vault};
+
+pub const SAMPLE_VAULT_PATH: &'static str = "test/SampleVault.opvault";
+pub const SAMPLE_VAULT_PASSWORD: &'static str = "freddy";
+
+/// Unlocks the sample vault that ships with the repository.
+pub fn sample_vault() -> Vault {
+    LockedVault::new(SAMPLE_VAULT_PATH).unwrap().unlock(SAMPLE_VAULT_PASSWORD).unwrap()
+}
+
+/// Copies the sample vault into a fresh temporary directory so that tests can modify it.
+pub fn copy_sample_vault(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("decone-test-{}-{}.opvault", name, uuid::generate()));
+    fs::create_dir_all(path.join("default")).unwrap();
+    for entry in fs::read_dir(PathBuf::from(SAMPLE_VAULT_PATH).join("default")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), path.join("default").join(entry.file_name())).unwrap();
+    }
+    path
+}
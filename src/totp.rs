@@ -0,0 +1,250 @@
+//! One-time passwords as described in RFC 4226 (HOTP) and RFC 6238 (TOTP).
+
+use openssl::crypto::{hash, hmac};
+use std::io::{self, Result as IoResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match &name.to_uppercase()[..] {
+            "SHA1" => Some(Algorithm::Sha1),
+            "SHA256" => Some(Algorithm::Sha256),
+            "SHA512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hash_type(&self) -> hash::Type {
+        match *self {
+            Algorithm::Sha1 => hash::Type::SHA1,
+            Algorithm::Sha256 => hash::Type::SHA256,
+            Algorithm::Sha512 => hash::Type::SHA512,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Time-based, changing every `period` seconds.
+    Totp { period: u64 },
+    /// Counter-based.
+    Hotp { counter: u64 },
+}
+
+/// A one-time password secret with the parameters for generating codes from it.
+#[derive(Clone, Debug)]
+pub struct Otp {
+    pub kind: Kind,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub secret: Vec<u8>,
+}
+
+impl Otp {
+    /// Parses an `otpauth://` URI or a bare base32 secret, which gets the usual parameters of
+    /// 6-digit SHA-1 codes every 30 seconds.
+    pub fn parse(s: &str) -> IoResult<Otp> {
+        let s = s.trim();
+        if !s.to_lowercase().starts_with("otpauth://") {
+            let secret = try!(base32_decode(s)
+                .ok_or_else(|| invalid_input("invalid base32 secret")));
+            return Ok(Otp {
+                kind: Kind::Totp { period: 30 },
+                algorithm: Algorithm::Sha1,
+                digits: 6,
+                secret: secret,
+            });
+        }
+
+        let rest = &s["otpauth://".len()..];
+        let (kind, query) = match rest.find('?') {
+            Some(i) => (&rest[..rest.find('/').unwrap_or(i)], &rest[i + 1..]),
+            None => return Err(invalid_input("otpauth URI has no secret")),
+        };
+        let mut secret = None;
+        let mut algorithm = Algorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        let mut counter = 0;
+        for pair in query.split('&') {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("").to_lowercase();
+            let value = percent_decode(parts.next().unwrap_or(""));
+            match &key[..] {
+                "secret" => {
+                    secret = Some(try!(base32_decode(&value)
+                        .ok_or_else(|| invalid_input("invalid base32 secret"))));
+                }
+                "algorithm" => {
+                    algorithm = try!(Algorithm::from_name(&value)
+                        .ok_or_else(|| invalid_input("unknown algorithm")));
+                }
+                "digits" => {
+                    digits = match value.parse() {
+                        Ok(digits) if digits >= 1 && digits <= 9 => digits,
+                        _ => return Err(invalid_input("invalid number of digits")),
+                    };
+                }
+                "period" => {
+                    period = match value.parse() {
+                        Ok(period) if period > 0 => period,
+                        _ => return Err(invalid_input("invalid period")),
+                    };
+                }
+                "counter" => {
+                    counter = try!(value.parse().map_err(|_| invalid_input("invalid counter")));
+                }
+                _ => {}
+            }
+        }
+        let kind = match &kind.to_lowercase()[..] {
+            "totp" => Kind::Totp { period: period },
+            "hotp" => Kind::Hotp { counter: counter },
+            _ => return Err(invalid_input("otpauth URI is neither totp nor hotp")),
+        };
+        Ok(Otp {
+            kind: kind,
+            algorithm: algorithm,
+            digits: digits,
+            secret: try!(secret.ok_or_else(|| invalid_input("otpauth URI has no secret"))),
+        })
+    }
+
+    /// Returns the code at Unix time `time`, or the code for the counter of an HOTP secret.
+    pub fn code(&self, time: u64) -> String {
+        let counter = match self.kind {
+            Kind::Totp { period } => time / period,
+            Kind::Hotp { counter } => counter,
+        };
+        hotp(&self.secret, counter, self.digits, self.algorithm)
+    }
+
+    /// Returns how many seconds the code at `time` stays valid, or `None` for HOTP.
+    pub fn remaining(&self, time: u64) -> Option<u64> {
+        match self.kind {
+            Kind::Totp { period } => Some(period - time % period),
+            Kind::Hotp { .. } => None,
+        }
+    }
+}
+
+/// Computes the RFC 4226 code for `counter`, zero-padded to `digits` digits.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> String {
+    let mut message = [0u8; 8];
+    for (i, byte) in message.iter_mut().enumerate() {
+        *byte = (counter >> (8 * (7 - i))) as u8;
+    }
+    let mac = hmac::hmac(algorithm.hash_type(), secret, &message);
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let binary = ((mac[offset] as u32 & 0x7f) << 24) | ((mac[offset + 1] as u32) << 16) |
+                 ((mac[offset + 2] as u32) << 8) | (mac[offset + 3] as u32);
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+/// Decodes RFC 4648 base32, ignoring case, padding, spaces and hyphens as authenticator apps do.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.chars() {
+        let value = if c >= 'A' && c <= 'Z' {
+            c as u32 - 'A' as u32
+        } else if c >= 'a' && c <= 'z' {
+            c as u32 - 'a' as u32
+        } else if c >= '2' && c <= '7' {
+            c as u32 - '2' as u32 + 26
+        } else if c == ' ' || c == '-' || c == '=' {
+            continue;
+        } else {
+            return None;
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.is_empty() { None } else { Some(bytes) }
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let hex = String::from_utf8_lossy(&bytes[i + 1..i + 3]);
+            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, Kind, Otp, base32_decode, hotp};
+
+    #[test]
+    fn test_hotp() {
+        // RFC 4226, Appendix D.
+        let secret = b"12345678901234567890";
+        let codes = ["755224", "287082", "359152", "969429", "338314"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp(secret, counter as u64, 6, Algorithm::Sha1), *code);
+        }
+    }
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238, Appendix B.
+        let uris = ["otpauth://totp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8",
+                    "otpauth://totp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA\
+                     &digits=8&algorithm=SHA256",
+                    "otpauth://totp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZD\
+                     GNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA\
+                     &digits=8&algorithm=SHA512"];
+        let expected = [("94287082", "07081804"),
+                        ("46119246", "68084774"),
+                        ("90693936", "25091201")];
+        for (uri, &(at_59, at_1111111109)) in uris.iter().zip(expected.iter()) {
+            let otp = Otp::parse(uri).unwrap();
+            assert_eq!(otp.code(59), at_59);
+            assert_eq!(otp.code(1111111109), at_1111111109);
+        }
+        assert_eq!(Otp::parse(uris[0]).unwrap().remaining(59), Some(1));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(base32_decode("jbsw y3dp ehpk 3pxp").unwrap(), b"Hello!\xde\xad\xbe\xef");
+        let otp = Otp::parse("JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(otp.kind, Kind::Totp { period: 30 });
+        assert_eq!(otp.digits, 6);
+        let otp = Otp::parse("otpauth://hotp/Example:alice%40example.com?\
+                              secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1&issuer=Example")
+            .unwrap();
+        assert_eq!(otp.kind, Kind::Hotp { counter: 1 });
+        assert_eq!(otp.code(0), "287082");
+        assert!(Otp::parse("otpauth://totp/x?digits=6").is_err());
+        assert!(Otp::parse("not base32!").is_err());
+    }
+}
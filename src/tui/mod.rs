@@ -0,0 +1,680 @@
+//! A full-screen interface for looking things up in a vault: the folder tree on the left, the
+//! items of the selected folder in the middle and the details of the selected item on the right.
+
+pub mod terminal;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::env;
+use std::io::Result as IoResult;
+use std::time::{Duration, Instant};
+
+use iso8601;
+use op_vault::folder::Folder;
+use op_vault::item::Item;
+use op_vault::vault::Vault;
+use search::Query;
+use self::terminal::{Key, Terminal};
+use totp::Otp;
+use util::now;
+
+/// How often the screen is redrawn without input, for one-time passwords and resizes.
+const TICK_SECS: u64 = 1;
+
+/// Shown in place of concealed values.
+const CONCEALED: &'static str = "••••••••";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pane {
+    Folders,
+    Items,
+    Details,
+}
+
+struct FolderEntry {
+    /// `None` for the entry listing all items.
+    uuid: Option<String>,
+    title: String,
+    depth: usize,
+}
+
+enum FieldValue {
+    Text(String),
+    /// Shown as the current code.
+    Otp(Otp),
+}
+
+struct Field {
+    label: String,
+    value: FieldValue,
+    concealed: bool,
+}
+
+impl Field {
+    fn text(label: &str, value: &str, concealed: bool) -> Field {
+        Field {
+            label: label.to_string(),
+            value: FieldValue::Text(value.to_string()),
+            concealed: concealed,
+        }
+    }
+
+    fn value(&self, time: u64) -> String {
+        match self.value {
+            FieldValue::Text(ref s) => s.clone(),
+            FieldValue::Otp(ref otp) => otp.code(time),
+        }
+    }
+}
+
+/// The selection of a list and the first row shown.
+#[derive(Default)]
+struct ListState {
+    selected: usize,
+    offset: usize,
+}
+
+impl ListState {
+    fn move_by(&mut self, delta: isize, len: usize) {
+        let selected = self.selected as isize + delta;
+        self.selected = cmp::max(0, cmp::min(selected, len as isize - 1)) as usize;
+    }
+
+    /// Scrolls so that `row` is among the `height` rows shown.
+    fn scroll_to(&mut self, row: usize, height: usize) {
+        if row < self.offset {
+            self.offset = row;
+        } else if height > 0 && row >= self.offset + height {
+            self.offset = row + 1 - height;
+        }
+    }
+}
+
+pub struct App {
+    vault: Vault,
+    folders: Vec<Folder>,
+    folder_entries: Vec<FolderEntry>,
+    /// Items outside the trash ordered by title.
+    items: Vec<Item>,
+    /// Indices of the items shown.
+    visible: Vec<usize>,
+    /// The UUID of the item `fields` belong to.
+    fields_uuid: Option<String>,
+    fields: Vec<Field>,
+    /// Indices of the concealed fields shown.
+    revealed: HashSet<usize>,
+    pane: Pane,
+    folder_list: ListState,
+    item_list: ListState,
+    field_list: ListState,
+    query: String,
+    searching: bool,
+    status: String,
+    /// Text to put on the clipboard at the next draw.
+    clipboard: Option<String>,
+    /// Rows of a list shown at the last draw, for paging.
+    page: usize,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(vault: Vault) -> App {
+        let folders: Vec<Folder> = vault.folders().into_iter().filter(|f| !f.is_smart()).collect();
+        let mut folder_entries = vec![FolderEntry {
+                                          uuid: None,
+                                          title: "All Items".to_string(),
+                                          depth: 0,
+                                      }];
+        add_folder_entries(&folders, None, 1, &mut folder_entries);
+        let mut items: Vec<Item> = vault.items().into_iter().filter(|i| !i.is_trashed()).collect();
+        items.sort_by_key(|item| item.title().unwrap_or("").to_lowercase());
+        let mut app = App {
+            vault: vault,
+            folders: folders,
+            folder_entries: folder_entries,
+            items: items,
+            visible: Vec::new(),
+            fields_uuid: None,
+            fields: Vec::new(),
+            revealed: HashSet::new(),
+            pane: Pane::Items,
+            folder_list: ListState::default(),
+            item_list: ListState::default(),
+            field_list: ListState::default(),
+            query: String::new(),
+            searching: false,
+            status: String::new(),
+            clipboard: None,
+            page: 10,
+            quit: false,
+        };
+        app.filter();
+        app
+    }
+
+    /// Updates the items shown for the selected folder and the search query, the best matches
+    /// first.
+    fn filter(&mut self) {
+        // A query being typed may not parse yet, such as one with an open quote.
+        let query = match Query::parse(&self.query) {
+            Ok(query) => query,
+            Err(_) => return,
+        };
+        let folders = self.selected_folders();
+        let mut scored: Vec<(u32, usize)> = self.items
+            .iter()
+            .enumerate()
+            .filter(|&(_, item)| {
+                folders.as_ref().map_or(true, |folders| {
+                    item.folder().map_or(false, |uuid| folders.contains(uuid))
+                })
+            })
+            .filter_map(|(i, item)| query.score(item, &self.folders).map(|score| (score, i)))
+            .collect();
+        // Items are ordered by title, which breaks ties.
+        scored.sort_by(|a, b| (b.0, a.1).cmp(&(a.0, b.1)));
+        self.visible = scored.into_iter().map(|(_, i)| i).collect();
+        self.item_list = ListState::default();
+        self.load_fields();
+    }
+
+    /// Returns the UUIDs of the selected folder and the folders inside it, or `None` if all
+    /// items are to be shown.
+    fn selected_folders(&self) -> Option<HashSet<String>> {
+        let i = self.folder_list.selected;
+        let entry = &self.folder_entries[i];
+        entry.uuid.as_ref().map(|uuid| {
+            let mut uuids = HashSet::new();
+            uuids.insert(uuid.clone());
+            for entry in self.folder_entries[i + 1..].iter().take_while(|e| e.depth > entry.depth) {
+                if let Some(ref uuid) = entry.uuid {
+                    uuids.insert(uuid.clone());
+                }
+            }
+            uuids
+        })
+    }
+
+    fn selected_item(&self) -> Option<&Item> {
+        self.visible.get(self.item_list.selected).map(|&i| &self.items[i])
+    }
+
+    /// Decrypts the details of the selected item unless they are already shown.
+    fn load_fields(&mut self) {
+        let uuid = self.selected_item().map(|item| item.uuid().to_string());
+        if uuid == self.fields_uuid {
+            return;
+        }
+        self.fields = match self.selected_item() {
+            Some(item) => item_fields(&self.vault, item),
+            None => Vec::new(),
+        };
+        self.fields_uuid = uuid;
+        self.revealed.clear();
+        self.field_list = ListState::default();
+    }
+
+    pub fn handle_key(&mut self, key: Key) {
+        self.status.clear();
+        if self.searching {
+            match key {
+                Key::Enter | Key::Down | Key::Tab => self.searching = false,
+                Key::Escape => {
+                    self.searching = false;
+                    self.query.clear();
+                    self.filter();
+                }
+                Key::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                Key::Ctrl('u') => {
+                    self.query.clear();
+                    self.filter();
+                }
+                Key::Ctrl('c') => self.quit = true,
+                Key::Char(c) => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => {}
+            }
+            return;
+        }
+        let page = self.page as isize;
+        match key {
+            Key::Ctrl('c') | Key::Char('q') => self.quit = true,
+            Key::Char('/') => {
+                self.searching = true;
+                self.pane = Pane::Items;
+            }
+            Key::Tab | Key::Right | Key::Char('l') => {
+                self.pane = match self.pane {
+                    Pane::Folders => Pane::Items,
+                    _ => Pane::Details,
+                };
+            }
+            Key::BackTab | Key::Left | Key::Char('h') => {
+                self.pane = match self.pane {
+                    Pane::Details => Pane::Items,
+                    _ => Pane::Folders,
+                };
+            }
+            Key::Enter => {
+                match self.pane {
+                    Pane::Folders => self.pane = Pane::Items,
+                    Pane::Items => self.pane = Pane::Details,
+                    Pane::Details => self.toggle_reveal(),
+                }
+            }
+            Key::Escape => {
+                if !self.query.is_empty() {
+                    self.query.clear();
+                    self.filter();
+                } else if self.pane == Pane::Details {
+                    self.pane = Pane::Items;
+                }
+            }
+            Key::Char('r') | Key::Char(' ') if self.pane == Pane::Details => self.toggle_reveal(),
+            Key::Char('c') | Key::Char('y') => self.copy_selected(),
+            Key::Char('u') if self.pane == Pane::Items => self.copy_username(),
+            Key::Up | Key::Char('k') => self.move_selection(-1),
+            Key::Down | Key::Char('j') => self.move_selection(1),
+            Key::PageUp => self.move_selection(-page),
+            Key::PageDown => self.move_selection(page),
+            Key::Home | Key::Char('g') => self.move_selection(-isize::max_value() / 2),
+            Key::End | Key::Char('G') => self.move_selection(isize::max_value() / 2),
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        match self.pane {
+            Pane::Folders => {
+                let selected = self.folder_list.selected;
+                self.folder_list.move_by(delta, self.folder_entries.len());
+                if self.folder_list.selected != selected {
+                    self.filter();
+                }
+            }
+            Pane::Items => {
+                self.item_list.move_by(delta, self.visible.len());
+                self.load_fields();
+            }
+            Pane::Details => self.field_list.move_by(delta, self.fields.len()),
+        }
+    }
+
+    fn toggle_reveal(&mut self) {
+        let i = self.field_list.selected;
+        if !self.fields.get(i).map_or(false, |f| f.concealed) {
+            return;
+        }
+        if !self.revealed.remove(&i) {
+            self.revealed.insert(i);
+        }
+    }
+
+    /// Copies the selected field, or the password of the selected item outside the details.
+    fn copy_selected(&mut self) {
+        let field = if self.pane == Pane::Details {
+            self.fields.get(self.field_list.selected)
+        } else {
+            self.fields.iter().find(|f| f.concealed && f.label == "password")
+        };
+        match field.map(|f| (f.label.clone(), f.value(now()))) {
+            Some((label, value)) => {
+                self.clipboard = Some(value);
+                self.status = format!("Copied {} to the clipboard", label);
+            }
+            None => self.status = "Nothing to copy".to_string(),
+        }
+    }
+
+    fn copy_username(&mut self) {
+        match self.fields.iter().find(|f| f.label == "username").map(|f| f.value(now())) {
+            Some(value) => {
+                self.clipboard = Some(value);
+                self.status = "Copied username to the clipboard".to_string();
+            }
+            None => self.status = "No username".to_string(),
+        }
+    }
+
+    /// Returns the screen of `rows` by `cols` as what to write to the terminal.
+    pub fn render(&mut self, rows: usize, cols: usize, time: u64) -> String {
+        let folders_width = cmp::min(cols, cmp::max(14, cols / 5));
+        let items_width = cmp::min(cols - folders_width, cmp::max(20, (cols - folders_width) / 3));
+        let details_width = cols.saturating_sub(folders_width + items_width + 2);
+        let height = rows.saturating_sub(3);
+        self.page = cmp::max(1, height);
+
+        let folder_rows: Vec<String> = self.folder_entries
+            .iter()
+            .map(|entry| format!("{}{}", "  ".repeat(entry.depth.saturating_sub(1)), entry.title))
+            .collect();
+        let item_rows: Vec<String> = self.visible
+            .iter()
+            .map(|&i| {
+                let item = &self.items[i];
+                format!("{}  {}", item.title().unwrap_or(""), item.ainfo().unwrap_or(""))
+            })
+            .collect();
+        let (detail_rows, selected_rows) = self.detail_rows(details_width, time);
+        self.folder_list.scroll_to(self.folder_list.selected, height);
+        self.item_list.scroll_to(self.item_list.selected, height);
+        if let Some(&(first, last)) = selected_rows.get(self.field_list.selected) {
+            self.field_list.scroll_to(last, height);
+            self.field_list.scroll_to(first, height);
+        }
+
+        let mut screen = String::from("\x1b[H");
+        let header = format!(" decone  {}", self.vault.name());
+        screen.push_str(&styled("\x1b[7m", &header, cols));
+        screen.push_str("\r\n");
+        let title = |pane, title: &str, width| {
+            styled(if self.pane == pane { "\x1b[1;4m" } else { "\x1b[1m" }, title, width)
+        };
+        screen.push_str(&title(Pane::Folders, "Folders", folders_width));
+        screen.push('│');
+        let items_title = format!("Items ({})", self.visible.len());
+        screen.push_str(&title(Pane::Items, &items_title, items_width));
+        screen.push('│');
+        screen.push_str(&title(Pane::Details, "Details", details_width));
+        for row in 0..height {
+            screen.push_str("\r\n");
+            let folder = row + self.folder_list.offset;
+            screen.push_str(&list_cell(&folder_rows,
+                                       folder,
+                                       folder == self.folder_list.selected,
+                                       self.pane == Pane::Folders,
+                                       folders_width));
+            screen.push('│');
+            let item = row + self.item_list.offset;
+            screen.push_str(&list_cell(&item_rows,
+                                       item,
+                                       item == self.item_list.selected,
+                                       self.pane == Pane::Items,
+                                       items_width));
+            screen.push('│');
+            let detail = row + self.field_list.offset;
+            let selected = selected_rows.get(self.field_list.selected)
+                .map_or(false, |&(first, last)| detail >= first && detail <= last);
+            screen.push_str(&list_cell(&detail_rows,
+                                       detail,
+                                       selected,
+                                       self.pane == Pane::Details,
+                                       details_width));
+        }
+        screen.push_str("\r\n");
+        let footer = if self.searching {
+            format!("/{}▏", self.query)
+        } else if !self.status.is_empty() {
+            self.status.clone()
+        } else if !self.query.is_empty() {
+            format!("/{}  (Esc to clear)", self.query)
+        } else {
+            "Tab pane  / search  Enter open  r reveal  c copy  u username  q quit".to_string()
+        };
+        screen.push_str(&styled("\x1b[2m", &footer, cols));
+        screen
+    }
+
+    /// Returns the rows of the details pane and the first and last rows of each field.
+    fn detail_rows(&self, width: usize, time: u64) -> (Vec<String>, Vec<(usize, usize)>) {
+        let item = match self.selected_item() {
+            Some(item) => item,
+            None => return (Vec::new(), Vec::new()),
+        };
+        let folder = item.folder()
+            .and_then(|uuid| self.folders.iter().find(|f| f.uuid() == uuid))
+            .map(|folder| format!(" in {}", folder.path(&self.folders)))
+            .unwrap_or_else(String::new);
+        let mut rows = vec![item.title().unwrap_or("").to_string(),
+                            format!("{}{}", item.category().name(), folder),
+                            String::new()];
+        let label_width = cmp::min(18, width / 3);
+        let mut field_rows = Vec::new();
+        for (i, field) in self.fields.iter().enumerate() {
+            let value = if field.concealed && !self.revealed.contains(&i) {
+                CONCEALED.to_string()
+            } else {
+                let mut value = field.value(time);
+                if let FieldValue::Otp(ref otp) = field.value {
+                    if let Some(remaining) = otp.remaining(time) {
+                        value.push_str(&format!("  ({}s)", remaining));
+                    }
+                }
+                value
+            };
+            let first = rows.len();
+            for (j, line) in value.lines().enumerate() {
+                let label = if j == 0 { &field.label[..] } else { "" };
+                rows.push(format!("{} {}", fit(label, label_width), line));
+            }
+            if rows.len() == first {
+                rows.push(fit(&field.label, label_width));
+            }
+            field_rows.push((first, rows.len() - 1));
+        }
+        (rows, field_rows)
+    }
+}
+
+/// Lists the folders whose parent is `parent` and, after each, the folders inside it.
+fn add_folder_entries(folders: &[Folder],
+                      parent: Option<&str>,
+                      depth: usize,
+                      entries: &mut Vec<FolderEntry>) {
+    let mut children: Vec<&Folder> = folders.iter()
+        .filter(|folder| {
+            match (folder.parent(), parent) {
+                (Some(p), Some(parent)) => p == parent,
+                // Folders whose parent is missing are shown at the top.
+                (Some(p), None) => !folders.iter().any(|f| f.uuid() == p),
+                (None, parent) => parent.is_none(),
+            }
+        })
+        .collect();
+    children.sort_by_key(|folder| folder.title().to_lowercase());
+    for folder in children {
+        // Parents that form a cycle would otherwise never end.
+        if depth > folders.len() {
+            return;
+        }
+        entries.push(FolderEntry {
+            uuid: Some(folder.uuid().to_string()),
+            title: folder.title().to_string(),
+            depth: depth,
+        });
+        add_folder_entries(folders, Some(folder.uuid()), depth + 1, entries);
+    }
+}
+
+fn item_fields(vault: &Vault, item: &Item) -> Vec<Field> {
+    let mut fields: Vec<Field> = item.urls()
+        .iter()
+        .map(|url| Field::text("url", url, false))
+        .collect();
+    let details = match vault.item_details(item) {
+        Some(details) => details,
+        None => {
+            fields.push(Field::text("error", "could not decrypt the details", false));
+            return fields;
+        }
+    };
+    for field in details.fields() {
+        if field.value().is_empty() {
+            continue;
+        }
+        let label = field.designation().unwrap_or(field.name());
+        fields.push(Field::text(label, field.value(), field.kind() == "P"));
+    }
+    if let Some(password) = details.value().find("password").and_then(|v| v.as_string()) {
+        fields.push(Field::text("password", password, true));
+    }
+    for section in details.sections() {
+        for field in section.fields() {
+            let value = match field.value_string() {
+                Some(value) => value,
+                None => continue,
+            };
+            let label = if field.title().is_empty() { field.name() } else { field.title() };
+            let otp = if field.name().starts_with("TOTP_") || value.starts_with("otpauth://") {
+                Otp::parse(&value).ok()
+            } else {
+                None
+            };
+            fields.push(match otp {
+                Some(otp) => {
+                    Field {
+                        label: label.to_string(),
+                        value: FieldValue::Otp(otp),
+                        concealed: false,
+                    }
+                }
+                None => Field::text(label, &value, field.is_concealed()),
+            });
+        }
+    }
+    if let Some(notes) = details.notes() {
+        fields.push(Field::text("notes", notes, false));
+    }
+    let tags = item.tags();
+    if !tags.is_empty() {
+        fields.push(Field::text("tags", &tags.join(", "), false));
+    }
+    fields.push(Field::text("updated", &iso8601::format(item.updated()), false));
+    fields
+}
+
+fn list_cell(rows: &[String], row: usize, selected: bool, focused: bool, width: usize) -> String {
+    let text = rows.get(row).map(|s| &s[..]).unwrap_or("");
+    if row < rows.len() && selected {
+        styled(if focused { "\x1b[7m" } else { "\x1b[1m" }, text, width)
+    } else {
+        fit(text, width)
+    }
+}
+
+fn styled(style: &str, text: &str, width: usize) -> String {
+    format!("{}{}\x1b[0m", style, fit(text, width))
+}
+
+/// Truncates or pads `s` to `width` columns. Control characters become spaces, so that the
+/// contents of the vault cannot send escape sequences to the terminal.
+fn fit(s: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let c = if c.is_control() { ' ' } else { c };
+        let w = char_width(c);
+        if used + w > width {
+            break;
+        }
+        fitted.push(c);
+        used += w;
+    }
+    for _ in used..width {
+        fitted.push(' ');
+    }
+    fitted
+}
+
+/// Returns 2 for the East Asian wide characters and emoji that take two columns, 1 otherwise.
+fn char_width(c: char) -> usize {
+    let c = c as u32;
+    let wide = (c >= 0x1100 && c <= 0x115f) || (c >= 0x2e80 && c <= 0xa4cf) ||
+               (c >= 0xac00 && c <= 0xd7a3) || (c >= 0xf900 && c <= 0xfaff) ||
+               (c >= 0xfe30 && c <= 0xfe4f) || (c >= 0xff00 && c <= 0xff60) ||
+               (c >= 0xffe0 && c <= 0xffe6) || (c >= 0x1f300 && c <= 0x1f64f) ||
+               (c >= 0x1f900 && c <= 0x1f9ff) || (c >= 0x20000 && c <= 0x3fffd);
+    if wide { 2 } else { 1 }
+}
+
+/// Shows the interface until the user quits, returning `true` if it locked itself after
+/// `idle_timeout` without a key press instead. The vault is locked when this returns.
+pub fn run(vault: Vault, idle_timeout: Duration) -> IoResult<bool> {
+    let mut app = App::new(vault);
+    let mut terminal = try!(Terminal::open());
+    let tmux = env::var_os("TMUX").is_some();
+    let mut last_key = Instant::now();
+    loop {
+        if app.quit {
+            return Ok(false);
+        }
+        let (rows, cols) = terminal.size();
+        try!(terminal.write(&app.render(rows, cols, now())));
+        if let Some(text) = app.clipboard.take() {
+            try!(terminal.write(&terminal::osc52(&text, tmux)));
+        }
+        let elapsed = last_key.elapsed();
+        if elapsed >= idle_timeout {
+            return Ok(true);
+        }
+        let wait = cmp::min(idle_timeout - elapsed, Duration::from_secs(TICK_SECS));
+        if let Some(key) = try!(terminal.read_key(wait)) {
+            last_key = Instant::now();
+            app.handle_key(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::sample_vault;
+    use super::{fit, App, Pane, CONCEALED};
+    use super::terminal::Key;
+
+    fn type_keys(app: &mut App, keys: &[Key]) {
+        for &key in keys {
+            app.handle_key(key);
+        }
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcdef", 3), "abc");
+        assert_eq!(fit("日本語", 5), "日本 ");
+        assert_eq!(fit("a\x1b[2Jb", 6), "a [2Jb");
+    }
+
+    #[test]
+    fn test_app() {
+        let mut app = App::new(sample_vault());
+        let all = app.visible.len();
+        assert!(all > 1);
+
+        type_keys(&mut app, &[Key::Char('/'), Key::Char('h'), Key::Char('U'), Key::Char('l')]);
+        assert!(app.searching);
+        assert!(app.visible.len() < all);
+        assert_eq!(app.selected_item().and_then(|item| item.title()), Some("Hulu"));
+        let screen = app.render(24, 120, 0);
+        assert!(screen.contains("wendy@appleseed.com"));
+        assert!(screen.contains(CONCEALED));
+        assert!(!screen.contains("frirp7i1ob7wig4d"));
+
+        type_keys(&mut app, &[Key::Enter, Key::Char('c')]);
+        assert_eq!(app.clipboard.take(), Some("frirp7i1ob7wig4d".to_string()));
+        type_keys(&mut app, &[Key::Enter]);
+        assert_eq!(app.pane, Pane::Details);
+        let password = app.fields.iter().position(|f| f.label == "password").unwrap();
+        app.field_list.selected = password;
+        type_keys(&mut app, &[Key::Char('r')]);
+        assert!(app.render(24, 120, 0).contains("frirp7i1ob7wig4d"));
+        type_keys(&mut app, &[Key::Char('y')]);
+        assert_eq!(app.clipboard.take(), Some("frirp7i1ob7wig4d".to_string()));
+
+        type_keys(&mut app, &[Key::Escape, Key::Escape]);
+        assert_eq!(app.pane, Pane::Items);
+        assert_eq!(app.visible.len(), all);
+        let social = app.folder_entries.iter().position(|e| e.title == "Social").unwrap();
+        app.pane = Pane::Folders;
+        app.move_selection(social as isize);
+        assert!(app.visible.len() < all);
+        assert!(app.visible.iter().all(|&i| app.items[i].folder().is_some()));
+        type_keys(&mut app, &[Key::Char('q')]);
+        assert!(app.quit);
+    }
+}
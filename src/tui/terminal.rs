@@ -0,0 +1,309 @@
+//! Raw mode, key decoding and the few escape sequences the interface draws with.
+
+use base64;
+use libc;
+use std::io::{self, Result as IoResult, Write};
+use std::mem;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    /// A letter typed with Control, in lowercase.
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Delete,
+    Escape,
+}
+
+/// Decodes the key at the start of `bytes`, returning it with the number of bytes it took, or
+/// `None` if `bytes` holds only part of a key. Unknown escape sequences are skipped whole.
+pub fn parse_key(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    let first = match bytes.first() {
+        Some(&b) => b,
+        None => return None,
+    };
+    match first {
+        0x1b => parse_escape(bytes),
+        b'\r' | b'\n' => Some((Some(Key::Enter), 1)),
+        b'\t' => Some((Some(Key::Tab), 1)),
+        0x7f | 0x08 => Some((Some(Key::Backspace), 1)),
+        b if b >= 0x01 && b <= 0x1a => Some((Some(Key::Ctrl((b - 1 + b'a') as char)), 1)),
+        b if b < 0x20 => Some((None, 1)),
+        b if b < 0x80 => Some((Some(Key::Char(b as char)), 1)),
+        b if b < 0xc0 || b >= 0xf8 => Some((None, 1)),
+        _ => {
+            let len = if first >= 0xf0 {
+                4
+            } else if first >= 0xe0 {
+                3
+            } else {
+                2
+            };
+            if bytes.len() < len {
+                return None;
+            }
+            match ::std::str::from_utf8(&bytes[..len]) {
+                Ok(s) => Some((s.chars().next().map(Key::Char), len)),
+                Err(_) => Some((None, 1)),
+            }
+        }
+    }
+}
+
+fn parse_escape(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    if bytes.len() == 1 {
+        // Sequences arrive in one read, so a lone escape is the key itself.
+        return Some((Some(Key::Escape), 1));
+    }
+    if bytes[1] != b'[' && bytes[1] != b'O' {
+        return Some((Some(Key::Escape), 1));
+    }
+    // The sequence ends at its first byte in @ to ~ after the introducer.
+    let end = match bytes[2..].iter().position(|&b| b >= 0x40 && b <= 0x7e) {
+        Some(i) => i + 2,
+        None => return if bytes.len() > 16 { Some((None, bytes.len())) } else { None },
+    };
+    let key = match (&bytes[2..end], bytes[end]) {
+        (b"", b'A') => Some(Key::Up),
+        (b"", b'B') => Some(Key::Down),
+        (b"", b'C') => Some(Key::Right),
+        (b"", b'D') => Some(Key::Left),
+        (b"", b'H') | (b"1", b'~') | (b"7", b'~') => Some(Key::Home),
+        (b"", b'F') | (b"4", b'~') | (b"8", b'~') => Some(Key::End),
+        (b"", b'Z') => Some(Key::BackTab),
+        (b"3", b'~') => Some(Key::Delete),
+        (b"5", b'~') => Some(Key::PageUp),
+        (b"6", b'~') => Some(Key::PageDown),
+        _ => None,
+    };
+    Some((key, end + 1))
+}
+
+/// Returns the escape sequence that sets the system clipboard to `text` in terminals that
+/// support OSC 52, wrapped for tmux to pass on when `tmux` is true.
+pub fn osc52(text: &str, tmux: bool) -> String {
+    let encoded = base64::u8en(text.as_bytes()).expect("base64 encoding never fails");
+    wrap_for_tmux(format!("\x1b]52;c;{}\x07", String::from_utf8_lossy(&encoded)), tmux)
+}
+
+/// Returns the escape sequence that asks the terminal what the clipboard holds, which it answers
+/// as `osc52` would set it, if it allows programs to read the clipboard at all.
+pub fn osc52_query(tmux: bool) -> String {
+    wrap_for_tmux("\x1b]52;c;?\x07".to_string(), tmux)
+}
+
+/// Returns the text of the first complete answer to `osc52_query` in `bytes`, or `None` if
+/// there is none yet or it does not decode.
+pub fn parse_osc52_reply(bytes: &[u8]) -> Option<String> {
+    let start = match bytes.windows(5).position(|w| w == b"\x1b]52;") {
+        Some(i) => i + 5,
+        None => return None,
+    };
+    let rest = &bytes[start..];
+    // The selection, then the data, ended by BEL or ST.
+    let data_start = match rest.iter().position(|&b| b == b';') {
+        Some(i) => i + 1,
+        None => return None,
+    };
+    let data = &rest[data_start..];
+    let end = match data.iter().position(|&b| b == 0x07 || b == 0x1b) {
+        Some(i) => i,
+        None => return None,
+    };
+    if data[end] == 0x1b && data.get(end + 1) != Some(&b'\\') {
+        return None;
+    }
+    base64::u8de(&data[..end]).ok().and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+fn wrap_for_tmux(sequence: String, tmux: bool) -> String {
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace("\x1b", "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+/// Raw mode on a terminal, left when dropped.
+pub struct RawMode {
+    fd: libc::c_int,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Switches the terminal on `fd` to raw mode, so that input is neither echoed nor buffered
+    /// by line.
+    pub fn enable(fd: libc::c_int) -> IoResult<RawMode> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode {
+            fd: fd,
+            original: original,
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped.
+pub struct Terminal {
+    _raw_mode: RawMode,
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    /// Switches the terminal on stdin and stdout to raw mode, failing if either is not one.
+    pub fn open() -> IoResult<Terminal> {
+        if unsafe { libc::isatty(0) != 1 || libc::isatty(1) != 1 } {
+            return Err(io::Error::new(io::ErrorKind::Other, "not a terminal"));
+        }
+        let terminal = Terminal {
+            _raw_mode: try!(RawMode::enable(0)),
+            pending: Vec::new(),
+        };
+        // Alternate screen, hidden cursor.
+        try!(terminal.write("\x1b[?1049h\x1b[?25l"));
+        Ok(terminal)
+    }
+
+    /// Returns the number of rows and columns.
+    pub fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+            return (24, 80);
+        }
+        (size.ws_row as usize, size.ws_col as usize)
+    }
+
+    pub fn write(&self, s: &str) -> IoResult<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        try!(stdout.write_all(s.as_bytes()));
+        stdout.flush()
+    }
+
+    /// Waits up to `timeout` for a key, returning `None` if none was pressed.
+    pub fn read_key(&mut self, timeout: Duration) -> IoResult<Option<Key>> {
+        loop {
+            if let Some((key, len)) = parse_key(&self.pending) {
+                self.pending.drain(..len);
+                match key {
+                    Some(key) => return Ok(Some(key)),
+                    None => continue,
+                }
+            }
+            if !try!(wait_for_input(0, timeout)) {
+                return Ok(None);
+            }
+            let mut buf = [0; 64];
+            let n = unsafe { libc::read(0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "terminal closed"));
+            }
+            self.pending.extend_from_slice(&buf[..n as usize]);
+        }
+    }
+}
+
+impl Drop for Terminal {
+    // Raw mode is left after this, when the fields drop.
+    fn drop(&mut self) {
+        let _ = self.write("\x1b[0m\x1b[?25h\x1b[?1049l");
+    }
+}
+
+/// Waits up to `timeout` for `fd` to have input, returning whether it has.
+pub fn wait_for_input(fd: libc::c_int, timeout: Duration) -> IoResult<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1000000) as u64;
+    let millis = if millis > i32::max_value() as u64 {
+        i32::max_value()
+    } else {
+        millis as i32
+    };
+    match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+        -1 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(e) }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{osc52, osc52_query, parse_key, parse_osc52_reply, Key};
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key(b"q"), Some((Some(Key::Char('q')), 1)));
+        assert_eq!(parse_key("é!".as_bytes()), Some((Some(Key::Char('é')), 2)));
+        assert_eq!(parse_key(&"é".as_bytes()[..1]), None);
+        assert_eq!(parse_key(b"\x03"), Some((Some(Key::Ctrl('c')), 1)));
+        assert_eq!(parse_key(b"\r"), Some((Some(Key::Enter), 1)));
+        assert_eq!(parse_key(b"\x7f"), Some((Some(Key::Backspace), 1)));
+        assert_eq!(parse_key(b"\x1b"), Some((Some(Key::Escape), 1)));
+        assert_eq!(parse_key(b"\x1b[Ax"), Some((Some(Key::Up), 3)));
+        assert_eq!(parse_key(b"\x1bOB"), Some((Some(Key::Down), 3)));
+        assert_eq!(parse_key(b"\x1b[5~"), Some((Some(Key::PageUp), 4)));
+        assert_eq!(parse_key(b"\x1b[Z"), Some((Some(Key::BackTab), 3)));
+        assert_eq!(parse_key(b"\x1b[1;5C"), Some((None, 6)));
+        assert_eq!(parse_key(b"\x1b[1;5"), None);
+        assert_eq!(parse_key(b"\x1bq"), Some((Some(Key::Escape), 1)));
+    }
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(osc52("hunter2", false), "\x1b]52;c;aHVudGVyMg==\x07");
+        assert_eq!(osc52("hunter2", true), "\x1bPtmux;\x1b\x1b]52;c;aHVudGVyMg==\x07\x1b\\");
+        assert_eq!(osc52_query(false), "\x1b]52;c;?\x07");
+    }
+
+    #[test]
+    fn test_parse_osc52_reply() {
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;aHVudGVyMg==\x07"), Some("hunter2".to_string()));
+        assert_eq!(parse_osc52_reply(b"x\x1b]52;c;aHVudGVyMg==\x1b\\"),
+                   Some("hunter2".to_string()));
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;\x07"), Some(String::new()));
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;aHVudG"), None);
+        assert_eq!(parse_osc52_reply(b"\x1b]52;c;aHVudGVyMg==\x1b"), None);
+        assert_eq!(parse_osc52_reply(b"q"), None);
+    }
+}
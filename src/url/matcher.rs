@@ -0,0 +1,192 @@
+//! Finds the items whose URLs belong to the address of a page.
+
+use op_vault::item::Item;
+use url::Url;
+use url::public_suffix;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchMode {
+    /// The hosts have the same registrable domain, so that a page at `login.example.co.uk`
+    /// matches items saved for `example.co.uk` or `www.example.co.uk`. Hosts without one, such
+    /// as IP addresses, must be the same.
+    Domain,
+    /// The hosts are the same.
+    Host,
+    /// The hosts are the same and the path of the item URL is a leading part of the page path.
+    Prefix,
+}
+
+impl MatchMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "domain" => Some(MatchMode::Domain),
+            "host" => Some(MatchMode::Host),
+            "prefix" => Some(MatchMode::Prefix),
+            _ => None,
+        }
+    }
+}
+
+/// How closely an item URL matches the page. Closer matches compare greater.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Score {
+    /// 2 for the same host, 1 if the item host is a parent domain of the page host and 0
+    /// otherwise.
+    host: u8,
+    /// The length of the item path if it is a leading part of the page path.
+    path: usize,
+    /// Whether the schemes are the same, rather than `http` for the item and `https` for the page.
+    scheme: bool,
+}
+
+pub struct Match {
+    pub item: Item,
+    /// The item URL that matched best.
+    pub url: String,
+    pub score: Score,
+}
+
+pub struct Matcher {
+    url: Url,
+    domain: Option<String>,
+    mode: MatchMode,
+}
+
+impl Matcher {
+    /// Returns a matcher for the page at `url`, or `None` if `url` cannot be parsed.
+    pub fn new(url: &str, mode: MatchMode) -> Option<Matcher> {
+        Url::parse(url).map(|url| {
+            let domain = public_suffix::registrable_domain(&url.host).map(|s| s.to_string());
+            Matcher {
+                url: url,
+                domain: domain,
+                mode: mode,
+            }
+        })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns how closely `url` matches the page, or `None` if it does not. A page served over
+    /// HTTPS matches URLs for plain HTTP but not the other way around, and the ports must be the
+    /// same.
+    pub fn score(&self, url: &str) -> Option<Score> {
+        let url = match Url::parse(url) {
+            Some(url) => url,
+            None => return None,
+        };
+        let same_scheme = url.scheme == self.url.scheme;
+        if !same_scheme && !(url.scheme == "http" && self.url.scheme == "https") {
+            return None;
+        }
+        if url.port != self.url.port {
+            return None;
+        }
+        let domain = self.domain.as_ref().map(|s| &s[..]);
+        let host = if url.host == self.url.host {
+            2
+        } else if self.mode != MatchMode::Domain || domain.is_none() ||
+                  public_suffix::registrable_domain(&url.host) != domain {
+            return None;
+        } else if self.url.host.ends_with(&format!(".{}", url.host)) {
+            1
+        } else {
+            0
+        };
+        let path = url.path.trim_right_matches('/');
+        let path_matches = self.url.path == path ||
+                           self.url.path.starts_with(&format!("{}/", path));
+        if !path_matches && self.mode == MatchMode::Prefix {
+            return None;
+        }
+        Some(Score {
+            host: host,
+            path: if path_matches { path.len() } else { 0 },
+            scheme: same_scheme,
+        })
+    }
+
+    /// Returns the items among `items` that have a matching URL, the closest matches first, then
+    /// the items updated last. Trashed items are left out.
+    pub fn rank<I: IntoIterator<Item = Item>>(&self, items: I) -> Vec<Match> {
+        let mut matches: Vec<Match> = items.into_iter()
+            .filter(|item| !item.is_trashed())
+            .filter_map(|item| {
+                let best = item.urls()
+                    .into_iter()
+                    .filter_map(|url| self.score(&url).map(|score| (score, url)))
+                    .fold(None, |best: Option<(Score, String)>, (score, url)| match best {
+                        Some(ref best) if best.0 >= score => Some(best.clone()),
+                        _ => Some((score, url)),
+                    });
+                best.map(|(score, url)| {
+                    Match {
+                        item: item,
+                        url: url,
+                        score: score,
+                    }
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| {
+            (b.score, b.item.updated()).cmp(&(a.score, a.item.updated()))
+        });
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use op_vault::category::Category;
+    use testing::sample_vault;
+    use super::{MatchMode, Matcher};
+
+    #[test]
+    fn test_score() {
+        let page = "https://login.example.co.uk/account/settings?tab=1";
+        let domain = Matcher::new(page, MatchMode::Domain).unwrap();
+        let host = Matcher::new(page, MatchMode::Host).unwrap();
+        let prefix = Matcher::new(page, MatchMode::Prefix).unwrap();
+
+        let same = domain.score("https://login.example.co.uk/").unwrap();
+        let parent = domain.score("example.co.uk").unwrap();
+        let sibling = domain.score("https://www.example.co.uk/").unwrap();
+        let insecure = domain.score("http://login.example.co.uk/").unwrap();
+        let path = domain.score("https://login.example.co.uk/account/").unwrap();
+        assert!(path > same && same > insecure && insecure > parent && parent > sibling);
+        assert_eq!(domain.score("https://www.example.co.uk/elsewhere").unwrap(), sibling);
+        assert!(domain.score("https://example.uk/").is_none());
+        assert!(domain.score("https://other.co.uk/").is_none());
+        assert!(domain.score("https://login.example.co.uk:8443/").is_none());
+        assert!(domain.score("ftp://login.example.co.uk/").is_none());
+
+        assert!(host.score("https://LOGIN.example.co.uk:443/x").is_some());
+        assert!(host.score("https://www.example.co.uk/").is_none());
+
+        assert!(prefix.score("https://login.example.co.uk/account").is_some());
+        assert!(prefix.score("https://login.example.co.uk/acc").is_none());
+        assert!(prefix.score("https://login.example.co.uk/").is_some());
+
+        let http = Matcher::new("http://login.example.co.uk/", MatchMode::Domain).unwrap();
+        assert!(http.score("https://login.example.co.uk/").is_none());
+        let github = Matcher::new("https://alice.github.io/", MatchMode::Domain).unwrap();
+        assert!(github.score("https://bob.github.io/").is_none());
+        let ip = Matcher::new("http://192.168.0.1/", MatchMode::Domain).unwrap();
+        assert!(ip.score("http://192.168.0.2/").is_none());
+    }
+
+    #[test]
+    fn test_rank() {
+        let vault = sample_vault();
+        let matcher = Matcher::new("https://secure.hulu.com/login", MatchMode::Domain).unwrap();
+        let logins = vault.items().into_iter().filter(|item| item.category() == Category::Login);
+        let matches = matcher.rank(logins);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].item.title(), Some("Hulu"));
+        assert_eq!(matches[0].url, "http://www.hulu.com/");
+        let matcher = Matcher::new("https://secure.hulu.com/login", MatchMode::Host).unwrap();
+        assert!(matcher.rank(vault.items()).is_empty());
+    }
+}
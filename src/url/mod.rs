@@ -0,0 +1,200 @@
+//! Just enough URL parsing to compare the addresses saved with logins.
+
+use std::char;
+
+pub mod matcher;
+pub mod public_suffix;
+
+/// The parts of a URL that decide which site it belongs to. Scheme and host are lowercase, and
+/// internationalized hosts are in Unicode whether they were written in it or in Punycode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    pub scheme: String,
+    pub host: String,
+    /// `None` for the default port of the scheme.
+    pub port: Option<u16>,
+    /// Starts with `/`, without the query or fragment.
+    pub path: String,
+}
+
+impl Url {
+    /// Parses `s`, taking `https` for the scheme if it has none, as 1Password does for addresses
+    /// typed without one.
+    pub fn parse(s: &str) -> Option<Url> {
+        let s = s.trim();
+        let (scheme, rest) = match s.find("://") {
+            Some(i) => (s[..i].to_lowercase(), &s[i + 3..]),
+            None => ("https".to_string(), s),
+        };
+        let authority_end = rest.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+        let authority = &rest[..authority_end];
+        let rest = &rest[authority_end..];
+        // Drop credentials written into the URL.
+        let host_port = match authority.rfind('@') {
+            Some(i) => &authority[i + 1..],
+            None => authority,
+        };
+        let (host, port) = match split_port(host_port) {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        };
+        let host = normalize_host(host);
+        if scheme.is_empty() || host.is_empty() {
+            return None;
+        }
+        let port = match (&scheme[..], port) {
+            ("http", Some(80)) | ("https", Some(443)) => None,
+            (_, port) => port,
+        };
+        let path = &rest[..rest.find(|c| c == '?' || c == '#').unwrap_or(rest.len())];
+        Some(Url {
+            scheme: scheme,
+            host: host,
+            port: port,
+            path: if path.is_empty() { "/".to_string() } else { path.to_string() },
+        })
+    }
+}
+
+/// Lowercases `host`, drops the dot that may end it and decodes its Punycode labels.
+fn normalize_host(host: &str) -> String {
+    let host = host.to_lowercase();
+    let host = host.trim_right_matches('.');
+    if host.starts_with('[') {
+        return host.to_string();
+    }
+    host.split('.')
+        .map(|label| if label.starts_with("xn--") {
+            decode_punycode(&label[4..])
+                .map(|label| label.to_lowercase())
+                .unwrap_or_else(|| label.to_string())
+        } else {
+            label.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+const PUNYCODE_BASE: u32 = 36;
+const PUNYCODE_TMIN: u32 = 1;
+const PUNYCODE_TMAX: u32 = 26;
+
+/// Decodes a Punycode label without its `xn--` prefix as described in RFC 3492.
+fn decode_punycode(input: &str) -> Option<String> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => ("", input),
+    };
+    if basic.bytes().any(|b| b >= 0x80) {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n: u32 = 0x80;
+    let mut i: u32 = 0;
+    let mut bias = 72;
+    let mut digits = extended.bytes();
+    let mut digit = digits.next();
+    while digit.is_some() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = PUNYCODE_BASE;
+        loop {
+            let d = match digit {
+                Some(b) if b >= b'a' && b <= b'z' => (b - b'a') as u32,
+                Some(b) if b >= b'0' && b <= b'9' => (b - b'0') as u32 + 26,
+                _ => return None,
+            };
+            digit = digits.next();
+            i = match d.checked_mul(w).and_then(|dw| i.checked_add(dw)) {
+                Some(i) => i,
+                None => return None,
+            };
+            let t = if k <= bias {
+                PUNYCODE_TMIN
+            } else if k >= bias + PUNYCODE_TMAX {
+                PUNYCODE_TMAX
+            } else {
+                k - bias
+            };
+            if d < t {
+                break;
+            }
+            w = match w.checked_mul(PUNYCODE_BASE - t) {
+                Some(w) => w,
+                None => return None,
+            };
+            k += PUNYCODE_BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt_bias(i - old_i, len, old_i == 0);
+        n = match n.checked_add(i / len) {
+            Some(n) => n,
+            None => return None,
+        };
+        i %= len;
+        output.insert(i as usize, match char::from_u32(n) {
+            Some(c) => c,
+            None => return None,
+        });
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+fn adapt_bias(delta: u32, len: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / 700 } else { delta / 2 };
+    delta += delta / len;
+    let mut k = 0;
+    while delta > (PUNYCODE_BASE - PUNYCODE_TMIN) * PUNYCODE_TMAX / 2 {
+        delta /= PUNYCODE_BASE - PUNYCODE_TMIN;
+        k += PUNYCODE_BASE;
+    }
+    k + (PUNYCODE_BASE - PUNYCODE_TMIN + 1) * delta / (delta + 38)
+}
+
+/// Splits `host:port`, minding the colons of bracketed IPv6 addresses.
+fn split_port(host_port: &str) -> Option<(&str, u16)> {
+    let i = match host_port.rfind(':') {
+        Some(i) => i,
+        None => return None,
+    };
+    if host_port[..i].contains(':') && !host_port[..i].ends_with(']') {
+        return None;
+    }
+    host_port[i + 1..].parse().ok().map(|port| (&host_port[..i], port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_punycode, Url};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Url::parse("HTTPS://user@Git.Example.com:8443/org/repo.git?x#y").unwrap(),
+                   Url {
+                       scheme: "https".to_string(),
+                       host: "git.example.com".to_string(),
+                       port: Some(8443),
+                       path: "/org/repo.git".to_string(),
+                   });
+        let url = Url::parse("example.com").unwrap();
+        assert_eq!((&url.scheme[..], &url.host[..], url.port, &url.path[..]),
+                   ("https", "example.com", None, "/"));
+        assert_eq!(Url::parse("http://[::1]:8080/").unwrap().host, "[::1]");
+        assert_eq!(Url::parse("http://[::1]/").unwrap().port, None);
+        assert!(Url::parse("https:///path").is_none());
+        assert_eq!(Url::parse("https://Example.com.:443").unwrap(),
+                   Url::parse("example.com").unwrap());
+        assert_eq!(Url::parse("http://example.com:443/").unwrap().port, Some(443));
+        assert_eq!(Url::parse("https://www.XN--BCHER-KVA.de/").unwrap().host, "www.bücher.de");
+    }
+
+    #[test]
+    fn test_decode_punycode() {
+        assert_eq!(decode_punycode("bcher-kva"), Some("bücher".to_string()));
+        assert_eq!(decode_punycode("55qx5d"), Some("公司".to_string()));
+        assert_eq!(decode_punycode("abc-"), Some("abc".to_string()));
+        assert_eq!(decode_punycode("bcher-kv!"), None);
+        assert_eq!(decode_punycode("zzzzzzzzzzzz"), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Registrable domains by the Public Suffix List.
+//!
+//! public_suffix_list.dat is a verbatim copy of
+//! https://publicsuffix.org/list/public_suffix_list.dat, to be replaced with a newer copy from
+//! time to time. Both its ICANN and private sections are used, so that sites such as
+//! `alice.github.io` and `bob.github.io` are told apart.
+
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::{Once, ONCE_INIT};
+
+const LIST: &'static str = include_str!("public_suffix_list.dat");
+
+struct Rules {
+    /// Rules such as `co.uk`.
+    exact: HashSet<&'static str>,
+    /// Rules such as `*.kawasaki.jp`, without the `*.`.
+    wildcards: HashSet<&'static str>,
+    /// Rules such as `!city.kawasaki.jp`, without the `!`.
+    exceptions: HashSet<&'static str>,
+}
+
+impl Rules {
+    fn parse(list: &'static str) -> Rules {
+        let mut rules = Rules {
+            exact: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in list.lines() {
+            let rule = line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+            if rule.starts_with("*.") {
+                rules.wildcards.insert(&rule[2..]);
+            } else if rule.starts_with('!') {
+                rules.exceptions.insert(&rule[1..]);
+            } else {
+                rules.exact.insert(rule);
+            }
+        }
+        rules
+    }
+
+    /// Returns how many of the last `labels` form the public suffix.
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        // The first rule to match is the longest, and an exception wins over the rest.
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate[..]) {
+                return labels.len() - i - 1;
+            }
+            if self.exact.contains(&candidate[..]) ||
+               (i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join(".")[..])) {
+                return labels.len() - i;
+            }
+        }
+        // A domain no rule names is its own top-level domain.
+        1
+    }
+}
+
+fn rules() -> &'static Rules {
+    static INIT: Once = ONCE_INIT;
+    static mut RULES: *const Rules = 0 as *const Rules;
+    unsafe {
+        INIT.call_once(|| RULES = Box::into_raw(Box::new(Rules::parse(LIST))));
+        &*RULES
+    }
+}
+
+/// Returns the public suffix plus one label of `host`, such as `example.co.uk` for
+/// `login.example.co.uk`, or `None` if `host` is an IP address or a public suffix itself.
+///
+/// `host` must be lowercase and in Unicode, as `Url::parse` leaves it.
+pub fn registrable_domain(host: &str) -> Option<&str> {
+    if host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok() {
+        return None;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.iter().any(|label| label.is_empty()) {
+        return None;
+    }
+    let suffix_len = rules().suffix_len(&labels);
+    if suffix_len >= labels.len() {
+        return None;
+    }
+    let start = labels[..labels.len() - suffix_len - 1]
+        .iter()
+        .map(|label| label.len() + 1)
+        .fold(0, |sum, len| sum + len);
+    Some(&host[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::registrable_domain;
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("login.example.co.uk"), Some("example.co.uk"));
+        assert_eq!(registrable_domain("example.com"), Some("example.com"));
+        assert_eq!(registrable_domain("a.b.example.unknowntld"), Some("example.unknowntld"));
+        assert_eq!(registrable_domain("alice.github.io"), Some("alice.github.io"));
+        assert_eq!(registrable_domain("www.公司.cn"), Some("www.公司.cn"));
+        // *.kawasaki.jp with the exception !city.kawasaki.jp
+        assert_eq!(registrable_domain("www.city.kawasaki.jp"), Some("city.kawasaki.jp"));
+        assert_eq!(registrable_domain("a.b.kawasaki.jp"), Some("a.b.kawasaki.jp"));
+        assert_eq!(registrable_domain("b.kawasaki.jp"), None);
+        assert_eq!(registrable_domain("co.uk"), None);
+        assert_eq!(registrable_domain("localhost"), None);
+        assert_eq!(registrable_domain("192.168.0.1"), None);
+        assert_eq!(registrable_domain("[::1]"), None);
+    }
+}
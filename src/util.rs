@@ -0,0 +1,19 @@
+//! Small helpers used throughout the crate.
+
+use base64;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let b64 = base64::u8en(bytes).expect("base64 encoding never fails");
+    String::from_utf8(b64).expect("base64 is ASCII")
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Seconds since the Unix epoch, or zero if the clock is set before it.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}